@@ -47,6 +47,7 @@ docserver-builder -i ./embassy-net-driver-channel -o webroot/crates/embassy-net-
 docserver-builder -i ./embassy-net-wiznet -o webroot/crates/embassy-net-wiznet/git.zup
 docserver-builder -i ./embassy-net-ppp -o webroot/crates/embassy-net-ppp/git.zup
 docserver-builder -i ./embassy-net-tuntap -o webroot/crates/embassy-net-tuntap/git.zup
+docserver-builder -i ./embassy-hci-h4 -o webroot/crates/embassy-hci-h4/git.zup
 docserver-builder -i ./embassy-net-enc28j60 -o webroot/crates/embassy-net-enc28j60/git.zup
 docserver-builder -i ./embassy-net-esp-hosted -o webroot/crates/embassy-net-esp-hosted/git.zup
 docserver-builder -i ./embassy-net-adin1110 -o webroot/crates/embassy-net-adin1110/git.zup
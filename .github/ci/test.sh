@@ -13,10 +13,17 @@ export CARGO_TARGET_DIR=/ci/cache/target
 export CARGO_NET_GIT_FETCH_WITH_CLI=true
 
 cargo test --manifest-path ./embassy-executor/Cargo.toml
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features watchdog
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features alloc
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features task-info
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features alloc,task-info
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features arch-std,executor-thread
+cargo test --manifest-path ./embassy-executor/Cargo.toml --features arch-std,executor-thread,trace --test trace
 cargo test --manifest-path ./embassy-futures/Cargo.toml
 cargo test --manifest-path ./embassy-sync/Cargo.toml
 cargo test --manifest-path ./embassy-embedded-hal/Cargo.toml
 cargo test --manifest-path ./embassy-hal-internal/Cargo.toml
+cargo test --manifest-path ./embassy-hci-h4/Cargo.toml
 cargo test --manifest-path ./embassy-time/Cargo.toml --features mock-driver,embassy-time-queue-utils/generic-queue-8
 cargo test --manifest-path ./embassy-time-driver/Cargo.toml
 
@@ -33,4 +40,7 @@ cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --fe
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f732ze,exti,time-driver-any,exti
 cargo test --manifest-path ./embassy-stm32/Cargo.toml --no-default-features --features stm32f769ni,exti,time-driver-any,exti
 
+cargo test --manifest-path ./embassy-net/Cargo.toml --lib --features tcp,udp,raw,dns,icmp,dhcpv4,dhcpv4-server,proto-ipv4,proto-ipv6,medium-ethernet,medium-ip,multicast,slaac,ipv4-forwarding,http,mqtt,coap,sntp,mdns-responder,proto-ipv4-fragmentation,pmtu,stats,stats-checksums,pcap,tls,raw-ethernet
 cargo test --manifest-path ./embassy-net-adin1110/Cargo.toml
+cargo test --manifest-path ./embassy-net-driver-channel/Cargo.toml --features virtual-link
+cargo test --manifest-path ./cyw43/Cargo.toml --lib --features provisioning
@@ -38,6 +38,9 @@ struct BtStateInnre<'d> {
 }
 
 /// Bluetooth driver.
+///
+/// Implements the [`bt_hci::transport::Transport`] trait, so host stacks written against it run over
+/// cyw43 the same way as over other controllers, such as UART ones with the `embassy-hci-h4` crate.
 pub struct BtDriver<'d> {
     rx: RefCell<zerocopy_channel::Receiver<'d, NoopRawMutex, BtPacketBuf>>,
     tx: RefCell<zerocopy_channel::Sender<'d, NoopRawMutex, BtPacketBuf>>,
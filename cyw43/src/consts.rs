@@ -128,6 +128,7 @@ pub(crate) const WPA2_SECURITY: u32 = 0x00400000;
 
 pub(crate) const MIN_PSK_LEN: usize = 8;
 pub(crate) const MAX_PSK_LEN: usize = 64;
+pub(crate) const MAX_SAE_PASSWORD_LEN: usize = 128;
 
 // Bluetooth firmware extraction constants.
 pub(crate) const BTFW_ADDR_MODE_UNKNOWN: i32 = 0;
@@ -666,5 +667,13 @@ pub(crate) const MFP_REQUIRED: u32 = 2;
 
 pub(crate) const WPA_AUTH_DISABLED: u32 = 0x0000;
 pub(crate) const WPA_AUTH_WPA_PSK: u32 = 0x0004;
+pub(crate) const WPA_AUTH_WPA2_UNSPECIFIED: u32 = 0x0040;
 pub(crate) const WPA_AUTH_WPA2_PSK: u32 = 0x0080;
 pub(crate) const WPA_AUTH_WPA3_SAE_PSK: u32 = 0x40000;
+
+// Supplicant states, in the status of `PSK_SUP` events.
+pub(crate) const SUP_KEYXCHANGE_WAIT_M1: u32 = 4;
+pub(crate) const SUP_KEYED: u32 = 6;
+
+// Flags of event messages.
+pub(crate) const EVENT_FLAG_LINK: u16 = 0x01;
@@ -6,19 +6,12 @@ use embassy_net_driver_channel::driver::{HardwareAddress, LinkState};
 use embassy_time::{Duration, Timer};
 
 use crate::consts::*;
-use crate::events::{Event, EventSubscriber, Events};
+use crate::events::{Event, EventSubscriber, Events, JoinError, JoinProgress};
 use crate::fmt::Bytes;
 use crate::ioctl::{IoctlState, IoctlType};
 use crate::structs::*;
 use crate::{countries, events, PowerManagementMode};
 
-/// Control errors.
-#[derive(Debug)]
-pub struct Error {
-    /// Status code.
-    pub status: u32,
-}
-
 /// Multicast errors.
 #[derive(Debug)]
 pub enum AddMulticastAddressError {
@@ -92,8 +85,13 @@ pub enum JoinAuth {
     Wpa2,
     /// WPA3 only
     Wpa3,
-    /// WPA2 + WPA3
+    /// WPA2 + WPA3 transition mode: WPA3 with access points supporting it, WPA2 with the others.
     Wpa2Wpa3,
+    /// WPA2-Enterprise, with 802.1X authentication.
+    ///
+    /// Only joining is supported: EAP methods such as PEAP or EAP-TLS aren't implemented, nor offloaded
+    /// to the firmware. They must be run by a supplicant on the host, see [`Control::complete_enterprise_join`].
+    Wpa2Enterprise,
 }
 
 /// Options for [`Control::join`].
@@ -108,6 +106,9 @@ pub struct JoinOptions<'a> {
     /// Enable AES encryption. Default true.
     pub cipher_aes: bool,
     /// Passphrase. Default empty.
+    ///
+    /// WPA and WPA2 passphrases are 8 to 64 bytes long, WPA3 ones up to 128 bytes.
+    /// Ignored for open and enterprise networks.
     pub passphrase: &'a [u8],
     /// If false, `passphrase` is the human-readable passphrase string.
     /// If true, `passphrase` is the result of applying the PBKDF2 hash to the
@@ -139,6 +140,29 @@ impl<'a> JoinOptions<'a> {
         this.passphrase = passphrase;
         this
     }
+
+    /// Create a new `JoinOptions` for joining WPA2-Enterprise networks, with AES only.
+    pub fn new_enterprise() -> Self {
+        Self {
+            auth: JoinAuth::Wpa2Enterprise,
+            ..Default::default()
+        }
+    }
+
+    /// Check the passphrase suits the authentication type.
+    fn validate(&self) -> Result<(), JoinError> {
+        let len = self.passphrase.len();
+        let wpa12 = matches!(self.auth, JoinAuth::Wpa | JoinAuth::Wpa2 | JoinAuth::Wpa2Wpa3);
+        let wpa3 = matches!(self.auth, JoinAuth::Wpa3 | JoinAuth::Wpa2Wpa3);
+        let valid = (!wpa12
+            || (MIN_PSK_LEN..=MAX_PSK_LEN).contains(&len)
+            || (self.passphrase_is_prehashed && len <= MAX_PSK_LEN))
+            && (!wpa3 || (!self.passphrase_is_prehashed && (1..=MAX_SAE_PASSWORD_LEN).contains(&len)));
+        match valid {
+            true => Ok(()),
+            false => Err(JoinError::InvalidOptions),
+        }
+    }
 }
 
 impl<'a> Default for JoinOptions<'a> {
@@ -295,8 +319,19 @@ impl<'a> Control<'a> {
         self.ioctl_set_u32(Ioctl::SetPm, 0, mode_num).await;
     }
 
-    /// Join an unprotected network with the provided ssid.
-    pub async fn join(&mut self, ssid: &str, options: JoinOptions<'_>) -> Result<(), Error> {
+    /// Join a network with the provided ssid.
+    ///
+    /// Returns once the device is associated with the access point and, for protected networks, the
+    /// keys are exchanged. The link is then up.
+    ///
+    /// For [`JoinAuth::Wpa2Enterprise`] networks, returns once associated, with the link still down,
+    /// see [`complete_enterprise_join`](Self::complete_enterprise_join).
+    pub async fn join(&mut self, ssid: &str, options: JoinOptions<'_>) -> Result<(), JoinError> {
+        if ssid.is_empty() || ssid.len() > 32 {
+            return Err(JoinError::InvalidOptions);
+        }
+        options.validate()?;
+
         self.set_iovar_u32("ampdu_ba_wsize", 8).await;
 
         if options.auth == JoinAuth::Open {
@@ -317,7 +352,13 @@ impl<'a> Control<'a> {
 
             self.set_iovar_u32x2("bsscfg:sup_wpa", 0, 1).await;
             self.set_iovar_u32x2("bsscfg:sup_wpa2_eapver", 0, 0xFFFF_FFFF).await;
-            self.set_iovar_u32x2("bsscfg:sup_wpa_tmo", 0, 2500).await;
+            // The EAP exchange comes before the key exchange of enterprise networks, and can take
+            // longer than the supplicant waits for it.
+            let sup_wpa_tmo = match options.auth {
+                JoinAuth::Wpa2Enterprise => 0,
+                _ => 2500,
+            };
+            self.set_iovar_u32x2("bsscfg:sup_wpa_tmo", 0, sup_wpa_tmo).await;
 
             Timer::after_millis(100).await;
 
@@ -326,7 +367,14 @@ impl<'a> Control<'a> {
                 JoinAuth::Wpa => (true, false, AUTH_OPEN, MFP_NONE, WPA_AUTH_WPA_PSK),
                 JoinAuth::Wpa2 => (true, false, AUTH_OPEN, MFP_CAPABLE, WPA_AUTH_WPA2_PSK),
                 JoinAuth::Wpa3 => (false, true, AUTH_SAE, MFP_REQUIRED, WPA_AUTH_WPA3_SAE_PSK),
-                JoinAuth::Wpa2Wpa3 => (true, true, AUTH_SAE, MFP_CAPABLE, WPA_AUTH_WPA3_SAE_PSK),
+                JoinAuth::Wpa2Wpa3 => (
+                    true,
+                    true,
+                    AUTH_SAE,
+                    MFP_CAPABLE,
+                    WPA_AUTH_WPA2_PSK | WPA_AUTH_WPA3_SAE_PSK,
+                ),
+                JoinAuth::Wpa2Enterprise => (false, false, AUTH_OPEN, MFP_CAPABLE, WPA_AUTH_WPA2_UNSPECIFIED),
             };
 
             if wpa12 {
@@ -367,40 +415,73 @@ impl<'a> Control<'a> {
         };
         i.ssid[..ssid.len()].copy_from_slice(ssid.as_bytes());
 
-        self.wait_for_join(i).await
-    }
-
-    async fn wait_for_join(&mut self, i: SsidInfo) -> Result<(), Error> {
-        self.events.mask.enable(&[Event::SET_SSID, Event::AUTH]);
+        // The supplicant of the firmware exchanges the keys of networks using a passphrase.
+        let needs_keys = !matches!(options.auth, JoinAuth::Open | JoinAuth::Wpa2Enterprise);
+        self.events.mask.enable(&JoinProgress::EVENTS);
         let mut subscriber = self.events.queue.subscriber().unwrap();
         // the actual join operation starts here
         // we make sure to enable events before so we don't miss any
-
         self.ioctl(IoctlType::Set, Ioctl::SetSsid, 0, &mut i.to_bytes()).await;
 
-        // to complete the join, we wait for a SET_SSID event
-        // we also save the AUTH status for the user, it may be interesting
-        let mut auth_status = 0;
-        let status = loop {
+        self.wait_for_join(&mut subscriber, JoinProgress::new(needs_keys))
+            .await?;
+        if options.auth != JoinAuth::Wpa2Enterprise {
+            self.state_ch.set_link_state(LinkState::Up);
+        }
+        Ok(())
+    }
+
+    /// Complete joining a [`JoinAuth::Wpa2Enterprise`] network, with the PMK derived by the EAP method.
+    ///
+    /// Neither this driver nor the firmware run EAP methods such as PEAP or EAP-TLS, this is only a hook
+    /// for a supplicant running on the host. Once [`join`](Self::join) returns, the supplicant authenticates
+    /// with the access point, exchanging EAPOL frames (EtherType `0x888e`) through the network device.
+    /// Once it succeeds, this method gives the PMK it derived to the firmware, which exchanges the keys
+    /// with the access point, and sets the link up.
+    pub async fn complete_enterprise_join(&mut self, pmk: &[u8; 32]) -> Result<(), JoinError> {
+        self.events.mask.enable(&JoinProgress::EVENTS);
+        let mut subscriber = self.events.queue.subscriber().unwrap();
+
+        // The access point sends the first message of the key exchange now, the supplicant waits for it.
+        self.set_iovar_u32x2("bsscfg:sup_wpa_tmo", 0, 2500).await;
+        // The firmware takes the PMK as 64 hex digits, like a prehashed passphrase.
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut pfi = PassphraseInfo {
+            len: 64,
+            flags: 0,
+            passphrase: [0; 64],
+        };
+        for (digits, byte) in pfi.passphrase.chunks_exact_mut(2).zip(pmk) {
+            digits[0] = HEX[usize::from(byte >> 4)];
+            digits[1] = HEX[usize::from(byte & 0xf)];
+        }
+        self.ioctl(IoctlType::Set, Ioctl::SetWsecPmk, 0, &mut pfi.to_bytes())
+            .await;
+
+        self.wait_for_join(&mut subscriber, JoinProgress::associated()).await?;
+        self.state_ch.set_link_state(LinkState::Up);
+        Ok(())
+    }
+
+    /// Wait for the events completing a join.
+    async fn wait_for_join(
+        &mut self,
+        subscriber: &mut EventSubscriber<'_>,
+        mut progress: JoinProgress,
+    ) -> Result<(), JoinError> {
+        let res = loop {
             let msg = subscriber.next_message_pure().await;
-            if msg.header.event_type == Event::AUTH && msg.header.status != EStatus::SUCCESS {
-                auth_status = msg.header.status;
-            } else if msg.header.event_type == Event::SET_SSID {
-                // join operation ends with SET_SSID event
-                break msg.header.status;
+            if let Some(res) = progress.handle(&msg.header) {
+                break res;
             }
         };
 
         self.events.mask.disable_all();
-        if status == EStatus::SUCCESS {
-            // successful join
-            self.state_ch.set_link_state(LinkState::Up);
-            debug!("JOINED");
-            Ok(())
-        } else {
-            warn!("JOIN failed with status={} auth={}", status, auth_status);
-            Err(Error { status })
+        match res {
+            Ok(()) => debug!("JOINED"),
+            Err(e) => warn!("JOIN failed: {:?}", e),
         }
+        res
     }
 
     /// Set GPIO pin on WiFi chip.
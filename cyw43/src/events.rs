@@ -6,6 +6,7 @@ use core::cell::RefCell;
 use embassy_sync::blocking_mutex::raw::NoopRawMutex;
 use embassy_sync::pubsub::{PubSubChannel, Subscriber};
 
+use crate::consts::{EStatus, EVENT_FLAG_LINK, SUP_KEYED, SUP_KEYXCHANGE_WAIT_M1};
 use crate::structs::BssInfo;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, num_enum::FromPrimitive)]
@@ -309,6 +310,123 @@ impl Events {
 pub struct Status {
     pub event_type: Event,
     pub status: u32,
+    pub reason: u32,
+    pub flags: u16,
+}
+
+/// Error joining a network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum JoinError {
+    /// The SSID or [`JoinOptions`](crate::JoinOptions) are invalid, such as a passphrase of the wrong
+    /// length, or prehashed for WPA3.
+    InvalidOptions,
+    /// No network with the SSID was found.
+    NetworkNotFound,
+    /// The access point rejected the authentication, for example because the WPA3 passphrase is wrong.
+    AuthenticationFailed {
+        /// Status of the `AUTH` event.
+        status: u32,
+    },
+    /// The key exchange with the access point failed, usually because the WPA or WPA2 passphrase is wrong.
+    KeyExchangeFailed {
+        /// Supplicant state, in the status of the `PSK_SUP` event.
+        status: u32,
+        /// Supplicant failure reason.
+        reason: u32,
+    },
+    /// The access point disconnected the device during the join.
+    Disconnected {
+        /// IEEE 802.11 reason code of the deauthentication or disassociation.
+        reason: u32,
+    },
+    /// Joining failed, with the status of the `SET_SSID` event.
+    Failed {
+        /// Status of the `SET_SSID` event.
+        status: u32,
+    },
+}
+
+/// Progress of a join, following the events sent by the firmware.
+pub struct JoinProgress {
+    ssid_set: bool,
+    link_up: bool,
+    /// Whether the supplicant of the firmware must complete the key exchange.
+    needs_keys: bool,
+    keyed: bool,
+    auth_status: Option<u32>,
+    disconnect_reason: Option<u32>,
+}
+
+impl JoinProgress {
+    /// Events telling how a join progresses.
+    pub const EVENTS: [Event; 6] = [
+        Event::SET_SSID,
+        Event::AUTH,
+        Event::LINK,
+        Event::PSK_SUP,
+        Event::DEAUTH_IND,
+        Event::DISASSOC_IND,
+    ];
+
+    pub fn new(needs_keys: bool) -> Self {
+        Self {
+            ssid_set: false,
+            link_up: false,
+            needs_keys,
+            keyed: false,
+            auth_status: None,
+            disconnect_reason: None,
+        }
+    }
+
+    /// Progress of a join already associated, waiting for the key exchange.
+    pub fn associated() -> Self {
+        Self {
+            ssid_set: true,
+            link_up: true,
+            ..Self::new(true)
+        }
+    }
+
+    /// Handle `event`, returning the result of the join once it's known.
+    pub fn handle(&mut self, event: &Status) -> Option<Result<(), JoinError>> {
+        match event.event_type {
+            // The join operation ends with the SET_SSID event, successful once associated.
+            Event::SET_SSID if event.status == EStatus::SUCCESS => self.ssid_set = true,
+            Event::SET_SSID if event.status == EStatus::NO_NETWORKS => return Some(Err(JoinError::NetworkNotFound)),
+            Event::SET_SSID => {
+                let err = match (self.auth_status, self.disconnect_reason) {
+                    (Some(status), _) => JoinError::AuthenticationFailed { status },
+                    (None, Some(reason)) => JoinError::Disconnected { reason },
+                    (None, None) => JoinError::Failed { status: event.status },
+                };
+                return Some(Err(err));
+            }
+            Event::AUTH if event.status != EStatus::SUCCESS => self.auth_status = Some(event.status),
+            Event::LINK if event.flags & EVENT_FLAG_LINK != 0 => self.link_up = true,
+            Event::LINK if self.link_up => {
+                let reason = self.disconnect_reason.unwrap_or(event.reason);
+                return Some(Err(JoinError::Disconnected { reason }));
+            }
+            // Supplicant events before the link is up are left over from a previous association.
+            Event::PSK_SUP if self.link_up => {
+                if event.status == SUP_KEYED {
+                    self.keyed = true;
+                } else if !(event.status == SUP_KEYXCHANGE_WAIT_M1 && event.reason == 0) {
+                    return Some(Err(JoinError::KeyExchangeFailed {
+                        status: event.status,
+                        reason: event.reason,
+                    }));
+                }
+            }
+            Event::DEAUTH_IND | Event::DISASSOC_IND => self.disconnect_reason = Some(event.reason),
+            _ => {}
+        }
+        let done = self.ssid_set && self.link_up && (self.keyed || !self.needs_keys);
+        done.then_some(Ok(()))
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -413,3 +531,139 @@ impl Default for SharedEventMask {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: Event, status: u32) -> Status {
+        Status {
+            event_type,
+            status,
+            reason: 0,
+            flags: 0,
+        }
+    }
+
+    fn link_up() -> Status {
+        Status {
+            flags: EVENT_FLAG_LINK,
+            ..event(Event::LINK, 0)
+        }
+    }
+
+    fn link_down(reason: u32) -> Status {
+        Status {
+            reason,
+            ..event(Event::LINK, 0)
+        }
+    }
+
+    fn psk_sup(status: u32, reason: u32) -> Status {
+        Status {
+            reason,
+            ..event(Event::PSK_SUP, status)
+        }
+    }
+
+    #[test]
+    fn open_join_waits_for_link() {
+        let mut progress = JoinProgress::new(false);
+        assert_eq!(progress.handle(&event(Event::AUTH, EStatus::SUCCESS as u32)), None);
+        assert_eq!(progress.handle(&event(Event::SET_SSID, EStatus::SUCCESS as u32)), None);
+        assert_eq!(progress.handle(&link_up()), Some(Ok(())));
+
+        let mut progress = JoinProgress::new(false);
+        assert_eq!(progress.handle(&link_up()), None);
+        assert_eq!(
+            progress.handle(&event(Event::SET_SSID, EStatus::SUCCESS as u32)),
+            Some(Ok(()))
+        );
+    }
+
+    #[test]
+    fn protected_join_waits_for_keys() {
+        let mut progress = JoinProgress::new(true);
+        // Left over from a previous association.
+        assert_eq!(progress.handle(&psk_sup(SUP_KEYED, 0)), None);
+        assert_eq!(progress.handle(&event(Event::SET_SSID, EStatus::SUCCESS as u32)), None);
+        assert_eq!(progress.handle(&link_up()), None);
+        assert_eq!(progress.handle(&psk_sup(SUP_KEYXCHANGE_WAIT_M1, 0)), None);
+        assert_eq!(progress.handle(&psk_sup(SUP_KEYED, 0)), Some(Ok(())));
+    }
+
+    #[test]
+    fn enterprise_join_completes_with_keys() {
+        let mut progress = JoinProgress::associated();
+        assert_eq!(progress.handle(&psk_sup(SUP_KEYXCHANGE_WAIT_M1, 0)), None);
+        assert_eq!(progress.handle(&psk_sup(SUP_KEYED, 0)), Some(Ok(())));
+    }
+
+    #[test]
+    fn reports_network_not_found() {
+        let mut progress = JoinProgress::new(true);
+        assert_eq!(
+            progress.handle(&event(Event::SET_SSID, EStatus::NO_NETWORKS as u32)),
+            Some(Err(JoinError::NetworkNotFound))
+        );
+    }
+
+    #[test]
+    fn reports_authentication_failure() {
+        let mut progress = JoinProgress::new(true);
+        assert_eq!(progress.handle(&event(Event::AUTH, EStatus::FAIL as u32)), None);
+        assert_eq!(
+            progress.handle(&event(Event::SET_SSID, EStatus::FAIL as u32)),
+            Some(Err(JoinError::AuthenticationFailed {
+                status: EStatus::FAIL as u32
+            }))
+        );
+    }
+
+    #[test]
+    fn reports_key_exchange_failure() {
+        let mut progress = JoinProgress::new(true);
+        assert_eq!(progress.handle(&event(Event::SET_SSID, EStatus::SUCCESS as u32)), None);
+        assert_eq!(progress.handle(&link_up()), None);
+        assert_eq!(
+            progress.handle(&psk_sup(SUP_KEYXCHANGE_WAIT_M1, 15)),
+            Some(Err(JoinError::KeyExchangeFailed {
+                status: SUP_KEYXCHANGE_WAIT_M1,
+                reason: 15
+            }))
+        );
+    }
+
+    #[test]
+    fn reports_disconnection() {
+        let mut progress = JoinProgress::new(true);
+        let deauth = Status {
+            reason: 2,
+            ..event(Event::DEAUTH_IND, 0)
+        };
+        assert_eq!(progress.handle(&deauth), None);
+        assert_eq!(
+            progress.handle(&event(Event::SET_SSID, EStatus::FAIL as u32)),
+            Some(Err(JoinError::Disconnected { reason: 2 }))
+        );
+
+        let mut progress = JoinProgress::new(true);
+        assert_eq!(progress.handle(&link_down(1)), None);
+        assert_eq!(progress.handle(&link_up()), None);
+        assert_eq!(
+            progress.handle(&link_down(3)),
+            Some(Err(JoinError::Disconnected { reason: 3 }))
+        );
+    }
+
+    #[test]
+    fn reports_other_failures() {
+        let mut progress = JoinProgress::new(false);
+        assert_eq!(
+            progress.handle(&event(Event::SET_SSID, EStatus::TIMEOUT as u32)),
+            Some(Err(JoinError::Failed {
+                status: EStatus::TIMEOUT as u32
+            }))
+        );
+    }
+}
@@ -1,5 +1,5 @@
 #![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_main)]
 #![allow(async_fn_in_trait)]
 #![deny(unused_must_use)]
 #![doc = include_str!("../README.md")]
@@ -18,6 +18,8 @@ mod countries;
 mod events;
 mod ioctl;
 mod nvram;
+#[cfg(feature = "provisioning")]
+pub mod provisioning;
 mod runner;
 mod structs;
 mod util;
@@ -29,9 +31,8 @@ use ioctl::IoctlState;
 
 use crate::bus::Bus;
 pub use crate::bus::SpiBusCyw43;
-pub use crate::control::{
-    AddMulticastAddressError, Control, Error as ControlError, JoinAuth, JoinOptions, ScanOptions, ScanType, Scanner,
-};
+pub use crate::control::{AddMulticastAddressError, Control, JoinAuth, JoinOptions, ScanOptions, ScanType, Scanner};
+pub use crate::events::JoinError;
 pub use crate::runner::Runner;
 pub use crate::structs::BssInfo;
 
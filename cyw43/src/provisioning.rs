@@ -0,0 +1,826 @@
+//! Wi-Fi provisioning over a SoftAP with a captive portal.
+//!
+//! [`provision`] joins the network whose credentials are in a [`CredentialStorage`]. When there are
+//! none, or joining fails, it scans the networks in range, starts an access point and serves a
+//! configuration page to the stations connecting to it:
+//!
+//! - a DHCP server gives them an address, and the address of the device as gateway and DNS server,
+//! - a DNS server answers all queries with the address of the device, so phones and laptops
+//!   detect a captive portal and open the page on their own,
+//! - an HTTP server serves the page listing the networks found, and redirects all other paths to it.
+//!
+//! Once a network and its passphrase are submitted, the access point is closed and the device
+//! joins the network. On success, the credentials are stored and returned. Otherwise, the access
+//! point starts again and the page reports the failure.
+//!
+//! The [`embassy_net`] stack must use the network device of the [`Control`] passed to
+//! [`provision`], and have room for 4 sockets besides the ones of the application: the portal uses
+//! 2 TCP and 2 UDP sockets.
+//!
+//! ```ignore
+//! static STATE: StaticCell<provisioning::State> = StaticCell::new();
+//! let state = STATE.init(provisioning::State::new());
+//! let config = provisioning::Config::new("pico-setup");
+//! let credentials = provisioning::provision(&mut control, stack, &mut storage, state, &config).await?;
+//! info!("joined {}", credentials.ssid());
+//! ```
+
+use core::fmt::Write as _;
+
+use embassy_futures::join::join;
+use embassy_futures::select::{select3, Either3};
+use embassy_net::dhcp_server::{Config as DhcpServerConfig, DhcpServer, DhcpServerState};
+use embassy_net::http::{
+    BodyWriter, Config as HttpConfig, Error as HttpError, Handler, HttpServer, Method, Request, Responded, Response,
+    Status,
+};
+use embassy_net::tcp::listener::TcpListenerState;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_net::{ConfigV4, Ipv4Address, Ipv4Cidr, Stack, StaticConfigV4};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+
+use crate::control::{Control, JoinAuth, JoinOptions, ScanOptions};
+
+/// Maximum number of networks listed on the configuration page.
+pub const MAX_NETWORKS: usize = 16;
+/// Length of the encoding of [`Credentials`], see [`Credentials::to_bytes`].
+pub const CREDENTIALS_LEN: usize = 1 + 32 + 1 + 64;
+
+/// Number of HTTP connections served at the same time.
+const HTTP_WORKERS: usize = 2;
+/// Size of the buffers of the DNS socket, and largest query answered.
+const DNS_PACKET_LEN: usize = 512;
+/// TTL of the DNS answers, short so the stations stop using them soon after provisioning.
+const DNS_TTL: u32 = 10;
+/// Capability bit of the access points requiring encryption.
+const CAPABILITY_PRIVACY: u16 = 0x0010;
+
+/// Credentials of a network to join.
+///
+/// They don't implement `Debug`, so the passphrase doesn't end up in logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    ssid: String<32>,
+    passphrase: String<64>,
+}
+
+impl Credentials {
+    /// Create new credentials, with an empty passphrase for open networks.
+    ///
+    /// Returns `None` if the SSID is empty or longer than 32 bytes, or if the passphrase is neither
+    /// empty nor 8 to 63 bytes long.
+    pub fn new(ssid: &str, passphrase: &str) -> Option<Self> {
+        if ssid.is_empty() || !(passphrase.is_empty() || (8..=63).contains(&passphrase.len())) {
+            return None;
+        }
+        Some(Self {
+            ssid: String::try_from(ssid).ok()?,
+            passphrase: String::try_from(passphrase).ok()?,
+        })
+    }
+
+    /// SSID of the network.
+    pub fn ssid(&self) -> &str {
+        &self.ssid
+    }
+
+    /// Passphrase of the network, empty for open networks.
+    pub fn passphrase(&self) -> &str {
+        &self.passphrase
+    }
+
+    /// Options to join the network with [`Control::join`].
+    ///
+    /// Networks with a passphrase are joined in WPA2/WPA3 transition mode, so both WPA2 and WPA3
+    /// networks can be joined: WPA3 is used with the access points supporting it.
+    pub fn join_options(&self) -> JoinOptions<'_> {
+        if self.passphrase.is_empty() {
+            JoinOptions::new_open()
+        } else {
+            let mut options = JoinOptions::new(self.passphrase.as_bytes());
+            options.auth = JoinAuth::Wpa2Wpa3;
+            options
+        }
+    }
+
+    /// Encode the credentials, to store them in flash for example.
+    ///
+    /// The encoding is the length and the bytes of the SSID, then of the passphrase, padded with zeros.
+    pub fn to_bytes(&self) -> [u8; CREDENTIALS_LEN] {
+        let mut buf = [0; CREDENTIALS_LEN];
+        buf[0] = self.ssid.len() as u8;
+        buf[1..][..self.ssid.len()].copy_from_slice(self.ssid.as_bytes());
+        buf[33] = self.passphrase.len() as u8;
+        buf[34..][..self.passphrase.len()].copy_from_slice(self.passphrase.as_bytes());
+        buf
+    }
+
+    /// Decode credentials encoded by [`to_bytes`](Self::to_bytes).
+    ///
+    /// Returns `None` if `buf` doesn't hold valid credentials, such as erased flash.
+    pub fn from_bytes(buf: &[u8; CREDENTIALS_LEN]) -> Option<Self> {
+        let ssid = buf[1..33].get(..usize::from(buf[0]))?;
+        let passphrase = buf[34..].get(..usize::from(buf[33]))?;
+        Self::new(core::str::from_utf8(ssid).ok()?, core::str::from_utf8(passphrase).ok()?)
+    }
+}
+
+/// Storage of the credentials of the network to join, kept across reboots.
+pub trait CredentialStorage {
+    /// Storage error.
+    type Error;
+
+    /// Load the stored credentials, if any.
+    async fn load(&mut self) -> Result<Option<Credentials>, Self::Error>;
+
+    /// Store `credentials`, replacing the stored ones.
+    async fn store(&mut self, credentials: &Credentials) -> Result<(), Self::Error>;
+
+    /// Remove the stored credentials, so the next [`provision`] starts the portal.
+    async fn clear(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Provisioning configuration.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// SSID of the access point.
+    pub ssid: &'a str,
+    /// Passphrase of the access point, protected with WPA2. Default `None`, for an open access point.
+    pub passphrase: Option<&'a str>,
+    /// Channel of the access point. Default 6.
+    pub channel: u8,
+    /// Address of the device on the network of the access point, and subnet of the stations.
+    /// Default `192.168.4.1/24`.
+    pub address: Ipv4Cidr,
+    /// Number of attempts to join the network of the stored credentials, before starting the access point.
+    /// Default 3.
+    pub join_attempts: u8,
+    /// IPv4 configuration of the stack once the network is joined. Default DHCP.
+    pub station_config: ConfigV4,
+}
+
+impl<'a> Config<'a> {
+    /// Create a new `Config` for an open access point named `ssid`.
+    pub fn new(ssid: &'a str) -> Self {
+        Self {
+            ssid,
+            passphrase: None,
+            channel: 6,
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 4, 1), 24),
+            join_attempts: 3,
+            station_config: ConfigV4::Dhcp(Default::default()),
+        }
+    }
+}
+
+/// Network found by the scan, listed on the configuration page.
+struct Network {
+    ssid: String<32>,
+    rssi: i16,
+    secure: bool,
+}
+
+/// State for [`provision`].
+pub struct State {
+    http: TcpListenerState<HTTP_WORKERS>,
+    dhcp: DhcpServerState,
+    dns_rx_meta: [PacketMetadata; 4],
+    dns_rx_buffer: [u8; DNS_PACKET_LEN],
+    dns_tx_meta: [PacketMetadata; 4],
+    dns_tx_buffer: [u8; DNS_PACKET_LEN],
+    networks: Vec<Network, MAX_NETWORKS>,
+}
+
+impl State {
+    /// Create a new `State`.
+    pub const fn new() -> Self {
+        Self {
+            http: TcpListenerState::new(),
+            dhcp: DhcpServerState::new(),
+            dns_rx_meta: [PacketMetadata::EMPTY; 4],
+            dns_rx_buffer: [0; DNS_PACKET_LEN],
+            dns_tx_meta: [PacketMetadata::EMPTY; 4],
+            dns_tx_buffer: [0; DNS_PACKET_LEN],
+            networks: Vec::new(),
+        }
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Join the network of the stored credentials, or provision new ones through the captive portal.
+///
+/// Returns the credentials of the network joined, once the stack is given `config.station_config`.
+/// Storage errors are returned right away.
+pub async fn provision<S: CredentialStorage>(
+    control: &mut Control<'_>,
+    stack: Stack<'_>,
+    storage: &mut S,
+    state: &mut State,
+    config: &Config<'_>,
+) -> Result<Credentials, S::Error> {
+    if let Some(credentials) = storage.load().await? {
+        for _ in 0..config.join_attempts {
+            match control.join(credentials.ssid(), credentials.join_options()).await {
+                Ok(()) => {
+                    stack.set_config_v4(config.station_config.clone());
+                    return Ok(credentials);
+                }
+                Err(e) => warn!("joining {} failed: {:?}", credentials.ssid(), e),
+            }
+        }
+    }
+
+    let mut failed = None;
+    loop {
+        scan(control, &mut state.networks).await;
+
+        info!("starting provisioning access point {}", config.ssid);
+        match config.passphrase {
+            Some(passphrase) => control.start_ap_wpa2(config.ssid, passphrase, config.channel).await,
+            None => control.start_ap_open(config.ssid, config.channel).await,
+        }
+        stack.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+            address: config.address,
+            gateway: None,
+            dns_servers: Vec::new(),
+        }));
+
+        let credentials = serve_portal(stack, state, config.address, failed.as_ref()).await;
+
+        control.close_ap().await;
+        stack.set_config_v4(config.station_config.clone());
+        info!("joining {}", credentials.ssid());
+        match control.join(credentials.ssid(), credentials.join_options()).await {
+            Ok(()) => {
+                storage.store(&credentials).await?;
+                return Ok(credentials);
+            }
+            Err(e) => {
+                warn!("joining {} failed: {:?}", credentials.ssid(), e);
+                failed = Some(credentials.ssid.clone());
+            }
+        }
+    }
+}
+
+/// Scan the networks in range into `networks`, keeping the strongest access point of each SSID.
+async fn scan(control: &mut Control<'_>, networks: &mut Vec<Network, MAX_NETWORKS>) {
+    networks.clear();
+    let mut scanner = control.scan(ScanOptions::default()).await;
+    while let Some(bss) = scanner.next().await {
+        // Hidden networks, and SSIDs that can't be shown on the page, are skipped.
+        let Some(ssid) = bss
+            .ssid
+            .get(..usize::from(bss.ssid_len))
+            .and_then(|ssid| core::str::from_utf8(ssid).ok())
+            .filter(|ssid| !ssid.is_empty())
+        else {
+            continue;
+        };
+        let secure = bss.capability & CAPABILITY_PRIVACY != 0;
+        match networks.iter_mut().find(|n| n.ssid == ssid) {
+            Some(n) => n.rssi = n.rssi.max(bss.rssi),
+            None => {
+                // Once full, the weakest network is replaced.
+                if networks.is_full() {
+                    let weakest = unwrap!(networks.iter().enumerate().min_by_key(|(_, n)| n.rssi)).0;
+                    if networks[weakest].rssi >= bss.rssi {
+                        continue;
+                    }
+                    networks.swap_remove(weakest);
+                }
+                let _ = networks.push(Network {
+                    ssid: unwrap!(String::try_from(ssid)),
+                    rssi: bss.rssi,
+                    secure,
+                });
+            }
+        }
+    }
+    networks.sort_unstable_by_key(|n| core::cmp::Reverse(n.rssi));
+    debug!("found {} networks", networks.len());
+}
+
+/// Serve the captive portal until credentials are submitted.
+async fn serve_portal(
+    stack: Stack<'_>,
+    state: &mut State,
+    address: Ipv4Cidr,
+    failed: Option<&String<32>>,
+) -> Credentials {
+    let portal = Portal {
+        address: address.address(),
+        networks: &state.networks,
+        failed,
+        submitted: Signal::new(),
+    };
+
+    // The stations get the addresses after the device's, up to the end of the subnet.
+    let mut dhcp_config = DhcpServerConfig::new(
+        address,
+        Ipv4Address::from(u32::from(address.address()) + 1),
+        pool_size(address),
+    );
+    dhcp_config.dns_servers = unwrap!(Vec::from_slice(&[address.address()]));
+    let mut dhcp = DhcpServer::<'_, 8>::new(stack, dhcp_config, &mut state.dhcp);
+
+    let mut dns = UdpSocket::new(
+        stack,
+        &mut state.dns_rx_meta,
+        &mut state.dns_rx_buffer,
+        &mut state.dns_tx_meta,
+        &mut state.dns_tx_buffer,
+    );
+    unwrap!(dns.bind(53));
+
+    let http = HttpServer::new(stack, &mut state.http, 80, HttpConfig::new());
+    let serve = async {
+        let mut buf0 = [0; 1024];
+        let mut buf1 = [0; 1024];
+        join(http.run(&portal, &mut buf0), http.run(&portal, &mut buf1)).await;
+    };
+    let stop = async {
+        let credentials = portal.submitted.wait().await;
+        // The workers finish answering the submission before returning.
+        http.shutdown();
+        credentials
+    };
+
+    let (_, credentials) = match select3(
+        join(serve, stop),
+        dhcp.run(),
+        dns_responder(&mut dns, address.address()),
+    )
+    .await
+    {
+        Either3::First(res) => res,
+        Either3::Second(never) | Either3::Third(never) => match never {},
+    };
+    // Give the response time to reach the station before the access point closes.
+    Timer::after(Duration::from_secs(1)).await;
+    credentials
+}
+
+/// Number of addresses after `address` in its subnet, excluding the broadcast address.
+fn pool_size(address: Ipv4Cidr) -> u16 {
+    let host_bits = 32 - u32::from(address.prefix_len());
+    let host = u32::from(address.address()) & ((1u64 << host_bits) - 1) as u32;
+    let last = (1u64 << host_bits).saturating_sub(2) as u32;
+    last.saturating_sub(host).min(u32::from(u16::MAX)) as u16
+}
+
+/// Answer all DNS queries for IPv4 addresses with `address`, and other queries with no answers.
+async fn dns_responder(socket: &mut UdpSocket<'_>, address: Ipv4Address) -> ! {
+    let mut buf = [0; DNS_PACKET_LEN];
+    loop {
+        let Ok((n, meta)) = socket.recv_from(&mut buf).await else {
+            continue;
+        };
+        if let Some(len) = dns_answer(&mut buf, n, address) {
+            if let Err(e) = socket.send_to(&buf[..len], meta).await {
+                debug!("DNS send error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Turn the DNS query in `buf[..n]` into its answer in place, returning its length.
+///
+/// Only standard queries with a single question are answered.
+fn dns_answer(buf: &mut [u8], n: usize, address: Ipv4Address) -> Option<usize> {
+    const TYPE_A: u16 = 1;
+    const CLASS_IN: u16 = 1;
+
+    let query = buf.get(..n)?;
+    let header = query.get(..12)?;
+    let flags = u16::from_be_bytes([header[2], header[3]]);
+    // A query (QR = 0) with the standard opcode, and a single question.
+    if flags & 0xf800 != 0 || header[4..6] != [0, 1] {
+        return None;
+    }
+
+    // The question name is a sequence of labels, not compressed in queries.
+    let mut pos = 12;
+    loop {
+        let len = usize::from(*query.get(pos)?);
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        pos += 1 + len;
+        if len == 0 {
+            break;
+        }
+    }
+    let question = query.get(pos..pos + 4)?;
+    let qtype = u16::from_be_bytes([question[0], question[1]]);
+    let qclass = u16::from_be_bytes([question[2], question[3]]) & 0x7fff;
+    let end = pos + 4;
+    let answered = qtype == TYPE_A && qclass == CLASS_IN;
+    if answered && buf.len() < end + 16 {
+        return None;
+    }
+
+    // Response (QR), authoritative (AA), keeping the recursion desired bit (RD), no error.
+    buf[2] = 0x84 | (flags >> 8) as u8 & 0x01;
+    buf[3] = 0;
+    buf[6..8].copy_from_slice(&u16::from(answered).to_be_bytes());
+    buf[8..12].fill(0);
+    if !answered {
+        return Some(end);
+    }
+    let answer = &mut buf[end..end + 16];
+    // The name is a pointer to the question's.
+    answer[0..2].copy_from_slice(&0xc00cu16.to_be_bytes());
+    answer[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+    answer[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+    answer[6..10].copy_from_slice(&DNS_TTL.to_be_bytes());
+    answer[10..12].copy_from_slice(&4u16.to_be_bytes());
+    answer[12..16].copy_from_slice(&address.octets());
+    Some(end + 16)
+}
+
+/// HTTP handler of the captive portal.
+struct Portal<'a> {
+    address: Ipv4Address,
+    networks: &'a [Network],
+    failed: Option<&'a String<32>>,
+    submitted: Signal<NoopRawMutex, Credentials>,
+}
+
+impl Handler for Portal<'_> {
+    async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, HttpError> {
+        debug!("portal: {:?} {}", request.method(), request.path());
+        match (request.method(), request.path()) {
+            (Method::Get | Method::Head, "/") => self.page(response).await,
+            (Method::Post, "/join") => {
+                let mut buf = [0; 256];
+                let credentials = match request.body().read_to_end(&mut buf).await {
+                    Ok(body) => parse_form(body),
+                    Err(HttpError::BodyTooLarge) => None,
+                    Err(e) => return Err(e),
+                };
+                let Some(credentials) = credentials else {
+                    let mut page = Page::start(response, Status::BAD_REQUEST).await?;
+                    page.write("<p>Invalid network or passphrase. <a href=\"/\">Back</a></p>")
+                        .await?;
+                    return page.finish().await;
+                };
+
+                let mut page = Page::start(response, Status::OK).await?;
+                page.write("<p>Joining <b>").await?;
+                page.write_escaped(credentials.ssid()).await?;
+                page.write("</b>. This access point closes now. If joining fails, it comes back and tells so.</p>")
+                    .await?;
+                let responded = page.finish().await;
+                self.submitted.signal(credentials);
+                responded
+            }
+            // Operating systems detect captive portals by fetching pages of their own, redirecting them
+            // to the configuration page makes it open.
+            (Method::Get | Method::Head, _) => {
+                let mut location: String<32> = String::new();
+                let _ = write!(location, "http://{}/", self.address);
+                response
+                    .send(
+                        Status::FOUND,
+                        &[("Location", location.as_str()), ("Cache-Control", "no-store")],
+                        &[],
+                    )
+                    .await
+            }
+            _ => response.send(Status::NOT_FOUND, &[], &[]).await,
+        }
+    }
+}
+
+impl Portal<'_> {
+    async fn page(&self, response: Response<'_>) -> Result<Responded, HttpError> {
+        let mut page = Page::start(response, Status::OK).await?;
+        if let Some(failed) = self.failed {
+            page.write("<p><b>Joining ").await?;
+            page.write_escaped(failed).await?;
+            page.write(" failed.</b> Check the passphrase and try again.</p>")
+                .await?;
+        }
+        page.write("<form method=\"post\" action=\"/join\"><p>Network:</p>")
+            .await?;
+        for network in self.networks {
+            page.write("<label><input type=\"radio\" name=\"ssid\" value=\"")
+                .await?;
+            page.write_escaped(&network.ssid).await?;
+            page.write("\"> ").await?;
+            page.write_escaped(&network.ssid).await?;
+            let mut details: String<32> = String::new();
+            let _ = write!(
+                details,
+                " ({} dBm{})</label><br>",
+                network.rssi,
+                if network.secure { ", secured" } else { "" }
+            );
+            page.write(&details).await?;
+        }
+        page.write(
+            "<p><label>Other network: <input name=\"other\" maxlength=\"32\"></label></p>\
+             <p><label>Passphrase: <input type=\"password\" name=\"passphrase\" maxlength=\"63\"></label></p>\
+             <p><button>Join</button></p></form>",
+        )
+        .await?;
+        page.finish().await
+    }
+}
+
+/// Parse the submitted form, whose SSID is the `other` field if set, or the selected network.
+fn parse_form(body: &[u8]) -> Option<Credentials> {
+    let body = core::str::from_utf8(body).ok()?;
+    let other: String<32> = form_field(body, "other")?;
+    let ssid = match other.is_empty() {
+        true => form_field(body, "ssid")?,
+        false => other,
+    };
+    let passphrase: String<64> = form_field(body, "passphrase")?;
+    Credentials::new(&ssid, &passphrase)
+}
+
+/// Decode the field `name` of an `application/x-www-form-urlencoded` body.
+///
+/// Returns an empty string for missing fields, and `None` for fields that don't decode or fit.
+fn form_field<const N: usize>(body: &str, name: &str) -> Option<String<N>> {
+    let Some(value) = body.split('&').find_map(|field| {
+        let (n, value) = field.split_once('=').unwrap_or((field, ""));
+        (n == name).then_some(value)
+    }) else {
+        return Some(String::new());
+    };
+
+    let mut bytes: Vec<u8, N> = Vec::new();
+    let mut encoded = value.bytes();
+    while let Some(b) = encoded.next() {
+        let b = match b {
+            b'+' => b' ',
+            b'%' => {
+                let hex = [encoded.next()?, encoded.next()?];
+                u8::from_str_radix(core::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            b => b,
+        };
+        bytes.push(b).ok()?;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// HTML page of the portal, buffered to send it in a few chunks.
+struct Page<'r> {
+    body: BodyWriter<'r>,
+    buf: Vec<u8, 256>,
+}
+
+impl<'r> Page<'r> {
+    async fn start(response: Response<'r>, status: Status) -> Result<Page<'r>, HttpError> {
+        let body = response
+            .start(
+                status,
+                &[
+                    ("Content-Type", "text/html; charset=utf-8"),
+                    ("Cache-Control", "no-store"),
+                ],
+                None,
+            )
+            .await?;
+        let mut page = Self { body, buf: Vec::new() };
+        page.write(
+            "<!DOCTYPE html><html><head><meta name=\"viewport\" content=\"width=device-width\">\
+             <title>Wi-Fi setup</title></head><body><h1>Wi-Fi setup</h1>",
+        )
+        .await?;
+        Ok(page)
+    }
+
+    async fn write(&mut self, s: &str) -> Result<(), HttpError> {
+        for &b in s.as_bytes() {
+            if self.buf.push(b).is_err() {
+                self.body.write_all(&self.buf).await?;
+                self.buf.clear();
+                unwrap!(self.buf.push(b));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write `s` escaping the characters with a meaning in HTML, see [`escape_html`].
+    async fn write_escaped(&mut self, s: &str) -> Result<(), HttpError> {
+        for part in escape_html(s) {
+            self.write(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<Responded, HttpError> {
+        self.write("</body></html>").await?;
+        self.body.write_all(&self.buf).await?;
+        self.body.finish().await
+    }
+}
+
+/// Split `s` into the parts to write to escape the characters with a meaning in HTML, in text or
+/// attribute values.
+fn escape_html(s: &str) -> impl Iterator<Item = &str> {
+    s.split_inclusive(['<', '>', '&', '"', '\'']).flat_map(|part| {
+        let (text, escape) = match part.as_bytes().last() {
+            Some(b'<') => (&part[..part.len() - 1], "&lt;"),
+            Some(b'>') => (&part[..part.len() - 1], "&gt;"),
+            Some(b'&') => (&part[..part.len() - 1], "&amp;"),
+            Some(b'"') => (&part[..part.len() - 1], "&quot;"),
+            Some(b'\'') => (&part[..part.len() - 1], "&#39;"),
+            _ => (part, ""),
+        };
+        [text, escape]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: Ipv4Address = Ipv4Address::new(192, 168, 4, 1);
+
+    /// DNS query for `name`, of type `qtype`, in a buffer of `N` bytes.
+    fn query<const N: usize>(name: &[u8], qtype: u16) -> ([u8; N], usize) {
+        let mut buf = [0; N];
+        // ID, flags with recursion desired, one question.
+        buf[..12].copy_from_slice(&[0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+        buf[12..][..name.len()].copy_from_slice(name);
+        let pos = 12 + name.len();
+        buf[pos..pos + 2].copy_from_slice(&qtype.to_be_bytes());
+        buf[pos + 2..pos + 4].copy_from_slice(&1u16.to_be_bytes());
+        (buf, pos + 4)
+    }
+
+    const NAME: &[u8] = b"\x07example\x03com\x00";
+
+    #[test]
+    fn dns_answers_a_queries() {
+        let (mut buf, n) = query::<64>(NAME, 1);
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), Some(n + 16));
+        // Same ID, response with the authoritative and recursion desired bits, one answer.
+        assert_eq!(buf[..12], [0x12, 0x34, 0x85, 0x00, 0, 1, 0, 1, 0, 0, 0, 0]);
+        // The question is kept.
+        assert_eq!(buf[12..][..NAME.len()], *NAME);
+        assert_eq!(
+            buf[n..n + 16],
+            [0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 10, 0, 4, 192, 168, 4, 1]
+        );
+    }
+
+    #[test]
+    fn dns_answers_other_queries_without_records() {
+        // AAAA query.
+        let (mut buf, n) = query::<64>(NAME, 28);
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), Some(n));
+        assert_eq!(buf[..12], [0x12, 0x34, 0x85, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn dns_ignores_invalid_queries() {
+        // Responses.
+        let (mut buf, n) = query::<64>(NAME, 1);
+        buf[2] |= 0x80;
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), None);
+
+        // Several questions.
+        let (mut buf, n) = query::<64>(NAME, 1);
+        buf[5] = 2;
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), None);
+
+        // Compressed names.
+        let (mut buf, n) = query::<64>(b"\xc0\x0c", 1);
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), None);
+
+        // Truncated queries, in the header, the name or the question.
+        let (mut buf, _) = query::<64>(NAME, 1);
+        for n in [6, 12 + 5, 12 + NAME.len() + 2] {
+            assert_eq!(dns_answer(&mut buf, n, ADDRESS), None);
+        }
+
+        // No room for the answer.
+        let (mut buf, n) = query::<40>(NAME, 1);
+        assert_eq!(dns_answer(&mut buf, n, ADDRESS), None);
+    }
+
+    fn form(body: &str) -> Option<(String<32>, String<64>)> {
+        parse_form(body.as_bytes()).map(|c| (c.ssid, c.passphrase))
+    }
+
+    #[test]
+    fn form_decodes_fields() {
+        assert_eq!(
+            form("ssid=Home+network&passphrase=pass%20word%21"),
+            Some((
+                unwrap!(String::try_from("Home network")),
+                unwrap!(String::try_from("pass word!"))
+            ))
+        );
+        // The other network takes precedence, and an empty passphrase is for open networks.
+        assert_eq!(
+            form("ssid=Home&other=Caf%C3%A9&passphrase="),
+            Some((unwrap!(String::try_from("Café")), String::new()))
+        );
+        // Lower case hexadecimal digits.
+        assert_eq!(
+            form("ssid=a%2fb&passphrase=12345678").map(|(ssid, _)| ssid),
+            Some(unwrap!(String::try_from("a/b")))
+        );
+    }
+
+    #[test]
+    fn form_rejects_invalid_fields() {
+        // Truncated or invalid percent encoding.
+        assert!(form("ssid=Home&passphrase=abcdefg%2").is_none());
+        assert!(form("ssid=Home&passphrase=abcdefg%").is_none());
+        assert!(form("ssid=Home%zz&passphrase=").is_none());
+        // Invalid UTF-8.
+        assert!(form("ssid=Home%ff&passphrase=").is_none());
+        // No network, or a passphrase too short.
+        assert!(form("passphrase=12345678").is_none());
+        assert!(form("ssid=Home&passphrase=short").is_none());
+        // Too long for the field.
+        assert!(form_field::<4>("ssid=Home1", "ssid").is_none());
+        // Missing fields are empty.
+        assert_eq!(form_field::<4>("ssid=Home", "other"), Some(String::new()));
+        assert_eq!(form_field::<4>("ssid", "ssid"), Some(String::new()));
+    }
+
+    #[test]
+    fn credentials_round_trip() {
+        for (ssid, passphrase) in [
+            ("Home", "12345678"),
+            ("Open", ""),
+            ("s".repeat(32).as_str(), &"p".repeat(63)),
+        ] {
+            let credentials = unwrap!(Credentials::new(ssid, passphrase));
+            let bytes = credentials.to_bytes();
+            assert_eq!(bytes[0], ssid.len() as u8);
+            assert_eq!(bytes[33], passphrase.len() as u8);
+            assert!(Credentials::from_bytes(&bytes) == Some(credentials));
+        }
+    }
+
+    #[test]
+    fn credentials_reject_invalid_bytes() {
+        // Erased flash, and zeroed memory.
+        assert!(Credentials::from_bytes(&[0xff; CREDENTIALS_LEN]).is_none());
+        assert!(Credentials::from_bytes(&[0; CREDENTIALS_LEN]).is_none());
+
+        let mut bytes = unwrap!(Credentials::new("Home", "12345678")).to_bytes();
+        // SSID too long.
+        bytes[0] = 33;
+        assert!(Credentials::from_bytes(&bytes).is_none());
+        // Passphrase too long, or too short.
+        bytes[0] = 4;
+        bytes[33] = 65;
+        assert!(Credentials::from_bytes(&bytes).is_none());
+        bytes[33] = 7;
+        assert!(Credentials::from_bytes(&bytes).is_none());
+        // Invalid UTF-8.
+        bytes[33] = 8;
+        bytes[1] = 0xff;
+        assert!(Credentials::from_bytes(&bytes).is_none());
+    }
+
+    #[test]
+    fn credentials_join_options() {
+        let secured = unwrap!(Credentials::new("Home", "12345678"));
+        let options = secured.join_options();
+        assert_eq!(options.auth, JoinAuth::Wpa2Wpa3);
+        assert_eq!(options.passphrase, b"12345678");
+        let open = unwrap!(Credentials::new("Open", ""));
+        assert_eq!(open.join_options().auth, JoinAuth::Open);
+    }
+
+    fn escaped(s: &str) -> String<128> {
+        let mut out = String::new();
+        for part in escape_html(s) {
+            unwrap!(out.push_str(part));
+        }
+        out
+    }
+
+    #[test]
+    fn html_is_escaped() {
+        assert_eq!(escaped("Home"), "Home");
+        assert_eq!(
+            escaped("<b>\"Tom & Jerry's\"</b>"),
+            "&lt;b&gt;&quot;Tom &amp; Jerry&#39;s&quot;&lt;/b&gt;"
+        );
+        assert_eq!(escaped("&&"), "&amp;&amp;");
+        assert_eq!(escaped(""), "");
+    }
+}
@@ -523,6 +523,8 @@ where
                             Status {
                                 event_type: evt_type,
                                 status,
+                                reason: event_packet.msg.reason,
+                                flags: event_packet.msg.flags,
                             },
                             event_payload,
                         ));
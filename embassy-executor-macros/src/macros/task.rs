@@ -142,6 +142,7 @@ pub fn run(args: TokenStream, item: TokenStream) -> TokenStream {
         const POOL_SIZE: usize = #pool_size;
         static POOL: #embassy_executor::raw::TaskPool<<() as _EmbassyInternalTaskTrait>::Fut, POOL_SIZE> = #embassy_executor::raw::TaskPool::new();
         unsafe { POOL._spawn_async_fn(move || <() as _EmbassyInternalTaskTrait>::construct(#(#full_args,)*)) }
+            ._with_name(::core::stringify!(#task_ident))
     };
     #[cfg(not(feature = "nightly"))]
     let mut task_outer_body = quote! {
@@ -159,10 +160,28 @@ pub fn run(args: TokenStream, item: TokenStream) -> TokenStream {
             {#embassy_executor::_export::task_pool_align::<_, _, _, POOL_SIZE>(#task_inner_ident)},
         > = unsafe { ::core::mem::transmute(#embassy_executor::_export::task_pool_new::<_, _, _, POOL_SIZE>(#task_inner_ident)) };
         unsafe { __task_pool_get(#task_inner_ident)._spawn_async_fn(move || #task_inner_ident(#(#full_args,)*)) }
+            ._with_name(::core::stringify!(#task_ident))
     };
 
     let task_outer_attrs = task_inner.attrs.clone();
 
+    // Memory usage information, only if the task is valid. Otherwise it causes spurious errors.
+    let mut task_info = TokenStream::new();
+    if cfg!(feature = "task-info") && errors.is_empty() {
+        let task_name = task_ident.to_string();
+        let task_name = task_name.trim_start_matches("r#");
+        let task_info_ident = format_ident!("{}_TASK_INFO", task_name.to_uppercase());
+        let task_info_doc = format!("Memory usage information for the [`{}`] task.", task_name);
+        task_info = quote! {
+            #[doc = #task_info_doc]
+            #[allow(dead_code)]
+            #visibility const #task_info_ident: #embassy_executor::TaskInfo =
+                #embassy_executor::_export::task_info::<_, _, _, {#pool_size}>(#task_name, #task_inner_ident);
+
+            #embassy_executor::_export::task_info_section!(#task_info_ident);
+        };
+    }
+
     if !errors.is_empty() {
         task_outer_body = quote! {
             #![allow(unused_variables, unreachable_code)]
@@ -188,6 +207,8 @@ pub fn run(args: TokenStream, item: TokenStream) -> TokenStream {
             #task_outer_body
         }
 
+        #task_info
+
         #errors
     };
 
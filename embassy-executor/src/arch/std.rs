@@ -5,12 +5,13 @@ compile_error!("`executor-interrupt` is not supported with `arch-std`.");
 pub use thread::*;
 #[cfg(feature = "executor-thread")]
 mod thread {
+    use std::collections::VecDeque;
     use std::marker::PhantomData;
     use std::sync::{Condvar, Mutex};
 
     pub use embassy_executor_macros::main_std as main;
 
-    use crate::{raw, Spawner};
+    use crate::{raw, SendSpawner, Spawner};
 
     #[export_name = "__pender"]
     fn __pender(context: *mut ()) {
@@ -64,6 +65,159 @@ mod thread {
         }
     }
 
+    /// Multi-threaded std-based executor.
+    ///
+    /// Tasks are polled by a pool of worker threads sharing a single run queue, so a task
+    /// may be polled by a different thread each time it's woken. A task is never polled by
+    /// more than one thread at a time.
+    ///
+    /// Since tasks move between threads, only `Send` tasks can be spawned, using a [`SendSpawner`].
+    /// [`Spawner::for_current_executor()`] panics when called from a task running in this executor,
+    /// use [`SendSpawner::for_current_executor()`] instead.
+    pub struct MultiThreadExecutor {
+        inner: raw::Executor,
+        signaler: &'static Signaler,
+        threads: usize,
+        state: Mutex<RunState>,
+    }
+
+    // Safety: the executor only accepts `Send` tasks, and all shared state is synchronized.
+    unsafe impl Sync for MultiThreadExecutor {}
+
+    struct RunState {
+        /// Tasks dequeued from the raw executor's run queue, waiting to be polled.
+        queue: VecDeque<raw::TaskRef>,
+        /// Tasks currently being polled.
+        running: Vec<raw::TaskRef>,
+        /// Running tasks that were woken while being polled, and must be polled again afterwards.
+        rerun: Vec<raw::TaskRef>,
+    }
+
+    impl MultiThreadExecutor {
+        /// Create a new MultiThreadExecutor, running tasks on `threads` worker threads.
+        ///
+        /// # Panics
+        ///
+        /// Panics if `threads` is zero.
+        pub fn new(threads: usize) -> Self {
+            assert!(threads > 0, "MultiThreadExecutor needs at least one thread");
+
+            let signaler = Box::leak(Box::new(Signaler::new()));
+            let mut inner = raw::Executor::new(signaler as *mut Signaler as *mut ());
+            inner.inner.send_only = true;
+            Self {
+                inner,
+                signaler,
+                threads,
+                state: Mutex::new(RunState {
+                    queue: VecDeque::new(),
+                    running: Vec::new(),
+                    rerun: Vec::new(),
+                }),
+            }
+        }
+
+        /// Get a spawner that spawns tasks in this executor.
+        pub fn spawner(&'static self) -> SendSpawner {
+            self.inner.spawner().make_send()
+        }
+
+        /// Run the executor.
+        ///
+        /// The `init` closure is called with a [`SendSpawner`] that spawns tasks on
+        /// this executor. Use it to spawn the initial task(s). After `init` returns,
+        /// the worker threads are started, and the current thread becomes one of them.
+        ///
+        /// This function requires `&'static mut self`, see [`Executor::run()`] for details.
+        ///
+        /// This function never returns.
+        pub fn run(&'static mut self, init: impl FnOnce(SendSpawner)) -> ! {
+            let this: &'static Self = self;
+            init(this.spawner());
+
+            for i in 1..this.threads {
+                std::thread::Builder::new()
+                    .name(format!("embassy-worker-{}", i))
+                    .spawn(move || this.work())
+                    .unwrap();
+            }
+            this.work()
+        }
+
+        fn work(&'static self) -> ! {
+            let mut woken = Vec::new();
+            // Whether the worker is polling tasks, for tracing, like a pass of `raw::Executor::poll`.
+            #[cfg(feature = "trace")]
+            let mut scheduling = false;
+            loop {
+                self.inner.inner.dequeue_all(|task| woken.push(task));
+
+                let task = {
+                    let mut state = self.state.lock().unwrap();
+                    state.queue.extend(woken.drain(..));
+                    let task = state.pop_runnable();
+                    if task.is_some() && !state.queue.is_empty() {
+                        // Get another worker to help with the remaining tasks.
+                        self.signaler.signal();
+                    }
+                    task
+                };
+
+                match task {
+                    Some(task) => {
+                        #[cfg(feature = "trace")]
+                        if !scheduling {
+                            raw::trace::poll_start(&self.inner.inner);
+                            scheduling = true;
+                        }
+
+                        // Safety: `pop_runnable` ensures the task isn't being polled by another thread.
+                        unsafe { self.inner.inner.poll_task(task) };
+
+                        let mut state = self.state.lock().unwrap();
+                        state.running.retain(|t| *t != task);
+                        if let Some(i) = state.rerun.iter().position(|t| *t == task) {
+                            state.rerun.swap_remove(i);
+                            state.queue.push_back(task);
+                            self.signaler.signal();
+                        }
+                    }
+                    None => {
+                        #[cfg(feature = "trace")]
+                        if scheduling {
+                            raw::trace::executor_idle(&self.inner.inner);
+                            scheduling = false;
+                        }
+
+                        self.signaler.wait()
+                    }
+                }
+            }
+        }
+    }
+
+    impl RunState {
+        /// Pop the next task that isn't already being polled, and mark it as running.
+        fn pop_runnable(&mut self) -> Option<raw::TaskRef> {
+            while let Some(task) = self.queue.pop_front() {
+                if !self.running.contains(&task) {
+                    self.running.push(task);
+                    return Some(task);
+                }
+
+                // The task was woken while being polled. Poll it again once the current poll is done.
+                if self.rerun.contains(&task) {
+                    // Already scheduled for a re-run, so this entry is redundant.
+                    // Safety: each run queue entry holds a reference to the task.
+                    unsafe { task.release() };
+                } else {
+                    self.rerun.push(task);
+                }
+            }
+            None
+        }
+    }
+
     struct Signaler {
         mutex: Mutex<bool>,
         condvar: Condvar,
@@ -9,6 +9,9 @@
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 pub use embassy_executor_macros::task;
 
 macro_rules! check_at_most_one {
@@ -49,19 +52,33 @@ pub use embassy_executor_macros::main_unspecified as main;
 
 pub mod raw;
 
+#[cfg(feature = "alloc")]
+pub mod scope;
+
+#[cfg(feature = "watchdog")]
+pub mod watchdog;
+
 mod spawner;
 pub use spawner::*;
 
+#[cfg(feature = "task-info")]
+pub mod task_info;
+#[cfg(feature = "task-info")]
+pub use task_info::TaskInfo;
+
 /// Implementation details for embassy macros.
 /// Do not use. Used for macros and HALs only. Not covered by semver guarantees.
 #[doc(hidden)]
-#[cfg(not(feature = "nightly"))]
 pub mod _export {
     use core::cell::UnsafeCell;
     use core::future::Future;
     use core::mem::MaybeUninit;
 
+    #[cfg(feature = "task-info")]
+    pub use crate::__task_info_section as task_info_section;
     use crate::raw::TaskPool;
+    #[cfg(feature = "task-info")]
+    use crate::TaskInfo;
 
     pub trait TaskFn<Args>: Copy {
         type Fut: Future + 'static;
@@ -136,6 +153,20 @@ pub mod _export {
         align_of::<TaskPool<Fut, POOL_SIZE>>()
     }
 
+    #[cfg(feature = "task-info")]
+    pub const fn task_info<F, Args, Fut, const POOL_SIZE: usize>(name: &'static str, _: F) -> TaskInfo
+    where
+        F: TaskFn<Args, Fut = Fut>,
+        Fut: Future + 'static,
+    {
+        TaskInfo {
+            name,
+            future_size: size_of::<Fut>(),
+            pool_size: POOL_SIZE,
+            storage_size: size_of::<TaskPool<Fut, POOL_SIZE>>(),
+        }
+    }
+
     pub const fn task_pool_new<F, Args, Fut, const POOL_SIZE: usize>(_: F) -> TaskPool<Fut, POOL_SIZE>
     where
         F: TaskFn<Args, Fut = Fut>,
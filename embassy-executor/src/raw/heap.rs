@@ -0,0 +1,112 @@
+//! Heap-allocated tasks.
+//!
+//! A heap-allocated task is freed as soon as nothing references it anymore. The references are
+//! counted, and are held by:
+//!
+//! - The task's future, while the task is spawned.
+//! - The executor's run queue, while the task is enqueued or being polled.
+//! - Each clone of the task's [`Waker`](core::task::Waker).
+//! - The integrated timer queue, while the task is in it. See [`TaskRef::retain`].
+//!
+//! Statically allocated tasks are not reference counted, all operations are no-ops for them.
+
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::future::Future;
+
+use critical_section::Mutex;
+
+use super::util::SyncUnsafeCell;
+use super::{AvailableTask, TaskRef, TaskStorage};
+use crate::SpawnToken;
+
+/// Reference count of a heap-allocated task, stored in the task header.
+pub(crate) struct HeapItem {
+    refs: Mutex<Cell<usize>>,
+    /// Frees the task. `None` for tasks that are not heap-allocated.
+    dealloc: SyncUnsafeCell<Option<unsafe fn(TaskRef)>>,
+}
+
+impl HeapItem {
+    pub(crate) const fn new() -> Self {
+        Self {
+            refs: Mutex::new(Cell::new(0)),
+            dealloc: SyncUnsafeCell::new(None),
+        }
+    }
+}
+
+/// Total size of the heap-allocated task storage currently alive, in bytes.
+#[cfg(feature = "task-info")]
+static STORAGE_SIZE: Mutex<Cell<usize>> = Mutex::new(Cell::new(0));
+
+#[cfg(feature = "task-info")]
+pub(crate) fn storage_size() -> usize {
+    critical_section::with(|cs| STORAGE_SIZE.borrow(cs).get())
+}
+
+#[cfg(feature = "task-info")]
+fn add_storage_size(size: isize) {
+    critical_section::with(|cs| {
+        let total = STORAGE_SIZE.borrow(cs);
+        total.set(total.get().wrapping_add_signed(size));
+    })
+}
+
+unsafe fn dealloc<F: Future + 'static>(p: TaskRef) {
+    #[cfg(feature = "task-info")]
+    add_storage_size(-(core::mem::size_of::<TaskStorage<F>>() as isize));
+    drop(Box::from_raw(p.as_ptr().cast::<TaskStorage<F>>().cast_mut()));
+}
+
+/// Allocate a new task on the heap, and initialize it to run `future`.
+///
+/// Once spawned, the task holds one reference for its future and one for the run queue.
+pub(crate) fn allocate<F: Future + 'static>(future: F) -> SpawnToken<F> {
+    let storage: &'static TaskStorage<F> = Box::leak(Box::new(TaskStorage::new()));
+    let heap = &storage.raw.heap;
+    unsafe { heap.dealloc.set(Some(dealloc::<F>)) };
+    critical_section::with(|cs| heap.refs.borrow(cs).set(2));
+    #[cfg(feature = "task-info")]
+    add_storage_size(core::mem::size_of::<TaskStorage<F>>() as isize);
+
+    // The storage is freshly allocated, so it can't be spawned already.
+    unwrap!(AvailableTask::claim(storage)).initialize(move || future)
+}
+
+/// Returns whether the task is heap-allocated.
+#[inline(always)]
+pub(crate) fn is_heap(task: TaskRef) -> bool {
+    unsafe { task.header().heap.dealloc.get().is_some() }
+}
+
+/// Add a reference to the task. No-op for static tasks.
+#[inline(always)]
+pub(crate) fn retain(task: TaskRef) {
+    if is_heap(task) {
+        critical_section::with(|cs| {
+            let refs = task.header().heap.refs.borrow(cs);
+            refs.set(refs.get() + 1);
+        })
+    }
+}
+
+/// Remove a reference to the task, freeing it if it was the last one. No-op for static tasks.
+///
+/// # Safety
+///
+/// The caller must own one of the task's references, and must not use the task afterwards.
+#[inline(always)]
+pub(crate) unsafe fn release(task: TaskRef) {
+    if is_heap(task) {
+        let last = critical_section::with(|cs| {
+            let refs = task.header().heap.refs.borrow(cs);
+            refs.set(refs.get() - 1);
+            refs.get() == 0
+        });
+        if last {
+            let dealloc = task.header().heap.dealloc.get().unwrap_unchecked();
+            dealloc(task);
+        }
+    }
+}
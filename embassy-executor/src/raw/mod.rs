@@ -16,9 +16,11 @@ mod run_queue;
 #[cfg_attr(not(target_has_atomic = "8"), path = "state_critical_section.rs")]
 mod state;
 
+#[cfg(feature = "alloc")]
+pub(crate) mod heap;
 pub mod timer_queue;
 #[cfg(feature = "trace")]
-mod trace;
+pub(crate) mod trace;
 pub(crate) mod util;
 #[cfg_attr(feature = "turbowakers", path = "waker_turbo.rs")]
 mod waker;
@@ -89,6 +91,12 @@ pub(crate) struct TaskHeader {
 
     /// Integrated timer queue storage. This field should not be accessed outside of the timer queue.
     pub(crate) timer_queue_item: timer_queue::TimerQueueItem,
+
+    #[cfg(feature = "watchdog")]
+    pub(crate) watchdog: crate::watchdog::TaskWatchdogItem,
+
+    #[cfg(feature = "alloc")]
+    pub(crate) heap: heap::HeapItem,
 }
 
 /// This is essentially a `&'static TaskStorage<F>` where the type of the future has been erased.
@@ -139,6 +147,30 @@ impl TaskRef {
         &self.header().timer_queue_item
     }
 
+    /// Keep the task's memory alive until a matching call to [`release`](Self::release).
+    ///
+    /// Tasks spawned with `Spawner::spawn_boxed` (available with the `alloc` feature) are freed
+    /// once they have finished running and nothing references them anymore. Code that keeps a
+    /// `TaskRef` after the `Waker` it was obtained from is dropped, such as an integrated timer
+    /// queue, must hold a reference for as long as it keeps the `TaskRef`.
+    ///
+    /// This is a no-op for statically allocated tasks, and when the `alloc` feature is disabled.
+    pub fn retain(self) {
+        #[cfg(feature = "alloc")]
+        heap::retain(self);
+    }
+
+    /// Drop a reference acquired with [`retain`](Self::retain).
+    ///
+    /// # Safety
+    ///
+    /// Each call must be matched with a previous call to `retain`. The task may be freed by this
+    /// call, so the `TaskRef` must not be used afterwards.
+    pub unsafe fn release(self) {
+        #[cfg(feature = "alloc")]
+        heap::release(self);
+    }
+
     /// The returned pointer is valid for the entire TaskStorage.
     pub(crate) fn as_ptr(self) -> *const TaskHeader {
         self.ptr.as_ptr()
@@ -190,6 +222,12 @@ impl<F: Future + 'static> TaskStorage<F> {
                 poll_fn: SyncUnsafeCell::new(None),
 
                 timer_queue_item: timer_queue::TimerQueueItem::new(),
+
+                #[cfg(feature = "watchdog")]
+                watchdog: crate::watchdog::TaskWatchdogItem::new(),
+
+                #[cfg(feature = "alloc")]
+                heap: heap::HeapItem::new(),
             },
             future: UninitCell::uninit(),
         }
@@ -235,12 +273,20 @@ impl<F: Future + 'static> TaskStorage<F> {
                 // when the executor polls it next.
                 this.raw.poll_fn.set(Some(poll_exited));
 
+                #[cfg(feature = "watchdog")]
+                crate::watchdog::task_end(&p);
+
                 // Make sure we despawn last, so that other threads can only spawn the task
                 // after we're done with it.
                 this.raw.state.despawn();
 
                 #[cfg(feature = "trace")]
                 trace::task_end(exec_ptr, &p);
+
+                // Drop the reference held by the future. The task is still referenced by
+                // the run queue, as it's being polled.
+                #[cfg(feature = "alloc")]
+                heap::release(p);
             }
             Poll::Pending => {}
         }
@@ -394,6 +440,11 @@ impl Pender {
 pub(crate) struct SyncExecutor {
     run_queue: RunQueue,
     pender: Pender,
+
+    /// Set if tasks may be polled from multiple threads, in which case only `Send` tasks
+    /// may be spawned.
+    #[cfg(feature = "arch-std")]
+    pub(crate) send_only: bool,
 }
 
 impl SyncExecutor {
@@ -401,6 +452,8 @@ impl SyncExecutor {
         Self {
             run_queue: RunQueue::new(),
             pender,
+            #[cfg(feature = "arch-std")]
+            send_only: false,
         }
     }
 
@@ -440,21 +493,47 @@ impl SyncExecutor {
         #[cfg(feature = "trace")]
         trace::poll_start(self);
 
-        self.run_queue.dequeue_all(|p| {
-            let task = p.header();
+        self.dequeue_all(|p| self.poll_task(p));
 
-            #[cfg(feature = "trace")]
-            trace::task_exec_begin(self, &p);
+        #[cfg(feature = "trace")]
+        trace::executor_idle(self)
+    }
 
-            // Run the task
-            task.poll_fn.get().unwrap_unchecked()(p);
+    /// Empty the run queue, then call `on_task` for each task that was in it.
+    ///
+    /// The tasks must then be polled with [`poll_task`](Self::poll_task).
+    pub(crate) fn dequeue_all(&self, on_task: impl FnMut(TaskRef)) {
+        self.run_queue.dequeue_all(on_task)
+    }
 
-            #[cfg(feature = "trace")]
-            trace::task_exec_end(self, &p);
-        });
+    /// Poll a task that was dequeued from the run queue.
+    ///
+    /// # Safety
+    ///
+    /// `p` must have been dequeued from this executor's run queue, and this method must be called
+    /// exactly once for each dequeued task. The task must not be polled concurrently from another
+    /// thread.
+    pub(crate) unsafe fn poll_task(&'static self, p: TaskRef) {
+        let task = p.header();
 
         #[cfg(feature = "trace")]
-        trace::executor_idle(self)
+        trace::task_exec_begin(self, &p);
+
+        #[cfg(feature = "watchdog")]
+        let poll_start = crate::watchdog::poll_start(&p);
+
+        // Run the task
+        task.poll_fn.get().unwrap_unchecked()(p);
+
+        #[cfg(feature = "watchdog")]
+        crate::watchdog::poll_end(self as *const Self as usize, &p, poll_start);
+
+        #[cfg(feature = "trace")]
+        trace::task_exec_end(self, &p);
+
+        // Drop the reference held by the run queue.
+        #[cfg(feature = "alloc")]
+        heap::release(p);
     }
 }
 
@@ -573,6 +652,9 @@ impl Executor {
 pub fn wake_task(task: TaskRef) {
     let header = task.header();
     header.state.run_enqueue(|l| {
+        #[cfg(feature = "alloc")]
+        heap::retain(task);
+
         // We have just marked the task as scheduled, so enqueue it.
         unsafe {
             let executor = header.executor.load(Ordering::Relaxed).as_ref().unwrap_unchecked();
@@ -587,6 +669,9 @@ pub fn wake_task(task: TaskRef) {
 pub fn wake_task_no_pend(task: TaskRef) {
     let header = task.header();
     header.state.run_enqueue(|l| {
+        #[cfg(feature = "alloc")]
+        heap::retain(task);
+
         // We have just marked the task as scheduled, so enqueue it.
         unsafe {
             let executor = header.executor.load(Ordering::Relaxed).as_ref().unwrap_unchecked();
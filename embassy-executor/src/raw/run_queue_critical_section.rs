@@ -54,7 +54,7 @@ impl RunQueue {
     /// Empty the queue, then call `on_task` for each task that was in the queue.
     /// NOTE: It is OK for `on_task` to enqueue more tasks. In this case they're left in the queue
     /// and will be processed by the *next* call to `dequeue_all`, *not* the current one.
-    pub(crate) fn dequeue_all(&self, on_task: impl Fn(TaskRef)) {
+    pub(crate) fn dequeue_all(&self, mut on_task: impl FnMut(TaskRef)) {
         // Atomically empty the queue.
         let mut next = critical_section::with(|cs| self.head.borrow(cs).take());
 
@@ -16,7 +16,37 @@ unsafe fn drop(_: *const ()) {
     // nop
 }
 
+/// Wakers of heap-allocated tasks hold a reference to the task.
+#[cfg(feature = "alloc")]
+static HEAP_VTABLE: RawWakerVTable = RawWakerVTable::new(heap_clone, heap_wake, wake, heap_drop);
+
+#[cfg(feature = "alloc")]
+unsafe fn heap_clone(p: *const ()) -> RawWaker {
+    super::heap::retain(TaskRef::from_ptr(p as *const TaskHeader));
+    RawWaker::new(p, &HEAP_VTABLE)
+}
+
+#[cfg(feature = "alloc")]
+unsafe fn heap_wake(p: *const ()) {
+    wake(p);
+    heap_drop(p);
+}
+
+#[cfg(feature = "alloc")]
+unsafe fn heap_drop(p: *const ()) {
+    super::heap::release(TaskRef::from_ptr(p as *const TaskHeader))
+}
+
+/// Create a waker for the task.
+///
+/// For heap-allocated tasks, the returned waker does not hold a reference to the task, so it must
+/// not be dropped. Only clones of it may be dropped.
 pub(crate) unsafe fn from_task(p: TaskRef) -> Waker {
+    #[cfg(feature = "alloc")]
+    if super::heap::is_heap(p) {
+        return Waker::from_raw(RawWaker::new(p.as_ptr() as _, &HEAP_VTABLE));
+    }
+
     Waker::from_raw(RawWaker::new(p.as_ptr() as _, &VTABLE))
 }
 
@@ -28,13 +58,21 @@ pub(crate) unsafe fn from_task(p: TaskRef) -> Waker {
 ///
 /// You can use the returned task pointer to wake the task with [`wake_task`].
 ///
+/// The returned task pointer is only guaranteed to be valid while `waker` is alive. To keep it
+/// around for longer, you must use [`TaskRef::retain`].
+///
 /// # Panics
 ///
 /// Panics if the waker is not created by the Embassy executor.
 pub fn task_from_waker(waker: &Waker) -> TaskRef {
     // make sure to compare vtable addresses. Doing `==` on the references
     // will compare the contents, which is slower.
-    if waker.vtable() as *const _ != &VTABLE as *const _ {
+    #[cfg(feature = "alloc")]
+    let is_heap = core::ptr::eq(waker.vtable(), &HEAP_VTABLE);
+    #[cfg(not(feature = "alloc"))]
+    let is_heap = false;
+
+    if waker.vtable() as *const _ != &VTABLE as *const _ && !is_heap {
         panic!("Found waker not created by the Embassy executor. `embassy_time::Timer` only works with the Embassy executor.")
     }
     // safety: our wakers are always created with `TaskRef::as_ptr`
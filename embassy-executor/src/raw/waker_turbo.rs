@@ -1,3 +1,6 @@
+#[cfg(feature = "alloc")]
+compile_error!("The `alloc` feature is not supported with `turbowakers`.");
+
 use core::ptr::NonNull;
 use core::task::Waker;
 
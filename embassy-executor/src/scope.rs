@@ -0,0 +1,219 @@
+//! Scoped tasks.
+//!
+//! [`Spawner::scope`] runs an async closure that can spawn tasks borrowing non-`'static` data, such as
+//! locals of the enclosing async function. The scope only completes once all the tasks spawned
+//! in it have completed, so the borrowed data is guaranteed to outlive them.
+//!
+//! ```rust,ignore
+//! async fn handle_request(spawner: Spawner, req: &Request) -> [Response; 4] {
+//!     let mut responses = [Response::default(); 4];
+//!     spawner.scope(async |s| {
+//!         for (backend, resp) in BACKENDS.iter().zip(responses.iter_mut()) {
+//!             s.spawn(async move {
+//!                 *resp = backend.query(req).await;
+//!             });
+//!         }
+//!     })
+//!     .await;
+//!     responses
+//! }
+//! ```
+//!
+//! Scoped tasks are not spawned into the executor as independent tasks. Instead, they are polled
+//! by the scope future itself, and run concurrently with the scope closure and with each other,
+//! within the task that awaits the scope. This is what makes scopes sound: if the scope future
+//! is dropped before completion, all the scoped tasks are dropped (aborted) with it, and if it's
+//! leaked with [`core::mem::forget`], the scoped tasks are never polled again, so they can't
+//! access the borrowed data after it's gone.
+//!
+//! Each scoped task has its own waker: when the scope future is polled, only the scoped tasks
+//! that were woken since they were last polled are polled again.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::pin::{pin, Pin};
+use core::ptr::NonNull;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use crate::Spawner;
+
+/// A scope to spawn scoped tasks in.
+///
+/// See [`Spawner::scope`] for details.
+pub struct Scope<'env> {
+    tasks: RefCell<Vec<ScopedTask<'env>>>,
+}
+
+struct ScopedTask<'env> {
+    future: Pin<Box<dyn Future<Output = ()> + 'env>>,
+    waker: TaskWaker,
+}
+
+impl<'env> Scope<'env> {
+    /// Spawn a task in this scope.
+    ///
+    /// The task may borrow anything that outlives the scope. It starts running the next
+    /// time the scope future is polled, and the scope won't complete until it has finished.
+    ///
+    /// The task's future is allocated on the heap, and freed when the task finishes.
+    pub fn spawn(&self, future: impl Future<Output = ()> + 'env) {
+        self.tasks.borrow_mut().push(ScopedTask {
+            future: Box::pin(future),
+            waker: TaskWaker::new(),
+        });
+    }
+
+    /// Return the number of scoped tasks that haven't finished yet.
+    pub fn len(&self) -> usize {
+        self.tasks.borrow().len()
+    }
+
+    /// Return whether all scoped tasks have finished.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.borrow().is_empty()
+    }
+
+    /// Poll the tasks woken since they were last polled.
+    ///
+    /// `waker_changed` is set when the waker of the task awaiting the scope changed, it's then given to
+    /// all the task wakers.
+    fn poll_tasks(&self, cx: &mut Context<'_>, waker_changed: bool) {
+        // Tasks don't have access to the scope, so they can't spawn more tasks while
+        // the `RefCell` is borrowed.
+        self.tasks.borrow_mut().retain_mut(|task| {
+            if !task.waker.take_woken(cx.waker(), waker_changed) {
+                return true;
+            }
+            let waker = task.waker.waker();
+            task.future.as_mut().poll(&mut Context::from_waker(&waker)).is_pending()
+        });
+    }
+}
+
+/// Reference counted waker of a scoped task.
+///
+/// Waking it marks the task as woken and wakes the task awaiting the scope. The state is protected by a
+/// critical section, so this works on targets without atomic read-modify-write operations.
+struct TaskWaker(NonNull<critical_section::Mutex<RefCell<TaskWakerState>>>);
+
+struct TaskWakerState {
+    refs: usize,
+    woken: bool,
+    /// Waker of the task awaiting the scope.
+    parent: Option<Waker>,
+}
+
+impl TaskWaker {
+    const VTABLE: RawWakerVTable =
+        RawWakerVTable::new(Self::clone_raw, Self::wake_raw, Self::wake_by_ref_raw, Self::drop_raw);
+
+    fn new() -> Self {
+        let state = TaskWakerState {
+            refs: 1,
+            // Poll the task when the scope is next polled.
+            woken: true,
+            parent: None,
+        };
+        let state = Box::new(critical_section::Mutex::new(RefCell::new(state)));
+        Self(NonNull::from(Box::leak(state)))
+    }
+
+    /// Clear the woken flag, returning whether it was set. Sets the parent waker first if it's missing or
+    /// `changed`.
+    fn take_woken(&self, parent: &Waker, changed: bool) -> bool {
+        let state = unsafe { self.0.as_ref() };
+        critical_section::with(|cs| {
+            let mut state = state.borrow_ref_mut(cs);
+            if changed || state.parent.is_none() {
+                state.parent = Some(parent.clone());
+            }
+            core::mem::replace(&mut state.woken, false)
+        })
+    }
+
+    fn waker(&self) -> Waker {
+        unsafe { Waker::from_raw(Self::clone_raw(self.0.as_ptr() as *const ())) }
+    }
+
+    unsafe fn clone_raw(ptr: *const ()) -> RawWaker {
+        let state = &*(ptr as *const critical_section::Mutex<RefCell<TaskWakerState>>);
+        critical_section::with(|cs| state.borrow_ref_mut(cs).refs += 1);
+        RawWaker::new(ptr, &Self::VTABLE)
+    }
+
+    unsafe fn wake_raw(ptr: *const ()) {
+        Self::wake_by_ref_raw(ptr);
+        Self::drop_raw(ptr);
+    }
+
+    unsafe fn wake_by_ref_raw(ptr: *const ()) {
+        let state = &*(ptr as *const critical_section::Mutex<RefCell<TaskWakerState>>);
+        let parent = critical_section::with(|cs| {
+            let mut state = state.borrow_ref_mut(cs);
+            state.woken = true;
+            state.parent.clone()
+        });
+        if let Some(parent) = parent {
+            parent.wake();
+        }
+    }
+
+    unsafe fn drop_raw(ptr: *const ()) {
+        let state = ptr as *mut critical_section::Mutex<RefCell<TaskWakerState>>;
+        let last = critical_section::with(|cs| {
+            let mut state = (*state).borrow_ref_mut(cs);
+            state.refs -= 1;
+            state.refs == 0
+        });
+        if last {
+            drop(Box::from_raw(state));
+        }
+    }
+}
+
+impl Drop for TaskWaker {
+    fn drop(&mut self) {
+        unsafe { Self::drop_raw(self.0.as_ptr() as *const ()) }
+    }
+}
+
+impl Spawner {
+    /// Run `f` in a new [`Scope`], in which tasks borrowing non-`'static` data can be spawned.
+    ///
+    /// The returned future completes with the output of `f`, once `f` and all the tasks
+    /// spawned in the scope have completed. Dropping it aborts all the scoped tasks.
+    ///
+    /// The scoped tasks run within the task awaiting the scope, rather than as independent tasks
+    /// of this spawner's executor. See the [module documentation](crate::scope) for details.
+    pub async fn scope<'env, R>(&self, f: impl AsyncFnOnce(&Scope<'env>) -> R) -> R {
+        let scope = Scope {
+            tasks: RefCell::new(Vec::new()),
+        };
+        let mut body = pin!(f(&scope));
+        let mut output = None;
+        let mut parent: Option<Waker> = None;
+
+        poll_fn(|cx| {
+            if output.is_none() {
+                if let Poll::Ready(r) = body.as_mut().poll(cx) {
+                    output = Some(r);
+                }
+            }
+
+            let waker_changed = !parent.as_ref().is_some_and(|w| w.will_wake(cx.waker()));
+            if waker_changed {
+                parent = Some(cx.waker().clone());
+            }
+            scope.poll_tasks(cx, waker_changed);
+
+            if output.is_some() && scope.is_empty() {
+                Poll::Ready(output.take().unwrap())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
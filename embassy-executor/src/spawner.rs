@@ -50,6 +50,20 @@ impl<S> SpawnToken<S> {
             phantom: PhantomData,
         }
     }
+
+    /// Record the task name. This is a no-op unless the `watchdog` feature is enabled.
+    ///
+    /// Not covered by semver guarantees. Intended to be used by the Embassy macros.
+    #[doc(hidden)]
+    pub fn _with_name(self, name: &'static str) -> Self {
+        #[cfg(feature = "watchdog")]
+        if let Some(task) = self.raw_task {
+            task.set_name(Some(name));
+        }
+        #[cfg(not(feature = "watchdog"))]
+        let _ = name;
+        self
+    }
 }
 
 impl<S> Drop for SpawnToken<S> {
@@ -123,6 +137,10 @@ impl Spawner {
     /// # Panics
     ///
     /// Panics if the current executor is not an Embassy executor.
+    ///
+    /// With `arch-std`, also panics if the current executor is a `MultiThreadExecutor`, which can only
+    /// spawn `Send` tasks: use [`SendSpawner::for_current_executor()`] in tasks it runs. The other executors,
+    /// including the single-threaded `arch-std` one, are not affected.
     pub fn for_current_executor() -> impl Future<Output = Self> {
         poll_fn(|cx| {
             let task = raw::task_from_waker(cx.waker());
@@ -133,6 +151,10 @@ impl Spawner {
                     .as_ref()
                     .unwrap_unchecked()
             };
+            #[cfg(feature = "arch-std")]
+            if executor.send_only {
+                panic!("The current executor only accepts Send tasks. Use SendSpawner::for_current_executor() instead.")
+            }
             let executor = unsafe { raw::Executor::wrap(executor) };
             Poll::Ready(Self::new(executor))
         })
@@ -167,6 +189,16 @@ impl Spawner {
         unwrap!(self.spawn(token));
     }
 
+    /// Spawn a future as a new heap-allocated task.
+    ///
+    /// Unlike tasks declared with `#[embassy_executor::task]`, which are stored in a
+    /// fixed-size static pool, the task storage is allocated on the heap, and freed
+    /// once the task has finished running and nothing references it anymore.
+    #[cfg(feature = "alloc")]
+    pub fn spawn_boxed<F: Future + 'static>(&self, future: F) {
+        unwrap!(self.spawn(raw::heap::allocate(future)));
+    }
+
     /// Convert this Spawner to a SendSpawner. This allows you to send the
     /// spawner to other threads, but the spawner loses the ability to spawn
     /// non-Send tasks.
@@ -243,4 +275,12 @@ impl SendSpawner {
     pub fn must_spawn<S: Send>(&self, token: SpawnToken<S>) {
         unwrap!(self.spawn(token));
     }
+
+    /// Spawn a future as a new heap-allocated task.
+    ///
+    /// See [`Spawner::spawn_boxed()`] for details.
+    #[cfg(feature = "alloc")]
+    pub fn spawn_boxed<F: Future + Send + 'static>(&self, future: F) {
+        unwrap!(self.spawn(raw::heap::allocate(future)));
+    }
 }
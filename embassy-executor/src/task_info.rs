@@ -0,0 +1,109 @@
+//! Task memory usage information.
+//!
+//! This module is only available with the `task-info` feature.
+//!
+//! For each task declared with the [`task`](embassy_executor_macros::task) macro, a `TaskInfo`
+//! constant named after the task in upper case, with a `_TASK_INFO` suffix, is generated next
+//! to the task function. For example, for `async fn blinky()`, the macro generates
+//! `const BLINKY_TASK_INFO: TaskInfo`. It has the same visibility as the task function, and can
+//! be used to check the size of task futures at compile time:
+//!
+//! ```rust,ignore
+//! #[embassy_executor::task(pool_size = 2)]
+//! async fn blinky() { /* ... */ }
+//!
+//! const _: () = assert!(BLINKY_TASK_INFO.future_size <= 1024, "blinky future is too large");
+//! ```
+//!
+//! ## Task table
+//!
+//! The macro also places the `TaskInfo` of every task in a linker section named `embassy_task_info`, so that the full list of tasks with their memory
+//! usage can be extracted from the final binary (for example with
+//! `objdump -s -j embassy_task_info`), and read at runtime with [`tasks`] and [`total_storage_size`].
+//!
+//! The linker has to keep the section. This is done automatically on hosted targets, but on
+//! embedded targets you have to place it in flash, for example by adding the following to your
+//! `memory.x`:
+//!
+//! ```text
+//! SECTIONS {
+//!     embassy_task_info : ALIGN(4) { KEEP(*(embassy_task_info)) } > FLASH
+//! } INSERT AFTER .rodata;
+//! ```
+//!
+//! The section is only supported on targets using ELF object files.
+
+/// Memory usage information for a task.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TaskInfo {
+    /// The task function name.
+    pub name: &'static str,
+    /// Size of the task's future, in bytes.
+    pub future_size: usize,
+    /// Maximum number of instances of the task that can run at the same time.
+    pub pool_size: usize,
+    /// Size of the statically allocated storage for all the task's instances, in bytes.
+    ///
+    /// This includes the task headers, and any padding.
+    pub storage_size: usize,
+}
+
+/// Places a `TaskInfo` in the `embassy_task_info` linker section.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __task_info_section {
+    ($info:expr) => {
+        const _: () = {
+            #[used]
+            #[link_section = "embassy_task_info"]
+            static INFO: $crate::TaskInfo = $info;
+        };
+    };
+}
+
+// Makes sure the section is never empty, so that its start and stop symbols are always defined.
+#[used]
+#[link_section = "embassy_task_info"]
+static PLACEHOLDER: TaskInfo = TaskInfo {
+    name: "",
+    future_size: 0,
+    pool_size: 0,
+    storage_size: 0,
+};
+
+/// Iterate over the memory usage information of all the tasks in the program.
+pub fn tasks() -> impl Iterator<Item = &'static TaskInfo> {
+    extern "Rust" {
+        static __start_embassy_task_info: TaskInfo;
+        static __stop_embassy_task_info: TaskInfo;
+    }
+
+    let tasks = unsafe {
+        let start = core::ptr::addr_of!(__start_embassy_task_info);
+        let stop = core::ptr::addr_of!(__stop_embassy_task_info);
+        core::slice::from_raw_parts(start, stop.offset_from(start) as usize)
+    };
+    tasks.iter().filter(|info| info.pool_size != 0)
+}
+
+/// Return the size of the storage of the heap-allocated tasks currently alive, in bytes.
+///
+/// These are the tasks spawned with [`Spawner::spawn_boxed`](crate::Spawner::spawn_boxed). Unlike
+/// [`tasks`], this changes at runtime: tasks are counted from when they are spawned until they are
+/// freed.
+#[cfg(feature = "alloc")]
+pub fn heap_storage_size() -> usize {
+    crate::raw::heap::storage_size()
+}
+
+/// Return the total size of the storage for all tasks, in bytes.
+///
+/// This is the statically allocated storage of all task pools, plus with the `alloc` feature the
+/// storage of the heap-allocated tasks currently alive (see [`heap_storage_size`]).
+pub fn total_storage_size() -> usize {
+    let size: usize = tasks().map(|info| info.storage_size).sum();
+    #[cfg(feature = "alloc")]
+    let size = size + heap_storage_size();
+    size
+}
@@ -0,0 +1,303 @@
+//! Task watchdog.
+//!
+//! The `watchdog` feature instruments the executor so that misbehaving tasks can be detected.
+//! It provides two independent mechanisms:
+//!
+//! - **Poll budget**: every call to a task's `poll` is timed. If a single poll takes longer than
+//!   the budget set with [`set_poll_budget`], the hook set with [`set_slow_poll_hook`] is invoked.
+//!   If no hook is set, a warning with the task name is logged through `defmt` or `log` instead.
+//!   A task that blocks for a long time without yielding starves every other task in the same
+//!   executor, so this is usually a bug.
+//! - **Liveness**: tasks can be registered with [`watch`] (or [`watch_current`] from within the
+//!   task itself) to require that they're polled at least once every `max_interval` ticks.
+//!   [`check`] reports whether all registered tasks are healthy, which makes it easy to only feed
+//!   a hardware watchdog when the whole system is making progress:
+//!
+//! ```rust,ignore
+//! #[embassy_executor::task]
+//! async fn feeder(mut wdt: Watchdog) {
+//!     loop {
+//!         if embassy_executor::watchdog::check().is_ok() {
+//!             wdt.pet();
+//!         }
+//!         Timer::after_millis(100).await;
+//!     }
+//! }
+//! ```
+//!
+//! All times are expressed in ticks of the [`embassy_time_driver`] time driver, see
+//! [`embassy_time_driver::TICK_HZ`]. If you're using `embassy-time`, you can get them with
+//! `Duration::as_ticks()`.
+//!
+//! Task names are recorded automatically for tasks spawned with the
+//! [`task`](embassy_executor_macros::task) macro. For manually spawned tasks, use
+//! [`TaskRef::set_name`].
+
+use core::cell::Cell;
+use core::future::{poll_fn, Future};
+use core::task::Poll;
+
+use critical_section::{CriticalSection, Mutex};
+
+use crate::raw::{task_from_waker, TaskRef};
+
+/// Information about a task poll that exceeded the configured budget.
+#[derive(Clone, Copy)]
+pub struct SlowPoll {
+    /// The task that was polled.
+    pub task: TaskRef,
+    /// The task name, if known.
+    pub name: Option<&'static str>,
+    /// The ID of the executor the task was polled on. See [`Executor::id`](crate::raw::Executor::id).
+    pub executor_id: usize,
+    /// How long the poll took, in ticks.
+    pub elapsed: u64,
+    /// The configured budget, in ticks.
+    pub budget: u64,
+}
+
+/// Error returned by [`check`] when a watched task is overdue.
+#[derive(Clone, Copy)]
+pub struct Overdue {
+    /// The task that is overdue.
+    pub task: TaskRef,
+    /// The task name, if known.
+    pub name: Option<&'static str>,
+    /// Time since the task was last polled, in ticks.
+    pub since_last_poll: u64,
+}
+
+impl core::fmt::Debug for Overdue {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Overdue")
+            .field("task", &self.task.as_ptr())
+            .field("name", &self.name)
+            .field("since_last_poll", &self.since_last_poll)
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for Overdue {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Overdue {{ task: {=usize:x}, name: {:?}, since_last_poll: {=u64} }}",
+            self.task.as_ptr() as usize,
+            self.name,
+            self.since_last_poll
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Config {
+    budget: Option<u64>,
+    hook: Option<fn(&SlowPoll)>,
+}
+
+static CONFIG: Mutex<Cell<Config>> = Mutex::new(Cell::new(Config {
+    budget: None,
+    hook: None,
+}));
+
+/// Head of the intrusive list of watched tasks.
+static WATCHED: Mutex<Cell<Option<TaskRef>>> = Mutex::new(Cell::new(None));
+
+/// Per-task watchdog state, stored in the task header.
+pub(crate) struct TaskWatchdogItem {
+    inner: Mutex<TaskWatchdogState>,
+}
+
+struct TaskWatchdogState {
+    name: Cell<Option<&'static str>>,
+    /// Time of the start of the last poll.
+    last_poll: Cell<u64>,
+    /// Maximum interval between polls. Only meaningful if `watched` is set.
+    max_interval: Cell<u64>,
+    /// Whether the task is in the `WATCHED` list.
+    watched: Cell<bool>,
+    /// Next task in the `WATCHED` list.
+    next: Cell<Option<TaskRef>>,
+}
+
+impl TaskWatchdogItem {
+    pub(crate) const fn new() -> Self {
+        Self {
+            inner: Mutex::new(TaskWatchdogState {
+                name: Cell::new(None),
+                last_poll: Cell::new(0),
+                max_interval: Cell::new(0),
+                watched: Cell::new(false),
+                next: Cell::new(None),
+            }),
+        }
+    }
+
+    fn borrow<'cs>(&'cs self, cs: CriticalSection<'cs>) -> &'cs TaskWatchdogState {
+        self.inner.borrow(cs)
+    }
+}
+
+impl TaskRef {
+    /// Get the name of this task, if known.
+    pub fn name(&self) -> Option<&'static str> {
+        critical_section::with(|cs| self.header().watchdog.borrow(cs).name.get())
+    }
+
+    /// Set the name of this task.
+    ///
+    /// Tasks spawned with the [`task`](embassy_executor_macros::task) macro are named after
+    /// the task function automatically.
+    pub fn set_name(&self, name: Option<&'static str>) {
+        critical_section::with(|cs| self.header().watchdog.borrow(cs).name.set(name))
+    }
+}
+
+/// Set the maximum duration of a single task poll, in ticks.
+///
+/// Pass `None` to disable poll time checking. It is disabled by default.
+pub fn set_poll_budget(budget: Option<u64>) {
+    critical_section::with(|cs| {
+        let config = CONFIG.borrow(cs);
+        config.set(Config {
+            budget,
+            hook: config.get().hook,
+        })
+    })
+}
+
+/// Set the function called when a task poll exceeds the budget.
+///
+/// The hook is called from the executor, right after the slow poll returns. It must not block.
+///
+/// Pass `None` to restore the default behavior, which is to log a warning.
+pub fn set_slow_poll_hook(hook: Option<fn(&SlowPoll)>) {
+    critical_section::with(|cs| {
+        let config = CONFIG.borrow(cs);
+        config.set(Config {
+            budget: config.get().budget,
+            hook,
+        })
+    })
+}
+
+/// Require `task` to be polled at least once every `max_interval` ticks.
+///
+/// Calling this for a task that is already watched updates its interval. The interval is measured
+/// starting from now.
+///
+/// Tasks are unwatched automatically when they exit.
+pub fn watch(task: TaskRef, max_interval: u64) {
+    let now = embassy_time_driver::now();
+    critical_section::with(|cs| {
+        let state = task.header().watchdog.borrow(cs);
+        state.max_interval.set(max_interval);
+        state.last_poll.set(now);
+        if !state.watched.replace(true) {
+            let head = WATCHED.borrow(cs);
+            state.next.set(head.get());
+            head.set(Some(task));
+        }
+    })
+}
+
+/// Require the current task to be polled at least once every `max_interval` ticks.
+///
+/// See [`watch`] for details.
+///
+/// This function is `async` just to get access to the current async
+/// context. It returns instantly, it does not block/yield.
+pub fn watch_current(max_interval: u64) -> impl Future<Output = ()> {
+    poll_fn(move |cx| {
+        watch(task_from_waker(cx.waker()), max_interval);
+        Poll::Ready(())
+    })
+}
+
+/// Stop watching `task`.
+///
+/// This does nothing if the task is not watched.
+pub fn unwatch(task: TaskRef) {
+    critical_section::with(|cs| {
+        let state = task.header().watchdog.borrow(cs);
+        if !state.watched.replace(false) {
+            return;
+        }
+
+        let next = state.next.take();
+        let mut link = WATCHED.borrow(cs);
+        while let Some(cur) = link.get() {
+            if cur == task {
+                link.set(next);
+                return;
+            }
+            link = &cur.header().watchdog.borrow(cs).next;
+        }
+    })
+}
+
+/// Check whether all watched tasks have been polled within their interval.
+///
+/// Returns the first overdue task found, if any.
+pub fn check() -> Result<(), Overdue> {
+    let now = embassy_time_driver::now();
+    critical_section::with(|cs| {
+        let mut cur = WATCHED.borrow(cs).get();
+        while let Some(task) = cur {
+            let state = task.header().watchdog.borrow(cs);
+            let since_last_poll = now.saturating_sub(state.last_poll.get());
+            if since_last_poll > state.max_interval.get() {
+                return Err(Overdue {
+                    task,
+                    name: state.name.get(),
+                    since_last_poll,
+                });
+            }
+            cur = state.next.get();
+        }
+        Ok(())
+    })
+}
+
+/// Called by the executor right before polling a task. Returns the poll start time.
+#[inline]
+pub(crate) fn poll_start(task: &TaskRef) -> u64 {
+    let now = embassy_time_driver::now();
+    critical_section::with(|cs| task.header().watchdog.borrow(cs).last_poll.set(now));
+    now
+}
+
+/// Called by the executor right after polling a task.
+#[inline]
+pub(crate) fn poll_end(executor_id: usize, task: &TaskRef, start: u64) {
+    let Config { budget, hook } = critical_section::with(|cs| CONFIG.borrow(cs).get());
+    let Some(budget) = budget else {
+        return;
+    };
+
+    let elapsed = embassy_time_driver::now().saturating_sub(start);
+    if elapsed <= budget {
+        return;
+    }
+
+    let slow = SlowPoll {
+        task: *task,
+        name: task.name(),
+        executor_id,
+        elapsed,
+        budget,
+    };
+    match hook {
+        Some(hook) => hook(&slow),
+        None => warn!(
+            "task {:?} polled for {} ticks, exceeding the budget of {} ticks",
+            slow.name, slow.elapsed, slow.budget
+        ),
+    }
+}
+
+/// Called when a task exits.
+pub(crate) fn task_end(task: &TaskRef) {
+    unwatch(*task)
+}
@@ -0,0 +1,139 @@
+#![cfg(all(feature = "arch-std", feature = "executor-thread"))]
+#![cfg_attr(feature = "nightly", feature(impl_trait_in_assoc_type))]
+
+use std::boxed::Box;
+use std::collections::HashSet;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::task::{Context, Poll};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+use embassy_executor::{task, MultiThreadExecutor, SendSpawner};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run `init` on a new executor with `threads` worker threads, in the background.
+fn start(threads: usize, init: impl FnOnce(SendSpawner) + Send + 'static) {
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(MultiThreadExecutor::new(threads)));
+        executor.run(init)
+    });
+}
+
+#[test]
+fn runs_tasks_on_worker_threads() {
+    #[task(pool_size = 16)]
+    async fn record(tx: mpsc::Sender<ThreadId>) {
+        // Keep the worker busy, so the other tasks are picked up by other workers.
+        thread::sleep(Duration::from_millis(20));
+        tx.send(thread::current().id()).unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    start(4, move |spawner| {
+        for _ in 0..16 {
+            spawner.spawn(record(tx.clone())).unwrap();
+        }
+    });
+
+    let threads: HashSet<ThreadId> = (0..16).map(|_| rx.recv_timeout(TIMEOUT).unwrap()).collect();
+    assert!(threads.len() > 1, "all tasks ran on a single worker");
+}
+
+#[test]
+fn never_polls_a_task_concurrently() {
+    /// Wakes itself while being polled, until polled `POLLS` times.
+    struct SelfWaking {
+        polling: AtomicBool,
+        polls: AtomicUsize,
+    }
+
+    const POLLS: usize = 50;
+
+    impl Future for &SelfWaking {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            assert!(!self.polling.swap(true, Ordering::SeqCst), "task polled concurrently");
+            // Woken while being polled: a worker may dequeue the task before this poll returns.
+            cx.waker().wake_by_ref();
+            thread::sleep(Duration::from_millis(1));
+            self.polling.store(false, Ordering::SeqCst);
+
+            match self.polls.fetch_add(1, Ordering::SeqCst) + 1 {
+                POLLS => Poll::Ready(()),
+                _ => Poll::Pending,
+            }
+        }
+    }
+
+    #[task]
+    async fn self_waking(tx: mpsc::Sender<usize>) {
+        let future = SelfWaking {
+            polling: AtomicBool::new(false),
+            polls: AtomicUsize::new(0),
+        };
+        (&future).await;
+        tx.send(future.polls.load(Ordering::SeqCst)).unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    start(4, move |spawner| spawner.spawn(self_waking(tx)).unwrap());
+
+    assert_eq!(rx.recv_timeout(TIMEOUT).unwrap(), POLLS);
+}
+
+#[test]
+fn spawns_from_tasks() {
+    #[task]
+    async fn parent(tx: mpsc::Sender<&'static str>) {
+        let spawner = SendSpawner::for_current_executor().await;
+        spawner.spawn(child(tx.clone())).unwrap();
+        tx.send("parent").unwrap();
+    }
+
+    #[task]
+    async fn child(tx: mpsc::Sender<&'static str>) {
+        tx.send("child").unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    start(2, move |spawner| spawner.spawn(parent(tx)).unwrap());
+
+    let mut received = [rx.recv_timeout(TIMEOUT).unwrap(), rx.recv_timeout(TIMEOUT).unwrap()];
+    received.sort();
+    assert_eq!(received, ["child", "parent"]);
+}
+
+#[test]
+fn wakes_idle_workers() {
+    #[task]
+    async fn wait(rx: mpsc::Receiver<()>, tx: mpsc::Sender<()>) {
+        // Pending until woken from another thread, while the workers are idle.
+        let waker = poll_fn(|cx| Poll::Ready(cx.waker().clone())).await;
+        let woken = std::sync::Arc::new(AtomicBool::new(false));
+        let flag = woken.clone();
+        thread::spawn(move || {
+            rx.recv().unwrap();
+            flag.store(true, Ordering::SeqCst);
+            waker.wake();
+        });
+        poll_fn(|_| match woken.load(Ordering::SeqCst) {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        })
+        .await;
+        tx.send(()).unwrap();
+    }
+
+    let (wake_tx, wake_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel();
+    start(3, move |spawner| spawner.spawn(wait(wake_rx, done_tx)).unwrap());
+
+    thread::sleep(Duration::from_millis(50));
+    wake_tx.send(()).unwrap();
+    done_rx.recv_timeout(TIMEOUT).unwrap();
+}
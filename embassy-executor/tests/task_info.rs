@@ -0,0 +1,65 @@
+#![cfg(feature = "task-info")]
+#![cfg_attr(feature = "nightly", feature(impl_trait_in_assoc_type))]
+
+use embassy_executor::task;
+
+#[export_name = "__pender"]
+fn __pender(_context: *mut ()) {}
+
+#[task(pool_size = 3)]
+async fn big_task(x: u32) {
+    let buf = [x as u8; 1000];
+    core::future::ready(()).await;
+    core::hint::black_box(&buf);
+}
+
+#[task]
+async fn small_task() {}
+
+#[test]
+fn task_info_const() {
+    assert_eq!(BIG_TASK_TASK_INFO.name, "big_task");
+    assert_eq!(BIG_TASK_TASK_INFO.pool_size, 3);
+    assert!(BIG_TASK_TASK_INFO.future_size >= 1000);
+    assert!(BIG_TASK_TASK_INFO.storage_size >= 3 * BIG_TASK_TASK_INFO.future_size);
+
+    assert_eq!(SMALL_TASK_TASK_INFO.name, "small_task");
+    assert_eq!(SMALL_TASK_TASK_INFO.pool_size, 1);
+    assert!(SMALL_TASK_TASK_INFO.future_size < 1000);
+}
+
+#[test]
+fn task_info_section() {
+    let mut tasks: Vec<_> = embassy_executor::task_info::tasks().map(|info| info.name).collect();
+    tasks.sort();
+    assert_eq!(tasks, ["big_task", "small_task"]);
+
+    let static_size = BIG_TASK_TASK_INFO.storage_size + SMALL_TASK_TASK_INFO.storage_size;
+    assert_eq!(embassy_executor::task_info::total_storage_size(), static_size);
+
+    #[cfg(feature = "alloc")]
+    heap_tasks(static_size);
+}
+
+/// Checks that heap-allocated tasks are counted while they are alive.
+#[cfg(feature = "alloc")]
+fn heap_tasks(static_size: usize) {
+    use embassy_executor::raw::Executor;
+    use embassy_executor::task_info::{heap_storage_size, total_storage_size};
+
+    let executor: &'static Executor = Box::leak(Box::new(Executor::new(core::ptr::null_mut())));
+    assert_eq!(heap_storage_size(), 0);
+
+    executor.spawner().spawn_boxed(async {
+        let buf = [0u8; 1000];
+        core::future::ready(()).await;
+        core::hint::black_box(&buf);
+    });
+    assert!(heap_storage_size() >= 1000);
+    assert_eq!(total_storage_size(), static_size + heap_storage_size());
+
+    // The task is freed once it has finished.
+    unsafe { executor.poll() };
+    assert_eq!(heap_storage_size(), 0);
+    assert_eq!(total_storage_size(), static_size);
+}
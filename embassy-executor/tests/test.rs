@@ -16,6 +16,57 @@ fn __pender(context: *mut ()) {
     }
 }
 
+// The `watchdog` feature needs a time driver.
+#[cfg(feature = "watchdog")]
+mod time_driver {
+    struct ZeroDriver;
+
+    impl embassy_time_driver::Driver for ZeroDriver {
+        fn now(&self) -> u64 {
+            0
+        }
+
+        fn schedule_wake(&self, _at: u64, _waker: &core::task::Waker) {
+            unimplemented!()
+        }
+    }
+
+    embassy_time_driver::time_driver_impl!(static DRIVER: ZeroDriver = ZeroDriver);
+}
+
+// Heap-allocated tasks must be freed, count the bytes allocated by each test thread to check it.
+#[cfg(feature = "alloc")]
+mod counting_alloc {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    std::thread_local! {
+        static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    }
+
+    struct CountingAlloc;
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED.try_with(|a| a.set(a.get() + layout.size() as isize));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = ALLOCATED.try_with(|a| a.set(a.get() - layout.size() as isize));
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    /// Bytes allocated by the current thread, and not freed yet.
+    pub fn allocated() -> isize {
+        ALLOCATED.with(|a| a.get())
+    }
+}
+
 #[derive(Clone)]
 struct Trace {
     trace: Arc<Mutex<Vec<&'static str>>>,
@@ -283,3 +334,235 @@ fn executor_task_cfg_args() {
         let (_, _, _) = (a, b, c);
     }
 }
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_spawn_boxed() {
+    use embassy_sync::waitqueue::AtomicWaker;
+
+    let waker: &'static AtomicWaker = Box::leak(Box::new(AtomicWaker::new()));
+
+    let (executor, trace) = setup();
+    for _ in 0..2 {
+        let trace = trace.clone();
+        executor.spawner().spawn_boxed(async move {
+            poll_fn(|cx| {
+                trace.push("poll boxed");
+                waker.register(cx.waker());
+                Poll::Ready(())
+            })
+            .await
+        });
+    }
+
+    unsafe { executor.poll() };
+
+    // The task has exited, but is kept alive by the registered waker.
+    waker.wake();
+    unsafe { executor.poll() };
+
+    assert_eq!(
+        trace.get(),
+        &[
+            "pend",       // spawning a task pends the executor
+            "poll boxed", //
+            "poll boxed", //
+            "pend",       // manual wake, exited task is not polled
+        ]
+    )
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_spawn_boxed_frees_tasks() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::task::Waker;
+
+    use counting_alloc::allocated;
+
+    /// Sets a flag when the task's future is dropped.
+    struct Guard(Arc<AtomicBool>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed)
+        }
+    }
+
+    /// Spawn a task keeping a clone of its waker in `waker`. If `pending`, it stores it in its first poll
+    /// and finishes in the second one, otherwise it stores it and finishes in its first poll. `dropped` is
+    /// set once its future is dropped.
+    fn spawn(
+        executor: &'static Executor,
+        waker: &'static Mutex<Option<Waker>>,
+        dropped: &Arc<AtomicBool>,
+        pending: bool,
+    ) {
+        let guard = Guard(dropped.clone());
+        let mut polled = false;
+        executor.spawner().spawn_boxed(async move {
+            let _guard = guard;
+            poll_fn(|cx| {
+                if pending && polled {
+                    return Poll::Ready(());
+                }
+                *waker.lock().unwrap() = Some(cx.waker().clone());
+                if pending {
+                    polled = true;
+                    Poll::Pending
+                } else {
+                    Poll::Ready(())
+                }
+            })
+            .await
+        });
+    }
+
+    let (executor, trace) = setup();
+    // Pending the executor must not allocate.
+    trace.trace.lock().unwrap().reserve(16);
+    let waker: &'static Mutex<Option<Waker>> = Box::leak(Box::new(Mutex::new(None)));
+
+    // The last waker is dropped before the task finishes: it's freed once it finishes.
+    let dropped = Arc::new(AtomicBool::new(false));
+    let base = allocated();
+    spawn(executor, waker, &dropped, true);
+    assert!(allocated() > base);
+    unsafe { executor.poll() };
+    assert!(!dropped.load(Ordering::Relaxed));
+    waker.lock().unwrap().take().unwrap().wake();
+    assert!(allocated() > base);
+    unsafe { executor.poll() };
+    assert!(dropped.load(Ordering::Relaxed));
+    assert_eq!(allocated(), base);
+
+    // The task finishes while a waker is alive: it's freed once the last waker is dropped.
+    let dropped = Arc::new(AtomicBool::new(false));
+    let base = allocated();
+    spawn(executor, waker, &dropped, false);
+    unsafe { executor.poll() };
+    assert!(dropped.load(Ordering::Relaxed));
+    assert!(allocated() > base);
+    drop(waker.lock().unwrap().take());
+    assert_eq!(allocated(), base);
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_scope() {
+    async fn yield_now() {
+        let mut yielded = false;
+        poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    let (executor, trace) = setup();
+    let task_trace = trace.clone();
+    let spawner = executor.spawner();
+    spawner.spawn_boxed(async move {
+        let trace = task_trace;
+        let mut results = [0; 2];
+        let ret = spawner
+            .scope(async |s| {
+                for (i, result) in results.iter_mut().enumerate() {
+                    let trace = trace.clone();
+                    s.spawn(async move {
+                        trace.push("child start");
+                        yield_now().await;
+                        *result = i + 1;
+                        trace.push("child end");
+                    });
+                }
+                trace.push("body end");
+                42
+            })
+            .await;
+        assert_eq!(ret, 42);
+        assert_eq!(results, [1, 2]);
+        trace.push("scope end");
+    });
+
+    unsafe { executor.poll() };
+    unsafe { executor.poll() };
+
+    assert_eq!(
+        trace.get(),
+        &[
+            "pend",        // spawning a task pends the executor
+            "body end",    //
+            "child start", //
+            "pend",        // first child yields
+            "child start", // second child yields, shouldn't pend
+            "child end",   //
+            "child end",   //
+            "scope end",   //
+        ]
+    )
+}
+
+#[cfg(feature = "alloc")]
+#[test]
+fn executor_scope_polls_woken_tasks() {
+    use std::cell::Cell;
+    use std::task::Waker;
+
+    let (executor, trace) = setup();
+    let spawner = executor.spawner();
+    let waiting_polls = std::rc::Rc::new(Cell::new(0));
+    let waker = std::rc::Rc::new(Cell::new(None::<Waker>));
+    let (task_polls, task_waker, task_trace) = (waiting_polls.clone(), waker.clone(), trace.clone());
+    spawner.spawn_boxed(async move {
+        spawner
+            .scope(async |s| {
+                // Pending until woken from outside.
+                s.spawn(poll_fn(|cx| {
+                    task_polls.set(task_polls.get() + 1);
+                    match task_polls.get() {
+                        1 => {
+                            task_waker.set(Some(cx.waker().clone()));
+                            Poll::Pending
+                        }
+                        _ => Poll::Ready(()),
+                    }
+                }));
+                // Yields 3 times.
+                s.spawn(async {
+                    for _ in 0..3 {
+                        let mut yielded = false;
+                        poll_fn(|cx| {
+                            if yielded {
+                                Poll::Ready(())
+                            } else {
+                                yielded = true;
+                                cx.waker().wake_by_ref();
+                                Poll::Pending
+                            }
+                        })
+                        .await
+                    }
+                });
+            })
+            .await;
+        task_trace.push("scope end");
+    });
+
+    for _ in 0..4 {
+        unsafe { executor.poll() };
+    }
+    // The waiting task isn't polled again while the other one yields.
+    assert_eq!(waiting_polls.get(), 1);
+    assert!(!trace.get().contains(&"scope end"));
+
+    waker.take().unwrap().wake();
+    unsafe { executor.poll() };
+    assert_eq!(waiting_polls.get(), 2);
+    assert_eq!(trace.get().last(), Some(&"scope end"));
+}
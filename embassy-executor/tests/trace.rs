@@ -0,0 +1,76 @@
+#![cfg(all(feature = "trace", feature = "arch-std", feature = "executor-thread"))]
+#![cfg_attr(feature = "nightly", feature(impl_trait_in_assoc_type))]
+
+use std::boxed::Box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embassy_executor::{task, MultiThreadExecutor};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+static POLL_STARTS: AtomicUsize = AtomicUsize::new(0);
+static IDLES: AtomicUsize = AtomicUsize::new(0);
+static EXEC_BEGINS: AtomicUsize = AtomicUsize::new(0);
+static EXEC_ENDS: AtomicUsize = AtomicUsize::new(0);
+
+#[no_mangle]
+fn _embassy_trace_poll_start(_executor_id: u32) {
+    POLL_STARTS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[no_mangle]
+fn _embassy_trace_executor_idle(_executor_id: u32) {
+    IDLES.fetch_add(1, Ordering::SeqCst);
+}
+
+#[no_mangle]
+fn _embassy_trace_task_exec_begin(_executor_id: u32, _task_id: u32) {
+    EXEC_BEGINS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[no_mangle]
+fn _embassy_trace_task_exec_end(_executor_id: u32, _task_id: u32) {
+    EXEC_ENDS.fetch_add(1, Ordering::SeqCst);
+}
+
+#[no_mangle]
+fn _embassy_trace_task_new(_executor_id: u32, _task_id: u32) {}
+
+#[no_mangle]
+fn _embassy_trace_task_end(_executor_id: u32, _task_id: u32) {}
+
+#[no_mangle]
+fn _embassy_trace_task_ready_begin(_executor_id: u32, _task_id: u32) {}
+
+#[test]
+fn multi_thread_executor_traces_polls() {
+    #[task(pool_size = 8)]
+    async fn done(tx: mpsc::Sender<()>) {
+        tx.send(()).unwrap();
+    }
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(MultiThreadExecutor::new(4)));
+        executor.run(move |spawner| {
+            for _ in 0..8 {
+                spawner.spawn(done(tx.clone())).unwrap();
+            }
+        })
+    });
+    for _ in 0..8 {
+        rx.recv_timeout(TIMEOUT).unwrap();
+    }
+
+    // Once the tasks are done, every worker that polled some goes idle again.
+    let start = Instant::now();
+    while EXEC_ENDS.load(Ordering::SeqCst) < 8 || POLL_STARTS.load(Ordering::SeqCst) != IDLES.load(Ordering::SeqCst) {
+        assert!(start.elapsed() < TIMEOUT, "workers didn't go idle");
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(EXEC_BEGINS.load(Ordering::SeqCst), 8);
+    assert!(POLL_STARTS.load(Ordering::SeqCst) > 0);
+}
@@ -0,0 +1,109 @@
+#![cfg(feature = "watchdog")]
+#![cfg_attr(feature = "nightly", feature(impl_trait_in_assoc_type))]
+
+use std::boxed::Box;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::task::Poll;
+
+use embassy_executor::raw::Executor;
+use embassy_executor::{task, watchdog};
+
+#[export_name = "__pender"]
+fn __pender(_context: *mut ()) {}
+
+static NOW: AtomicU64 = AtomicU64::new(0);
+
+struct MockDriver;
+
+impl embassy_time_driver::Driver for MockDriver {
+    fn now(&self) -> u64 {
+        NOW.load(Ordering::Relaxed)
+    }
+
+    fn schedule_wake(&self, _at: u64, _waker: &core::task::Waker) {
+        unimplemented!()
+    }
+}
+
+embassy_time_driver::time_driver_impl!(static DRIVER: MockDriver = MockDriver);
+
+// The watchdog configuration is global, so tests must not run concurrently.
+static LOCK: Mutex<()> = Mutex::new(());
+
+static SLOW: Mutex<Vec<(Option<&'static str>, u64)>> = Mutex::new(Vec::new());
+
+fn record_slow_poll(slow: &watchdog::SlowPoll) {
+    SLOW.lock().unwrap().push((slow.name, slow.elapsed));
+}
+
+fn executor() -> &'static Executor {
+    Box::leak(Box::new(Executor::new(core::ptr::null_mut())))
+}
+
+#[test]
+fn poll_budget() {
+    let _guard = LOCK.lock().unwrap();
+
+    #[task]
+    async fn busy_task() {
+        poll_fn(|cx| {
+            NOW.fetch_add(50, Ordering::Relaxed);
+            cx.waker().wake_by_ref();
+            Poll::<()>::Pending
+        })
+        .await
+    }
+
+    watchdog::set_poll_budget(Some(100));
+    watchdog::set_slow_poll_hook(Some(record_slow_poll));
+    SLOW.lock().unwrap().clear();
+
+    let executor = executor();
+    executor.spawner().spawn(busy_task()).unwrap();
+    unsafe { executor.poll() };
+    assert!(SLOW.lock().unwrap().is_empty());
+
+    watchdog::set_poll_budget(Some(10));
+    unsafe { executor.poll() };
+    assert_eq!(&*SLOW.lock().unwrap(), &[(Some("busy_task"), 50)]);
+
+    watchdog::set_poll_budget(None);
+    watchdog::set_slow_poll_hook(None);
+}
+
+#[test]
+fn liveness() {
+    let _guard = LOCK.lock().unwrap();
+
+    #[task]
+    async fn watched_task() {
+        watchdog::watch_current(100).await;
+        poll_fn(|_| Poll::<()>::Pending).await
+    }
+
+    #[task]
+    async fn short_task() {
+        watchdog::watch_current(1).await;
+    }
+
+    let executor = executor();
+    executor.spawner().spawn(watched_task()).unwrap();
+    executor.spawner().spawn(short_task()).unwrap();
+    unsafe { executor.poll() };
+
+    // `short_task` exited, so it's no longer watched.
+    assert!(watchdog::check().is_ok());
+
+    NOW.fetch_add(100, Ordering::Relaxed);
+    assert!(watchdog::check().is_ok());
+
+    NOW.fetch_add(1, Ordering::Relaxed);
+    let overdue = watchdog::check().unwrap_err();
+    assert_eq!(overdue.name, Some("watched_task"));
+    assert_eq!(overdue.since_last_poll, 101);
+
+    watchdog::unwatch(overdue.task);
+    assert!(watchdog::check().is_ok());
+}
@@ -0,0 +1,200 @@
+#![no_std]
+#![doc = include_str!("../README.md")]
+#![warn(missing_docs)]
+
+pub use bt_hci;
+use bt_hci::transport::{Error, Transport, WithIndicator};
+use bt_hci::{ControllerToHostPacket, HostToControllerPacket, ReadHci, WriteHci};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::Mutex;
+
+/// HCI transport over a serial port, using the UART transport layer protocol (H4).
+///
+/// Each packet is preceded by a byte giving its kind: command, ACL, synchronous or isochronous data,
+/// or event. Packets can be read and written at the same time, for example from different tasks.
+pub struct H4Transport<M: RawMutex, R, W> {
+    reader: Mutex<M, R>,
+    writer: Mutex<M, W>,
+}
+
+impl<M: RawMutex, R: embedded_io_async::Read, W: embedded_io_async::Write> H4Transport<M, R, W> {
+    /// Create a new H4 transport, reading the packets from the controller from `reader` and writing
+    /// the packets to it to `writer`.
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader: Mutex::new(reader),
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<M, R, W, E> embedded_io::ErrorType for H4Transport<M, R, W>
+where
+    M: RawMutex,
+    R: embedded_io::ErrorType<Error = E>,
+    W: embedded_io::ErrorType<Error = E>,
+    E: embedded_io::Error,
+{
+    type Error = Error<E>;
+}
+
+impl<M, R, W, E> Transport for H4Transport<M, R, W>
+where
+    M: RawMutex,
+    R: embedded_io_async::Read<Error = E>,
+    W: embedded_io_async::Write<Error = E>,
+    E: embedded_io::Error,
+{
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let mut reader = self.reader.lock().await;
+        ControllerToHostPacket::read_hci_async(&mut *reader, rx)
+            .await
+            .map_err(Error::Read)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, val: &T) -> Result<(), Self::Error> {
+        let mut writer = self.writer.lock().await;
+        WithIndicator::new(val)
+            .write_hci_async(&mut *writer)
+            .await
+            .map_err(Error::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bt_hci::cmd::controller_baseband::Reset;
+    use bt_hci::cmd::Cmd;
+    use bt_hci::data::{AclBroadcastFlag, AclPacket, AclPacketBoundary};
+    use bt_hci::event::Event;
+    use bt_hci::param::ConnHandle;
+    use bt_hci::ReadHciError;
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pipe::Pipe;
+
+    use super::*;
+
+    type Pipe64 = Pipe<NoopRawMutex, 64>;
+
+    /// Read all the bytes in `pipe`.
+    fn drain(pipe: &Pipe64) -> ([u8; 64], usize) {
+        let mut buf = [0; 64];
+        let len = pipe.try_read(&mut buf).unwrap_or(0);
+        (buf, len)
+    }
+
+    #[test]
+    fn writes_commands() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        block_on(transport.write(&Reset::new())).unwrap();
+        let (buf, len) = drain(&tx);
+        // Command indicator, opcode and parameter length.
+        assert_eq!(&buf[..len], [0x01, 0x03, 0x0c, 0x00]);
+    }
+
+    #[test]
+    fn writes_acl_data() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        let acl = AclPacket::new(
+            ConnHandle::new(1),
+            AclPacketBoundary::FirstFlushable,
+            AclBroadcastFlag::PointToPoint,
+            b"hello",
+        );
+        block_on(transport.write(&acl)).unwrap();
+        let (buf, len) = drain(&tx);
+        // ACL indicator, handle and flags, data length and data.
+        assert_eq!(&buf[..len], b"\x02\x01\x20\x05\x00hello");
+    }
+
+    #[test]
+    fn reads_events() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        // Command complete for a reset, with a success status.
+        rx.try_write(&[0x04, 0x0e, 0x04, 0x01, 0x03, 0x0c, 0x00]).unwrap();
+        let mut buf = [0; 64];
+        match block_on(transport.read(&mut buf)).unwrap() {
+            ControllerToHostPacket::Event(Event::CommandComplete(event)) => {
+                assert_eq!(event.num_hci_cmd_pkts, 1);
+                assert_eq!(event.cmd_opcode, Reset::OPCODE);
+            }
+            _ => panic!("expected a command complete event"),
+        }
+    }
+
+    #[test]
+    fn reads_acl_data() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        rx.try_write(b"\x02\x01\x20\x05\x00hello").unwrap();
+        let mut buf = [0; 64];
+        match block_on(transport.read(&mut buf)).unwrap() {
+            ControllerToHostPacket::Acl(acl) => {
+                assert_eq!(acl.handle(), ConnHandle::new(1));
+                assert_eq!(acl.boundary_flag(), AclPacketBoundary::FirstFlushable);
+                assert_eq!(acl.data(), b"hello");
+            }
+            _ => panic!("expected ACL data"),
+        }
+    }
+
+    #[test]
+    fn reads_packets_in_pieces() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        // The controller sends two packets a byte at a time, while the host reads them.
+        let controller = async {
+            for byte in b"\x02\x01\x20\x05\x00hello\x02\x02\x20\x02\x00hi" {
+                rx.write(&[*byte]).await;
+                embassy_futures::yield_now().await;
+            }
+        };
+        let host = async {
+            let mut buf = [0; 64];
+            for (handle, data) in [(1, &b"hello"[..]), (2, b"hi")] {
+                match transport.read(&mut buf).await.unwrap() {
+                    ControllerToHostPacket::Acl(acl) => {
+                        assert_eq!(acl.handle(), ConnHandle::new(handle));
+                        assert_eq!(acl.data(), data);
+                    }
+                    _ => panic!("expected ACL data"),
+                }
+            }
+        };
+        block_on(join(controller, host));
+    }
+
+    #[test]
+    fn rejects_invalid_packets() {
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+
+        // Command packets are only sent by the host.
+        rx.try_write(&[0x01, 0x03, 0x0c, 0x00]).unwrap();
+        let mut buf = [0; 64];
+        assert!(matches!(
+            block_on(transport.read(&mut buf)),
+            Err(Error::Read(ReadHciError::InvalidValue))
+        ));
+
+        // Data longer than the buffer.
+        let (rx, tx) = (Pipe64::new(), Pipe64::new());
+        let transport = H4Transport::<NoopRawMutex, _, _>::new(&rx, &tx);
+        rx.try_write(b"\x02\x01\x20\x05\x00hello").unwrap();
+        let mut buf = [0; 4];
+        assert!(matches!(
+            block_on(transport.read(&mut buf)),
+            Err(Error::Read(ReadHciError::BufferTooSmall))
+        ));
+    }
+}
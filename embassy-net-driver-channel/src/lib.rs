@@ -5,6 +5,9 @@
 // must go first!
 mod fmt;
 
+#[cfg(feature = "virtual-link")]
+pub mod virtual_link;
+
 use core::cell::RefCell;
 use core::mem::MaybeUninit;
 use core::task::{Context, Poll};
@@ -0,0 +1,543 @@
+//! In-memory virtual link, connecting several `embassy-net` stacks without any hardware.
+//!
+//! [`new`] creates a virtual switch with a number of ports, and a [`Device`] for each of them.
+//! Each device is given to its own stack, and the switch [`Runner`] passes the packets sent by
+//! each stack to the others. With two ports, this is a point-to-point link between two stacks.
+//!
+//! Ethernet ports learn the address of the stack they're connected to from the frames it sends,
+//! frames are only sent to the port that has their destination address. Broadcast and multicast
+//! frames, frames to unknown addresses, and packets sent by ports of other mediums are sent to
+//! all the other ports.
+//!
+//! The link of each port can be impaired with latency, jitter, packet loss, reordering and a
+//! bandwidth limit, see [`LinkConfig`]. They're applied to the packets the port sends. Random
+//! decisions are taken with a pseudo-random generator seeded by [`new`], so a test sees the same
+//! losses on each run.
+//!
+//! This is meant for testing network protocol code, on `std` targets with the `embassy-time`
+//! `std` or `mock-driver` drivers as well as on microcontrollers.
+
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::task::Poll;
+
+use embassy_net_driver::{HardwareAddress, LinkState};
+use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::{Device, StateRunner};
+
+/// Number of packets queued by the device of each port, in each direction.
+const PORT_QUEUE_LEN: usize = 4;
+
+/// Impairments of the link of a port, applied to the packets it sends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct LinkConfig {
+    /// Time packets take to cross the link.
+    pub latency: Duration,
+    /// Maximum random delay added to the latency of each packet.
+    ///
+    /// Packets can overtake each other when it's larger than the time between them.
+    pub jitter: Duration,
+    /// Percentage of packets lost, from 0 to 100.
+    pub loss_percent: u8,
+    /// Percentage of packets delayed by [`reorder_delay`](Self::reorder_delay), from 0 to 100.
+    pub reorder_percent: u8,
+    /// Extra delay of reordered packets, so that the packets sent after them overtake them.
+    pub reorder_delay: Duration,
+    /// Bandwidth of the link in bits per second, `None` for no limit.
+    ///
+    /// Packets are queued while the link is busy sending the previous ones, and dropped when the
+    /// queue of the switch is full.
+    pub bandwidth: Option<u32>,
+}
+
+impl LinkConfig {
+    /// Create a configuration for a perfect link, delivering all packets immediately.
+    pub const fn new() -> Self {
+        Self {
+            latency: Duration::from_ticks(0),
+            jitter: Duration::from_ticks(0),
+            loss_percent: 0,
+            reorder_percent: 0,
+            reorder_delay: Duration::from_millis(10),
+            bandwidth: None,
+        }
+    }
+}
+
+impl Default for LinkConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Virtual link state.
+///
+/// Holds `PORTS` devices with packets of size `MTU`, and up to `QUEUE` packets in flight in
+/// the switch.
+pub struct State<const MTU: usize, const PORTS: usize, const QUEUE: usize> {
+    ports: [crate::State<MTU, PORT_QUEUE_LEN, PORT_QUEUE_LEN>; PORTS],
+    queue: [InFlight<MTU>; QUEUE],
+    shared: Mutex<NoopRawMutex, RefCell<[Port; PORTS]>>,
+}
+
+impl<const MTU: usize, const PORTS: usize, const QUEUE: usize> State<MTU, PORTS, QUEUE> {
+    /// Create a new virtual link state.
+    pub const fn new() -> Self {
+        Self {
+            ports: [const { crate::State::new() }; PORTS],
+            queue: [const { InFlight::new() }; QUEUE],
+            shared: Mutex::new(RefCell::new([Port::new(); PORTS])),
+        }
+    }
+}
+
+impl<const MTU: usize, const PORTS: usize, const QUEUE: usize> Default for State<MTU, PORTS, QUEUE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Port state shared between the runner and the control handle.
+#[derive(Clone, Copy)]
+struct Port {
+    config: LinkConfig,
+    link_up: bool,
+}
+
+impl Port {
+    const fn new() -> Self {
+        Self {
+            config: LinkConfig::new(),
+            link_up: true,
+        }
+    }
+}
+
+/// A packet in the switch, waiting for its delivery time.
+struct InFlight<const MTU: usize> {
+    /// `None` if the slot is free.
+    deliver_at: Option<Instant>,
+    /// Order the packet entered the switch, packets due at the same time are delivered in it.
+    seq: u64,
+    from: usize,
+    len: usize,
+    buf: [u8; MTU],
+}
+
+impl<const MTU: usize> InFlight<MTU> {
+    const fn new() -> Self {
+        Self {
+            deliver_at: None,
+            seq: 0,
+            from: 0,
+            len: 0,
+            buf: [0; MTU],
+        }
+    }
+}
+
+/// Handle to change the links of the ports while the switch runs.
+#[derive(Clone, Copy)]
+pub struct Control<'d, const PORTS: usize> {
+    shared: &'d Mutex<NoopRawMutex, RefCell<[Port; PORTS]>>,
+    states: [StateRunner<'d>; PORTS],
+}
+
+impl<const PORTS: usize> Control<'_, PORTS> {
+    /// Get the link configuration of `port`.
+    pub fn config(&self, port: usize) -> LinkConfig {
+        self.shared.lock(|s| s.borrow()[port].config)
+    }
+
+    /// Set the link configuration of `port`.
+    ///
+    /// It applies to the packets sent from now on, the ones in flight keep their delivery time.
+    pub fn set_config(&self, port: usize, config: LinkConfig) {
+        self.shared.lock(|s| s.borrow_mut()[port].config = config);
+    }
+
+    /// Set the link state of `port`, as seen by its stack.
+    ///
+    /// While the link is down, packets sent by and to the port are dropped. All links start up.
+    pub fn set_link_state(&self, port: usize, state: LinkState) {
+        self.shared
+            .lock(|s| s.borrow_mut()[port].link_up = state == LinkState::Up);
+        self.states[port].set_link_state(state);
+    }
+}
+
+/// Virtual switch runner.
+///
+/// You must call `.run()` in a background task for the stacks to be able to talk to each other.
+pub struct Runner<'d, const MTU: usize, const PORTS: usize> {
+    ports: [crate::Runner<'d, MTU>; PORTS],
+    /// Whether each port uses the Ethernet medium.
+    ethernet: [bool; PORTS],
+    /// Ethernet address learned on each port.
+    addresses: [Option<[u8; 6]>; PORTS],
+    /// Time the link of each port is done sending the packets it's given, with a bandwidth limit.
+    busy_until: [Instant; PORTS],
+    queue: &'d mut [InFlight<MTU>],
+    shared: &'d Mutex<NoopRawMutex, RefCell<[Port; PORTS]>>,
+    seq: u64,
+    random: u64,
+}
+
+impl<const MTU: usize, const PORTS: usize> Runner<'_, MTU, PORTS> {
+    /// Run the switch.
+    pub async fn run(mut self) -> ! {
+        loop {
+            self.take_sent();
+            self.deliver_due();
+
+            let mut timer = self.queue.iter().filter_map(|p| p.deliver_at).min().map(Timer::at);
+            poll_fn(|cx| {
+                let sent = self.ports.iter_mut().any(|p| p.poll_tx_buf(cx).is_ready());
+                let due = timer.as_mut().is_some_and(|t| Pin::new(t).poll(cx).is_ready());
+                if sent || due {
+                    Poll::Ready(())
+                } else {
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+
+    /// Move the packets sent by the ports to the queue of the switch.
+    fn take_sent(&mut self) {
+        let now = Instant::now();
+        for from in 0..PORTS {
+            while let Some(buf) = self.ports[from].try_tx_buf() {
+                let port = self.shared.lock(|s| s.borrow()[from]);
+                let config = port.config;
+
+                if self.ethernet[from] && buf.len() >= 12 && buf[6] & 1 == 0 {
+                    self.addresses[from] = Some(unwrap!(buf[6..12].try_into()));
+                }
+
+                let lost = !port.link_up || random_percent(&mut self.random) < config.loss_percent;
+                let slot = self.queue.iter_mut().find(|p| p.deliver_at.is_none());
+                match (lost, slot) {
+                    (true, _) => trace!("virtual link: packet from port {} lost", from),
+                    (false, None) => debug!("virtual link: queue full, dropping packet from port {}", from),
+                    (false, Some(slot)) => {
+                        let mut sent_at = now;
+                        if let Some(bandwidth) = config.bandwidth {
+                            let bits = buf.len() as u64 * 8;
+                            let duration = Duration::from_micros(bits * 1_000_000 / u64::from(bandwidth.max(1)));
+                            sent_at = self.busy_until[from].max(now) + duration;
+                            self.busy_until[from] = sent_at;
+                        }
+                        let mut deliver_at = sent_at + config.latency;
+                        if config.jitter.as_ticks() > 0 {
+                            let jitter = next_random(&mut self.random) % (config.jitter.as_ticks() + 1);
+                            deliver_at += Duration::from_ticks(jitter);
+                        }
+                        if random_percent(&mut self.random) < config.reorder_percent {
+                            deliver_at += config.reorder_delay;
+                        }
+
+                        slot.buf[..buf.len()].copy_from_slice(buf);
+                        slot.len = buf.len();
+                        slot.from = from;
+                        slot.seq = self.seq;
+                        slot.deliver_at = Some(deliver_at);
+                        self.seq += 1;
+                    }
+                }
+                self.ports[from].tx_done();
+            }
+        }
+    }
+
+    /// Deliver the packets whose time has come, in order.
+    fn deliver_due(&mut self) {
+        let now = Instant::now();
+        while let Some(packet) = self
+            .queue
+            .iter_mut()
+            .filter(|p| p.deliver_at.is_some_and(|t| t <= now))
+            .min_by_key(|p| (p.deliver_at, p.seq))
+        {
+            let data = &packet.buf[..packet.len];
+            // Unicast Ethernet frames go to the port that has their destination, if it's known.
+            let to = if self.ethernet[packet.from] && data.len() >= 6 && data[0] & 1 == 0 {
+                let destination = &data[..6];
+                self.addresses.iter().position(|a| a.is_some_and(|a| a == destination))
+            } else {
+                None
+            };
+            let links = self.shared.lock(|s| (*s.borrow()).map(|p| p.link_up));
+
+            for (n, port) in self.ports.iter_mut().enumerate() {
+                if n == packet.from || to.is_some_and(|to| to != n) || !links[n] {
+                    continue;
+                }
+                match port.try_rx_buf() {
+                    Some(buf) => {
+                        buf[..data.len()].copy_from_slice(data);
+                        port.rx_done(data.len());
+                    }
+                    None => debug!("virtual link: port {} is full, dropping packet", n),
+                }
+            }
+            packet.deliver_at = None;
+        }
+    }
+}
+
+/// Create a virtual link.
+///
+/// Returns a handle to change the links while the switch runs, the switch runner, and the
+/// devices to give to the stacks, connected to the ports of the switch. Each port gets the
+/// hardware address from `hardware_addresses`, which also sets its medium.
+pub fn new<'d, const MTU: usize, const PORTS: usize, const QUEUE: usize>(
+    state: &'d mut State<MTU, PORTS, QUEUE>,
+    hardware_addresses: [HardwareAddress; PORTS],
+    random_seed: u64,
+) -> (Control<'d, PORTS>, Runner<'d, MTU, PORTS>, [Device<'d, MTU>; PORTS]) {
+    let mut runners = [const { None }; PORTS];
+    let mut devices = [const { None }; PORTS];
+    for ((port, address), (runner, device)) in state
+        .ports
+        .iter_mut()
+        .zip(hardware_addresses)
+        .zip(runners.iter_mut().zip(devices.iter_mut()))
+    {
+        let (mut r, d) = crate::new(port, address);
+        r.set_link_state(LinkState::Up);
+        *runner = Some(r);
+        *device = Some(d);
+    }
+    let runners = runners.map(|r| unwrap!(r));
+
+    (
+        Control {
+            shared: &state.shared,
+            states: core::array::from_fn(|n| runners[n].state_runner()),
+        },
+        Runner {
+            ports: runners,
+            ethernet: hardware_addresses.map(|a| matches!(a, HardwareAddress::Ethernet(_))),
+            addresses: [None; PORTS],
+            busy_until: [Instant::from_ticks(0); PORTS],
+            queue: &mut state.queue,
+            shared: &state.shared,
+            seq: 0,
+            // xorshift gets stuck on 0.
+            random: random_seed | 1,
+        },
+        devices.map(|d| unwrap!(d)),
+    )
+}
+
+/// Get the next number of a xorshift64 generator.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// Get a random number from 0 to 99.
+fn random_percent(state: &mut u64) -> u8 {
+    (next_random(state) % 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::task::{Context, Waker};
+    use std::boxed::Box;
+    use std::sync::{Mutex as StdMutex, MutexGuard};
+    use std::vec::Vec;
+
+    use embassy_net_driver::{Driver, RxToken, TxToken};
+    use embassy_time::MockDriver;
+
+    use super::*;
+
+    const MTU: usize = 100;
+    const SEED: u64 = 0x5eed;
+
+    /// The tests share the mock time driver, so they must not run at the same time.
+    static TIME: StdMutex<()> = StdMutex::new(());
+
+    /// Two ports, port 0 sending packets to port 1 through a link with the given configuration.
+    struct Link {
+        runner: Runner<'static, MTU, 2>,
+        devices: [Device<'static, MTU>; 2],
+        _time: MutexGuard<'static, ()>,
+    }
+
+    impl Link {
+        fn new(config: LinkConfig, seed: u64) -> Self {
+            let time = TIME.lock().unwrap_or_else(|e| e.into_inner());
+            MockDriver::get().reset();
+
+            let state = Box::leak(Box::new(State::<MTU, 2, 16>::new()));
+            let (control, runner, devices) = new(state, [HardwareAddress::Ip; 2], seed);
+            control.set_config(0, config);
+            Self {
+                runner,
+                devices,
+                _time: time,
+            }
+        }
+
+        /// Send a packet filled with `id` from port 0.
+        fn send(&mut self, id: u8) {
+            let mut cx = Context::from_waker(Waker::noop());
+            let token = unwrap!(self.devices[0].transmit(&mut cx));
+            token.consume(MTU, |buf| buf.fill(id));
+            self.runner.take_sent();
+        }
+
+        /// Deliver the packets due now and in the next `ms` milliseconds.
+        ///
+        /// Returns the time in milliseconds each packet received by port 1 arrived at, and its id.
+        fn advance(&mut self, ms: u64) -> Vec<(u64, u8)> {
+            let mut cx = Context::from_waker(Waker::noop());
+            let mut received = Vec::new();
+            for n in 0..=ms {
+                if n > 0 {
+                    MockDriver::get().advance(Duration::from_millis(1));
+                }
+                self.runner.deliver_due();
+                while let Some((rx, _)) = self.devices[1].receive(&mut cx) {
+                    let id = rx.consume(|buf| buf[0]);
+                    received.push((Instant::now().as_millis(), id));
+                }
+            }
+            received
+        }
+    }
+
+    #[test]
+    fn loses_packets() {
+        let mut link = Link::new(
+            LinkConfig {
+                loss_percent: 30,
+                ..LinkConfig::new()
+            },
+            SEED,
+        );
+        let mut received = Vec::new();
+        for id in 0..200 {
+            link.send(id);
+            received.extend(link.advance(0));
+        }
+
+        assert!(
+            (100..180).contains(&received.len()),
+            "{} packets received",
+            received.len()
+        );
+        // The packets that aren't lost arrive immediately and in order.
+        assert!(received.iter().all(|&(at, _)| at == 0));
+        assert!(received.windows(2).all(|w| w[0].1 < w[1].1));
+    }
+
+    #[test]
+    fn delays_packets_with_jitter() {
+        let mut link = Link::new(
+            LinkConfig {
+                latency: Duration::from_millis(10),
+                jitter: Duration::from_millis(20),
+                ..LinkConfig::new()
+            },
+            SEED,
+        );
+        for id in 0..8 {
+            link.send(id);
+        }
+        let received = link.advance(40);
+
+        assert_eq!(received.len(), 8);
+        assert!(received.iter().all(|&(at, _)| (10..=30).contains(&at)));
+        assert!(received.iter().any(|&(at, _)| at != received[0].0));
+        // The packets overtake each other.
+        assert!(received.windows(2).any(|w| w[0].1 > w[1].1));
+    }
+
+    #[test]
+    fn reorders_packets() {
+        let mut link = Link::new(
+            LinkConfig {
+                reorder_percent: 50,
+                reorder_delay: Duration::from_millis(10),
+                ..LinkConfig::new()
+            },
+            SEED,
+        );
+        let mut received = Vec::new();
+        for id in 0..20 {
+            link.send(id);
+            received.extend(link.advance(1));
+        }
+        received.extend(link.advance(20));
+
+        assert_eq!(received.len(), 20);
+        // Packet `id` is sent at `id` milliseconds, and is either delivered then or reordered.
+        let delays: Vec<u64> = received.iter().map(|&(at, id)| at - u64::from(id)).collect();
+        assert!(delays.iter().all(|&d| d == 0 || d == 10));
+        let reordered = delays.iter().filter(|&&d| d == 10).count();
+        assert!((3..=17).contains(&reordered), "{} packets reordered", reordered);
+        assert!(received.windows(2).any(|w| w[0].1 > w[1].1));
+    }
+
+    #[test]
+    fn limits_bandwidth() {
+        // A packet takes 10 ms to send.
+        let mut link = Link::new(
+            LinkConfig {
+                bandwidth: Some(MTU as u32 * 8 * 100),
+                ..LinkConfig::new()
+            },
+            SEED,
+        );
+        for id in 0..4 {
+            link.send(id);
+        }
+        assert_eq!(link.advance(50), [(10, 0), (20, 1), (30, 2), (40, 3)]);
+
+        // Once the link is idle, packets are delayed by their own sending time only.
+        link.send(4);
+        assert_eq!(link.advance(20), [(60, 4)]);
+    }
+
+    #[test]
+    fn seed_reproduces_runs() {
+        fn run(seed: u64) -> Vec<(u64, u8)> {
+            let mut link = Link::new(
+                LinkConfig {
+                    latency: Duration::from_millis(2),
+                    jitter: Duration::from_millis(5),
+                    loss_percent: 20,
+                    reorder_percent: 20,
+                    reorder_delay: Duration::from_millis(5),
+                    bandwidth: None,
+                },
+                seed,
+            );
+            let mut received = Vec::new();
+            for id in 0..30 {
+                link.send(id);
+                received.extend(link.advance(1));
+            }
+            received.extend(link.advance(20));
+            received
+        }
+
+        assert_eq!(run(SEED), run(SEED));
+        assert_ne!(run(SEED), run(1));
+    }
+}
@@ -0,0 +1,2241 @@
+//! CoAP server and client.
+//!
+//! [`CoapServer`] serves resources with the Constrained Application Protocol (RFC 7252), and
+//! [`client::CoapClient`] sends requests to CoAP servers. Both run on a UDP socket, over IPv4 or IPv6,
+//! including 6LoWPAN on IEEE 802.15.4 networks.
+//!
+//! Confirmable messages are retransmitted with an exponential back-off until they're acknowledged, as
+//! set by [`Config`]. Payloads larger than the block size are transferred block-wise (RFC 7959), and
+//! clients can observe resources (RFC 7641) to be notified when they change.
+//!
+//! A [`Router`] dispatches requests to handlers by method and path, answers `4.04 Not Found` and
+//! `4.05 Method Not Allowed` on its own, and lists its resources at `/.well-known/core`:
+//!
+//! ```ignore
+//! enum Api {
+//!     Temperature,
+//!     Led,
+//! }
+//!
+//! impl Handler for Api {
+//!     async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code {
+//!         match self {
+//!             Api::Temperature => {
+//!                 response.set_content_format(ContentFormat::TEXT_PLAIN);
+//!                 let _ = write!(response, "{}", read_temperature());
+//!                 Code::CONTENT
+//!             }
+//!             Api::Led => {
+//!                 let led = request.param("id");
+//!                 // ...
+//!                 Code::CHANGED
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! static ROUTES: [Route<Api>; 2] = [
+//!     Route::new(Method::Get, "/temperature", Api::Temperature),
+//!     Route::new(Method::Put, "/leds/{id}", Api::Led),
+//! ];
+//!
+//! server.run(&Router::new(&ROUTES), &mut [0; 1024]).await;
+//! ```
+//!
+//! The server handles one request at a time. It answers duplicates of the last confirmable request with the
+//! same response, and ignores older duplicates. DTLS, multicast requests and proxies aren't supported.
+
+use core::cell::{Cell, RefCell};
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{with_deadline, Duration, Instant};
+use heapless::{String, Vec};
+
+use crate::udp::{PacketMetadata, RecvError, SendError, UdpMetadata, UdpSocket};
+use crate::{IpEndpoint, IpListenEndpoint, Stack};
+
+/// Default CoAP port.
+pub const PORT: u16 = 5683;
+/// Maximum number of observers of the resources of a server.
+pub const MAX_OBSERVERS: usize = 4;
+pub use crate::router::MAX_PARAMS;
+/// Maximum length of the path of a request, and of its query.
+pub const MAX_PATH_LEN: usize = 64;
+/// Maximum size of a message, fitting a 1024 bytes payload with its header and options.
+const MAX_MESSAGE_LEN: usize = 1152;
+/// Maximum length of a token.
+const MAX_TOKEN_LEN: usize = 8;
+/// Number of notifications sent as non-confirmable in a row, before a confirmable one checks the
+/// observer is still interested.
+const MAX_NON_NOTIFICATIONS: u8 = 8;
+/// Path of the resource listing the resources of a server.
+const WELL_KNOWN_CORE: &str = "/.well-known/core";
+
+const VERSION: u8 = 1;
+const PAYLOAD_MARKER: u8 = 0xff;
+
+/// CoAP error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The peer didn't answer in time, after all the retransmissions.
+    Timeout,
+    /// The peer rejected the message with a reset.
+    Reset,
+    /// A message or payload doesn't fit its buffer.
+    TooLarge,
+    /// The peer sent a malformed message or an inconsistent block-wise transfer.
+    Malformed,
+    /// There is no route to the peer.
+    NoRoute,
+    /// The resource isn't observed, or no longer.
+    NotObserved,
+}
+
+impl From<SendError> for Error {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::NoRoute | SendError::SocketNotBound | SendError::SocketSetFull => Self::NoRoute,
+            SendError::PacketTooLarge => Self::TooLarge,
+        }
+    }
+}
+
+/// Request method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    /// `GET`
+    Get,
+    /// `POST`
+    Post,
+    /// `PUT`
+    Put,
+    /// `DELETE`
+    Delete,
+    /// `FETCH`
+    Fetch,
+    /// `PATCH`
+    Patch,
+    /// `iPATCH`
+    IPatch,
+}
+
+impl Method {
+    /// Code of requests with the method.
+    pub const fn code(self) -> Code {
+        Code::new(0, self as u8 + 1)
+    }
+
+    fn from_code(code: Code) -> Option<Self> {
+        Some(match (code.class, code.detail) {
+            (0, 1) => Self::Get,
+            (0, 2) => Self::Post,
+            (0, 3) => Self::Put,
+            (0, 4) => Self::Delete,
+            (0, 5) => Self::Fetch,
+            (0, 6) => Self::Patch,
+            (0, 7) => Self::IPatch,
+            _ => return None,
+        })
+    }
+}
+
+/// Code of a message, `class.detail`, such as `2.05` for the `Content` responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Code {
+    /// Class: 0 for requests, 2 for successful responses, 4 for client errors and 5 for server errors.
+    pub class: u8,
+    /// Detail, from 0 to 31.
+    pub detail: u8,
+}
+
+impl Code {
+    /// `0.00`, code of the messages without request or response.
+    pub const EMPTY: Self = Self::new(0, 0);
+    /// `2.01 Created`
+    pub const CREATED: Self = Self::new(2, 1);
+    /// `2.02 Deleted`
+    pub const DELETED: Self = Self::new(2, 2);
+    /// `2.03 Valid`
+    pub const VALID: Self = Self::new(2, 3);
+    /// `2.04 Changed`
+    pub const CHANGED: Self = Self::new(2, 4);
+    /// `2.05 Content`
+    pub const CONTENT: Self = Self::new(2, 5);
+    /// `2.31 Continue`
+    pub const CONTINUE: Self = Self::new(2, 31);
+    /// `4.00 Bad Request`
+    pub const BAD_REQUEST: Self = Self::new(4, 0);
+    /// `4.01 Unauthorized`
+    pub const UNAUTHORIZED: Self = Self::new(4, 1);
+    /// `4.02 Bad Option`
+    pub const BAD_OPTION: Self = Self::new(4, 2);
+    /// `4.03 Forbidden`
+    pub const FORBIDDEN: Self = Self::new(4, 3);
+    /// `4.04 Not Found`
+    pub const NOT_FOUND: Self = Self::new(4, 4);
+    /// `4.05 Method Not Allowed`
+    pub const METHOD_NOT_ALLOWED: Self = Self::new(4, 5);
+    /// `4.06 Not Acceptable`
+    pub const NOT_ACCEPTABLE: Self = Self::new(4, 6);
+    /// `4.08 Request Entity Incomplete`
+    pub const REQUEST_ENTITY_INCOMPLETE: Self = Self::new(4, 8);
+    /// `4.12 Precondition Failed`
+    pub const PRECONDITION_FAILED: Self = Self::new(4, 12);
+    /// `4.13 Request Entity Too Large`
+    pub const REQUEST_ENTITY_TOO_LARGE: Self = Self::new(4, 13);
+    /// `4.15 Unsupported Content-Format`
+    pub const UNSUPPORTED_CONTENT_FORMAT: Self = Self::new(4, 15);
+    /// `5.00 Internal Server Error`
+    pub const INTERNAL_SERVER_ERROR: Self = Self::new(5, 0);
+    /// `5.01 Not Implemented`
+    pub const NOT_IMPLEMENTED: Self = Self::new(5, 1);
+    /// `5.03 Service Unavailable`
+    pub const SERVICE_UNAVAILABLE: Self = Self::new(5, 3);
+
+    /// Create a new `Code`.
+    pub const fn new(class: u8, detail: u8) -> Self {
+        Self { class, detail }
+    }
+
+    /// Whether the code is the one of a successful response, `2.xx`.
+    pub const fn is_success(self) -> bool {
+        self.class == 2
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        Self::new(byte >> 5, byte & 0x1f)
+    }
+
+    fn to_byte(self) -> u8 {
+        (self.class << 5) | (self.detail & 0x1f)
+    }
+}
+
+impl core::fmt::Display for Code {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{:02}", self.class, self.detail)
+    }
+}
+
+/// Number of an option.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OptionNumber(pub u16);
+
+impl OptionNumber {
+    /// `If-Match`
+    pub const IF_MATCH: Self = Self(1);
+    /// `Uri-Host`
+    pub const URI_HOST: Self = Self(3);
+    /// `ETag`
+    pub const ETAG: Self = Self(4);
+    /// `If-None-Match`
+    pub const IF_NONE_MATCH: Self = Self(5);
+    /// `Observe`
+    pub const OBSERVE: Self = Self(6);
+    /// `Uri-Port`
+    pub const URI_PORT: Self = Self(7);
+    /// `Location-Path`
+    pub const LOCATION_PATH: Self = Self(8);
+    /// `Uri-Path`
+    pub const URI_PATH: Self = Self(11);
+    /// `Content-Format`
+    pub const CONTENT_FORMAT: Self = Self(12);
+    /// `Max-Age`
+    pub const MAX_AGE: Self = Self(14);
+    /// `Uri-Query`
+    pub const URI_QUERY: Self = Self(15);
+    /// `Accept`
+    pub const ACCEPT: Self = Self(17);
+    /// `Location-Query`
+    pub const LOCATION_QUERY: Self = Self(20);
+    /// `Block2`
+    pub const BLOCK2: Self = Self(23);
+    /// `Block1`
+    pub const BLOCK1: Self = Self(27);
+    /// `Size2`
+    pub const SIZE2: Self = Self(28);
+    /// `Proxy-Uri`
+    pub const PROXY_URI: Self = Self(35);
+    /// `Proxy-Scheme`
+    pub const PROXY_SCHEME: Self = Self(39);
+    /// `Size1`
+    pub const SIZE1: Self = Self(60);
+
+    /// Whether a message with the option must be rejected if the option isn't understood.
+    pub const fn is_critical(self) -> bool {
+        self.0 & 1 != 0
+    }
+}
+
+/// Content format of a payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ContentFormat(pub u16);
+
+impl ContentFormat {
+    /// `text/plain; charset=utf-8`
+    pub const TEXT_PLAIN: Self = Self(0);
+    /// `application/link-format`
+    pub const LINK_FORMAT: Self = Self(40);
+    /// `application/xml`
+    pub const XML: Self = Self(41);
+    /// `application/octet-stream`
+    pub const OCTET_STREAM: Self = Self(42);
+    /// `application/json`
+    pub const JSON: Self = Self(50);
+    /// `application/cbor`
+    pub const CBOR: Self = Self(60);
+    /// `application/senml+json`
+    pub const SENML_JSON: Self = Self(110);
+    /// `application/senml+cbor`
+    pub const SENML_CBOR: Self = Self(112);
+}
+
+/// CoAP configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// Time to wait for the acknowledgement of a confirmable message before sending it again.
+    ///
+    /// It's randomized up to 1.5 times longer, and doubles after each retransmission.
+    pub ack_timeout: Duration,
+    /// Number of retransmissions of a confirmable message before giving up.
+    pub max_retransmit: u8,
+    /// Largest block of block-wise transfers, a power of two from 16 to 1024 bytes.
+    ///
+    /// Messages larger than the MTU are fragmented, use smaller blocks to avoid it, such as 64 bytes on
+    /// IEEE 802.15.4 networks.
+    pub block_size: u16,
+}
+
+impl Config {
+    /// Create a new `Config`, with the default transmission parameters of RFC 7252 and 1024 bytes blocks.
+    pub const fn new() -> Self {
+        Self {
+            ack_timeout: Duration::from_secs(2),
+            max_retransmit: 4,
+            block_size: 1024,
+        }
+    }
+
+    /// Size exponent of the largest blocks.
+    fn block_szx(&self) -> u8 {
+        let size = self.block_size.clamp(16, 1024);
+        (15 - size.leading_zeros() - 4) as u8
+    }
+
+    /// Timeout before the first retransmission.
+    fn initial_timeout(&self, rng: &Rng) -> Duration {
+        let ticks = self.ack_timeout.as_ticks();
+        Duration::from_ticks(ticks + ticks * (rng.next() % 512) as u64 / 1024)
+    }
+
+    /// Maximum time between the first transmission of a confirmable message and its last retransmission,
+    /// and the time to wait for its acknowledgement after it.
+    fn max_transmit_wait(&self) -> Duration {
+        let ticks = self.ack_timeout.as_ticks() * ((2 << self.max_retransmit.min(16)) - 1);
+        Duration::from_ticks(ticks * 3 / 2)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Type of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Type {
+    Confirmable,
+    NonConfirmable,
+    Acknowledgement,
+    Reset,
+}
+
+impl Type {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0x03 {
+            0 => Self::Confirmable,
+            1 => Self::NonConfirmable,
+            2 => Self::Acknowledgement,
+            _ => Self::Reset,
+        }
+    }
+}
+
+/// Block option value of a block-wise transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Block {
+    num: u32,
+    more: bool,
+    /// Size exponent, blocks being `16 << szx` bytes long.
+    szx: u8,
+}
+
+impl Block {
+    fn decode(value: u32) -> Option<Self> {
+        let szx = (value & 0x07) as u8;
+        (szx < 7).then_some(Self {
+            num: value >> 4,
+            more: value & 0x08 != 0,
+            szx,
+        })
+    }
+
+    fn encode(self) -> u32 {
+        (self.num << 4) | ((self.more as u32) << 3) | self.szx as u32
+    }
+
+    fn size(self) -> usize {
+        16 << self.szx
+    }
+
+    fn offset(self) -> usize {
+        self.num as usize * self.size()
+    }
+}
+
+/// Iterator over the options of a message, as numbers and values.
+#[derive(Clone)]
+pub struct Options<'a> {
+    buf: &'a [u8],
+    number: u16,
+}
+
+impl<'a> Options<'a> {
+    const fn new(buf: &'a [u8]) -> Self {
+        Self { buf, number: 0 }
+    }
+}
+
+impl<'a> Iterator for Options<'a> {
+    type Item = (OptionNumber, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (delta, value, rest) = parse_option(self.buf)?;
+        self.buf = rest;
+        self.number = self.number.checked_add(delta)?;
+        Some((OptionNumber(self.number), value))
+    }
+}
+
+/// Parse the option at the start of `buf`, and return its delta, its value and the rest of `buf`.
+fn parse_option(buf: &[u8]) -> Option<(u16, &[u8], &[u8])> {
+    let (&first, mut rest) = buf.split_first()?;
+    let delta = extended(first >> 4, &mut rest)?;
+    let len = extended(first & 0x0f, &mut rest)? as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    Some((delta, value, rest))
+}
+
+/// Read the value of an option delta or length, with the extended bytes following the option header.
+fn extended(nibble: u8, rest: &mut &[u8]) -> Option<u16> {
+    match nibble {
+        0..=12 => Some(nibble as u16),
+        13 => {
+            let (&byte, r) = rest.split_first()?;
+            *rest = r;
+            Some(byte as u16 + 13)
+        }
+        14 => {
+            let bytes = rest.get(..2)?;
+            let value = u16::from_be_bytes([bytes[0], bytes[1]]).checked_add(269)?;
+            *rest = &rest[2..];
+            Some(value)
+        }
+        _ => None,
+    }
+}
+
+/// Split an option delta or length into the nibble of the option header and the extended bytes.
+fn split_extended(value: u16) -> (u8, [u8; 2], usize) {
+    match value {
+        0..=12 => (value as u8, [0; 2], 0),
+        13..=268 => (13, [(value - 13) as u8, 0], 1),
+        _ => (14, (value - 269).to_be_bytes(), 2),
+    }
+}
+
+fn parse_uint(value: &[u8]) -> Option<u32> {
+    (value.len() <= 4).then(|| value.iter().fold(0, |acc, &b| (acc << 8) | b as u32))
+}
+
+/// Message parsed from a buffer.
+struct Message<'a> {
+    ty: Type,
+    code: Code,
+    id: u16,
+    token: &'a [u8],
+    options: &'a [u8],
+    payload: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    fn parse(buf: &'a [u8]) -> Option<Self> {
+        let (ty, id) = parse_header(buf)?;
+        let token_len = (buf[0] & 0x0f) as usize;
+        if token_len > MAX_TOKEN_LEN || buf.len() < 4 + token_len {
+            return None;
+        }
+        let code = Code::from_byte(buf[1]);
+        let token = &buf[4..4 + token_len];
+        let rest = &buf[4 + token_len..];
+
+        let mut options = rest;
+        while let Some(&first) = options.first() {
+            if first == PAYLOAD_MARKER {
+                break;
+            }
+            options = parse_option(options)?.2;
+        }
+        let payload = match options.split_first() {
+            // The marker is only sent before a payload.
+            Some((_, [])) => return None,
+            Some((_, payload)) => payload,
+            None => &[],
+        };
+        let options = &rest[..rest.len() - options.len()];
+        if code == Code::EMPTY && buf.len() != 4 {
+            return None;
+        }
+        Some(Self {
+            ty,
+            code,
+            id,
+            token,
+            options,
+            payload,
+        })
+    }
+
+    fn options(&self) -> Options<'a> {
+        Options::new(self.options)
+    }
+
+    fn option(&self, number: OptionNumber) -> Option<&'a [u8]> {
+        self.options().find(|(n, _)| *n == number).map(|(_, value)| value)
+    }
+
+    fn uint_option(&self, number: OptionNumber) -> Option<u32> {
+        self.option(number).and_then(parse_uint)
+    }
+
+    fn block(&self, number: OptionNumber) -> Option<Block> {
+        self.uint_option(number).and_then(Block::decode)
+    }
+}
+
+/// Parse the type and the message ID of a message.
+fn parse_header(buf: &[u8]) -> Option<(Type, u16)> {
+    if buf.len() < 4 || buf[0] >> 6 != VERSION {
+        return None;
+    }
+    Some((Type::from_bits(buf[0] >> 4), u16::from_be_bytes([buf[2], buf[3]])))
+}
+
+/// Writer of a message into a buffer.
+///
+/// Options must be written by increasing number.
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+    number: u16,
+    overflow: bool,
+}
+
+impl<'a> Writer<'a> {
+    fn new(buf: &'a mut [u8], ty: Type, code: Code, id: u16, token: &[u8]) -> Self {
+        let mut writer = Self {
+            buf,
+            pos: 0,
+            number: 0,
+            overflow: false,
+        };
+        writer.bytes(&[(VERSION << 6) | ((ty as u8) << 4) | token.len() as u8, code.to_byte()]);
+        writer.bytes(&id.to_be_bytes());
+        writer.bytes(token);
+        writer
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        match self.buf.get_mut(self.pos..self.pos + data.len()) {
+            Some(buf) => {
+                buf.copy_from_slice(data);
+                self.pos += data.len();
+            }
+            None => self.overflow = true,
+        }
+    }
+
+    fn option(&mut self, number: OptionNumber, value: &[u8]) {
+        debug_assert!(number.0 >= self.number);
+        let (delta, delta_ext, delta_len) = split_extended(number.0 - self.number);
+        let (len, len_ext, len_len) = split_extended(value.len().min(u16::MAX as usize) as u16);
+        self.number = number.0;
+        self.bytes(&[(delta << 4) | len]);
+        self.bytes(&delta_ext[..delta_len]);
+        self.bytes(&len_ext[..len_len]);
+        self.bytes(value);
+    }
+
+    fn uint_option(&mut self, number: OptionNumber, value: u32) {
+        let bytes = value.to_be_bytes();
+        self.option(number, &bytes[value.leading_zeros() as usize / 8..]);
+    }
+
+    /// Write the segments of `path` as options, separated by `separator`.
+    fn segments(&mut self, number: OptionNumber, path: &str, separator: char) {
+        let path = path.strip_prefix(separator).unwrap_or(path);
+        if !path.is_empty() {
+            for segment in path.split(separator) {
+                self.option(number, segment.as_bytes());
+            }
+        }
+    }
+
+    fn payload(&mut self, payload: &[u8]) {
+        if !payload.is_empty() {
+            self.bytes(&[PAYLOAD_MARKER]);
+            self.bytes(payload);
+        }
+    }
+
+    fn finish(&self) -> Result<usize, Error> {
+        match self.overflow {
+            true => Err(Error::TooLarge),
+            false => Ok(self.pos),
+        }
+    }
+}
+
+/// Pseudo-random numbers for message IDs, tokens and retransmission timeouts.
+struct Rng(Cell<u32>);
+
+impl Rng {
+    fn new(stack: Stack<'_>) -> Self {
+        let seed = stack.random_seed() ^ Instant::now().as_ticks();
+        Self(Cell::new((seed as u32 ^ (seed >> 32) as u32) | 1))
+    }
+
+    fn next(&self) -> u32 {
+        // xorshift32
+        let mut x = self.0.get();
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0.set(x);
+        x
+    }
+}
+
+async fn send_empty(socket: &UdpSocket<'_>, ty: Type, id: u16, remote: IpEndpoint) {
+    let [id_hi, id_lo] = id.to_be_bytes();
+    send(socket, &[(VERSION << 6) | ((ty as u8) << 4), 0, id_hi, id_lo], remote).await;
+}
+
+async fn send(socket: &UdpSocket<'_>, message: &[u8], remote: IpEndpoint) {
+    if let Err(e) = socket.send_to(message, remote).await {
+        warn!("coap: failed to send to {}: {:?}", remote, e);
+    }
+}
+
+/// Handler of the requests of a [`CoapServer`].
+pub trait Handler {
+    /// Handle `request`, writing the payload of the response to `response`, and return the response code.
+    ///
+    /// The payload is the whole representation of the resource, the server sends it block-wise if it's
+    /// larger than the block size, calling the handler again for each block. The payload of block-wise
+    /// requests is passed whole too, also when the next blocks of their response are requested.
+    ///
+    /// The handler is also called to notify the observers of a resource, with a `GET` request without
+    /// payload for which [`Request::is_notification`] is true.
+    async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code;
+}
+
+impl<T: Handler> Handler for &T {
+    async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code {
+        T::handle(self, request, response).await
+    }
+}
+
+/// CoAP request.
+pub struct Request<'r> {
+    method: Method,
+    path: &'r str,
+    query: &'r str,
+    params: crate::router::Params<'r>,
+    options: Options<'r>,
+    payload: &'r [u8],
+    source: IpEndpoint,
+    notification: bool,
+}
+
+impl<'r> Request<'r> {
+    /// Request method.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Request path, the `Uri-Path` options joined by `/`.
+    pub fn path(&self) -> &'r str {
+        self.path
+    }
+
+    /// Request query, the `Uri-Query` options joined by `&`.
+    pub fn query(&self) -> Option<&'r str> {
+        (!self.query.is_empty()).then_some(self.query)
+    }
+
+    /// Value of the query parameter `name`, such as `on` for `state=on`.
+    ///
+    /// Returns an empty string for a parameter without a value.
+    pub fn query_param(&self, name: &str) -> Option<&'r str> {
+        self.query.split('&').find_map(|param| match param.split_once('=') {
+            Some((n, value)) if n == name => Some(value),
+            None if param == name => Some(""),
+            _ => None,
+        })
+    }
+
+    /// Value of the path parameter `name`, captured by the [`Route`] that matched the request.
+    ///
+    /// The rest of the path matched by a trailing `*` is the parameter `*`.
+    pub fn param(&self, name: &str) -> Option<&'r str> {
+        self.params.iter().find(|(n, _)| *n == name).map(|(_, value)| *value)
+    }
+
+    /// Request options.
+    pub fn options(&self) -> Options<'r> {
+        self.options.clone()
+    }
+
+    /// Content format of the payload.
+    pub fn content_format(&self) -> Option<ContentFormat> {
+        self.uint_option(OptionNumber::CONTENT_FORMAT)
+            .map(|value| ContentFormat(value as u16))
+    }
+
+    /// Content format accepted in the response.
+    pub fn accept(&self) -> Option<ContentFormat> {
+        self.uint_option(OptionNumber::ACCEPT)
+            .map(|value| ContentFormat(value as u16))
+    }
+
+    /// Request payload.
+    pub fn payload(&self) -> &'r [u8] {
+        self.payload
+    }
+
+    /// Endpoint of the client.
+    pub fn source(&self) -> IpEndpoint {
+        self.source
+    }
+
+    /// Whether the request is made by the server to notify an observer.
+    pub fn is_notification(&self) -> bool {
+        self.notification
+    }
+
+    fn uint_option(&self, number: OptionNumber) -> Option<u32> {
+        self.options()
+            .find(|(n, _)| *n == number)
+            .and_then(|(_, value)| parse_uint(value))
+    }
+}
+
+/// CoAP response, written by a [`Handler`].
+pub struct Response<'r> {
+    buf: &'r mut [u8],
+    len: usize,
+    content_format: Option<ContentFormat>,
+    max_age: Option<u32>,
+    etag: Vec<u8, 8>,
+}
+
+impl<'r> Response<'r> {
+    fn new(buf: &'r mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            content_format: None,
+            max_age: None,
+            etag: Vec::new(),
+        }
+    }
+
+    /// Set the content format of the payload.
+    pub fn set_content_format(&mut self, content_format: ContentFormat) {
+        self.content_format = Some(content_format);
+    }
+
+    /// Set how long the response can be cached, in seconds. It's 60 seconds by default.
+    pub fn set_max_age(&mut self, seconds: u32) {
+        self.max_age = Some(seconds);
+    }
+
+    /// Set the entity tag of the representation, of up to 8 bytes.
+    pub fn set_etag(&mut self, etag: &[u8]) -> Result<(), Error> {
+        self.etag = Vec::from_slice(etag).map_err(|_| Error::TooLarge)?;
+        Ok(())
+    }
+
+    /// Append `data` to the payload.
+    ///
+    /// Returns [`Error::TooLarge`] if it doesn't fit the buffer of the server.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        let buf = self
+            .buf
+            .get_mut(self.len..self.len + data.len())
+            .ok_or(Error::TooLarge)?;
+        buf.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    /// Payload written so far.
+    pub fn payload(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Write the options and the payload, or the block of the payload selected by `block2`.
+    fn write_to(&self, writer: &mut Writer, observe: Option<u32>, block2: Option<Block>, block1: Option<Block>) {
+        if !self.etag.is_empty() {
+            writer.option(OptionNumber::ETAG, &self.etag);
+        }
+        if let Some(seq) = observe {
+            writer.uint_option(OptionNumber::OBSERVE, seq);
+        }
+        if let Some(content_format) = self.content_format {
+            writer.uint_option(OptionNumber::CONTENT_FORMAT, content_format.0 as u32);
+        }
+        if let Some(max_age) = self.max_age {
+            writer.uint_option(OptionNumber::MAX_AGE, max_age);
+        }
+        if let Some(block) = block2 {
+            writer.uint_option(OptionNumber::BLOCK2, block.encode());
+        }
+        if let Some(block) = block1 {
+            writer.uint_option(OptionNumber::BLOCK1, block.encode());
+        }
+        match block2 {
+            Some(block) => {
+                if block.num == 0 {
+                    writer.uint_option(OptionNumber::SIZE2, self.len as u32);
+                }
+                let start = block.offset();
+                writer.payload(&self.payload()[start..self.len.min(start + block.size())]);
+            }
+            None => writer.payload(self.payload()),
+        }
+    }
+}
+
+impl core::fmt::Write for Response<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write(s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+/// Select the block of a `len` bytes payload requested by `requested`, or the first block if the payload
+/// doesn't fit in one.
+///
+/// Returns `Err` if the requested block is past the end of the payload.
+fn select_block(requested: Option<Block>, len: usize, max_szx: u8) -> Result<Option<Block>, ()> {
+    let block = match requested {
+        Some(block) => {
+            // Larger blocks than requested are never sent, smaller ones cover the same offset.
+            let szx = block.szx.min(max_szx);
+            Block {
+                num: block.num << (block.szx - szx),
+                more: false,
+                szx,
+            }
+        }
+        None if len > 16 << max_szx => Block {
+            num: 0,
+            more: false,
+            szx: max_szx,
+        },
+        None => return Ok(None),
+    };
+    let start = block.offset();
+    if start > len || (start == len && start != 0) {
+        return Err(());
+    }
+    Ok(Some(Block {
+        more: start + block.size() < len,
+        ..block
+    }))
+}
+
+/// State for [`CoapServer`], receiving block-wise request payloads of up to `N` bytes.
+pub struct CoapServerState<const N: usize = 1024> {
+    rx_meta: [PacketMetadata; 4],
+    rx_buffer: [u8; 2 * MAX_MESSAGE_LEN],
+    tx_meta: [PacketMetadata; 2],
+    tx_buffer: [u8; MAX_MESSAGE_LEN],
+    body: [u8; N],
+}
+
+impl<const N: usize> CoapServerState<N> {
+    /// Create a new `CoapServerState`.
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 4],
+            rx_buffer: [0; 2 * MAX_MESSAGE_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 2],
+            tx_buffer: [0; MAX_MESSAGE_LEN],
+            body: [0; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for CoapServerState<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Client observing a resource.
+#[derive(Clone)]
+struct Observer {
+    endpoint: IpEndpoint,
+    token: Vec<u8, MAX_TOKEN_LEN>,
+    path: String<MAX_PATH_LEN>,
+    query: String<MAX_PATH_LEN>,
+    /// Sequence number of the last notification.
+    seq: u32,
+    /// ID of the last notification.
+    last_id: Option<u16>,
+    changed: bool,
+    /// Notifications sent as non-confirmable since the last confirmable one.
+    non_confirmable: u8,
+    /// Retransmission of the last notification, if it's confirmable and not acknowledged yet.
+    retransmission: Option<Retransmission>,
+}
+
+#[derive(Clone, Copy)]
+struct Retransmission {
+    deadline: Instant,
+    timeout: Duration,
+    count: u8,
+}
+
+/// Block-wise request being received, or received last.
+#[derive(Clone, Copy)]
+struct Transfer {
+    source: IpEndpoint,
+    /// Hash of the method, path and query of the request.
+    hash: u32,
+    len: usize,
+    /// Whether the whole payload was received, and is kept for the requests of the next blocks of the response.
+    complete: bool,
+}
+
+struct Inner {
+    observers: Vec<Observer, MAX_OBSERVERS>,
+    waker: WakerRegistration,
+}
+
+/// Last response to a confirmable request, kept to answer its duplicates.
+#[derive(Clone, Copy)]
+struct LastResponse {
+    source: IpEndpoint,
+    id: u16,
+    len: usize,
+}
+
+/// CoAP server.
+pub struct CoapServer<'d> {
+    socket: UdpSocket<'d>,
+    config: Config,
+    rng: Rng,
+    /// Message ID of the next message, counting from a random start.
+    next_id: Cell<u16>,
+    /// Buffer for the payload of block-wise requests, lent to [`CoapServer::run`].
+    body: Cell<Option<&'d mut [u8]>>,
+    transfer: Cell<Option<Transfer>>,
+    inner: RefCell<Inner>,
+}
+
+impl<'d> CoapServer<'d> {
+    /// Create a new server, listening on `endpoint`, such as [`PORT`].
+    pub fn new<T, const N: usize>(
+        stack: Stack<'d>,
+        state: &'d mut CoapServerState<N>,
+        endpoint: T,
+        config: Config,
+    ) -> Self
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut state.rx_meta,
+            &mut state.rx_buffer,
+            &mut state.tx_meta,
+            &mut state.tx_buffer,
+        );
+        unwrap!(socket.bind(endpoint));
+        let rng = Rng::new(stack);
+        Self {
+            socket,
+            config,
+            next_id: Cell::new(rng.next() as u16),
+            rng,
+            body: Cell::new(Some(&mut state.body)),
+            transfer: Cell::new(None),
+            inner: RefCell::new(Inner {
+                observers: Vec::new(),
+                waker: WakerRegistration::new(),
+            }),
+        }
+    }
+
+    /// Notify the observers of the resource at `path` that it changed.
+    ///
+    /// The handler is called again for each of them, to send the new representation of the resource.
+    pub fn notify(&self, path: &str) {
+        let mut inner = self.inner.borrow_mut();
+        let mut observed = false;
+        for observer in inner.observers.iter_mut().filter(|o| o.path == path) {
+            observer.changed = true;
+            observed = true;
+        }
+        if observed {
+            inner.waker.wake();
+        }
+    }
+
+    /// Whether clients observe the resource at `path`.
+    pub fn is_observed(&self, path: &str) -> bool {
+        self.inner.borrow().observers.iter().any(|o| o.path == path)
+    }
+
+    /// Run the server.
+    ///
+    /// The handler writes the payload of responses to `buf`, which bounds their size.
+    pub async fn run<H: Handler>(&self, handler: &H, buf: &mut [u8]) -> ! {
+        let mut rx = [0; MAX_MESSAGE_LEN];
+        let mut tx = [0; MAX_MESSAGE_LEN];
+        let mut last = None;
+        let mut body = Lent {
+            cell: &self.body,
+            body: self.body.take(),
+        };
+        let body = unwrap!(body.body.as_deref_mut());
+        loop {
+            match self.wait(&mut rx).await {
+                Some(Ok((n, meta))) => {
+                    self.receive(handler, &rx[..n], meta.endpoint, buf, body, &mut tx, &mut last)
+                        .await
+                }
+                Some(Err(RecvError::Truncated)) => warn!("coap: dropped a message larger than the buffer"),
+                // Notifications are written to `rx`, to keep the last response in `tx`.
+                None => self.notify_observers(handler, buf, &mut rx).await,
+            }
+        }
+    }
+
+    /// Wait for a message, or for notifications to send.
+    async fn wait(&self, rx: &mut [u8]) -> Option<Result<(usize, UdpMetadata), RecvError>> {
+        let deadline = {
+            let inner = self.inner.borrow();
+            let retransmissions = inner.observers.iter().filter_map(|o| o.retransmission);
+            retransmissions.map(|r| r.deadline).min().unwrap_or(Instant::MAX)
+        };
+        let received = poll_fn(|cx| {
+            {
+                let mut inner = self.inner.borrow_mut();
+                if inner.observers.iter().any(|o| o.changed && o.retransmission.is_none()) {
+                    return Poll::Ready(None);
+                }
+                inner.waker.register(cx.waker());
+            }
+            self.socket.poll_recv_from(rx, cx).map(Some)
+        });
+        with_deadline(deadline, received).await.unwrap_or(None)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn receive<H: Handler>(
+        &self,
+        handler: &H,
+        data: &[u8],
+        source: IpEndpoint,
+        buf: &mut [u8],
+        body: &mut [u8],
+        tx: &mut [u8],
+        last: &mut Option<LastResponse>,
+    ) {
+        let Some(request) = Message::parse(data) else {
+            // Malformed confirmable messages are rejected, others are ignored.
+            if let Some((Type::Confirmable, id)) = parse_header(data) {
+                send_empty(&self.socket, Type::Reset, id, source).await;
+            }
+            return;
+        };
+        let method = match request.ty {
+            Type::Acknowledgement | Type::Reset => {
+                self.acknowledged(source, request.id, request.ty == Type::Reset);
+                return;
+            }
+            _ => Method::from_code(request.code),
+        };
+        let Some(method) = method else {
+            // Empty confirmable messages are pings, answered with a reset like other unexpected messages.
+            if request.ty == Type::Confirmable {
+                send_empty(&self.socket, Type::Reset, request.id, source).await;
+            }
+            return;
+        };
+        if request.ty == Type::Confirmable {
+            if let Some(response) = *last {
+                if response.source == source && response.id == request.id {
+                    send(&self.socket, &tx[..response.len], source).await;
+                    return;
+                }
+            }
+        }
+
+        let Some((path, query)) = uri(&request) else {
+            return self.respond(&request, source, tx, last, Code::BAD_OPTION, |_| {}).await;
+        };
+        if request.options().any(|(number, _)| !understood(number)) {
+            return self.respond(&request, source, tx, last, Code::BAD_OPTION, |_| {}).await;
+        }
+
+        let mut block1 = None;
+        let payload = match request.block(OptionNumber::BLOCK1) {
+            Some(block) => match self.receive_block(&request, block, method, &path, &query, source, body) {
+                Ok(Some(len)) => {
+                    block1 = Some(block);
+                    &body[..len]
+                }
+                Ok(None) => {
+                    // Ask for the next block, possibly smaller.
+                    let block = Block {
+                        szx: block.szx.min(self.config.block_szx()),
+                        ..block
+                    };
+                    return self
+                        .respond(&request, source, tx, last, Code::CONTINUE, |w| {
+                            w.uint_option(OptionNumber::BLOCK1, block.encode())
+                        })
+                        .await;
+                }
+                Err(code) => {
+                    let size = body.len() as u32;
+                    return self
+                        .respond(&request, source, tx, last, code, |w| {
+                            if code == Code::REQUEST_ENTITY_TOO_LARGE {
+                                w.uint_option(OptionNumber::SIZE1, size);
+                            }
+                        })
+                        .await;
+                }
+            },
+            None => self
+                .received_payload(&request, method, &path, &query, source, body)
+                .unwrap_or(request.payload),
+        };
+
+        let mut req = Request {
+            method,
+            path: &path,
+            query: &query,
+            params: Vec::new(),
+            options: request.options(),
+            payload,
+            source,
+            notification: false,
+        };
+        let mut response = Response::new(buf);
+        let code = handler.handle(&mut req, &mut response).await;
+
+        let requested = request.block(OptionNumber::BLOCK2);
+        let Ok(block2) = select_block(requested, response.len, self.config.block_szx()) else {
+            return self.respond(&request, source, tx, last, Code::BAD_OPTION, |_| {}).await;
+        };
+
+        // Requests for the next blocks of a representation don't change the observation.
+        let mut observe = None;
+        if method == Method::Get && requested.is_none_or(|b| b.num == 0) {
+            match request.uint_option(OptionNumber::OBSERVE) {
+                Some(0) if code.is_success() => observe = self.register(source, request.token, &path, &query),
+                _ => self.deregister(source, request.token),
+            }
+        }
+
+        self.respond(&request, source, tx, last, code, |w| {
+            response.write_to(w, observe, block2, block1)
+        })
+        .await;
+    }
+
+    /// Receive a block of a block-wise request.
+    ///
+    /// Returns the length of the payload once it's complete, or `None` if more blocks are expected.
+    #[allow(clippy::too_many_arguments)]
+    fn receive_block(
+        &self,
+        request: &Message<'_>,
+        block: Block,
+        method: Method,
+        path: &str,
+        query: &str,
+        source: IpEndpoint,
+        body: &mut [u8],
+    ) -> Result<Option<usize>, Code> {
+        let hash = fnv1a(&[&[method as u8], path.as_bytes(), query.as_bytes()]);
+        let transfer = match self.transfer.get() {
+            _ if block.num == 0 => {
+                if let Some(size) = request.uint_option(OptionNumber::SIZE1) {
+                    if size as usize > body.len() {
+                        return Err(Code::REQUEST_ENTITY_TOO_LARGE);
+                    }
+                }
+                Transfer {
+                    source,
+                    hash,
+                    len: 0,
+                    complete: false,
+                }
+            }
+            Some(t) if !t.complete && t.source == source && t.hash == hash && t.len == block.offset() => t,
+            _ => return Err(Code::REQUEST_ENTITY_INCOMPLETE),
+        };
+        let payload = request.payload;
+        if block.more && payload.len() != block.size() {
+            return Err(Code::BAD_REQUEST);
+        }
+        let Some(dest) = body.get_mut(transfer.len..transfer.len + payload.len()) else {
+            self.transfer.set(None);
+            return Err(Code::REQUEST_ENTITY_TOO_LARGE);
+        };
+        dest.copy_from_slice(payload);
+        let len = transfer.len + payload.len();
+        self.transfer.set(Some(Transfer {
+            len,
+            complete: !block.more,
+            ..transfer
+        }));
+        Ok((!block.more).then_some(len))
+    }
+
+    /// Get the payload of the last block-wise request, for the requests of the next blocks of its response.
+    ///
+    /// These don't repeat the payload (RFC 7959, section 3.2).
+    fn received_payload<'b>(
+        &self,
+        request: &Message<'_>,
+        method: Method,
+        path: &str,
+        query: &str,
+        source: IpEndpoint,
+        body: &'b [u8],
+    ) -> Option<&'b [u8]> {
+        let transfer = self.transfer.get()?;
+        let hash = fnv1a(&[&[method as u8], path.as_bytes(), query.as_bytes()]);
+        let next_block = request.block(OptionNumber::BLOCK2).is_some_and(|b| b.num > 0);
+        (next_block
+            && request.payload.is_empty()
+            && transfer.complete
+            && transfer.source == source
+            && transfer.hash == hash)
+            .then(|| &body[..transfer.len])
+    }
+
+    /// Send a response to `request`, with options and payload written by `f`.
+    async fn respond(
+        &self,
+        request: &Message<'_>,
+        source: IpEndpoint,
+        tx: &mut [u8],
+        last: &mut Option<LastResponse>,
+        code: Code,
+        f: impl FnOnce(&mut Writer),
+    ) {
+        // Responses to confirmable requests are piggybacked on their acknowledgement.
+        let (ty, id) = match request.ty {
+            Type::Confirmable => (Type::Acknowledgement, request.id),
+            _ => (Type::NonConfirmable, self.next_id()),
+        };
+        let mut writer = Writer::new(tx, ty, code, id, request.token);
+        f(&mut writer);
+        let len = match writer.finish() {
+            Ok(len) => len,
+            Err(_) => unwrap!(Writer::new(tx, ty, Code::INTERNAL_SERVER_ERROR, id, request.token).finish()),
+        };
+        *last = (request.ty == Type::Confirmable).then_some(LastResponse {
+            source,
+            id: request.id,
+            len,
+        });
+        send(&self.socket, &tx[..len], source).await;
+    }
+
+    /// Allocate a message ID.
+    ///
+    /// IDs are sequential, so that they aren't reused before the peers forget them (RFC 7252, section 4.4).
+    fn next_id(&self) -> u16 {
+        let id = self.next_id.get();
+        self.next_id.set(id.wrapping_add(1));
+        id
+    }
+
+    /// Register an observer, and return the sequence number of the response.
+    fn register(&self, source: IpEndpoint, token: &[u8], path: &str, query: &str) -> Option<u32> {
+        let mut inner = self.inner.borrow_mut();
+        let mut observer = Observer {
+            endpoint: source,
+            token: Vec::from_slice(token).ok()?,
+            path: String::try_from(path).ok()?,
+            query: String::try_from(query).ok()?,
+            seq: 0,
+            last_id: None,
+            changed: false,
+            non_confirmable: 0,
+            retransmission: None,
+        };
+        // A client observes a resource once, registering again replaces the token.
+        let existing = inner
+            .observers
+            .iter()
+            .position(|o| o.endpoint == source && (o.token == token || (o.path == path && o.query == query)));
+        match existing {
+            Some(i) => {
+                observer.seq = inner.observers[i].seq;
+                inner.observers[i] = observer;
+                Some(inner.observers[i].seq)
+            }
+            None => {
+                inner.observers.push(observer).ok()?;
+                debug!("coap: {} observes {}", source, path);
+                Some(0)
+            }
+        }
+    }
+
+    fn deregister(&self, source: IpEndpoint, token: &[u8]) {
+        let mut inner = self.inner.borrow_mut();
+        inner.observers.retain(|o| !(o.endpoint == source && o.token == token));
+    }
+
+    /// Handle an acknowledgement or reset of a notification.
+    fn acknowledged(&self, source: IpEndpoint, id: u16, reset: bool) {
+        let mut inner = self.inner.borrow_mut();
+        let Some(i) = inner
+            .observers
+            .iter()
+            .position(|o| o.endpoint == source && o.last_id == Some(id))
+        else {
+            return;
+        };
+        if reset {
+            inner.observers.remove(i);
+        } else {
+            inner.observers[i].retransmission = None;
+        }
+    }
+
+    /// Send the notifications of changed resources, and retransmit the unacknowledged ones.
+    async fn notify_observers<H: Handler>(&self, handler: &H, buf: &mut [u8], out: &mut [u8]) {
+        let now = Instant::now();
+        let mut i = 0;
+        loop {
+            let observer = {
+                let mut inner = self.inner.borrow_mut();
+                let Some(observer) = inner.observers.get_mut(i) else {
+                    break;
+                };
+                let due = match observer.retransmission {
+                    Some(r) => r.deadline <= now,
+                    None => observer.changed,
+                };
+                if !due {
+                    i += 1;
+                    continue;
+                }
+                if observer
+                    .retransmission
+                    .is_some_and(|r| r.count >= self.config.max_retransmit)
+                {
+                    debug!("coap: {} stopped observing {}", observer.endpoint, observer.path);
+                    inner.observers.remove(i);
+                    continue;
+                }
+                observer.changed = false;
+                observer.clone()
+            };
+
+            let mut request = Request {
+                method: Method::Get,
+                path: &observer.path,
+                query: &observer.query,
+                params: Vec::new(),
+                options: Options::new(&[]),
+                payload: &[],
+                source: observer.endpoint,
+                notification: true,
+            };
+            let mut response = Response::new(buf);
+            let code = handler.handle(&mut request, &mut response).await;
+
+            // Notifications are retransmitted with the current representation, instead of the initial one.
+            let confirmable = observer.retransmission.is_some() || observer.non_confirmable >= MAX_NON_NOTIFICATIONS;
+            let ty = if confirmable {
+                Type::Confirmable
+            } else {
+                Type::NonConfirmable
+            };
+            let id = self.next_id();
+            let seq = (observer.seq + 1) & 0x00ff_ffff;
+            let mut writer = Writer::new(out, ty, code, id, &observer.token);
+            let block2 = unwrap!(select_block(None, response.len, self.config.block_szx()));
+            // Error responses end the observation.
+            response.write_to(&mut writer, code.is_success().then_some(seq), block2, None);
+            if let Ok(len) = writer.finish() {
+                send(&self.socket, &out[..len], observer.endpoint).await;
+            }
+
+            let mut inner = self.inner.borrow_mut();
+            if !code.is_success() {
+                inner.observers.remove(i);
+                continue;
+            }
+            let o = &mut inner.observers[i];
+            o.seq = seq;
+            o.last_id = Some(id);
+            if confirmable {
+                let (timeout, count) = match o.retransmission {
+                    Some(r) => (r.timeout * 2, r.count + 1),
+                    None => (self.config.initial_timeout(&self.rng), 0),
+                };
+                o.retransmission = Some(Retransmission {
+                    deadline: now + timeout,
+                    timeout,
+                    count,
+                });
+                o.non_confirmable = 0;
+            } else {
+                o.non_confirmable += 1;
+            }
+            i += 1;
+        }
+    }
+}
+
+/// Buffer taken from a cell, and put back when dropped.
+struct Lent<'a, 'd> {
+    cell: &'a Cell<Option<&'d mut [u8]>>,
+    body: Option<&'d mut [u8]>,
+}
+
+impl Drop for Lent<'_, '_> {
+    fn drop(&mut self) {
+        self.cell.set(self.body.take());
+    }
+}
+
+/// Join the `Uri-Path` and `Uri-Query` options of a request.
+fn uri(request: &Message<'_>) -> Option<(String<MAX_PATH_LEN>, String<MAX_PATH_LEN>)> {
+    let mut path = String::new();
+    let mut query = String::new();
+    for (number, value) in request.options() {
+        let (uri, separator) = match number {
+            OptionNumber::URI_PATH => (&mut path, Some('/')),
+            OptionNumber::URI_QUERY if query.is_empty() => (&mut query, None),
+            OptionNumber::URI_QUERY => (&mut query, Some('&')),
+            _ => continue,
+        };
+        if let Some(separator) = separator {
+            uri.push(separator).ok()?;
+        }
+        uri.push_str(core::str::from_utf8(value).ok()?).ok()?;
+    }
+    if path.is_empty() {
+        path.push('/').ok()?;
+    }
+    Some((path, query))
+}
+
+/// Whether the server understands the option, or can ignore it.
+///
+/// The conditional request options are left to the handlers.
+fn understood(number: OptionNumber) -> bool {
+    !number.is_critical()
+        || matches!(
+            number,
+            OptionNumber::IF_MATCH
+                | OptionNumber::URI_HOST
+                | OptionNumber::IF_NONE_MATCH
+                | OptionNumber::URI_PORT
+                | OptionNumber::URI_PATH
+                | OptionNumber::URI_QUERY
+                | OptionNumber::ACCEPT
+                | OptionNumber::BLOCK2
+                | OptionNumber::BLOCK1
+        )
+}
+
+fn fnv1a(parts: &[&[u8]]) -> u32 {
+    parts
+        .iter()
+        .flat_map(|part| part.iter())
+        .fold(0x811c_9dc5, |hash, &byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+}
+
+/// Route of a [`Router`].
+///
+/// See [`router`](crate::router) for the patterns.
+pub type Route<H> = crate::router::Route<Method, H>;
+
+/// Handler dispatching requests to the first [`Route`] matching their method and path.
+///
+/// Requests matching no route are answered with `4.04 Not Found`, or `4.05 Method Not Allowed` if only
+/// the method doesn't match. `GET /.well-known/core` is answered with the links to the routes without
+/// parameters, unless a route matches it.
+pub type Router<'a, H> = crate::router::Router<'a, Method, H>;
+
+/// Write the links to the routes without parameters of `router`, in the CoRE link format.
+fn write_links<H>(router: &Router<'_, H>, response: &mut Response<'_>) -> Code {
+    response.set_content_format(ContentFormat::LINK_FORMAT);
+    let mut first = true;
+    for (i, route) in router.routes.iter().enumerate() {
+        let listed = router.routes[..i].iter().any(|r| r.pattern == route.pattern);
+        if listed || route.pattern.contains(['{', '*']) {
+            continue;
+        }
+        let separator: &[u8] = if first { b"<" } else { b",<" };
+        first = false;
+        let written = [separator, route.pattern.as_bytes(), b">"]
+            .iter()
+            .try_for_each(|part| response.write(part));
+        if written.is_err() {
+            return Code::INTERNAL_SERVER_ERROR;
+        }
+    }
+    Code::CONTENT
+}
+
+impl<H: Handler> Handler for Router<'_, H> {
+    async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code {
+        let mut path_matched = false;
+        for (route, params) in self.matching(request.path) {
+            if route.method == request.method {
+                request.params = params;
+                return route.handler.handle(request, response).await;
+            }
+            path_matched = true;
+        }
+
+        if path_matched {
+            Code::METHOD_NOT_ALLOWED
+        } else if request.method == Method::Get && request.path == WELL_KNOWN_CORE {
+            write_links(self, response)
+        } else {
+            Code::NOT_FOUND
+        }
+    }
+}
+
+pub mod client {
+    //! CoAP client.
+
+    use super::*;
+
+    /// Length of the tokens of the requests.
+    const TOKEN_LEN: usize = 4;
+
+    type Token = [u8; TOKEN_LEN];
+
+    /// State for [`CoapClient`].
+    pub struct CoapClientState {
+        rx_meta: [PacketMetadata; 2],
+        rx_buffer: [u8; 2 * MAX_MESSAGE_LEN],
+        tx_meta: [PacketMetadata; 1],
+        tx_buffer: [u8; MAX_MESSAGE_LEN],
+    }
+
+    impl CoapClientState {
+        /// Create a new `CoapClientState`.
+        pub const fn new() -> Self {
+            Self {
+                rx_meta: [PacketMetadata::EMPTY; 2],
+                rx_buffer: [0; 2 * MAX_MESSAGE_LEN],
+                tx_meta: [PacketMetadata::EMPTY; 1],
+                tx_buffer: [0; MAX_MESSAGE_LEN],
+            }
+        }
+    }
+
+    impl Default for CoapClientState {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Response to a request, or notification of an observed resource.
+    #[derive(Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub struct Response<'b> {
+        /// Response code.
+        pub code: Code,
+        /// Content format of the payload.
+        pub content_format: Option<ContentFormat>,
+        /// Response payload, all its blocks put together.
+        pub payload: &'b [u8],
+    }
+
+    /// CoAP client.
+    ///
+    /// Requests are confirmable, and sent one at a time. Payloads larger than the block size are sent
+    /// block-wise, and the blocks of responses are requested until the payload is complete.
+    pub struct CoapClient<'d> {
+        socket: UdpSocket<'d>,
+        config: Config,
+        rng: Rng,
+        /// Message ID of the next message, counting from a random start.
+        next_id: Cell<u16>,
+        /// Last confirmable response, acknowledged again if it's retransmitted.
+        last_response: Option<(IpEndpoint, u16)>,
+    }
+
+    impl<'d> CoapClient<'d> {
+        /// Create a new client, on an ephemeral port.
+        pub fn new(stack: Stack<'d>, state: &'d mut CoapClientState, config: Config) -> Self {
+            let mut socket = UdpSocket::new(
+                stack,
+                &mut state.rx_meta,
+                &mut state.rx_buffer,
+                &mut state.tx_meta,
+                &mut state.tx_buffer,
+            );
+            unwrap!(socket.bind(0));
+            let rng = Rng::new(stack);
+            Self {
+                socket,
+                config,
+                next_id: Cell::new(rng.next() as u16),
+                rng,
+                last_response: None,
+            }
+        }
+
+        /// Send a `GET` request for `path`, which may have a query after a `?`, and read the response
+        /// payload to `buf`.
+        pub async fn get<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            path: &str,
+            buf: &'b mut [u8],
+        ) -> Result<Response<'b>, Error> {
+            self.request(remote, Method::Get, path, &[], None, buf).await
+        }
+
+        /// Send a `POST` request with `payload`.
+        pub async fn post<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            path: &str,
+            payload: &[u8],
+            content_format: Option<ContentFormat>,
+            buf: &'b mut [u8],
+        ) -> Result<Response<'b>, Error> {
+            self.request(remote, Method::Post, path, payload, content_format, buf)
+                .await
+        }
+
+        /// Send a `PUT` request with `payload`.
+        pub async fn put<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            path: &str,
+            payload: &[u8],
+            content_format: Option<ContentFormat>,
+            buf: &'b mut [u8],
+        ) -> Result<Response<'b>, Error> {
+            self.request(remote, Method::Put, path, payload, content_format, buf)
+                .await
+        }
+
+        /// Send a `DELETE` request.
+        pub async fn delete<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            path: &str,
+            buf: &'b mut [u8],
+        ) -> Result<Response<'b>, Error> {
+            self.request(remote, Method::Delete, path, &[], None, buf).await
+        }
+
+        /// Send a request, and read the response payload to `buf`.
+        pub async fn request<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            method: Method,
+            path: &str,
+            payload: &[u8],
+            content_format: Option<ContentFormat>,
+            buf: &'b mut [u8],
+        ) -> Result<Response<'b>, Error> {
+            let token = self.token();
+            let (response, _) = self
+                .exchange(remote, method, path, payload, content_format, token, None, buf)
+                .await?;
+            Ok(response)
+        }
+
+        /// Observe the resource at `path`, returning its current representation.
+        ///
+        /// The notifications are then received with [`Observation::next`]. If the server doesn't let the
+        /// resource be observed, it returns [`Error::NotObserved`].
+        pub async fn observe<'a, 'b>(
+            &'a mut self,
+            remote: IpEndpoint,
+            path: &'a str,
+            buf: &'b mut [u8],
+        ) -> Result<(Observation<'a, 'd>, Response<'b>), Error> {
+            let token = self.token();
+            let (response, seq) = self
+                .exchange(remote, Method::Get, path, &[], None, token, Some(0), buf)
+                .await?;
+            let last = seq
+                .filter(|_| response.code.is_success())
+                .map(|seq| (seq, Instant::now()));
+            let observation = Observation {
+                client: self,
+                remote,
+                path,
+                token,
+                last,
+            };
+            Ok((observation, response))
+        }
+
+        fn token(&self) -> Token {
+            self.rng.next().to_be_bytes()
+        }
+
+        /// Allocate a message ID, see [`CoapServer::next_id`].
+        fn next_id(&self) -> u16 {
+            let id = self.next_id.get();
+            self.next_id.set(id.wrapping_add(1));
+            id
+        }
+
+        /// Send a request, block-wise if needed, and receive the response.
+        ///
+        /// Returns the response, and the value of its `Observe` option.
+        #[allow(clippy::too_many_arguments)]
+        async fn exchange<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            method: Method,
+            path: &str,
+            payload: &[u8],
+            content_format: Option<ContentFormat>,
+            token: Token,
+            observe: Option<u32>,
+            buf: &'b mut [u8],
+        ) -> Result<(Response<'b>, Option<u32>), Error> {
+            let (path, query) = path.split_once('?').unwrap_or((path, ""));
+            let mut tx = [0; MAX_MESSAGE_LEN];
+            let mut rx = [0; MAX_MESSAGE_LEN];
+            let mut szx = self.config.block_szx();
+            let mut offset = 0;
+            let n = loop {
+                let size = 16 << szx;
+                let block1 = (payload.len() > size).then_some(Block {
+                    num: (offset / size) as u32,
+                    more: offset + size < payload.len(),
+                    szx,
+                });
+                let chunk = match block1 {
+                    Some(_) => &payload[offset..payload.len().min(offset + size)],
+                    None => payload,
+                };
+                let mut writer = Writer::new(&mut tx, Type::Confirmable, method.code(), self.next_id(), &token);
+                if let Some(observe) = observe.filter(|_| offset == 0) {
+                    writer.uint_option(OptionNumber::OBSERVE, observe);
+                }
+                writer.segments(OptionNumber::URI_PATH, path, '/');
+                if let Some(content_format) = content_format {
+                    writer.uint_option(OptionNumber::CONTENT_FORMAT, content_format.0 as u32);
+                }
+                writer.segments(OptionNumber::URI_QUERY, query, '&');
+                if let Some(block) = block1 {
+                    writer.uint_option(OptionNumber::BLOCK1, block.encode());
+                    if block.num == 0 {
+                        writer.uint_option(OptionNumber::SIZE1, payload.len() as u32);
+                    }
+                }
+                writer.payload(chunk);
+                let len = writer.finish()?;
+
+                let n = self.transmit(remote, &tx[..len], &token, &mut rx).await?;
+                let response = Message::parse(&rx[..n]).ok_or(Error::Malformed)?;
+                match block1 {
+                    Some(sent) if sent.more && response.code == Code::CONTINUE => {
+                        // The server may ask for smaller blocks, the offset being a multiple of their size.
+                        let acked = response.block(OptionNumber::BLOCK1).ok_or(Error::Malformed)?;
+                        offset += chunk.len();
+                        szx = acked.szx.min(szx);
+                    }
+                    _ => break n,
+                }
+            };
+            self.read_response(remote, method, path, query, &token, n, &mut rx, &mut tx, buf)
+                .await
+        }
+
+        /// Read the payload of the response in `rx` to `buf`, requesting its next blocks if any.
+        #[allow(clippy::too_many_arguments)]
+        async fn read_response<'b>(
+            &mut self,
+            remote: IpEndpoint,
+            method: Method,
+            path: &str,
+            query: &str,
+            token: &[u8],
+            n: usize,
+            rx: &mut [u8],
+            tx: &mut [u8],
+            buf: &'b mut [u8],
+        ) -> Result<(Response<'b>, Option<u32>), Error> {
+            let response = Message::parse(&rx[..n]).ok_or(Error::Malformed)?;
+            let code = response.code;
+            let content_format = response
+                .uint_option(OptionNumber::CONTENT_FORMAT)
+                .map(|value| ContentFormat(value as u16));
+            let observe = response.uint_option(OptionNumber::OBSERVE);
+            let mut len = append(buf, 0, response.payload)?;
+            let mut block = response.block(OptionNumber::BLOCK2);
+
+            while let Some(received) = block.filter(|b| b.more) {
+                if len != received.offset() + received.size() {
+                    return Err(Error::Malformed);
+                }
+                let next = Block {
+                    num: received.num + 1,
+                    more: false,
+                    szx: received.szx,
+                };
+                let mut writer = Writer::new(tx, Type::Confirmable, method.code(), self.next_id(), token);
+                writer.segments(OptionNumber::URI_PATH, path, '/');
+                writer.segments(OptionNumber::URI_QUERY, query, '&');
+                writer.uint_option(OptionNumber::BLOCK2, next.encode());
+                let tx_len = writer.finish()?;
+
+                let n = self.transmit(remote, &tx[..tx_len], token, rx).await?;
+                let response = Message::parse(&rx[..n]).ok_or(Error::Malformed)?;
+                if response.code != code {
+                    return Err(Error::Malformed);
+                }
+                len = append(buf, len, response.payload)?;
+                block = response.block(OptionNumber::BLOCK2);
+            }
+
+            let buf: &'b [u8] = buf;
+            let response = Response {
+                code,
+                content_format,
+                payload: &buf[..len],
+            };
+            Ok((response, observe))
+        }
+
+        /// Send a confirmable message until it's acknowledged, and receive the response to it in `rx`.
+        ///
+        /// Returns the length of the response.
+        async fn transmit(
+            &mut self,
+            remote: IpEndpoint,
+            message: &[u8],
+            token: &[u8],
+            rx: &mut [u8],
+        ) -> Result<usize, Error> {
+            let id = u16::from_be_bytes([message[2], message[3]]);
+            let mut timeout = self.config.initial_timeout(&self.rng);
+            let mut retransmissions = 0;
+            loop {
+                self.socket.send_to(message, remote).await?;
+                let mut deadline = Instant::now() + timeout;
+                let mut acknowledged = false;
+                while let Ok(received) = with_deadline(deadline, self.socket.recv_from(rx)).await {
+                    let Ok((n, meta)) = received else {
+                        continue;
+                    };
+                    let Some(response) = Message::parse(&rx[..n]) else {
+                        continue;
+                    };
+                    if meta.endpoint != remote {
+                        self.reject(&response, meta.endpoint).await;
+                        continue;
+                    }
+                    match response.ty {
+                        Type::Acknowledgement if response.id == id => {
+                            if response.code != Code::EMPTY {
+                                return Ok(n);
+                            }
+                            // The response is sent separately.
+                            acknowledged = true;
+                            deadline = Instant::now() + self.config.max_transmit_wait();
+                        }
+                        Type::Reset if response.id == id => return Err(Error::Reset),
+                        Type::Confirmable | Type::NonConfirmable
+                            if response.token == token && response.code.class >= 2 =>
+                        {
+                            if response.ty == Type::Confirmable {
+                                self.acknowledge(response.id, remote).await;
+                            }
+                            return Ok(n);
+                        }
+                        _ => self.reject(&response, meta.endpoint).await,
+                    }
+                }
+                if acknowledged || retransmissions >= self.config.max_retransmit {
+                    return Err(Error::Timeout);
+                }
+                retransmissions += 1;
+                timeout *= 2;
+            }
+        }
+
+        async fn acknowledge(&mut self, id: u16, remote: IpEndpoint) {
+            send_empty(&self.socket, Type::Acknowledgement, id, remote).await;
+            self.last_response = Some((remote, id));
+        }
+
+        /// Reject an unexpected confirmable message, unless it's a retransmission of the last response.
+        async fn reject(&mut self, message: &Message<'_>, remote: IpEndpoint) {
+            if message.ty == Type::Confirmable {
+                let ty = match self.last_response == Some((remote, message.id)) {
+                    true => Type::Acknowledgement,
+                    false => Type::Reset,
+                };
+                send_empty(&self.socket, ty, message.id, remote).await;
+            }
+        }
+    }
+
+    /// Copy `data` at `offset` in `buf`, and return the length of the data in `buf`.
+    fn append(buf: &mut [u8], offset: usize, data: &[u8]) -> Result<usize, Error> {
+        let dest = buf.get_mut(offset..offset + data.len()).ok_or(Error::TooLarge)?;
+        dest.copy_from_slice(data);
+        Ok(offset + data.len())
+    }
+
+    /// Observation of a resource, created by [`CoapClient::observe`].
+    ///
+    /// If no notification comes for longer than the max age of the resource, the server may have forgotten
+    /// the observation, which should be made again.
+    pub struct Observation<'a, 'd> {
+        client: &'a mut CoapClient<'d>,
+        remote: IpEndpoint,
+        path: &'a str,
+        token: Token,
+        /// Sequence number of the last notification and when it was received.
+        last: Option<(u32, Instant)>,
+    }
+
+    impl Observation<'_, '_> {
+        /// Wait for the next notification, and read its payload to `buf`.
+        ///
+        /// Returns [`Error::NotObserved`] once the server ended the observation, after sending a notification
+        /// without `Observe` option, or an error response.
+        pub async fn next<'b>(&mut self, buf: &'b mut [u8]) -> Result<Response<'b>, Error> {
+            let Some((last_seq, last_time)) = self.last else {
+                return Err(Error::NotObserved);
+            };
+            let mut rx = [0; MAX_MESSAGE_LEN];
+            let mut tx = [0; MAX_MESSAGE_LEN];
+            let n = loop {
+                let Ok((n, meta)) = self.client.socket.recv_from(&mut rx).await else {
+                    continue;
+                };
+                let Some(notification) = Message::parse(&rx[..n]) else {
+                    continue;
+                };
+                let expected = meta.endpoint == self.remote
+                    && notification.token == self.token
+                    && notification.code.class >= 2
+                    && matches!(notification.ty, Type::Confirmable | Type::NonConfirmable);
+                if !expected {
+                    self.client.reject(&notification, meta.endpoint).await;
+                    continue;
+                }
+                if notification.ty == Type::Confirmable {
+                    self.client.acknowledge(notification.id, self.remote).await;
+                }
+                let seq = notification.uint_option(OptionNumber::OBSERVE);
+                match seq {
+                    Some(seq) if notification.code.is_success() => {
+                        if !is_newer(last_seq, last_time, seq) {
+                            continue;
+                        }
+                        self.last = Some((seq, Instant::now()));
+                    }
+                    _ => self.last = None,
+                }
+                break n;
+            };
+            let (path, query) = self.path.split_once('?').unwrap_or((self.path, ""));
+            let (response, _) = self
+                .client
+                .read_response(
+                    self.remote,
+                    Method::Get,
+                    path,
+                    query,
+                    &self.token,
+                    n,
+                    &mut rx,
+                    &mut tx,
+                    buf,
+                )
+                .await?;
+            Ok(response)
+        }
+
+        /// Cancel the observation, telling the server.
+        pub async fn cancel(self) -> Result<(), Error> {
+            let (path, query) = self.path.split_once('?').unwrap_or((self.path, ""));
+            let mut tx = [0; MAX_MESSAGE_LEN];
+            let mut rx = [0; MAX_MESSAGE_LEN];
+            let mut writer = Writer::new(
+                &mut tx,
+                Type::Confirmable,
+                Method::Get.code(),
+                self.client.next_id(),
+                &self.token,
+            );
+            writer.uint_option(OptionNumber::OBSERVE, 1);
+            writer.segments(OptionNumber::URI_PATH, path, '/');
+            writer.segments(OptionNumber::URI_QUERY, query, '&');
+            let len = writer.finish()?;
+            self.client
+                .transmit(self.remote, &tx[..len], &self.token, &mut rx)
+                .await?;
+            Ok(())
+        }
+    }
+
+    /// Whether the notification `seq` is newer than the one numbered `last_seq`, received at `last_time`.
+    fn is_newer(last_seq: u32, last_time: Instant, seq: u32) -> bool {
+        const HALF: u32 = 1 << 23;
+        (last_seq < seq && seq - last_seq < HALF)
+            || (last_seq > seq && last_seq - seq > HALF)
+            || Instant::now() > last_time + Duration::from_secs(128)
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use core::fmt::Write as _;
+    use std::boxed::Box;
+    use std::string::ToString;
+    use std::vec::Vec as StdVec;
+
+    use embassy_futures::select::{select, Either};
+
+    use super::client::{CoapClient, CoapClientState};
+    use super::*;
+    use crate::test_util::{self, addr, static_config};
+
+    #[test]
+    fn encodes_and_parses_messages() {
+        let mut buf = [0; 64];
+        let long = [b'x'; 20];
+        let mut writer = Writer::new(&mut buf, Type::Confirmable, Method::Post.code(), 0x1234, b"tok");
+        writer.segments(OptionNumber::URI_PATH, "/a/b", '/');
+        writer.uint_option(OptionNumber::CONTENT_FORMAT, 0);
+        // Extended delta and length.
+        writer.option(OptionNumber::PROXY_SCHEME, &long);
+        writer.uint_option(OptionNumber::SIZE1, 300);
+        writer.payload(b"hi");
+        let len = unwrap!(writer.finish());
+
+        let message = unwrap!(Message::parse(&buf[..len]));
+        assert_eq!(message.ty, Type::Confirmable);
+        assert_eq!(message.code, Method::Post.code());
+        assert_eq!(message.id, 0x1234);
+        assert_eq!(message.token, b"tok");
+        assert_eq!(message.payload, b"hi");
+        let options: StdVec<_> = message.options().collect();
+        assert_eq!(
+            options,
+            [
+                (OptionNumber::URI_PATH, &b"a"[..]),
+                (OptionNumber::URI_PATH, b"b"),
+                (OptionNumber::CONTENT_FORMAT, b""),
+                (OptionNumber::PROXY_SCHEME, &long),
+                (OptionNumber::SIZE1, &[1, 44]),
+            ]
+        );
+        assert_eq!(message.uint_option(OptionNumber::SIZE1), Some(300));
+
+        let mut small = [0; 8];
+        let mut writer = Writer::new(&mut small, Type::Confirmable, Code::CONTENT, 1, b"tok");
+        writer.payload(b"too long");
+        assert_eq!(writer.finish(), Err(Error::TooLarge));
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        // Payload marker without payload.
+        assert!(Message::parse(&[0x40, 0x01, 0, 1, PAYLOAD_MARKER]).is_none());
+        // Wrong version.
+        assert!(Message::parse(&[0x80, 0x01, 0, 1]).is_none());
+        // Token longer than 8 bytes, or than the message.
+        assert!(Message::parse(&[0x49, 0x01, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+        assert!(Message::parse(&[0x42, 0x01, 0, 1, 0]).is_none());
+        // Empty message with a token.
+        assert!(Message::parse(&[0x41, 0x00, 0, 1, 0]).is_none());
+        // Option longer than the message, and reserved length nibble.
+        assert!(Message::parse(&[0x40, 0x01, 0, 1, 0xb3, b'a']).is_none());
+        assert!(Message::parse(&[0x40, 0x01, 0, 1, 0xbf]).is_none());
+    }
+
+    #[test]
+    fn selects_blocks() {
+        let block = |num, more, szx| Block { num, more, szx };
+        assert_eq!(Block::decode(block(5, true, 2).encode()), Some(block(5, true, 2)));
+        assert_eq!(Block::decode(0x07), None);
+
+        // Payloads fitting in a block aren't sent block-wise, unless requested.
+        assert_eq!(select_block(None, 16, 0), Ok(None));
+        assert_eq!(select_block(None, 17, 0), Ok(Some(block(0, true, 0))));
+        assert_eq!(
+            select_block(Some(block(1, false, 0)), 32, 0),
+            Ok(Some(block(1, false, 0)))
+        );
+        // Requests for larger blocks than allowed get smaller ones at the same offset.
+        assert_eq!(
+            select_block(Some(block(1, false, 1)), 100, 0),
+            Ok(Some(block(2, true, 0)))
+        );
+        assert_eq!(select_block(Some(block(2, false, 0)), 32, 0), Err(()));
+    }
+
+    enum Resource<'a> {
+        Large,
+        Echo,
+        Counter(&'a Cell<u32>),
+    }
+
+    impl Handler for Resource<'_> {
+        async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code {
+            match self {
+                Resource::Large => {
+                    for i in 0..100 {
+                        unwrap!(response.write(&[i]));
+                    }
+                    Code::CONTENT
+                }
+                Resource::Echo => {
+                    unwrap!(response.write(request.payload()));
+                    Code::CHANGED
+                }
+                Resource::Counter(count) => {
+                    unwrap!(write!(response, "{}", count.get()));
+                    Code::CONTENT
+                }
+            }
+        }
+    }
+
+    /// Run a server on `b` with `routes`, and `test` with a client on `a`, with blocks of 16 bytes.
+    fn run<F, Fut>(routes: &'static [Route<Resource<'static>>], test: F)
+    where
+        F: FnOnce(CoapClient<'static>, &'static CoapServer<'static>) -> Fut + 'static,
+        Fut: core::future::Future<Output = ()>,
+    {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut config = Config::new();
+            config.block_size = 16;
+            let server_state = Box::leak(Box::new(CoapServerState::<128>::new()));
+            let server: &'static CoapServer = Box::leak(Box::new(CoapServer::new(net.b, server_state, PORT, config)));
+            let client_state = Box::leak(Box::new(CoapClientState::new()));
+            let client = CoapClient::new(net.a, client_state, config);
+            let router = Router::new(routes);
+            let mut buf = [0; 128];
+
+            match select(server.run(&router, &mut buf), test(client, server)).await {
+                Either::Second(()) => {}
+            }
+        })
+    }
+
+    fn remote() -> IpEndpoint {
+        (addr(2), PORT).into()
+    }
+
+    #[test]
+    fn transfers_blocks() {
+        let routes = Box::leak(Box::new([
+            Route::new(Method::Get, "/large", Resource::Large),
+            Route::new(Method::Post, "/echo", Resource::Echo),
+        ]));
+        run(routes, |mut client, _| async move {
+            let mut buf = [0; 128];
+            let response = unwrap!(client.get(remote(), "/large", &mut buf).await);
+            assert_eq!(response.code, Code::CONTENT);
+            assert_eq!(response.payload, (0..100).collect::<StdVec<u8>>());
+
+            let payload: StdVec<u8> = (0..50).rev().collect();
+            let response = unwrap!(client.post(remote(), "/echo", &payload, None, &mut buf).await);
+            assert_eq!(response.code, Code::CHANGED);
+            assert_eq!(response.payload, payload);
+
+            let response = unwrap!(client.put(remote(), "/echo", &[], None, &mut buf).await);
+            assert_eq!(response.code, Code::METHOD_NOT_ALLOWED);
+            let response = unwrap!(client.get(remote(), "/.well-known/core", &mut buf).await);
+            assert_eq!(response.payload, b"</large>,</echo>");
+
+            // Too large for the buffer of the server.
+            let response = unwrap!(client.post(remote(), "/echo", &[0; 200], None, &mut buf).await);
+            assert_eq!(response.code, Code::REQUEST_ENTITY_TOO_LARGE);
+        });
+    }
+
+    #[test]
+    fn notifies_observers() {
+        let count: &'static Cell<u32> = Box::leak(Box::new(Cell::new(1)));
+        let routes = Box::leak(Box::new([Route::new(Method::Get, "/count", Resource::Counter(count))]));
+        run(routes, move |mut client, server| async move {
+            // Message IDs are sequential.
+            let id = server.next_id();
+            assert_eq!(server.next_id(), id.wrapping_add(1));
+
+            let mut buf = [0; 16];
+            let (mut observation, response) = unwrap!(client.observe(remote(), "/count", &mut buf).await);
+            assert_eq!(response.payload, b"1");
+            assert!(server.is_observed("/count"));
+
+            for n in 2..4 {
+                count.set(n);
+                server.notify("/count");
+                let response = unwrap!(observation.next(&mut buf).await);
+                assert_eq!(response.payload, n.to_string().as_bytes());
+            }
+
+            unwrap!(observation.cancel().await);
+            assert!(!server.is_observed("/count"));
+        });
+    }
+}
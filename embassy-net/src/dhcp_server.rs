@@ -0,0 +1,560 @@
+//! DHCPv4 server.
+//!
+//! [`DhcpServer`] leases addresses from a pool to the clients on the link of a network interface,
+//! for example the stations connected to an access point, or the host at the other end of a USB
+//! Ethernet link. The interface must already have the static IPv4 address of the server.
+//!
+//! Leases are kept in a table of `N` entries. Offered addresses are reserved there for a minute,
+//! until the client requests them, so that they aren't offered to other clients. To keep the
+//! leases across reboots, store them when [`DhcpServer::next_event`] reports a change, and add
+//! them back with [`DhcpServer::add_lease`] after creating the server.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::wire::{
+    DhcpMessageType, DhcpPacket, DhcpRepr, DHCP_CLIENT_PORT, DHCP_MAX_DNS_SERVER_COUNT, DHCP_SERVER_PORT,
+};
+
+use crate::udp::{PacketMetadata, RecvError, UdpSocket};
+use crate::{EthernetAddress, IpEndpoint, IpListenEndpoint, Ipv4Address, Ipv4Cidr, Stack};
+
+/// Maximum size of DHCP packets every host must accept.
+const PACKET_LEN: usize = 576;
+/// Minimum size of a BOOTP packet, some clients ignore shorter replies.
+const MIN_PACKET_LEN: usize = 300;
+/// Hardware address of the leases of declined addresses.
+const DECLINED: EthernetAddress = EthernetAddress([0; 6]);
+/// How long an offered address is reserved for the client it was offered to.
+const OFFER_TIME: Duration = Duration::from_secs(60);
+
+/// DHCP server configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// Address of the server and subnet of the clients.
+    pub address: Ipv4Cidr,
+    /// First address of the pool.
+    pub pool_start: Ipv4Address,
+    /// Number of addresses in the pool.
+    pub pool_size: u16,
+    /// Duration of the leases.
+    pub lease_time: Duration,
+    /// Default gateway given to the clients.
+    pub gateway: Option<Ipv4Address>,
+    /// DNS servers given to the clients.
+    pub dns_servers: Vec<Ipv4Address, DHCP_MAX_DNS_SERVER_COUNT>,
+}
+
+impl Config {
+    /// Create a new configuration for a server at `address`, leasing `pool_size` addresses from `pool_start`.
+    ///
+    /// The server is given as the default gateway, without DNS servers, and leases last one hour.
+    pub const fn new(address: Ipv4Cidr, pool_start: Ipv4Address, pool_size: u16) -> Self {
+        Self {
+            address,
+            pool_start,
+            pool_size,
+            lease_time: Duration::from_secs(3600),
+            gateway: Some(address.address()),
+            dns_servers: Vec::new(),
+        }
+    }
+
+    fn in_pool(&self, address: Ipv4Address) -> bool {
+        let offset = u32::from(address).wrapping_sub(u32::from(self.pool_start));
+        offset < self.pool_size as u32 && address != self.address.address()
+    }
+}
+
+/// Address leased to a client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lease {
+    /// Hardware address of the client.
+    ///
+    /// Addresses declined by clients, because they are already in use, are leased to the all-zero address.
+    pub hardware_address: EthernetAddress,
+    /// Leased address.
+    pub address: Ipv4Address,
+    /// When the lease expires.
+    pub expires_at: Instant,
+}
+
+/// Change of the lease table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event {
+    /// A lease was granted or renewed.
+    Leased(Lease),
+    /// A client released its lease.
+    Released(Lease),
+}
+
+/// State for [`DhcpServer`].
+pub struct DhcpServerState {
+    rx_meta: [PacketMetadata; 2],
+    rx_buffer: [u8; 2 * PACKET_LEN],
+    tx_meta: [PacketMetadata; 1],
+    tx_buffer: [u8; PACKET_LEN],
+}
+
+impl DhcpServerState {
+    /// Create a new `DhcpServerState`.
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx_buffer: [0; 2 * PACKET_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 1],
+            tx_buffer: [0; PACKET_LEN],
+        }
+    }
+}
+
+impl Default for DhcpServerState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DHCPv4 server, with a table of `N` leases.
+pub struct DhcpServer<'d, const N: usize = 8> {
+    socket: UdpSocket<'d>,
+    config: Config,
+    leases: Vec<Lease, N>,
+}
+
+impl<'d, const N: usize> DhcpServer<'d, N> {
+    /// Create a new DHCP server.
+    ///
+    /// It serves the interface that has the address of the server, `config.address`.
+    pub fn new(stack: Stack<'d>, config: Config, state: &'d mut DhcpServerState) -> Self {
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut state.rx_meta,
+            &mut state.rx_buffer,
+            &mut state.tx_meta,
+            &mut state.tx_buffer,
+        );
+        // Bound to the server address, the socket still receives broadcasts.
+        unwrap!(socket.bind(IpListenEndpoint {
+            addr: Some(config.address.address().into()),
+            port: DHCP_SERVER_PORT,
+        }));
+        Self {
+            socket,
+            config,
+            leases: Vec::new(),
+        }
+    }
+
+    /// Get the lease table, including expired leases.
+    pub fn leases(&self) -> &[Lease] {
+        &self.leases
+    }
+
+    /// Add a lease to the table, replacing the leases of the same client or address.
+    ///
+    /// Returns the lease back if the table is full of leases that didn't expire.
+    pub fn add_lease(&mut self, lease: Lease) -> Result<(), Lease> {
+        // Several addresses can be declined.
+        let same_client =
+            |l: &Lease| l.hardware_address == lease.hardware_address && lease.hardware_address != DECLINED;
+        self.leases.retain(|l| !same_client(l) && l.address != lease.address);
+        if self.leases.is_full() {
+            // Replace the lease that expired first.
+            let now = Instant::now();
+            match self
+                .leases
+                .iter()
+                .enumerate()
+                .filter(|(_, l)| l.expires_at <= now)
+                .min_by_key(|(_, l)| l.expires_at)
+            {
+                Some((i, _)) => self.leases[i] = lease,
+                None => return Err(lease),
+            }
+        } else {
+            unwrap!(self.leases.push(lease).ok());
+        }
+        Ok(())
+    }
+
+    /// Remove the lease of a client.
+    pub fn remove_lease(&mut self, hardware_address: EthernetAddress) -> Option<Lease> {
+        let i = self
+            .leases
+            .iter()
+            .position(|l| l.hardware_address == hardware_address)?;
+        Some(self.leases.swap_remove(i))
+    }
+
+    /// Serve requests until the lease table changes.
+    pub async fn next_event(&mut self) -> Event {
+        let mut buf = [0; PACKET_LEN];
+        loop {
+            let n = match self.socket.recv_from(&mut buf).await {
+                Ok((n, _)) => n,
+                Err(RecvError::Truncated) => continue,
+            };
+            let Ok(packet) = DhcpPacket::new_checked(&buf[..n]) else {
+                continue;
+            };
+            let Ok(request) = DhcpRepr::parse(&packet) else {
+                continue;
+            };
+            if let Some(event) = self.process(&request).await {
+                return event;
+            }
+        }
+    }
+
+    /// Serve requests.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            let event = self.next_event().await;
+            debug!("dhcp server: {:?}", event);
+        }
+    }
+
+    async fn process(&mut self, request: &DhcpRepr<'_>) -> Option<Event> {
+        let now = Instant::now();
+        let client = request.client_hardware_address;
+        let server = self.config.address.address();
+        match request.message_type {
+            DhcpMessageType::Discover => {
+                let Some(address) = self.select_address(client, request.requested_ip, now) else {
+                    warn!("dhcp server: no address left for {}", client);
+                    return None;
+                };
+                // Reserve the address until the client requests it, unless it's already leased to the client.
+                let reserved = self
+                    .leases
+                    .iter()
+                    .any(|l| l.hardware_address == client && l.address == address && l.expires_at >= now + OFFER_TIME);
+                let offer = Lease {
+                    hardware_address: client,
+                    address,
+                    expires_at: now + OFFER_TIME,
+                };
+                if !reserved && self.add_lease(offer).is_err() {
+                    warn!("dhcp server: lease table full, not offering {} to {}", address, client);
+                    return None;
+                }
+                self.reply(request, DhcpMessageType::Offer, address).await;
+                None
+            }
+            DhcpMessageType::Request => {
+                // The client selected another server.
+                if request.server_identifier.is_some_and(|id| id != server) {
+                    return None;
+                }
+                let address = request.requested_ip.unwrap_or(request.client_ip);
+                let lease = Lease {
+                    hardware_address: client,
+                    address,
+                    expires_at: now + self.config.lease_time,
+                };
+                if self.config.in_pool(address) && self.is_free(address, client, now) && self.add_lease(lease).is_ok() {
+                    self.reply(request, DhcpMessageType::Ack, address).await;
+                    Some(Event::Leased(lease))
+                } else {
+                    self.reply(request, DhcpMessageType::Nak, Ipv4Address::UNSPECIFIED)
+                        .await;
+                    None
+                }
+            }
+            DhcpMessageType::Release => {
+                if request.server_identifier != Some(server) {
+                    return None;
+                }
+                let lease = self
+                    .leases
+                    .iter()
+                    .find(|l| l.hardware_address == client && l.address == request.client_ip)?;
+                self.remove_lease(lease.hardware_address).map(Event::Released)
+            }
+            DhcpMessageType::Decline => {
+                if request.server_identifier != Some(server) {
+                    return None;
+                }
+                let address = request.requested_ip?;
+                warn!("dhcp server: {} declined {}, already in use", client, address);
+                let released = self.remove_lease(client);
+                // Keep the address from being offered again for a while.
+                let declined = Lease {
+                    hardware_address: DECLINED,
+                    address,
+                    expires_at: now + self.config.lease_time,
+                };
+                if self.add_lease(declined).is_err() {
+                    warn!("dhcp server: lease table full, {} may be offered again", address);
+                }
+                released.map(Event::Released)
+            }
+            DhcpMessageType::Inform => {
+                self.reply(request, DhcpMessageType::Ack, Ipv4Address::UNSPECIFIED)
+                    .await;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Check if `address` can be leased to `client`.
+    fn is_free(&self, address: Ipv4Address, client: EthernetAddress, now: Instant) -> bool {
+        self.leases
+            .iter()
+            .all(|l| l.address != address || l.hardware_address == client || l.expires_at <= now)
+    }
+
+    /// Select the address to offer to `client`.
+    fn select_address(
+        &self,
+        client: EthernetAddress,
+        requested: Option<Ipv4Address>,
+        now: Instant,
+    ) -> Option<Ipv4Address> {
+        // Prefer the previous address of the client, then the address it requests.
+        if let Some(lease) = self.leases.iter().find(|l| l.hardware_address == client) {
+            if self.config.in_pool(lease.address) {
+                return Some(lease.address);
+            }
+        }
+        if let Some(address) = requested {
+            if self.config.in_pool(address) && self.is_free(address, client, now) {
+                return Some(address);
+            }
+        }
+
+        // Then addresses that were never leased, and finally the address whose lease expired first.
+        let has_free_entry = !self.leases.is_full() || self.leases.iter().any(|l| l.expires_at <= now);
+        if has_free_entry {
+            let start = u32::from(self.config.pool_start);
+            let unused = (0..self.config.pool_size as u32)
+                .map(|i| Ipv4Address::from(start + i))
+                .find(|a| self.config.in_pool(*a) && self.leases.iter().all(|l| l.address != *a));
+            if unused.is_some() {
+                return unused;
+            }
+        }
+        self.leases
+            .iter()
+            .filter(|l| l.expires_at <= now && self.config.in_pool(l.address))
+            .min_by_key(|l| l.expires_at)
+            .map(|l| l.address)
+    }
+
+    async fn reply(&mut self, request: &DhcpRepr<'_>, message_type: DhcpMessageType, your_ip: Ipv4Address) {
+        let nak = message_type == DhcpMessageType::Nak;
+        let lease_time = self.config.lease_time.as_secs().min(u32::MAX as u64) as u32;
+        let reply = DhcpRepr {
+            message_type,
+            transaction_id: request.transaction_id,
+            secs: 0,
+            client_hardware_address: request.client_hardware_address,
+            client_ip: match request.message_type {
+                DhcpMessageType::Inform => request.client_ip,
+                _ => Ipv4Address::UNSPECIFIED,
+            },
+            your_ip,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: self.config.gateway.filter(|_| !nak),
+            subnet_mask: (!nak).then(|| self.config.address.netmask()),
+            relay_agent_ip: request.relay_agent_ip,
+            broadcast: request.broadcast,
+            requested_ip: None,
+            client_identifier: None,
+            server_identifier: Some(self.config.address.address()),
+            parameter_request_list: None,
+            dns_servers: (!nak && !self.config.dns_servers.is_empty()).then(|| self.config.dns_servers.clone()),
+            max_size: None,
+            lease_duration: (!your_ip.is_unspecified()).then_some(lease_time),
+            renew_duration: None,
+            rebind_duration: None,
+            additional_options: &[],
+        };
+
+        let mut buf = [0; PACKET_LEN];
+        let len = reply.buffer_len().max(MIN_PACKET_LEN);
+        unwrap!(reply.emit(&mut DhcpPacket::new_unchecked(&mut buf[..len])));
+
+        // Clients without an address can't answer ARP requests, so replies to them are broadcast.
+        let destination = if !request.relay_agent_ip.is_unspecified() {
+            IpEndpoint::new(request.relay_agent_ip.into(), DHCP_SERVER_PORT)
+        } else if !nak && !request.client_ip.is_unspecified() {
+            IpEndpoint::new(request.client_ip.into(), DHCP_CLIENT_PORT)
+        } else {
+            IpEndpoint::new(Ipv4Address::BROADCAST.into(), DHCP_CLIENT_PORT)
+        };
+        if let Err(e) = self.socket.send_to(&buf[..len], destination).await {
+            warn!("dhcp server: failed to send reply: {:?}", e);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+
+    use embassy_futures::select::{select, Either};
+    use embassy_time::with_timeout;
+
+    use super::*;
+    use crate::test_util::{self, addr, static_config};
+    use crate::Config as StackConfig;
+
+    const POOL_START: Ipv4Address = Ipv4Address::new(10, 0, 0, 100);
+
+    fn server(stack: Stack<'static>) -> DhcpServer<'static, 2> {
+        let config = Config::new(Ipv4Cidr::new(addr(2), 24), POOL_START, 10);
+        DhcpServer::new(stack, config, Box::leak(Box::new(DhcpServerState::new())))
+    }
+
+    fn client_socket(stack: Stack<'static>) -> UdpSocket<'static> {
+        let mut socket = UdpSocket::new(
+            stack,
+            Box::leak(Box::new([PacketMetadata::EMPTY; 2])),
+            Box::leak(Box::new([0; 2 * PACKET_LEN])),
+            Box::leak(Box::new([PacketMetadata::EMPTY; 1])),
+            Box::leak(Box::new([0; PACKET_LEN])),
+        );
+        unwrap!(socket.bind(DHCP_CLIENT_PORT));
+        socket
+    }
+
+    /// Send a request from the client with hardware address `[2, .., n]`, and wait for the reply if there's one.
+    async fn exchange(
+        socket: &mut UdpSocket<'_>,
+        message_type: DhcpMessageType,
+        n: u8,
+        requested_ip: Option<Ipv4Address>,
+    ) -> Option<(DhcpMessageType, Ipv4Address)> {
+        let request = DhcpRepr {
+            message_type,
+            transaction_id: n.into(),
+            secs: 0,
+            client_hardware_address: EthernetAddress([2, 0, 0, 0, 0, n]),
+            client_ip: Ipv4Address::UNSPECIFIED,
+            your_ip: Ipv4Address::UNSPECIFIED,
+            server_ip: Ipv4Address::UNSPECIFIED,
+            router: None,
+            subnet_mask: None,
+            relay_agent_ip: Ipv4Address::UNSPECIFIED,
+            broadcast: true,
+            requested_ip,
+            client_identifier: None,
+            server_identifier: (message_type != DhcpMessageType::Discover).then_some(addr(2)),
+            parameter_request_list: None,
+            dns_servers: None,
+            max_size: None,
+            lease_duration: None,
+            renew_duration: None,
+            rebind_duration: None,
+            additional_options: &[],
+        };
+        let mut buf = [0; PACKET_LEN];
+        let len = request.buffer_len();
+        unwrap!(request.emit(&mut DhcpPacket::new_unchecked(&mut buf[..len])));
+        unwrap!(socket.send_to(&buf[..len], (addr(2), DHCP_SERVER_PORT)).await);
+
+        if message_type == DhcpMessageType::Decline {
+            return None;
+        }
+        let (n, _) = unwrap!(socket.recv_from(&mut buf).await);
+        let packet = unwrap!(DhcpPacket::new_checked(&buf[..n]));
+        let reply = unwrap!(DhcpRepr::parse(&packet));
+        assert_eq!(reply.transaction_id, request.transaction_id);
+        Some((reply.message_type, reply.your_ip))
+    }
+
+    #[test]
+    fn leases_address_to_dhcp_client() {
+        test_util::run(
+            [StackConfig::dhcpv4(Default::default()), static_config(2)],
+            |net| async move {
+                let mut server = server(net.b);
+                let Event::Leased(lease) = server.next_event().await else {
+                    panic!("expected a lease");
+                };
+                assert_eq!(lease.address, POOL_START);
+                assert_eq!(
+                    crate::HardwareAddress::Ethernet(lease.hardware_address),
+                    net.a.hardware_address()
+                );
+
+                let config = match select(server.run(), net.a.wait_config_up()).await {
+                    Either::Second(()) => unwrap!(net.a.config_v4()),
+                };
+                assert_eq!(config.address, Ipv4Cidr::new(POOL_START, 24));
+                assert_eq!(config.gateway, Some(addr(2)));
+            },
+        );
+    }
+
+    #[test]
+    fn reserves_offered_addresses() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut server = server(net.b);
+            let mut socket = client_socket(net.a);
+            let second = Ipv4Address::new(10, 0, 0, 101);
+
+            let client = async {
+                use DhcpMessageType::*;
+                // Offered addresses aren't offered to other clients.
+                assert_eq!(
+                    exchange(&mut socket, Discover, 1, None).await,
+                    Some((Offer, POOL_START))
+                );
+                assert_eq!(exchange(&mut socket, Discover, 2, None).await, Some((Offer, second)));
+                // A client offered an address gets it again, or can request it.
+                assert_eq!(
+                    exchange(&mut socket, Discover, 1, None).await,
+                    Some((Offer, POOL_START))
+                );
+                assert_eq!(
+                    exchange(&mut socket, Request, 1, Some(POOL_START)).await,
+                    Some((Ack, POOL_START))
+                );
+                // An address reserved for another client can't be requested.
+                assert_eq!(
+                    exchange(&mut socket, Request, 3, Some(second)).await,
+                    Some((Nak, Ipv4Address::UNSPECIFIED))
+                );
+                // The table is full of offers and leases that didn't expire.
+                let sent = with_timeout(Duration::from_millis(100), exchange(&mut socket, Discover, 3, None));
+                assert!(sent.await.is_err());
+            };
+            match select(server.run(), client).await {
+                Either::Second(()) => {}
+            }
+            assert_eq!(server.leases().len(), 2);
+        });
+    }
+
+    #[test]
+    fn declined_addresses_are_not_offered() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut server = server(net.b);
+            let mut socket = client_socket(net.a);
+
+            let client = async {
+                use DhcpMessageType::*;
+                assert_eq!(
+                    exchange(&mut socket, Request, 1, Some(POOL_START)).await,
+                    Some((Ack, POOL_START))
+                );
+                assert_eq!(exchange(&mut socket, Decline, 1, Some(POOL_START)).await, None);
+                let offer = exchange(&mut socket, Discover, 1, None).await;
+                assert_eq!(offer, Some((Offer, Ipv4Address::new(10, 0, 0, 101))));
+            };
+            match select(server.run(), client).await {
+                Either::Second(()) => {}
+            }
+            assert!(server
+                .leases()
+                .iter()
+                .any(|l| l.hardware_address == DECLINED && l.address == POOL_START));
+        });
+    }
+}
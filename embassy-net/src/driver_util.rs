@@ -1,3 +1,6 @@
+#[cfg(feature = "stats")]
+use core::cell::Cell;
+use core::marker::PhantomData;
 use core::task::Context;
 
 use embassy_net_driver::{Capabilities, Checksum, Driver, RxToken, TxToken};
@@ -12,6 +15,59 @@ where
     pub cx: Option<&'d mut Context<'c>>,
     pub inner: &'d mut T,
     pub medium: Medium,
+    /// Where to queue received packets to forward.
+    #[cfg(feature = "ipv4-forwarding")]
+    pub forward: Option<&'d mut crate::forward::Forward>,
+    /// Ethernet sockets to copy received frames to.
+    #[cfg(feature = "raw-ethernet")]
+    pub ethernet: Option<&'d mut crate::ethernet::EthernetSockets>,
+    /// Where to reassemble received IPv4 fragments, and fragment sent IPv4 packets.
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    pub fragments: Option<&'d mut crate::fragment::Fragments>,
+    /// Where to learn path MTUs from received ICMP errors.
+    #[cfg(feature = "pmtu")]
+    pub pmtu: &'d mut crate::pmtu::PathMtus,
+    #[cfg(feature = "stats")]
+    pub stats: &'d Cell<crate::stats::InterfaceStats>,
+    /// Where to count TCP retransmissions.
+    #[cfg(all(feature = "stats", feature = "tcp"))]
+    pub tcp: &'d mut crate::stats::TcpTracker,
+    #[cfg(feature = "pcap")]
+    pub sink: Option<&'d dyn crate::pcap::PacketSink>,
+}
+
+impl<T> DriverAdapter<'_, '_, T>
+where
+    T: Driver,
+{
+    /// Send the fragments of the previous IPv4 packet still waiting, returns whether all were sent.
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    pub fn send_fragments(&mut self) -> bool {
+        let Some(fragments) = self.fragments.as_deref_mut() else {
+            return true;
+        };
+        while fragments.fragmenter.is_pending() {
+            let Some(tx) = self.inner.transmit(unwrap!(self.cx.as_deref_mut())) else {
+                #[cfg(feature = "stats")]
+                update(self.stats, |s| s.tx_buffer_full = s.tx_buffer_full.wrapping_add(1));
+                return false;
+            };
+            let tx = TxTokenAdapter {
+                inner: tx,
+                fragmenter: None,
+                #[cfg(feature = "stats")]
+                stats: self.stats,
+                #[cfg(all(feature = "stats", feature = "tcp"))]
+                tcp: (&mut *self.tcp, self.medium),
+                #[cfg(feature = "pcap")]
+                sink: self.sink.map(|s| (s, self.medium)),
+                _lifetime: PhantomData,
+            };
+            let fragmenter = &mut fragments.fragmenter;
+            tx.send(fragmenter.next_len(), |buf| fragmenter.emit_next(buf));
+        }
+        true
+    }
 }
 
 impl<'d, 'c, T> phy::Device for DriverAdapter<'d, 'c, T>
@@ -19,23 +75,87 @@ where
     T: Driver,
 {
     type RxToken<'a>
-        = RxTokenAdapter<T::RxToken<'a>>
+        = RxTokenAdapter<'a, T::RxToken<'a>>
     where
         Self: 'a;
     type TxToken<'a>
-        = TxTokenAdapter<T::TxToken<'a>>
+        = TxTokenAdapter<'a, T::TxToken<'a>>
     where
         Self: 'a;
 
     fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
-        self.inner
-            .receive(unwrap!(self.cx.as_deref_mut()))
-            .map(|(rx, tx)| (RxTokenAdapter(rx), TxTokenAdapter(tx)))
+        // Replies could need the fragmentation buffer too.
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if !self.send_fragments() {
+            return None;
+        }
+        #[cfg(feature = "stats-checksums")]
+        let checksum = self.inner.capabilities().checksum;
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        let (reassembler, fragmenter) = match self.fragments.as_deref_mut() {
+            Some(f) => (Some(&mut f.reassembler), Some(&mut f.fragmenter)),
+            None => (None, None),
+        };
+        self.inner.receive(unwrap!(self.cx.as_deref_mut())).map(|(rx, tx)| {
+            let rx = RxTokenAdapter {
+                inner: rx,
+                medium: self.medium,
+                #[cfg(feature = "ipv4-forwarding")]
+                forward: self.forward.as_deref_mut(),
+                #[cfg(feature = "raw-ethernet")]
+                ethernet: self.ethernet.as_deref_mut(),
+                #[cfg(feature = "proto-ipv4-fragmentation")]
+                reassembler,
+                #[cfg(feature = "pmtu")]
+                pmtu: &mut *self.pmtu,
+                #[cfg(feature = "stats")]
+                stats: self.stats,
+                #[cfg(feature = "stats-checksums")]
+                checksum,
+                #[cfg(feature = "pcap")]
+                sink: self.sink,
+                _lifetime: PhantomData,
+            };
+            let tx = TxTokenAdapter {
+                inner: tx,
+                #[cfg(feature = "proto-ipv4-fragmentation")]
+                fragmenter,
+                #[cfg(feature = "stats")]
+                stats: self.stats,
+                #[cfg(all(feature = "stats", feature = "tcp"))]
+                tcp: (&mut *self.tcp, self.medium),
+                #[cfg(feature = "pcap")]
+                sink: self.sink.map(|s| (s, self.medium)),
+                _lifetime: PhantomData,
+            };
+            (rx, tx)
+        })
     }
 
     /// Construct a transmit token.
     fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
-        self.inner.transmit(unwrap!(self.cx.as_deref_mut())).map(TxTokenAdapter)
+        // The fragments of the previous packet are sent first.
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if !self.send_fragments() {
+            return None;
+        }
+        let tx = self.inner.transmit(unwrap!(self.cx.as_deref_mut()));
+        #[cfg(feature = "stats")]
+        if tx.is_none() {
+            update(self.stats, |s| s.tx_buffer_full = s.tx_buffer_full.wrapping_add(1));
+        }
+        tx.map(|tx| TxTokenAdapter {
+            inner: tx,
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            fragmenter: self.fragments.as_deref_mut().map(|f| &mut f.fragmenter),
+            #[cfg(feature = "stats")]
+            stats: self.stats,
+            #[cfg(all(feature = "stats", feature = "tcp"))]
+            tcp: (&mut *self.tcp, self.medium),
+            #[cfg(feature = "pcap")]
+            sink: self.sink.map(|s| (s, self.medium)),
+            _lifetime: PhantomData,
+        })
     }
 
     /// Get a description of device capabilities.
@@ -52,6 +172,10 @@ where
         let mut smolcaps = phy::DeviceCapabilities::default();
 
         smolcaps.max_transmission_unit = caps.max_transmission_unit;
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if let Some(fragments) = &self.fragments {
+            smolcaps.max_transmission_unit = fragments.max_frame_len();
+        }
         smolcaps.max_burst_size = caps.max_burst_size;
         smolcaps.medium = self.medium;
         smolcaps.checksum.ipv4 = convert(caps.checksum.ipv4);
@@ -70,11 +194,35 @@ where
     }
 }
 
-pub(crate) struct RxTokenAdapter<T>(T)
+pub(crate) struct RxTokenAdapter<'a, T>
 where
-    T: RxToken;
+    T: RxToken,
+{
+    inner: T,
+    #[cfg_attr(
+        not(any(feature = "ipv4-forwarding", feature = "stats", feature = "pcap", feature = "pmtu")),
+        allow(unused)
+    )]
+    medium: Medium,
+    #[cfg(feature = "ipv4-forwarding")]
+    forward: Option<&'a mut crate::forward::Forward>,
+    #[cfg(feature = "raw-ethernet")]
+    ethernet: Option<&'a mut crate::ethernet::EthernetSockets>,
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    reassembler: Option<&'a mut crate::fragment::Reassembler>,
+    #[cfg(feature = "pmtu")]
+    pmtu: &'a mut crate::pmtu::PathMtus,
+    #[cfg(feature = "stats")]
+    stats: &'a Cell<crate::stats::InterfaceStats>,
+    /// Checksums the driver verifies itself.
+    #[cfg(feature = "stats-checksums")]
+    checksum: embassy_net_driver::ChecksumCapabilities,
+    #[cfg(feature = "pcap")]
+    sink: Option<&'a dyn crate::pcap::PacketSink>,
+    _lifetime: PhantomData<&'a mut ()>,
+}
 
-impl<T> phy::RxToken for RxTokenAdapter<T>
+impl<T> phy::RxToken for RxTokenAdapter<'_, T>
 where
     T: RxToken,
 {
@@ -82,31 +230,148 @@ where
     where
         F: FnOnce(&[u8]) -> R,
     {
-        self.0.consume(|buf| {
+        self.inner.consume(|buf| {
             #[cfg(feature = "packet-trace")]
             trace!("embassy device rx: {:02x}", buf);
+            #[cfg(feature = "pcap")]
+            if let Some(sink) = self.sink {
+                capture(sink, self.medium, crate::pcap::Direction::Rx, buf);
+            }
+            #[cfg(feature = "stats")]
+            update(self.stats, |s| {
+                s.rx_packets = s.rx_packets.wrapping_add(1);
+                s.rx_bytes = s.rx_bytes.wrapping_add(buf.len() as u64);
+                #[cfg(feature = "stats-checksums")]
+                if !crate::stats::verify_checksums(self.medium, &self.checksum, buf) {
+                    s.rx_checksum_errors = s.rx_checksum_errors.wrapping_add(1);
+                }
+            });
+            #[cfg(feature = "pmtu")]
+            self.pmtu.capture(self.medium, buf);
+            #[cfg(feature = "raw-ethernet")]
+            if let Some(ethernet) = self.ethernet {
+                let _queued = ethernet.capture(buf);
+                #[cfg(feature = "stats")]
+                if !_queued {
+                    update(self.stats, |s| s.rx_dropped = s.rx_dropped.wrapping_add(1));
+                }
+            }
+            #[cfg(feature = "ipv4-forwarding")]
+            if let Some(forward) = self.forward {
+                let _queued = forward.capture(self.medium, buf);
+                #[cfg(feature = "stats")]
+                if !_queued {
+                    update(self.stats, |s| s.rx_dropped = s.rx_dropped.wrapping_add(1));
+                }
+            }
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            if let Some(reassembler) = self.reassembler {
+                // smoltcp drops empty frames.
+                return f(reassembler.process(buf).unwrap_or(&[]));
+            }
             f(buf)
         })
     }
 }
 
-pub(crate) struct TxTokenAdapter<T>(T)
+pub(crate) struct TxTokenAdapter<'a, T>
 where
-    T: TxToken;
+    T: TxToken,
+{
+    inner: T,
+    /// Where to split IPv4 packets larger than the driver MTU into fragments.
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    fragmenter: Option<&'a mut crate::fragment::Fragmenter>,
+    #[cfg(feature = "stats")]
+    stats: &'a Cell<crate::stats::InterfaceStats>,
+    /// Where to count TCP retransmissions, and the medium of the packets.
+    #[cfg(all(feature = "stats", feature = "tcp"))]
+    tcp: (&'a mut crate::stats::TcpTracker, Medium),
+    /// Where to capture sent packets, and their medium.
+    #[cfg(feature = "pcap")]
+    sink: Option<(&'a dyn crate::pcap::PacketSink, Medium)>,
+    _lifetime: PhantomData<&'a ()>,
+}
 
-impl<T> phy::TxToken for TxTokenAdapter<T>
+impl<T> phy::TxToken for TxTokenAdapter<'_, T>
 where
     T: TxToken,
 {
-    fn consume<R, F>(self, len: usize, f: F) -> R
+    #[cfg_attr(not(feature = "proto-ipv4-fragmentation"), allow(unused_mut))]
+    fn consume<R, F>(mut self, len: usize, f: F) -> R
     where
         F: FnOnce(&mut [u8]) -> R,
     {
-        self.0.consume(len, |buf| {
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if let Some(fragmenter) = self.fragmenter.take().filter(|f| len > f.mtu()) {
+            let r = f(fragmenter.buffer(len));
+            if fragmenter.start(len) {
+                self.send(fragmenter.next_len(), |buf| fragmenter.emit_next(buf));
+            } else {
+                debug!("dropping packet larger than the MTU, only IPv4 packets are fragmented");
+            }
+            return r;
+        }
+        self.send(len, f)
+    }
+}
+
+impl<T> TxTokenAdapter<'_, T>
+where
+    T: TxToken,
+{
+    /// Send a frame of `len` bytes, written by `f`.
+    fn send<R>(self, len: usize, f: impl FnOnce(&mut [u8]) -> R) -> R {
+        #[cfg(feature = "stats")]
+        update(self.stats, |s| {
+            s.tx_packets = s.tx_packets.wrapping_add(1);
+            s.tx_bytes = s.tx_bytes.wrapping_add(len as u64);
+        });
+        self.inner.consume(len, |buf| {
             let r = f(buf);
             #[cfg(feature = "packet-trace")]
             trace!("embassy device tx: {:02x}", buf);
+            #[cfg(all(feature = "stats", feature = "tcp"))]
+            {
+                let (tcp, medium) = self.tcp;
+                if tcp.capture(medium, buf) {
+                    update(self.stats, |s| {
+                        s.tcp_retransmissions = s.tcp_retransmissions.wrapping_add(1)
+                    });
+                }
+            }
+            #[cfg(feature = "pcap")]
+            if let Some((sink, medium)) = self.sink {
+                capture(sink, medium, crate::pcap::Direction::Tx, buf);
+            }
             r
         })
     }
 }
+
+#[cfg(feature = "stats")]
+fn update(stats: &Cell<crate::stats::InterfaceStats>, f: impl FnOnce(&mut crate::stats::InterfaceStats)) {
+    let mut s = stats.get();
+    f(&mut s);
+    stats.set(s);
+}
+
+#[cfg(feature = "pcap")]
+fn capture(sink: &dyn crate::pcap::PacketSink, medium: Medium, direction: crate::pcap::Direction, data: &[u8]) {
+    use crate::pcap::LinkType;
+
+    let link_type = match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => LinkType::Ethernet,
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => LinkType::Ip,
+        #[cfg(feature = "medium-ieee802154")]
+        Medium::Ieee802154 => LinkType::Ieee802154,
+    };
+    sink.capture(&crate::pcap::Packet {
+        link_type,
+        direction,
+        timestamp: embassy_time::Instant::now(),
+        data,
+    });
+}
@@ -0,0 +1,562 @@
+//! Raw Ethernet sockets.
+//!
+//! Ethernet sockets send and receive whole Ethernet frames, header included, on an interface
+//! using the Ethernet medium. They're meant for protocols running directly over Ethernet, such
+//! as LLDP or EtherCAT, alongside the IP stack on the same driver.
+//!
+//! Received frames matching the [`Filter`] of a socket are copied to it before smoltcp handles
+//! them, so sockets receiving IPv4, IPv6 or ARP frames get a copy and the IP stack keeps working.
+//! Sent frames are passed to the driver as they are.
+
+use core::future::{poll_fn, Future};
+use core::mem;
+use core::task::{Context, Poll};
+
+use embassy_sync::waitqueue::WakerRegistration;
+use smoltcp::phy::{self, TxToken as _};
+use smoltcp::storage::PacketBuffer;
+use smoltcp::time::Instant;
+
+use crate::{HardwareAddress, InterfaceId, Stack};
+
+/// Number of Ethernet sockets each interface can hold.
+pub(crate) const MAX_SOCKETS: usize = 4;
+/// Size of the Ethernet header, without VLAN tag.
+const HEADER_LEN: usize = 14;
+
+/// Metadata of a queued frame, sized with the buffers given to [`EthernetSocket::new`].
+pub type PacketMetadata = smoltcp::storage::PacketMetadata<()>;
+
+/// Frames received by an [`EthernetSocket`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Filter {
+    /// All frames received by the interface.
+    All,
+    /// Frames with this EtherType, for example `0x88CC` for LLDP or `0x88A4` for EtherCAT.
+    ///
+    /// VLAN tags aren't looked through, the EtherType is the one following the source address.
+    EtherType(u16),
+}
+
+impl Filter {
+    fn matches(&self, frame: &[u8]) -> bool {
+        match self {
+            Filter::All => true,
+            Filter::EtherType(ethertype) => u16::from_be_bytes([frame[12], frame[13]]) == *ethertype,
+        }
+    }
+}
+
+/// Error returned by [`EthernetSocket::recv`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RecvError {
+    /// Provided buffer was smaller than the received frame.
+    Truncated,
+}
+
+/// Error returned by [`EthernetSocket::send`].
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SendError {
+    /// The frame is larger than the MTU of the driver, or than the transmit buffer.
+    PacketTooLarge,
+    /// The frame is shorter than an Ethernet header.
+    Truncated,
+}
+
+struct Slot {
+    filter: Filter,
+    rx: PacketBuffer<'static, ()>,
+    tx: PacketBuffer<'static, ()>,
+    rx_waker: WakerRegistration,
+    tx_waker: WakerRegistration,
+}
+
+/// Ethernet sockets of an interface.
+pub(crate) struct EthernetSockets {
+    slots: [Option<Slot>; MAX_SOCKETS],
+}
+
+impl EthernetSockets {
+    pub(crate) const fn new() -> Self {
+        Self {
+            slots: [const { None }; MAX_SOCKETS],
+        }
+    }
+
+    fn add(&mut self, slot: Slot) -> Option<usize> {
+        let n = self.slots.iter().position(|s| s.is_none())?;
+        self.slots[n] = Some(slot);
+        Some(n)
+    }
+
+    fn get(&mut self, n: usize) -> &mut Slot {
+        unwrap!(self.slots[n].as_mut())
+    }
+
+    /// Copy a received frame to the sockets it matches.
+    ///
+    /// Returns `false` if a socket had to drop the frame because its receive buffer is full.
+    pub(crate) fn capture(&mut self, frame: &[u8]) -> bool {
+        if frame.len() < HEADER_LEN {
+            return true;
+        }
+        let mut queued = true;
+        for slot in self.slots.iter_mut().flatten().filter(|s| s.filter.matches(frame)) {
+            match slot.rx.enqueue(frame.len(), ()) {
+                Ok(buf) => {
+                    buf.copy_from_slice(frame);
+                    slot.rx_waker.wake();
+                }
+                Err(_) => {
+                    trace!("ethernet socket buffer full, dropping frame");
+                    queued = false;
+                }
+            }
+        }
+        queued
+    }
+
+    /// Send the frames queued by the sockets, as long as the driver has room for them.
+    pub(crate) fn dispatch<D: phy::Device>(&mut self, device: &mut D, timestamp: Instant) {
+        for slot in self.slots.iter_mut().flatten() {
+            while !slot.tx.is_empty() {
+                let Some(token) = device.transmit(timestamp) else {
+                    return;
+                };
+                let Ok(((), frame)) = slot.tx.dequeue() else {
+                    unreachable!();
+                };
+                token.consume(frame.len(), |buf| buf.copy_from_slice(frame));
+                slot.tx_waker.wake();
+            }
+        }
+    }
+}
+
+/// A raw Ethernet socket.
+pub struct EthernetSocket<'a> {
+    stack: Stack<'a>,
+    interface: InterfaceId,
+    slot: usize,
+}
+
+impl<'a> EthernetSocket<'a> {
+    /// Create a new Ethernet socket on the primary interface, using the provided stack and buffers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the primary interface doesn't use the Ethernet medium, or if it already has
+    /// 4 Ethernet sockets.
+    pub fn new(
+        stack: Stack<'a>,
+        filter: Filter,
+        rx_meta: &'a mut [PacketMetadata],
+        rx_buffer: &'a mut [u8],
+        tx_meta: &'a mut [PacketMetadata],
+        tx_buffer: &'a mut [u8],
+    ) -> Self {
+        let rx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(rx_meta) };
+        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
+        let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
+        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
+        let slot = Slot {
+            filter,
+            rx: PacketBuffer::new(rx_meta, rx_buffer),
+            tx: PacketBuffer::new(tx_meta, tx_buffer),
+            rx_waker: WakerRegistration::new(),
+            tx_waker: WakerRegistration::new(),
+        };
+        let slot = stack.with_mut(|i| add(i.iface_mut(InterfaceId::PRIMARY), slot));
+
+        Self {
+            stack,
+            interface: InterfaceId::PRIMARY,
+            slot,
+        }
+    }
+
+    /// Bind the socket to a network interface of the stack.
+    ///
+    /// Sockets are created on the primary interface, and only send and receive frames through
+    /// the interface they're on. Queued frames are moved along with the socket.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the interface doesn't use the Ethernet medium, or if it already has 4 Ethernet
+    /// sockets.
+    pub fn bind_to_interface(&mut self, interface: InterfaceId) {
+        if interface == self.interface {
+            return;
+        }
+        self.stack.with_mut(|i| {
+            let slot = unwrap!(i.iface_mut(self.interface).ethernet.slots[self.slot].take());
+            self.slot = add(i.iface_mut(interface), slot);
+            self.interface = interface;
+        })
+    }
+
+    /// Get the hardware address of the interface the socket is on, to use as source address.
+    pub fn hardware_address(&self) -> HardwareAddress {
+        self.stack.with(|i| i.iface(self.interface).hardware_address)
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(&mut Slot) -> R) -> R {
+        self.stack.with_mut(|i| {
+            let iface = i.iface_mut(self.interface);
+            let res = f(iface.ethernet.get(self.slot));
+            iface.waker.wake();
+            res
+        })
+    }
+
+    /// Wait until the socket becomes readable.
+    ///
+    /// A socket is readable when a frame has been received, or when there are queued frames in
+    /// the buffer.
+    pub fn wait_recv_ready(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| self.poll_recv_ready(cx))
+    }
+
+    /// Wait until a frame can be read.
+    ///
+    /// When no frame is readable, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a frame is received.
+    ///
+    /// When a frame is received, this method will return `Poll::Ready`.
+    pub fn poll_recv_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.with_mut(|s| {
+            if s.rx.is_empty() {
+                s.rx_waker.register(cx.waker());
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+    }
+
+    /// Receive a frame, header included.
+    ///
+    /// This method will wait until a frame is received.
+    pub async fn recv(&self, buf: &mut [u8]) -> Result<usize, RecvError> {
+        poll_fn(move |cx| self.poll_recv(buf, cx)).await
+    }
+
+    /// Receive a frame, header included.
+    ///
+    /// When no frame is available, this method will return `Poll::Pending` and
+    /// register the current task to be notified when a frame is received.
+    ///
+    /// A frame larger than `buf` is dropped, and `Truncated` is returned.
+    pub fn poll_recv(&self, buf: &mut [u8], cx: &mut Context<'_>) -> Poll<Result<usize, RecvError>> {
+        self.with_mut(|s| match s.rx.dequeue() {
+            Ok(((), frame)) if frame.len() > buf.len() => Poll::Ready(Err(RecvError::Truncated)),
+            Ok(((), frame)) => {
+                buf[..frame.len()].copy_from_slice(frame);
+                Poll::Ready(Ok(frame.len()))
+            }
+            Err(_) => {
+                s.rx_waker.register(cx.waker());
+                Poll::Pending
+            }
+        })
+    }
+
+    /// Wait until the socket becomes writable.
+    ///
+    /// A socket becomes writable when there is space in the buffer, from initial memory or after
+    /// dispatching frames on a full buffer.
+    pub fn wait_send_ready(&self) -> impl Future<Output = ()> + '_ {
+        poll_fn(move |cx| self.poll_send_ready(cx))
+    }
+
+    /// Wait until a frame can be sent.
+    ///
+    /// When no frame can be sent (i.e. the buffer is full), this method will return
+    /// `Poll::Pending` and register the current task to be notified when
+    /// space is freed in the buffer after a frame has been dispatched.
+    ///
+    /// When a frame can be sent, this method will return `Poll::Ready`.
+    pub fn poll_send_ready(&self, cx: &mut Context<'_>) -> Poll<()> {
+        self.with_mut(|s| {
+            if s.tx.is_full() {
+                s.tx_waker.register(cx.waker());
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+    }
+
+    /// Send a frame, header included.
+    ///
+    /// This method will wait until the frame has been queued. The source address isn't filled
+    /// in, see [`hardware_address`](Self::hardware_address).
+    pub async fn send(&self, frame: &[u8]) -> Result<(), SendError> {
+        poll_fn(move |cx| self.poll_send(frame, cx)).await
+    }
+
+    /// Send a frame, header included.
+    ///
+    /// When the frame has been queued, this method will return `Poll::Ready(Ok())`.
+    ///
+    /// When the socket's send buffer is full, this method will return `Poll::Pending`
+    /// and register the current task to be notified when the buffer has space available.
+    pub fn poll_send(&self, frame: &[u8], cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        if frame.len() < HEADER_LEN {
+            return Poll::Ready(Err(SendError::Truncated));
+        }
+        let max_len = self.stack.with(|i| i.iface(self.interface).mtu) + HEADER_LEN;
+        self.with_mut(|s| {
+            if frame.len() > max_len || frame.len() > s.tx.payload_capacity() {
+                return Poll::Ready(Err(SendError::PacketTooLarge));
+            }
+            match s.tx.enqueue(frame.len(), ()) {
+                Ok(buf) => {
+                    buf.copy_from_slice(frame);
+                    Poll::Ready(Ok(()))
+                }
+                Err(_) => {
+                    s.tx_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Flush the socket.
+    ///
+    /// This method will wait until all queued frames have been passed to the driver.
+    pub fn flush(&mut self) -> impl Future<Output = ()> + '_ {
+        poll_fn(|cx| {
+            self.with_mut(|s| {
+                if s.tx.is_empty() {
+                    Poll::Ready(())
+                } else {
+                    s.tx_waker.register(cx.waker());
+                    Poll::Pending
+                }
+            })
+        })
+    }
+}
+
+/// Add a socket slot to `iface`, returning its index.
+fn add(iface: &mut crate::iface::Iface, slot: Slot) -> usize {
+    if !matches!(iface.hardware_address, HardwareAddress::Ethernet(_)) {
+        panic!("Ethernet sockets need an interface using the Ethernet medium");
+    }
+    match iface.ethernet.add(slot) {
+        Some(n) => n,
+        None => panic!("No room for another Ethernet socket on the interface"),
+    }
+}
+
+impl Drop for EthernetSocket<'_> {
+    fn drop(&mut self) {
+        self.stack
+            .with_mut(|i| i.iface_mut(self.interface).ethernet.slots[self.slot] = None);
+    }
+}
+
+fn _assert_covariant<'a, 'b: 'a>(x: EthernetSocket<'b>) -> EthernetSocket<'a> {
+    x
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use embassy_time::{with_timeout, Duration};
+
+    use super::*;
+    use crate::test_util::{self, static_config, MTU};
+
+    /// EtherTypes for local experiments, IEEE 802.
+    const LOCAL_1: u16 = 0x88b5;
+    const LOCAL_2: u16 = 0x88b6;
+
+    fn socket(stack: Stack<'static>, filter: Filter) -> EthernetSocket<'static> {
+        EthernetSocket::new(
+            stack,
+            filter,
+            Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+            Box::leak(Box::new([0; 4 * MTU])),
+            Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+            Box::leak(Box::new([0; 2 * MTU])),
+        )
+    }
+
+    fn mac(socket: &EthernetSocket<'_>) -> [u8; 6] {
+        match socket.hardware_address() {
+            HardwareAddress::Ethernet(addr) => addr.0,
+            #[allow(unreachable_patterns)]
+            _ => unreachable!(),
+        }
+    }
+
+    /// Ethernet frame from `src` to `dst`.
+    fn frame(dst: [u8; 6], src: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&ethertype.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    /// Receive a frame, or `None` if none is received within 100 ms.
+    async fn try_recv(socket: &EthernetSocket<'_>) -> Option<Vec<u8>> {
+        let mut buf = [0; MTU];
+        let n = unwrap!(with_timeout(Duration::from_millis(100), socket.recv(&mut buf))
+            .await
+            .ok()?);
+        Some(buf[..n].to_vec())
+    }
+
+    #[test]
+    fn receives_frames_of_its_ethertype() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut sender = socket(net.a, Filter::EtherType(LOCAL_1));
+            let receiver = socket(net.b, Filter::EtherType(LOCAL_1));
+            let other = socket(net.b, Filter::EtherType(LOCAL_2));
+            let (src, dst) = (mac(&sender), mac(&receiver));
+
+            let ignored = frame(dst, src, LOCAL_2, b"other protocol");
+            let matching = frame(dst, src, LOCAL_1, b"hello");
+            unwrap!(sender.send(&ignored).await);
+            unwrap!(sender.send(&matching).await);
+            sender.flush().await;
+
+            assert_eq!(try_recv(&receiver).await, Some(matching));
+            assert_eq!(try_recv(&receiver).await, None);
+            assert_eq!(try_recv(&other).await, Some(ignored));
+            // The sender doesn't receive its own frames.
+            assert_eq!(try_recv(&sender).await, None);
+        });
+    }
+
+    #[cfg(feature = "udp")]
+    #[test]
+    fn works_alongside_ip_traffic() {
+        use crate::test_util::addr;
+        use crate::udp::{self, UdpSocket};
+
+        fn udp_socket(stack: Stack<'static>) -> UdpSocket<'static> {
+            UdpSocket::new(
+                stack,
+                Box::leak(Box::new([udp::PacketMetadata::EMPTY; 4])),
+                Box::leak(Box::new([0; 512])),
+                Box::leak(Box::new([udp::PacketMetadata::EMPTY; 4])),
+                Box::leak(Box::new([0; 512])),
+            )
+        }
+
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let all = socket(net.b, Filter::All);
+            let ipv4 = socket(net.b, Filter::EtherType(0x0800));
+            let arp = socket(net.b, Filter::EtherType(0x0806));
+            let raw = socket(net.a, Filter::EtherType(LOCAL_1));
+            let mut sender = udp_socket(net.a);
+            unwrap!(sender.bind(1234));
+            let mut receiver = udp_socket(net.b);
+            unwrap!(receiver.bind(1234));
+
+            // Raw frames and IP packets are sent through the same interface.
+            let raw_frame = frame(mac(&all), mac(&raw), LOCAL_1, b"raw");
+            unwrap!(raw.send(&raw_frame).await);
+            unwrap!(sender.send_to(b"datagram", (addr(2), 1234)).await);
+
+            // The IP stack still gets the packets captured by the sockets.
+            let mut buf = [0; 16];
+            let (n, _) = unwrap!(receiver.recv_from(&mut buf).await);
+            assert_eq!(&buf[..n], b"datagram");
+
+            // The ARP request resolving the address of the receiver, and the datagram.
+            let request = unwrap!(try_recv(&arp).await);
+            assert_eq!(request[..6], [0xff; 6]);
+            let packet = unwrap!(try_recv(&ipv4).await);
+            assert!(packet.ends_with(b"datagram"));
+
+            // The socket receiving all frames gets all of them.
+            let mut frames = Vec::new();
+            while let Some(frame) = try_recv(&all).await {
+                frames.push(frame);
+            }
+            assert!(frames.contains(&raw_frame));
+            assert!(frames.contains(&request));
+            assert!(frames.contains(&packet));
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let sender = socket(net.a, Filter::EtherType(LOCAL_1));
+            let receiver = socket(net.b, Filter::EtherType(LOCAL_1));
+            let (src, dst) = (mac(&sender), mac(&receiver));
+
+            assert_eq!(sender.send(&dst).await, Err(SendError::Truncated));
+            // Larger than the MTU of the driver, then than the transmit buffer.
+            let too_large = frame(dst, src, LOCAL_1, &[0; MTU - HEADER_LEN + 1]);
+            assert_eq!(sender.send(&too_large).await, Err(SendError::PacketTooLarge));
+            let small_buffers = EthernetSocket::new(
+                net.a,
+                Filter::EtherType(LOCAL_2),
+                Box::leak(Box::new([PacketMetadata::EMPTY; 1])),
+                Box::leak(Box::new([0; 64])),
+                Box::leak(Box::new([PacketMetadata::EMPTY; 1])),
+                Box::leak(Box::new([0; 64])),
+            );
+            let larger_than_buffer = frame(dst, src, LOCAL_1, &[0; 64]);
+            assert_eq!(
+                small_buffers.send(&larger_than_buffer).await,
+                Err(SendError::PacketTooLarge)
+            );
+
+            // A frame larger than the receive buffer is dropped.
+            let large = frame(dst, src, LOCAL_1, &[1; 100]);
+            let small = frame(dst, src, LOCAL_1, &[2; 10]);
+            unwrap!(sender.send(&large).await);
+            unwrap!(sender.send(&small).await);
+            receiver.wait_recv_ready().await;
+            let mut buf = [0; 64];
+            assert_eq!(receiver.recv(&mut buf).await, Err(RecvError::Truncated));
+            let n = unwrap!(receiver.recv(&mut buf).await);
+            assert_eq!(buf[..n], small[..]);
+        });
+    }
+
+    #[test]
+    fn sockets_are_limited_per_interface() {
+        let (_, _, [device, _]) = test_util::link();
+        let (stack, _runner) = test_util::stack(device, static_config(1));
+        let sockets: Vec<_> = (0..MAX_SOCKETS).map(|_| socket(stack, Filter::All)).collect();
+        // Dropping a socket makes room for another one.
+        drop(sockets);
+        let _sockets: Vec<_> = (0..MAX_SOCKETS).map(|_| socket(stack, Filter::All)).collect();
+    }
+
+    #[test]
+    #[should_panic(expected = "No room for another Ethernet socket on the interface")]
+    fn panics_when_interface_is_full() {
+        let (_, _, [device, _]) = test_util::link();
+        let (stack, _runner) = test_util::stack(device, static_config(1));
+        let _sockets: Vec<_> = (0..=MAX_SOCKETS).map(|_| socket(stack, Filter::All)).collect();
+    }
+
+    #[cfg(feature = "medium-ip")]
+    #[test]
+    #[should_panic(expected = "Ethernet sockets need an interface using the Ethernet medium")]
+    fn panics_on_non_ethernet_interface() {
+        use embassy_net_driver_channel::virtual_link;
+
+        let state = Box::leak(Box::new(virtual_link::State::<MTU, 2, 16>::new()));
+        let (_, _, [device, _]) = virtual_link::new(state, [crate::driver::HardwareAddress::Ip; 2], 1);
+        let (stack, _runner) = test_util::stack(device, static_config(1));
+        socket(stack, Filter::All);
+    }
+}
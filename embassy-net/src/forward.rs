@@ -0,0 +1,207 @@
+//! IPv4 forwarding between interfaces.
+//!
+//! smoltcp drops received packets that aren't addressed to one of the interface's addresses, so
+//! they're captured from the driver before smoltcp sees them, and queued. The stack then looks up
+//! the outgoing interface for each queued packet, and sends it through one of that interface's
+//! forwarding raw sockets, which takes care of resolving the next hop. Packets stay in the queue
+//! while that socket is busy, so it only needs room for one packet.
+
+use core::task::Context;
+
+use smoltcp::iface::{SocketHandle, SocketSet};
+use smoltcp::phy::Medium;
+use smoltcp::socket::raw;
+use smoltcp::storage::{PacketBuffer, PacketMetadata};
+use smoltcp::wire::{IpCidr, IpProtocol, IpVersion, Ipv4Address, Ipv4Cidr, Ipv4Packet};
+
+use crate::HardwareAddress;
+
+/// Maximum size of a forwarded packet.
+const MAX_PACKET_SIZE: usize = 1500;
+/// Number of received packets queued for forwarding.
+const QUEUE_LEN: usize = 2;
+/// Protocols that can be forwarded, smoltcp raw sockets only handle one protocol each.
+const PROTOCOLS: [IpProtocol; 3] = [IpProtocol::Tcp, IpProtocol::Udp, IpProtocol::Icmp];
+/// Number of socket slots used for forwarding on each interface.
+pub(crate) const SOCKET_COUNT: usize = PROTOCOLS.len();
+
+pub(crate) struct ForwardResources {
+    rx_meta: [PacketMetadata<()>; QUEUE_LEN],
+    rx_data: [u8; QUEUE_LEN * MAX_PACKET_SIZE],
+    tx_meta: [[raw::PacketMetadata; 1]; SOCKET_COUNT],
+    tx_data: [[u8; MAX_PACKET_SIZE]; SOCKET_COUNT],
+}
+
+impl ForwardResources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; QUEUE_LEN],
+            rx_data: [0; QUEUE_LEN * MAX_PACKET_SIZE],
+            tx_meta: [[raw::PacketMetadata::EMPTY; 1]; SOCKET_COUNT],
+            tx_data: [[0; MAX_PACKET_SIZE]; SOCKET_COUNT],
+        }
+    }
+}
+
+pub(crate) struct Forward {
+    /// Received packets to forward.
+    queue: PacketBuffer<'static, ()>,
+    /// Raw sockets sending forwarded packets, one per protocol in `PROTOCOLS`.
+    sockets: [SocketHandle; SOCKET_COUNT],
+    /// Addresses of the interface, packets sent to them aren't forwarded.
+    addrs: heapless::Vec<Ipv4Cidr, 2>,
+    /// Hardware address of the interface, frames sent to other hosts aren't forwarded.
+    #[cfg_attr(not(feature = "medium-ethernet"), allow(unused))]
+    hardware_address: HardwareAddress,
+}
+
+impl Forward {
+    pub(crate) fn new(
+        sockets: &mut SocketSet<'static>,
+        resources: &'static mut ForwardResources,
+        hardware_address: HardwareAddress,
+    ) -> Self {
+        let mut tx = resources.tx_meta.iter_mut().zip(resources.tx_data.iter_mut());
+        let sockets = PROTOCOLS.map(|protocol| {
+            let (tx_meta, tx_data) = unwrap!(tx.next());
+            // Forwarded packets are never received through these sockets.
+            let rx_meta: &'static mut [raw::PacketMetadata] = &mut [];
+            let rx_data: &'static mut [u8] = &mut [];
+            let rx = raw::PacketBuffer::new(rx_meta, rx_data);
+            let tx = raw::PacketBuffer::new(&mut tx_meta[..], &mut tx_data[..]);
+            sockets.add(raw::Socket::new(IpVersion::Ipv4, protocol, rx, tx))
+        });
+
+        Self {
+            queue: PacketBuffer::new(&mut resources.rx_meta[..], &mut resources.rx_data[..]),
+            sockets,
+            addrs: heapless::Vec::new(),
+            hardware_address,
+        }
+    }
+
+    pub(crate) fn remove(self, sockets: &mut SocketSet<'static>) {
+        for handle in self.sockets {
+            sockets.remove(handle);
+        }
+    }
+
+    pub(crate) fn set_addrs(&mut self, addrs: &[IpCidr]) {
+        self.addrs.clear();
+        for addr in addrs {
+            #[allow(irrefutable_let_patterns)]
+            if let IpCidr::Ipv4(cidr) = addr {
+                if self.addrs.push(*cidr).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Queue `frame` for forwarding if it is an IPv4 packet sent to us, but not addressed to us.
+    ///
+    /// Returns `false` if the packet had to be dropped because the queue is full.
+    pub(crate) fn capture(&mut self, medium: Medium, frame: &[u8]) -> bool {
+        let packet = match medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => {
+                let Ok(frame) = smoltcp::wire::EthernetFrame::new_checked(frame) else {
+                    return true;
+                };
+                if frame.ethertype() != smoltcp::wire::EthernetProtocol::Ipv4
+                    || HardwareAddress::Ethernet(frame.dst_addr()) != self.hardware_address
+                {
+                    return true;
+                }
+                &frame.into_inner()[smoltcp::wire::EthernetFrame::<&[u8]>::header_len()..]
+            }
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => frame,
+            #[allow(unreachable_patterns)]
+            _ => return true,
+        };
+
+        let Ok(packet) = Ipv4Packet::new_checked(packet) else {
+            return true;
+        };
+        if !packet.verify_checksum() || !self.should_forward(&packet) {
+            return true;
+        }
+
+        let len = usize::from(packet.total_len());
+        let packet = &packet.into_inner()[..len];
+        match self.queue.enqueue(packet.len(), ()) {
+            Ok(buf) => {
+                buf.copy_from_slice(packet);
+                true
+            }
+            Err(_) => {
+                trace!("forwarding queue full, dropping packet");
+                false
+            }
+        }
+    }
+
+    fn should_forward(&self, packet: &Ipv4Packet<&[u8]>) -> bool {
+        let src = packet.src_addr();
+        let dst = packet.dst_addr();
+        let special = |addr: Ipv4Address| {
+            addr.is_unspecified()
+                || addr.is_broadcast()
+                || addr.is_multicast()
+                || addr.is_loopback()
+                || addr.is_link_local()
+                || self
+                    .addrs
+                    .iter()
+                    .any(|c| c.address() == addr || c.broadcast() == Some(addr))
+        };
+
+        !special(src)
+            && !special(dst)
+            && packet.hop_limit() > 1
+            // Fragments and oversized packets can't go through smoltcp raw sockets.
+            && !packet.more_frags()
+            && packet.frag_offset() == 0
+            && usize::from(packet.total_len()) <= MAX_PACKET_SIZE
+            && PROTOCOLS.contains(&packet.next_header())
+    }
+
+    /// Get the destination address of the next packet to forward.
+    pub(crate) fn peek(&mut self) -> Option<Ipv4Address> {
+        self.queue
+            .peek()
+            .ok()
+            .map(|(_, packet)| Ipv4Packet::new_unchecked(packet).dst_addr())
+    }
+
+    /// Drop the next packet to forward.
+    pub(crate) fn drop_next(&mut self) {
+        let _ = self.queue.dequeue();
+    }
+
+    /// Send the next packet queued in `self`, through the interface of `to`.
+    ///
+    /// Returns `false`, leaving the packet queued, if that interface is still sending the previous
+    /// packet of the same protocol. `cx` is woken when it's done.
+    pub(crate) fn forward_next(&mut self, to: &Self, sockets: &mut SocketSet<'static>, cx: &mut Context<'_>) -> bool {
+        let Ok(((), packet)) = self.queue.peek() else {
+            return true;
+        };
+        let protocol = Ipv4Packet::new_unchecked(packet).next_header();
+        let index = unwrap!(PROTOCOLS.iter().position(|p| *p == protocol));
+        let socket = sockets.get_mut::<raw::Socket>(to.sockets[index]);
+        if !socket.can_send() {
+            socket.register_send_waker(cx.waker());
+            return false;
+        }
+
+        let ((), packet) = unwrap!(self.queue.dequeue());
+        let mut packet = Ipv4Packet::new_unchecked(packet);
+        packet.set_hop_limit(packet.hop_limit() - 1);
+        packet.fill_checksum();
+        // The socket has room for a packet of any size that can be forwarded.
+        unwrap!(socket.send_slice(packet.into_inner()));
+        true
+    }
+}
@@ -0,0 +1,513 @@
+//! IPv4 fragmentation and reassembly.
+//!
+//! smoltcp keeps its fragmentation buffers inside the interface, sized by its own cargo features,
+//! so it's done here instead, between smoltcp and the driver, with buffers from the interface
+//! resources. smoltcp is told the MTU is the size of those buffers: IPv4 packets it sends that
+//! are larger than the driver MTU are split into fragments, and received fragments are
+//! reassembled before smoltcp sees them. Like smoltcp by default, one packet is reassembled and
+//! one is fragmented at a time.
+
+use embassy_time::{Duration, Instant};
+use smoltcp::phy::Medium;
+use smoltcp::storage::Assembler;
+use smoltcp::wire::{Ipv4FragKey, Ipv4Packet};
+
+/// Default time fragments of a packet are kept while waiting for the others.
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+/// Length of an IPv4 header without options.
+const MIN_HEADER_LEN: usize = 20;
+/// Length of the largest IPv4 header.
+const MAX_HEADER_LEN: usize = 60;
+/// Length of the largest link-layer header, the Ethernet one.
+const MAX_LINK_LEN: usize = 14;
+/// Largest IPv4 packet, its length has to fit the 16-bit total length field.
+const MAX_PACKET_LEN: usize = u16::MAX as usize;
+
+pub(crate) struct FragmentResources<const N: usize> {
+    reassembly: [u8; N],
+    fragmentation: [u8; N],
+}
+
+impl<const N: usize> FragmentResources<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            reassembly: [0; N],
+            fragmentation: [0; N],
+        }
+    }
+}
+
+pub(crate) struct Fragments {
+    pub(crate) reassembler: Reassembler,
+    pub(crate) fragmenter: Fragmenter,
+}
+
+impl Fragments {
+    /// Create the fragmentation state of an interface sending frames of up to `mtu` bytes.
+    ///
+    /// Returns `None` if the medium doesn't carry IPv4 packets.
+    #[cfg_attr(
+        not(any(feature = "medium-ethernet", feature = "medium-ip")),
+        allow(unreachable_code, unused_variables)
+    )]
+    pub(crate) fn new<const N: usize>(
+        resources: &'static mut FragmentResources<N>,
+        medium: Medium,
+        mtu: usize,
+        ident: u16,
+    ) -> Option<Self> {
+        let link_len = match medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => smoltcp::wire::EthernetFrame::<&[u8]>::header_len(),
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => 0,
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+        let FragmentResources {
+            reassembly,
+            fragmentation,
+        } = resources;
+        Some(Self {
+            reassembler: Reassembler::new(reassembly, link_len),
+            fragmenter: Fragmenter::new(fragmentation, link_len, mtu, ident),
+        })
+    }
+
+    /// Get the size of the largest frame smoltcp can send, to be split into fragments.
+    pub(crate) fn max_frame_len(&self) -> usize {
+        let f = &self.fragmenter;
+        f.mtu.max(f.buffer.len().min(f.link_len + MAX_PACKET_LEN))
+    }
+}
+
+/// Reassembles fragmented IPv4 packets.
+pub(crate) struct Reassembler {
+    /// The payload is written after the link-layer header and an IPv4 header without options.
+    buffer: &'static mut [u8],
+    link_len: usize,
+    /// The packet being reassembled.
+    key: Option<Ipv4FragKey>,
+    assembler: Assembler,
+    /// Link-layer and IPv4 headers of the first fragment.
+    headers: [u8; MAX_LINK_LEN + MAX_HEADER_LEN],
+    /// Length of the IPv4 header, once the first fragment is received.
+    header_len: Option<usize>,
+    /// Length of the payload, once the last fragment is received.
+    payload_len: Option<usize>,
+    expires_at: Instant,
+    /// Time fragments of a packet are kept while waiting for the others.
+    pub(crate) timeout: Duration,
+}
+
+impl Reassembler {
+    fn new(buffer: &'static mut [u8], link_len: usize) -> Self {
+        Self {
+            buffer,
+            link_len,
+            key: None,
+            assembler: Assembler::new(),
+            headers: [0; MAX_LINK_LEN + MAX_HEADER_LEN],
+            header_len: None,
+            payload_len: None,
+            expires_at: Instant::MIN,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Process a received frame.
+    ///
+    /// Returns the frame to give to smoltcp: `frame` itself if it isn't an IPv4 fragment, the
+    /// reassembled packet if `frame` completes it, or `None` otherwise.
+    pub(crate) fn process<'a>(&'a mut self, frame: &'a [u8]) -> Option<&'a [u8]> {
+        let Some(packet) = self.fragment(frame) else {
+            return Some(frame);
+        };
+        if !packet.verify_checksum() {
+            return None;
+        }
+
+        let now = Instant::now();
+        let key = packet.get_key();
+        if self.key != Some(key) {
+            if self.key.is_some() && self.expires_at > now {
+                debug!("dropping IPv4 fragment, another packet is being reassembled");
+                return None;
+            }
+            self.key = Some(key);
+            self.assembler.clear();
+            self.header_len = None;
+            self.payload_len = None;
+            self.expires_at = now + self.timeout;
+        }
+
+        let offset = usize::from(packet.frag_offset());
+        let payload = packet.payload();
+        let start = self.payload_start() + offset;
+        if start + payload.len() > self.max_len() {
+            return self.abort("dropping fragmented IPv4 packet larger than the reassembly buffer");
+        }
+        if self.assembler.add(offset, payload.len()).is_err() {
+            return self.abort("dropping fragmented IPv4 packet, too many fragments are missing");
+        }
+        self.buffer[start..][..payload.len()].copy_from_slice(payload);
+        if !packet.more_frags() {
+            self.payload_len = Some(offset + payload.len());
+        }
+        if offset == 0 {
+            let header_len = usize::from(packet.header_len());
+            let headers = self.link_len + header_len;
+            self.headers[..headers].copy_from_slice(&frame[..headers]);
+            self.header_len = Some(header_len);
+        }
+
+        let (Some(header_len), Some(payload_len)) = (self.header_len, self.payload_len) else {
+            return None;
+        };
+        if self.assembler.peek_front() < payload_len {
+            return None;
+        }
+        self.key = None;
+
+        // Make room for the options of the header, if any.
+        let headers = self.link_len + header_len;
+        let len = headers + payload_len;
+        if len > self.max_len() {
+            return self.abort("dropping fragmented IPv4 packet larger than the reassembly buffer");
+        }
+        let payload_start = self.payload_start();
+        self.buffer
+            .copy_within(payload_start..payload_start + payload_len, headers);
+        self.buffer[..headers].copy_from_slice(&self.headers[..headers]);
+
+        let frame = &mut self.buffer[..len];
+        let mut packet = Ipv4Packet::new_unchecked(&mut frame[self.link_len..]);
+        packet.set_total_len((header_len + payload_len) as u16);
+        packet.set_more_frags(false);
+        packet.set_frag_offset(0);
+        packet.fill_checksum();
+        Some(frame)
+    }
+
+    /// Get the IPv4 packet in `frame`, if it's a fragment.
+    fn fragment<'a>(&self, frame: &'a [u8]) -> Option<Ipv4Packet<&'a [u8]>> {
+        if self.link_len != 0 && frame.get(12..14) != Some(&[0x08, 0x00]) {
+            return None;
+        }
+        let packet = Ipv4Packet::new_checked(frame.get(self.link_len..)?).ok()?;
+        (packet.version() == 4 && (packet.more_frags() || packet.frag_offset() != 0)).then_some(packet)
+    }
+
+    fn abort<'a>(&mut self, reason: &'static str) -> Option<&'a [u8]> {
+        debug!("{}", reason);
+        self.key = None;
+        None
+    }
+
+    /// Get the offset of the payload in the buffer.
+    fn payload_start(&self) -> usize {
+        self.link_len + MIN_HEADER_LEN
+    }
+
+    /// Get the size of the largest frame that can be reassembled.
+    fn max_len(&self) -> usize {
+        self.buffer.len().min(self.link_len + MAX_PACKET_LEN)
+    }
+}
+
+/// Splits IPv4 packets larger than the driver MTU into fragments.
+pub(crate) struct Fragmenter {
+    /// The frame being sent.
+    buffer: &'static mut [u8],
+    link_len: usize,
+    /// Size of the largest frame the driver sends.
+    mtu: usize,
+    /// Length of the IPv4 header of the packet being sent.
+    header_len: usize,
+    /// Length of the frame being sent, 0 if there's none.
+    len: usize,
+    /// Offset in the frame of the payload not sent yet.
+    sent: usize,
+    next_ident: u16,
+}
+
+impl Fragmenter {
+    fn new(buffer: &'static mut [u8], link_len: usize, mtu: usize, ident: u16) -> Self {
+        Self {
+            buffer,
+            link_len,
+            mtu,
+            header_len: 0,
+            len: 0,
+            sent: 0,
+            next_ident: ident,
+        }
+    }
+
+    /// Get the size of the largest frame the driver sends.
+    pub(crate) fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Whether fragments of a packet are still waiting to be sent.
+    pub(crate) fn is_pending(&self) -> bool {
+        self.sent < self.len
+    }
+
+    /// Get the buffer to write a frame of `len` bytes in, before calling [`Fragmenter::start`].
+    pub(crate) fn buffer(&mut self, len: usize) -> &mut [u8] {
+        &mut self.buffer[..len]
+    }
+
+    /// Start sending the frame of `len` bytes written in the buffer.
+    ///
+    /// Returns false if it isn't an IPv4 packet, or has a header too large to be fragmented.
+    pub(crate) fn start(&mut self, len: usize) -> bool {
+        self.len = 0;
+        self.sent = 0;
+        let frame = &mut self.buffer[..len];
+        if self.link_len != 0 && frame.get(12..14) != Some(&[0x08, 0x00]) {
+            return false;
+        }
+        let Ok(mut packet) = Ipv4Packet::new_checked(&mut frame[self.link_len..]) else {
+            return false;
+        };
+        let header_len = usize::from(packet.header_len());
+        if packet.version() != 4 || self.link_len + header_len + 8 > self.mtu {
+            return false;
+        }
+        // smoltcp only sets the identification of the packets it fragments itself.
+        packet.set_ident(self.next_ident);
+        packet.set_dont_frag(false);
+        self.next_ident = self.next_ident.wrapping_add(1);
+        self.header_len = header_len;
+        self.len = self.link_len + usize::from(packet.total_len());
+        self.sent = self.link_len + header_len;
+        true
+    }
+
+    /// Get the length of the next fragment to send.
+    pub(crate) fn next_len(&self) -> usize {
+        let headers = self.link_len + self.header_len;
+        // The offsets of fragments are counted in units of 8 bytes.
+        let max_payload = (self.mtu - headers) / 8 * 8;
+        headers + (self.len - self.sent).min(max_payload)
+    }
+
+    /// Write the next fragment in `buf`, which has the length returned by [`Fragmenter::next_len`].
+    pub(crate) fn emit_next(&mut self, buf: &mut [u8]) {
+        let headers = self.link_len + self.header_len;
+        let payload_len = buf.len() - headers;
+        let offset = self.sent - headers;
+        buf[..headers].copy_from_slice(&self.buffer[..headers]);
+        buf[headers..].copy_from_slice(&self.buffer[self.sent..][..payload_len]);
+        self.sent += payload_len;
+
+        let mut packet = Ipv4Packet::new_unchecked(&mut buf[self.link_len..]);
+        packet.set_total_len((self.header_len + payload_len) as u16);
+        packet.set_more_frags(self.is_pending());
+        packet.set_frag_offset(offset as u16);
+        packet.fill_checksum();
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+    use std::vec::Vec;
+
+    use smoltcp::wire::{EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, IpProtocol, Ipv4Repr};
+
+    use super::*;
+    use crate::test_util::{self, addr, static_config};
+    use crate::udp::{PacketMetadata, UdpSocket};
+    use crate::{Stack, StackResources};
+
+    /// Size of the fragmentation buffers, for IPv4 packets of up to 4096 bytes.
+    const FRAG: usize = 4110;
+    /// Size of the largest frame of the test links.
+    const MTU: usize = test_util::MTU;
+
+    fn buffer(len: usize) -> &'static mut [u8] {
+        Box::leak(std::vec![0; len].into_boxed_slice())
+    }
+
+    /// Ethernet frame with an IPv4 packet carrying `payload_len` bytes.
+    fn frame(payload_len: usize) -> Vec<u8> {
+        let ethernet = EthernetRepr {
+            src_addr: EthernetAddress([0x02, 0, 0, 0, 0, 1]),
+            dst_addr: EthernetAddress([0x02, 0, 0, 0, 0, 2]),
+            ethertype: EthernetProtocol::Ipv4,
+        };
+        let ip = Ipv4Repr {
+            src_addr: addr(1),
+            dst_addr: addr(2),
+            next_header: IpProtocol::Udp,
+            payload_len,
+            hop_limit: 64,
+        };
+        let mut frame = std::vec![0; ethernet.buffer_len() + ip.buffer_len() + payload_len];
+        ethernet.emit(&mut EthernetFrame::new_unchecked(&mut frame[..]));
+        ip.emit(&mut Ipv4Packet::new_unchecked(&mut frame[14..]), &Default::default());
+        for (i, b) in frame[34..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        frame
+    }
+
+    /// Split `frame` in fragments of up to `mtu` bytes, with the identification `ident`.
+    fn fragment(frame: &[u8], mtu: usize, ident: u16) -> Vec<Vec<u8>> {
+        let mut fragmenter = Fragmenter::new(buffer(FRAG), 14, mtu, ident);
+        fragmenter.buffer(frame.len()).copy_from_slice(frame);
+        assert!(fragmenter.start(frame.len()));
+        let mut fragments = Vec::new();
+        while fragmenter.is_pending() {
+            let mut fragment = std::vec![0; fragmenter.next_len()];
+            fragmenter.emit_next(&mut fragment);
+            fragments.push(fragment);
+        }
+        fragments
+    }
+
+    /// Get `frame` as it's reassembled: with the identification `ident`, and without the "don't
+    /// fragment" flag smoltcp sets.
+    fn reassembled(mut frame: Vec<u8>, ident: u16) -> Vec<u8> {
+        let mut packet = Ipv4Packet::new_unchecked(&mut frame[14..]);
+        packet.set_ident(ident);
+        packet.set_dont_frag(false);
+        packet.fill_checksum();
+        frame
+    }
+
+    #[test]
+    fn fragments_packets() {
+        let frame = frame(3000);
+        let fragments = fragment(&frame, MTU, 7);
+
+        // The payload is split in 1480-byte parts, a multiple of 8.
+        let lens: Vec<_> = fragments.iter().map(Vec::len).collect();
+        assert_eq!(lens, [MTU, MTU, 14 + 20 + 40]);
+        for (i, fragment) in fragments.iter().enumerate() {
+            assert_eq!(fragment[..14], frame[..14]);
+            let packet = unwrap!(Ipv4Packet::new_checked(&fragment[14..]));
+            assert!(packet.verify_checksum());
+            assert_eq!(packet.ident(), 7);
+            assert!(!packet.dont_frag());
+            assert_eq!(packet.more_frags(), i < 2);
+            assert_eq!(usize::from(packet.frag_offset()), i * 1480);
+            assert_eq!(packet.payload(), &frame[34 + i * 1480..][..packet.payload().len()]);
+        }
+
+        // Packets with a header that doesn't leave room for 8 bytes of payload aren't fragmented.
+        let mut fragmenter = Fragmenter::new(buffer(FRAG), 14, 14 + 20 + 7, 7);
+        fragmenter.buffer(frame.len()).copy_from_slice(&frame);
+        assert!(!fragmenter.start(frame.len()));
+        assert!(!fragmenter.is_pending());
+    }
+
+    #[test]
+    fn reassembles_packets() {
+        let mut reassembler = Reassembler::new(buffer(FRAG), 14);
+
+        // Packets that aren't fragmented are passed through.
+        let small = frame(100);
+        assert_eq!(reassembler.process(&small), Some(&small[..]));
+
+        // Fragments out of order, with a duplicate.
+        let frame = frame(3000);
+        let fragments = fragment(&frame, MTU, 7);
+        for fragment in [&fragments[2], &fragments[0], &fragments[2]] {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+        let packet = unwrap!(reassembler.process(&fragments[1])).to_vec();
+        assert_eq!(packet, reassembled(frame.clone(), 7));
+
+        // Fragments with a bad checksum are dropped.
+        let mut fragments = fragment(&frame, MTU, 8);
+        fragments[1][24] ^= 1;
+        for fragment in &fragments {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+    }
+
+    #[test]
+    fn reassembles_one_packet_at_a_time() {
+        let mut reassembler = Reassembler::new(buffer(FRAG), 14);
+        let large = frame(3000);
+        let first = fragment(&large, MTU, 1);
+        let second = fragment(&large, MTU, 2);
+
+        // The fragments of the second packet are dropped until the first is reassembled.
+        assert_eq!(reassembler.process(&first[0]), None);
+        for fragment in &second {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+        assert_eq!(reassembler.process(&first[1]), None);
+        assert!(reassembler.process(&first[2]).is_some());
+        for fragment in &second[..2] {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+        assert!(reassembler.process(&second[2]).is_some());
+
+        // Or until it times out.
+        reassembler.timeout = Duration::from_ticks(0);
+        assert_eq!(reassembler.process(&first[0]), None);
+        for fragment in &second[..2] {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+        let packet = unwrap!(reassembler.process(&second[2])).to_vec();
+        assert_eq!(packet, reassembled(large.clone(), 2));
+
+        // Packets larger than the buffer are dropped.
+        let mut reassembler = Reassembler::new(buffer(2000), 14);
+        let fragments = fragment(&large, MTU, 3);
+        for fragment in &fragments {
+            assert_eq!(reassembler.process(fragment), None);
+        }
+        let small = frame(1900);
+        let fragments = fragment(&small, 1014, 4);
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(reassembler.process(&fragments[0]), None);
+        let packet = unwrap!(reassembler.process(&fragments[1])).to_vec();
+        assert_eq!(packet, reassembled(small, 4));
+    }
+
+    fn stack(device: test_util::Device, n: u8) -> (Stack<'static>, test_util::Runner) {
+        let resources = Box::leak(Box::new(StackResources::<8, FRAG>::new()));
+        crate::new(device, static_config(n), resources, n.into())
+    }
+
+    fn socket(stack: Stack<'static>) -> UdpSocket<'static> {
+        let mut socket = UdpSocket::new(
+            stack,
+            Box::leak(Box::new([PacketMetadata::EMPTY; 2])),
+            buffer(8192),
+            Box::leak(Box::new([PacketMetadata::EMPTY; 2])),
+            buffer(8192),
+        );
+        unwrap!(socket.bind(9));
+        socket
+    }
+
+    #[test]
+    fn sends_and_receives_fragmented_datagrams() {
+        let (_, switch, [device_a, device_b]) = test_util::link();
+        let (a, runner_a) = stack(device_a, 1);
+        let (b, runner_b) = stack(device_b, 2);
+        test_util::run_with([switch], [runner_a, runner_b], async {
+            assert_eq!((a.mtu(), b.mtu()), (1500, 1500));
+            let socket_a = socket(a);
+            let socket_b = socket(b);
+            let datagram: Vec<u8> = (0..4000).map(|i| (i % 251) as u8).collect();
+            let mut buf = std::vec![0; 8192];
+
+            unwrap!(socket_a.send_to(&datagram, (addr(2), 9)).await);
+            let (n, meta) = unwrap!(socket_b.recv_from(&mut buf).await);
+            assert_eq!(buf[..n], datagram[..]);
+
+            unwrap!(socket_b.send_to(&buf[..n], meta.endpoint).await);
+            let (n, _) = unwrap!(socket_a.recv_from(&mut buf).await);
+            assert_eq!(buf[..n], datagram[..]);
+        });
+    }
+}
@@ -0,0 +1,1176 @@
+//! HTTP/1.1 server.
+//!
+//! [`HttpServer`] accepts connections on a pool of TCP sockets and passes each request to a [`Handler`].
+//! It doesn't allocate: the request head is read into a buffer given by each worker, and the method,
+//! path, query and headers of the [`Request`] are slices of that buffer.
+//!
+//! Several workers can serve the same server concurrently, by calling [`HttpServer::run`] from several
+//! tasks, or joining several `run` futures. Each worker serves one connection at a time, and keeps it
+//! open between requests unless the client asks otherwise.
+//!
+//! Request bodies are read from [`Request::body`], delimited by their `Content-Length` or sent with
+//! the chunked transfer coding. Responses are written with [`Response`], with a body of known length
+//! or streamed in chunks.
+//!
+//! A [`Router`] dispatches requests to handlers by method and path, and answers `404 Not Found` and
+//! `405 Method Not Allowed` on its own:
+//!
+//! ```ignore
+//! enum Api {
+//!     Status,
+//!     Led,
+//! }
+//!
+//! impl Handler for Api {
+//!     async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, Error> {
+//!         match self {
+//!             Api::Status => response.send(Status::OK, &[("Content-Type", "text/plain")], b"ok").await,
+//!             Api::Led => {
+//!                 let led = request.param("id");
+//!                 // ...
+//!                 response.send(Status::NO_CONTENT, &[], &[]).await
+//!             }
+//!         }
+//!     }
+//! }
+//!
+//! static ROUTES: [Route<Api>; 2] = [
+//!     Route::new(Method::Get, "/status", Api::Status),
+//!     Route::new(Method::Put, "/leds/{id}", Api::Led),
+//! ];
+//!
+//! server.run(&Router::new(&ROUTES), &mut [0; 1024]).await;
+//! ```
+//!
+//! Paths and queries are passed as received, without percent-decoding.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write as _;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::MultiWakerRegistration;
+use embassy_time::{with_deadline, with_timeout, Duration, Instant};
+use embedded_io_async::Write as _;
+use heapless::{String, Vec};
+
+use crate::tcp::listener::{TcpConnection, TcpListener, TcpListenerState};
+use crate::tcp::{TcpReader, TcpWriter};
+use crate::{IpListenEndpoint, Stack};
+
+/// Maximum number of headers in a request.
+pub const MAX_HEADERS: usize = 24;
+pub use crate::router::MAX_PARAMS;
+/// Maximum length of the lines framing the chunks of a request body.
+const MAX_CHUNK_LINE_LEN: usize = 256;
+
+/// HTTP server error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The connection was reset by the client.
+    ConnectionReset,
+    /// The connection was closed in the middle of a request.
+    ConnectionClosed,
+    /// The client didn't send data in time.
+    Timeout,
+    /// The request body is malformed.
+    BadRequest,
+    /// The request body doesn't fit in the buffer.
+    BodyTooLarge,
+    /// The response body doesn't have the length given when starting the response.
+    BodyLength,
+}
+
+impl From<crate::tcp::Error> for Error {
+    fn from(err: crate::tcp::Error) -> Self {
+        match err {
+            crate::tcp::Error::ConnectionReset => Self::ConnectionReset,
+        }
+    }
+}
+
+impl embedded_io_async::Error for Error {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            Self::ConnectionReset => embedded_io_async::ErrorKind::ConnectionReset,
+            Self::ConnectionClosed => embedded_io_async::ErrorKind::BrokenPipe,
+            Self::Timeout => embedded_io_async::ErrorKind::TimedOut,
+            Self::BadRequest => embedded_io_async::ErrorKind::InvalidData,
+            Self::BodyTooLarge => embedded_io_async::ErrorKind::OutOfMemory,
+            Self::BodyLength => embedded_io_async::ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Request method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Method {
+    /// `GET`
+    Get,
+    /// `HEAD`
+    Head,
+    /// `POST`
+    Post,
+    /// `PUT`
+    Put,
+    /// `DELETE`
+    Delete,
+    /// `PATCH`
+    Patch,
+    /// `OPTIONS`
+    Options,
+}
+
+impl Method {
+    /// Name of the method.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Get => "GET",
+            Self::Head => "HEAD",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Delete => "DELETE",
+            Self::Patch => "PATCH",
+            Self::Options => "OPTIONS",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "GET" => Self::Get,
+            "HEAD" => Self::Head,
+            "POST" => Self::Post,
+            "PUT" => Self::Put,
+            "DELETE" => Self::Delete,
+            "PATCH" => Self::Patch,
+            "OPTIONS" => Self::Options,
+            _ => return None,
+        })
+    }
+}
+
+/// HTTP version of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Version {
+    /// HTTP/1.0
+    Http10,
+    /// HTTP/1.1
+    Http11,
+}
+
+/// Response status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status {
+    /// Status code.
+    pub code: u16,
+    /// Reason phrase.
+    pub reason: &'static str,
+}
+
+impl Status {
+    /// `200 OK`
+    pub const OK: Self = Self::new(200, "OK");
+    /// `201 Created`
+    pub const CREATED: Self = Self::new(201, "Created");
+    /// `202 Accepted`
+    pub const ACCEPTED: Self = Self::new(202, "Accepted");
+    /// `204 No Content`
+    pub const NO_CONTENT: Self = Self::new(204, "No Content");
+    /// `301 Moved Permanently`
+    pub const MOVED_PERMANENTLY: Self = Self::new(301, "Moved Permanently");
+    /// `302 Found`
+    pub const FOUND: Self = Self::new(302, "Found");
+    /// `303 See Other`
+    pub const SEE_OTHER: Self = Self::new(303, "See Other");
+    /// `304 Not Modified`
+    pub const NOT_MODIFIED: Self = Self::new(304, "Not Modified");
+    /// `307 Temporary Redirect`
+    pub const TEMPORARY_REDIRECT: Self = Self::new(307, "Temporary Redirect");
+    /// `308 Permanent Redirect`
+    pub const PERMANENT_REDIRECT: Self = Self::new(308, "Permanent Redirect");
+    /// `400 Bad Request`
+    pub const BAD_REQUEST: Self = Self::new(400, "Bad Request");
+    /// `401 Unauthorized`
+    pub const UNAUTHORIZED: Self = Self::new(401, "Unauthorized");
+    /// `403 Forbidden`
+    pub const FORBIDDEN: Self = Self::new(403, "Forbidden");
+    /// `404 Not Found`
+    pub const NOT_FOUND: Self = Self::new(404, "Not Found");
+    /// `405 Method Not Allowed`
+    pub const METHOD_NOT_ALLOWED: Self = Self::new(405, "Method Not Allowed");
+    /// `408 Request Timeout`
+    pub const REQUEST_TIMEOUT: Self = Self::new(408, "Request Timeout");
+    /// `409 Conflict`
+    pub const CONFLICT: Self = Self::new(409, "Conflict");
+    /// `411 Length Required`
+    pub const LENGTH_REQUIRED: Self = Self::new(411, "Length Required");
+    /// `413 Content Too Large`
+    pub const CONTENT_TOO_LARGE: Self = Self::new(413, "Content Too Large");
+    /// `414 URI Too Long`
+    pub const URI_TOO_LONG: Self = Self::new(414, "URI Too Long");
+    /// `415 Unsupported Media Type`
+    pub const UNSUPPORTED_MEDIA_TYPE: Self = Self::new(415, "Unsupported Media Type");
+    /// `431 Request Header Fields Too Large`
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: Self = Self::new(431, "Request Header Fields Too Large");
+    /// `500 Internal Server Error`
+    pub const INTERNAL_SERVER_ERROR: Self = Self::new(500, "Internal Server Error");
+    /// `501 Not Implemented`
+    pub const NOT_IMPLEMENTED: Self = Self::new(501, "Not Implemented");
+    /// `503 Service Unavailable`
+    pub const SERVICE_UNAVAILABLE: Self = Self::new(503, "Service Unavailable");
+    /// `505 HTTP Version Not Supported`
+    pub const HTTP_VERSION_NOT_SUPPORTED: Self = Self::new(505, "HTTP Version Not Supported");
+
+    /// Create a new `Status`.
+    pub const fn new(code: u16, reason: &'static str) -> Self {
+        Self { code, reason }
+    }
+
+    /// Whether responses with this status never have a body.
+    fn bodyless(self) -> bool {
+        matches!(self.code, 100..=199 | 204 | 304)
+    }
+}
+
+/// Header of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Header<'r> {
+    /// Header name, as received.
+    pub name: &'r str,
+    /// Header value, without surrounding whitespace.
+    pub value: &'r str,
+}
+
+/// Request handler.
+pub trait Handler {
+    /// Handle `request`, and answer it with `response`.
+    ///
+    /// The part of the request body the handler doesn't read is discarded. If the handler returns an
+    /// error, the connection is closed.
+    async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, Error>;
+}
+
+/// HTTP server configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config {
+    /// How long to wait for a request on an idle connection, new or kept alive, before closing it.
+    pub keep_alive_timeout: Duration,
+    /// How long the client can take to send the head of a request, or each part of its body, and to
+    /// acknowledge the response.
+    pub request_timeout: Duration,
+}
+
+impl Config {
+    /// Create a new `Config`, with the default timeouts.
+    pub const fn new() -> Self {
+        Self {
+            keep_alive_timeout: Duration::from_secs(5),
+            request_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// HTTP/1.1 server accepting up to N concurrent connections.
+pub struct HttpServer<'d, const N: usize> {
+    listener: TcpListener<'d, N>,
+    config: Config,
+    shutdown: Cell<bool>,
+    wakers: RefCell<MultiWakerRegistration<N>>,
+}
+
+impl<'d, const N: usize> HttpServer<'d, N> {
+    /// Create a new `HttpServer`, and start listening on `local_endpoint`.
+    ///
+    /// The sockets of `state` determine how many connections can be open at the same time.
+    /// See [`TcpListener::new`].
+    pub fn new<T, const TX_SZ: usize, const RX_SZ: usize>(
+        stack: Stack<'d>,
+        state: &'d mut TcpListenerState<N, TX_SZ, RX_SZ>,
+        local_endpoint: T,
+        config: Config,
+    ) -> Self
+    where
+        T: Into<IpListenEndpoint>,
+    {
+        let mut listener = TcpListener::new(stack, state, local_endpoint);
+        listener.set_timeout(Some(config.request_timeout));
+        Self {
+            listener,
+            config,
+            shutdown: Cell::new(false),
+            wakers: RefCell::new(MultiWakerRegistration::new()),
+        }
+    }
+
+    /// Serve connections with `handler`, until the server is shut down.
+    ///
+    /// `buf` holds the head of the requests, which must fit in it. Requests with a larger head are
+    /// answered with `431 Request Header Fields Too Large`.
+    ///
+    /// Call this from several tasks, or join several calls, to serve several connections at the same time.
+    pub async fn run<H: Handler>(&self, handler: &H, buf: &mut [u8]) {
+        while let Some(mut conn) = self.until_shutdown(self.listener.accept()).await {
+            self.serve(&mut conn, handler, buf).await;
+        }
+    }
+
+    /// Shut the server down.
+    ///
+    /// Workers stop accepting connections, and close the ones they serve after the response to the
+    /// current request, or right away if they're idle. [`run`](Self::run) then returns.
+    pub fn shutdown(&self) {
+        self.shutdown.set(true);
+        self.wakers.borrow_mut().wake();
+    }
+
+    /// Whether the server was shut down.
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown.get()
+    }
+
+    /// Wait for `fut`, or return `None` if the server is shut down first.
+    async fn until_shutdown<F: Future>(&self, fut: F) -> Option<F::Output> {
+        let mut fut = pin!(fut);
+        poll_fn(|cx| {
+            if self.shutdown.get() {
+                return Poll::Ready(None);
+            }
+            if let Poll::Ready(output) = fut.as_mut().poll(cx) {
+                return Poll::Ready(Some(output));
+            }
+            self.wakers.borrow_mut().register(cx.waker());
+            Poll::Pending
+        })
+        .await
+    }
+
+    async fn serve<H: Handler>(&self, conn: &mut TcpConnection<'_, 'd, N>, handler: &H, buf: &mut [u8]) {
+        let mut filled = 0;
+        loop {
+            let (mut reader, mut writer) = conn.split();
+            let head_len = match self.read_head(&mut reader, buf, &mut filled).await {
+                Ok(Some(len)) => len,
+                Ok(None) => return,
+                Err(status) => {
+                    let _ = send_error(&mut writer, status).await;
+                    return;
+                }
+            };
+
+            let (head, rest) = buf.split_at_mut(head_len);
+            let mut request = match Request::parse(head, &rest[..filled - head_len], reader, self.config) {
+                Ok(request) => request,
+                Err(status) => {
+                    let _ = send_error(&mut writer, status).await;
+                    return;
+                }
+            };
+            if request.expects_continue() && writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await.is_err() {
+                return;
+            }
+
+            let response = Response {
+                writer,
+                version: request.version,
+                keep_alive: request.keep_alive,
+                head: request.method == Method::Head,
+                shutdown: &self.shutdown,
+            };
+            let keep_alive = match handler.handle(&mut request, response).await {
+                Ok(responded) => responded.keep_alive,
+                Err(_) => false,
+            };
+            if !keep_alive || request.body.drain().await.is_err() {
+                return;
+            }
+
+            // Keep the start of the next request, if the client sent it already.
+            let consumed = head_len + request.body.consumed();
+            drop(request);
+            buf.copy_within(consumed..filled, 0);
+            filled -= consumed;
+        }
+    }
+
+    /// Read the head of a request into `buf`, after the `filled` bytes already received.
+    ///
+    /// Returns its length, or `None` if the connection must be closed without a response.
+    async fn read_head(
+        &self,
+        reader: &mut TcpReader<'_>,
+        buf: &mut [u8],
+        filled: &mut usize,
+    ) -> Result<Option<usize>, Status> {
+        // Once part of a request was received, the rest must follow in time.
+        let mut deadline = (*filled > 0).then(|| Instant::now() + self.config.request_timeout);
+        loop {
+            // Empty lines before a request are ignored.
+            let start = buf[..*filled].iter().take_while(|b| matches!(b, b'\r' | b'\n')).count();
+            buf.copy_within(start..*filled, 0);
+            *filled -= start;
+
+            if let Some(pos) = buf[..*filled].windows(4).position(|w| w == b"\r\n\r\n") {
+                return Ok(Some(pos + 4));
+            }
+            if *filled == buf.len() {
+                return Err(Status::REQUEST_HEADER_FIELDS_TOO_LARGE);
+            }
+
+            let read = reader.read(&mut buf[*filled..]);
+            let res = match deadline {
+                // Idle connection, waiting for the next request.
+                None => match self
+                    .until_shutdown(with_timeout(self.config.keep_alive_timeout, read))
+                    .await
+                {
+                    Some(Ok(res)) => res,
+                    Some(Err(_)) | None => return Ok(None),
+                },
+                Some(deadline) => match with_deadline(deadline, read).await {
+                    Ok(res) => res,
+                    Err(_) => return Err(Status::REQUEST_TIMEOUT),
+                },
+            };
+            match res {
+                Ok(0) | Err(_) => return Ok(None),
+                Ok(n) => *filled += n,
+            }
+            if *filled > 0 && deadline.is_none() {
+                deadline = Some(Instant::now() + self.config.request_timeout);
+            }
+        }
+    }
+}
+
+/// Send a response with no body for a request that can't be handled, before closing the connection.
+async fn send_error(writer: &mut TcpWriter<'_>, status: Status) -> Result<(), Error> {
+    let mut head: String<96> = String::new();
+    let _ = write!(
+        head,
+        "HTTP/1.1 {} {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status.code, status.reason
+    );
+    writer.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+/// HTTP request.
+pub struct Request<'r> {
+    method: Method,
+    path: &'r str,
+    query: Option<&'r str>,
+    version: Version,
+    headers: Vec<Header<'r>, MAX_HEADERS>,
+    params: crate::router::Params<'r>,
+    keep_alive: bool,
+    body: Body<'r>,
+}
+
+impl<'r> Request<'r> {
+    fn parse(head: &'r [u8], buffered: &'r [u8], reader: TcpReader<'r>, config: Config) -> Result<Self, Status> {
+        let head = core::str::from_utf8(head).map_err(|_| Status::BAD_REQUEST)?;
+        let mut lines = head.trim_end_matches("\r\n").split("\r\n");
+
+        let mut request_line = unwrap!(lines.next()).split(' ');
+        let (Some(method), Some(target), Some(version), None) = (
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+            request_line.next(),
+        ) else {
+            return Err(Status::BAD_REQUEST);
+        };
+        let version = match version {
+            "HTTP/1.1" => Version::Http11,
+            "HTTP/1.0" => Version::Http10,
+            v if v.starts_with("HTTP/") => return Err(Status::HTTP_VERSION_NOT_SUPPORTED),
+            _ => return Err(Status::BAD_REQUEST),
+        };
+        let method = Method::parse(method).ok_or(Status::NOT_IMPLEMENTED)?;
+
+        // Absolute targets, sent to proxies, are accepted as well.
+        let target = match target.strip_prefix("http://") {
+            Some(rest) => rest.find('/').map_or("/", |i| &rest[i..]),
+            None => target,
+        };
+        if !target.starts_with('/') {
+            return Err(Status::BAD_REQUEST);
+        }
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (target, None),
+        };
+
+        let mut headers = Vec::new();
+        for line in lines {
+            let (name, value) = line.split_once(':').ok_or(Status::BAD_REQUEST)?;
+            if name.is_empty() || name.contains([' ', '\t']) {
+                return Err(Status::BAD_REQUEST);
+            }
+            let value = value.trim_matches([' ', '\t']);
+            headers
+                .push(Header { name, value })
+                .map_err(|_| Status::REQUEST_HEADER_FIELDS_TOO_LARGE)?;
+        }
+
+        let mut length = None;
+        let mut chunked = false;
+        let mut close = version == Version::Http10;
+        for header in &headers {
+            if header.name.eq_ignore_ascii_case("Content-Length") {
+                // `parse` would accept a leading `+`.
+                if header.value.is_empty() || !header.value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(Status::BAD_REQUEST);
+                }
+                let value = header.value.parse::<u64>().map_err(|_| Status::BAD_REQUEST)?;
+                if length.is_some_and(|length| length != value) {
+                    return Err(Status::BAD_REQUEST);
+                }
+                length = Some(value);
+            } else if header.name.eq_ignore_ascii_case("Transfer-Encoding") {
+                // Only the chunked transfer coding is supported, without compression.
+                if chunked || !header.value.eq_ignore_ascii_case("chunked") {
+                    return Err(Status::NOT_IMPLEMENTED);
+                }
+                chunked = true;
+            } else if header.name.eq_ignore_ascii_case("Connection") {
+                for option in header.value.split(',').map(str::trim) {
+                    if option.eq_ignore_ascii_case("close") {
+                        close = true;
+                    } else if option.eq_ignore_ascii_case("keep-alive") && version == Version::Http10 {
+                        close = false;
+                    }
+                }
+            }
+        }
+
+        let framing = match (chunked, length) {
+            (true, Some(_)) => return Err(Status::BAD_REQUEST),
+            (true, None) => Framing::Chunked(Chunk::Size),
+            (false, length) => Framing::Length(length.unwrap_or(0)),
+        };
+
+        Ok(Self {
+            method,
+            path,
+            query,
+            version,
+            headers,
+            params: Vec::new(),
+            keep_alive: !close,
+            body: Body {
+                reader,
+                buffered,
+                buffered_len: buffered.len(),
+                framing,
+                timeout: config.request_timeout,
+            },
+        })
+    }
+
+    /// Request method.
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// Request path, without the query.
+    pub fn path(&self) -> &'r str {
+        self.path
+    }
+
+    /// Request query, after the `?` of the target.
+    pub fn query(&self) -> Option<&'r str> {
+        self.query
+    }
+
+    /// Value of the query parameter `name`.
+    ///
+    /// Returns an empty string for a parameter without a value, and the first value of parameters given
+    /// several times.
+    pub fn query_param(&self, name: &str) -> Option<&'r str> {
+        self.query?.split('&').find_map(|param| match param.split_once('=') {
+            Some((n, value)) if n == name => Some(value),
+            None if param == name => Some(""),
+            _ => None,
+        })
+    }
+
+    /// HTTP version of the request.
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Request headers.
+    pub fn headers(&self) -> &[Header<'r>] {
+        &self.headers
+    }
+
+    /// Value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&'r str> {
+        self.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| h.value)
+    }
+
+    /// Value of the path parameter `name`, captured by the [`Route`] that matched the request.
+    ///
+    /// The rest of the path matched by a trailing `*` is the parameter `*`.
+    pub fn param(&self, name: &str) -> Option<&'r str> {
+        self.params.iter().find(|(n, _)| *n == name).map(|(_, value)| *value)
+    }
+
+    /// Whether the connection is kept open after the response, unless the handler or server close it.
+    pub fn keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    /// Request body.
+    pub fn body(&mut self) -> &mut Body<'r> {
+        &mut self.body
+    }
+
+    fn expects_continue(&self) -> bool {
+        self.version == Version::Http11
+            && self.body.buffered.is_empty()
+            && !matches!(self.body.framing, Framing::Length(0))
+            && self
+                .header("Expect")
+                .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Framing {
+    /// Bytes of the body left to read.
+    Length(u64),
+    Chunked(Chunk),
+}
+
+#[derive(Clone, Copy)]
+enum Chunk {
+    /// Size line of the next chunk.
+    Size,
+    /// Bytes of the current chunk left to read, followed by a line break.
+    Data(u64),
+    /// Trailer section, after the last chunk.
+    Trailers,
+    Done,
+}
+
+/// Body of a [`Request`].
+pub struct Body<'r> {
+    reader: TcpReader<'r>,
+    buffered: &'r [u8],
+    buffered_len: usize,
+    framing: Framing,
+    timeout: Duration,
+}
+
+impl Body<'_> {
+    /// Read part of the body into `buf`.
+    ///
+    /// Returns how many bytes were read, 0 at the end of the body.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            match self.framing {
+                Framing::Length(0) | Framing::Chunked(Chunk::Done) => return Ok(0),
+                Framing::Length(remaining) => {
+                    let n = self.read_raw(limit(buf, remaining)).await?;
+                    self.framing = Framing::Length(remaining - n as u64);
+                    return Ok(n);
+                }
+                Framing::Chunked(Chunk::Data(0)) => {
+                    let mut end = [0; 2];
+                    self.read_exact(&mut end).await?;
+                    if end != *b"\r\n" {
+                        return Err(Error::BadRequest);
+                    }
+                    self.framing = Framing::Chunked(Chunk::Size);
+                }
+                Framing::Chunked(Chunk::Data(remaining)) => {
+                    let n = self.read_raw(limit(buf, remaining)).await?;
+                    self.framing = Framing::Chunked(Chunk::Data(remaining - n as u64));
+                    return Ok(n);
+                }
+                Framing::Chunked(Chunk::Size) => {
+                    let mut line = [0; MAX_CHUNK_LINE_LEN];
+                    let line = self.read_line(&mut line).await?;
+                    // Chunk extensions are ignored.
+                    let size = line.split(|b| *b == b';').next().unwrap_or_default();
+                    let size = core::str::from_utf8(size)
+                        .ok()
+                        .and_then(|s| u64::from_str_radix(s.trim_matches([' ', '\t']), 16).ok())
+                        .ok_or(Error::BadRequest)?;
+                    self.framing = Framing::Chunked(match size {
+                        0 => Chunk::Trailers,
+                        size => Chunk::Data(size),
+                    });
+                }
+                Framing::Chunked(Chunk::Trailers) => {
+                    let mut line = [0; MAX_CHUNK_LINE_LEN];
+                    if self.read_line(&mut line).await?.is_empty() {
+                        self.framing = Framing::Chunked(Chunk::Done);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Read the whole body into `buf`.
+    ///
+    /// Returns [`Error::BodyTooLarge`] if it doesn't fit.
+    pub async fn read_to_end<'b>(&mut self, buf: &'b mut [u8]) -> Result<&'b [u8], Error> {
+        let mut len = 0;
+        loop {
+            if len == buf.len() {
+                return match self.read(&mut [0]).await? {
+                    0 => Ok(buf),
+                    _ => Err(Error::BodyTooLarge),
+                };
+            }
+            match self.read(&mut buf[len..]).await? {
+                0 => return Ok(&buf[..len]),
+                n => len += n,
+            }
+        }
+    }
+
+    /// Read and discard the rest of the body.
+    async fn drain(&mut self) -> Result<(), Error> {
+        let mut buf = [0; 64];
+        while self.read(&mut buf).await? > 0 {}
+        Ok(())
+    }
+
+    /// Number of the bytes received with the request head that were read.
+    fn consumed(&self) -> usize {
+        self.buffered_len - self.buffered.len()
+    }
+
+    async fn read_raw(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if !self.buffered.is_empty() {
+            let n = buf.len().min(self.buffered.len());
+            buf[..n].copy_from_slice(&self.buffered[..n]);
+            self.buffered = &self.buffered[n..];
+            return Ok(n);
+        }
+        match with_timeout(self.timeout, self.reader.read(buf)).await {
+            Ok(Ok(0)) => Err(Error::ConnectionClosed),
+            Ok(Ok(n)) => Ok(n),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    async fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<(), Error> {
+        while !buf.is_empty() {
+            let n = self.read_raw(buf).await?;
+            buf = &mut buf[n..];
+        }
+        Ok(())
+    }
+
+    /// Read a line of chunk framing, without its line break.
+    async fn read_line<'b>(&mut self, buf: &'b mut [u8; MAX_CHUNK_LINE_LEN]) -> Result<&'b [u8], Error> {
+        let mut len = 0;
+        loop {
+            let mut byte = [0];
+            self.read_exact(&mut byte).await?;
+            if byte[0] == b'\n' {
+                return Ok(buf[..len].strip_suffix(b"\r").unwrap_or(&buf[..len]));
+            }
+            if len == buf.len() {
+                return Err(Error::BadRequest);
+            }
+            buf[len] = byte[0];
+            len += 1;
+        }
+    }
+}
+
+/// Part of `buf` to read into, with `remaining` bytes left to read.
+fn limit(buf: &mut [u8], remaining: u64) -> &mut [u8] {
+    let len = remaining.min(buf.len() as u64) as usize;
+    &mut buf[..len]
+}
+
+impl embedded_io_async::ErrorType for Body<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Read for Body<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        Body::read(self, buf).await
+    }
+}
+
+/// Proof that a request was answered, returned by [`Handler::handle`].
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[must_use]
+pub struct Responded {
+    keep_alive: bool,
+}
+
+/// Response to a [`Request`].
+///
+/// The status line and headers are sent when starting the response. `Content-Length`,
+/// `Transfer-Encoding` and `Connection` are added by the server.
+pub struct Response<'r> {
+    writer: TcpWriter<'r>,
+    version: Version,
+    keep_alive: bool,
+    head: bool,
+    shutdown: &'r Cell<bool>,
+}
+
+impl<'r> Response<'r> {
+    /// Send a response with a body.
+    pub async fn send(self, status: Status, headers: &[(&str, &str)], body: &[u8]) -> Result<Responded, Error> {
+        let mut writer = self.start(status, headers, Some(body.len())).await?;
+        writer.write_all(body).await?;
+        writer.finish().await
+    }
+
+    /// Start a response, with a body to write with the returned [`BodyWriter`].
+    ///
+    /// With a `content_length`, exactly this many bytes must be written. Otherwise, the body is sent
+    /// in chunks, or until the connection is closed for HTTP/1.0 clients.
+    pub async fn start(
+        mut self,
+        status: Status,
+        headers: &[(&str, &str)],
+        content_length: Option<usize>,
+    ) -> Result<BodyWriter<'r>, Error> {
+        let mut framing = match content_length {
+            _ if status.bodyless() => OutFraming::Length(0),
+            Some(len) => OutFraming::Length(len),
+            None if self.version == Version::Http11 => OutFraming::Chunked,
+            None => OutFraming::Close,
+        };
+        let keep_alive = self.keep_alive && !self.shutdown.get() && !matches!(framing, OutFraming::Close);
+
+        let mut line: String<64> = String::new();
+        let _ = write!(line, "HTTP/1.1 {} ", status.code);
+        for part in [&line, status.reason, "\r\n"] {
+            self.writer.write_all(part.as_bytes()).await?;
+        }
+        for (name, value) in headers {
+            for part in [name, ": ", value, "\r\n"] {
+                self.writer.write_all(part.as_bytes()).await?;
+            }
+        }
+        line.clear();
+        match framing {
+            OutFraming::Length(len) if !status.bodyless() => {
+                let _ = write!(line, "Content-Length: {}\r\n", len);
+            }
+            OutFraming::Chunked => unwrap!(line.push_str("Transfer-Encoding: chunked\r\n")),
+            _ => {}
+        }
+        match (keep_alive, self.version) {
+            (false, _) => unwrap!(line.push_str("Connection: close\r\n")),
+            (true, Version::Http10) => unwrap!(line.push_str("Connection: keep-alive\r\n")),
+            (true, Version::Http11) => {}
+        }
+        unwrap!(line.push_str("\r\n"));
+        self.writer.write_all(line.as_bytes()).await?;
+
+        if self.head {
+            framing = OutFraming::Discard(match framing {
+                OutFraming::Length(len) => len,
+                _ => 0,
+            });
+        }
+        Ok(BodyWriter {
+            writer: self.writer,
+            framing,
+            keep_alive,
+        })
+    }
+}
+
+enum OutFraming {
+    /// Bytes of the body left to write.
+    Length(usize),
+    Chunked,
+    /// Body delimited by closing the connection.
+    Close,
+    /// Body of a response to a `HEAD` request, which isn't sent. Bytes left to write, if known.
+    Discard(usize),
+}
+
+/// Writer of the body of a [`Response`].
+pub struct BodyWriter<'r> {
+    writer: TcpWriter<'r>,
+    framing: OutFraming,
+    keep_alive: bool,
+}
+
+impl BodyWriter<'_> {
+    /// Write all of `buf` to the body.
+    ///
+    /// Chunked bodies send each write as a chunk, so large writes have less overhead.
+    pub async fn write_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        match &mut self.framing {
+            OutFraming::Length(remaining) => {
+                *remaining = remaining.checked_sub(buf.len()).ok_or(Error::BodyLength)?;
+                self.writer.write_all(buf).await?;
+            }
+            OutFraming::Chunked => {
+                let mut size: String<20> = String::new();
+                let _ = write!(size, "{:x}\r\n", buf.len());
+                self.writer.write_all(size.as_bytes()).await?;
+                self.writer.write_all(buf).await?;
+                self.writer.write_all(b"\r\n").await?;
+            }
+            OutFraming::Close => self.writer.write_all(buf).await?,
+            OutFraming::Discard(remaining) => *remaining = remaining.saturating_sub(buf.len()),
+        }
+        Ok(())
+    }
+
+    /// Finish the response.
+    ///
+    /// Returns [`Error::BodyLength`] if the body is shorter than its `Content-Length`.
+    pub async fn finish(mut self) -> Result<Responded, Error> {
+        match self.framing {
+            OutFraming::Length(0) | OutFraming::Close | OutFraming::Discard(_) => {}
+            OutFraming::Length(_) => return Err(Error::BodyLength),
+            OutFraming::Chunked => self.writer.write_all(b"0\r\n\r\n").await?,
+        }
+        self.writer.flush().await?;
+        Ok(Responded {
+            keep_alive: self.keep_alive,
+        })
+    }
+}
+
+impl embedded_io_async::ErrorType for BodyWriter<'_> {
+    type Error = Error;
+}
+
+impl embedded_io_async::Write for BodyWriter<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        BodyWriter::write_all(self, buf).await?;
+        Ok(buf.len())
+    }
+}
+
+/// Route of a [`Router`].
+///
+/// See [`router`](crate::router) for the patterns. Routes for `GET` also match `HEAD` requests.
+pub type Route<H> = crate::router::Route<Method, H>;
+
+/// Handler dispatching requests to the first [`Route`] matching their method and path.
+///
+/// Requests matching no route are answered with `404 Not Found`, or `405 Method Not Allowed` if only
+/// the method doesn't match.
+pub type Router<'a, H> = crate::router::Router<'a, Method, H>;
+
+impl<H: Handler> Handler for Router<'_, H> {
+    async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, Error> {
+        let mut allow: String<64> = String::new();
+        for (route, params) in self.matching(request.path) {
+            if route.method == request.method || (route.method == Method::Get && request.method == Method::Head) {
+                request.params = params;
+                return route.handler.handle(request, response).await;
+            }
+            let methods = match route.method {
+                Method::Get => &[Method::Get, Method::Head][..],
+                _ => &[route.method][..],
+            };
+            for method in methods {
+                if !allow.split(", ").any(|m| m == method.as_str()) {
+                    if !allow.is_empty() {
+                        let _ = allow.push_str(", ");
+                    }
+                    let _ = allow.push_str(method.as_str());
+                }
+            }
+        }
+
+        if allow.is_empty() {
+            response.send(Status::NOT_FOUND, &[], &[]).await
+        } else {
+            response
+                .send(Status::METHOD_NOT_ALLOWED, &[("Allow", &allow)], &[])
+                .await
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+    use std::string::String as StdString;
+
+    use embassy_futures::select::{select, Either};
+
+    use super::*;
+    use crate::tcp::{State, TcpSocket};
+    use crate::test_util::{self, addr, static_config, wait_for};
+
+    const PORT: u16 = 80;
+
+    enum Api {
+        Led,
+        Echo,
+    }
+
+    impl Handler for Api {
+        async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, Error> {
+            match self {
+                Api::Led => {
+                    let mut body: String<64> = String::new();
+                    let _ = write!(
+                        body,
+                        "{} {} {}",
+                        unwrap!(request.param("id")),
+                        request.query_param("on").unwrap_or("-"),
+                        request.header("x-test").unwrap_or("-"),
+                    );
+                    response.send(Status::OK, &[], body.as_bytes()).await
+                }
+                Api::Echo => {
+                    let mut buf = [0; 64];
+                    let body = request.body().read_to_end(&mut buf).await?;
+                    response.send(Status::OK, &[], body).await
+                }
+            }
+        }
+    }
+
+    static ROUTES: [Route<Api>; 2] = [
+        Route::new(Method::Get, "/leds/{id}", Api::Led),
+        Route::new(Method::Post, "/echo", Api::Echo),
+    ];
+
+    /// Send each of `requests` on its own connection to the server of `b`, and return the responses.
+    fn exchange<const K: usize>(requests: [&'static str; K]) -> [StdString; K] {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let state = Box::leak(Box::new(TcpListenerState::<1, 1024, 1024>::new()));
+            let server = HttpServer::new(net.b, state, PORT, Config::new());
+            let router = Router::new(&ROUTES);
+            let mut buf = [0; 512];
+
+            let send = async {
+                let mut responses = [const { StdString::new() }; K];
+                for (request, response) in requests.iter().zip(&mut responses) {
+                    let rx = Box::leak(Box::new([0; 1024]));
+                    let tx = Box::leak(Box::new([0; 1024]));
+                    let mut socket = TcpSocket::new(net.a, rx, tx);
+                    unwrap!(socket.connect((addr(2), PORT)).await);
+                    unwrap!(socket.write_all(request.as_bytes()).await);
+                    let mut received = [0; 512];
+                    let mut len = 0;
+                    while let Ok(n @ 1..) = socket.read(&mut received[len..]).await {
+                        len += n;
+                    }
+                    *response = unwrap!(StdString::from_utf8(received[..len].to_vec()));
+                    // The listener only accepts the next connection once this one is closed.
+                    socket.close();
+                    wait_for(|| socket.state() == State::Closed).await;
+                }
+                responses
+            };
+            match select(server.run(&router, &mut buf), send).await {
+                Either::First(()) => panic!("server stopped"),
+                Either::Second(responses) => responses,
+            }
+        })
+    }
+
+    #[test]
+    fn parses_requests() {
+        let [led, absolute, echo, chunked] = exchange([
+            "GET /leds/3?on=1 HTTP/1.1\r\nX-Test:  yes \r\nConnection: close\r\n\r\n",
+            "GET http://10.0.0.2/leds/4 HTTP/1.0\r\n\r\n",
+            "POST /echo HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+            "POST /echo HTTP/1.1\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n3\r\nhel\r\n2\r\nlo\r\n0\r\n\r\n",
+        ]);
+        assert_eq!(
+            led,
+            "HTTP/1.1 200 OK\r\nContent-Length: 7\r\nConnection: close\r\n\r\n3 1 yes"
+        );
+        assert_eq!(
+            absolute,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n4 - -"
+        );
+        assert_eq!(
+            echo,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello"
+        );
+        assert_eq!(echo, chunked);
+    }
+
+    #[test]
+    fn rejects_malformed_requests() {
+        let responses = exchange([
+            "POST /echo HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello",
+            "POST /echo HTTP/1.1\r\nContent-Length: \r\n\r\n",
+            "POST /echo HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\nhello",
+            "POST /echo HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "GET leds HTTP/1.1\r\n\r\n",
+            "GET /leds/3 HTTP/2.0\r\n\r\n",
+            "BREW /leds/3 HTTP/1.1\r\n\r\n",
+        ]);
+        let statuses = responses.each_ref().map(|response| &response[..12]);
+        assert_eq!(
+            statuses,
+            [
+                "HTTP/1.1 400",
+                "HTTP/1.1 400",
+                "HTTP/1.1 400",
+                "HTTP/1.1 400",
+                "HTTP/1.1 400",
+                "HTTP/1.1 505",
+                "HTTP/1.1 501",
+            ]
+        );
+    }
+
+    #[test]
+    fn routes_requests() {
+        let [head, not_found, not_allowed, empty_param] = exchange([
+            "HEAD /leds/3 HTTP/1.1\r\nConnection: close\r\n\r\n",
+            "GET /echo/more HTTP/1.1\r\nConnection: close\r\n\r\n",
+            "PUT /leds/3 HTTP/1.1\r\nConnection: close\r\n\r\n",
+            "GET /leds/ HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ]);
+        assert_eq!(
+            head,
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(
+            not_found,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        assert_eq!(
+            not_allowed,
+            "HTTP/1.1 405 Method Not Allowed\r\nAllow: GET, HEAD\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        assert!(empty_param.starts_with("HTTP/1.1 404"));
+    }
+}
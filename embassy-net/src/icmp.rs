@@ -4,7 +4,7 @@ use core::future::{poll_fn, Future};
 use core::mem;
 use core::task::{Context, Poll};
 
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 pub use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::socket::icmp;
 pub use smoltcp::socket::icmp::{Endpoint as IcmpEndpoint, PacketMetadata};
@@ -14,7 +14,7 @@ pub use smoltcp::wire::{Icmpv4Message, Icmpv4Packet, Icmpv4Repr};
 #[cfg(feature = "proto-ipv6")]
 pub use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Icmpv6Repr};
 
-use crate::Stack;
+use crate::{InterfaceId, SocketHandle, SocketSetFull, Stack};
 
 /// Error returned by [`IcmpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -68,15 +68,29 @@ impl<'a> IcmpSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(icmp::Socket::new(
-                icmp::PacketBuffer::new(rx_meta, rx_buffer),
-                icmp::PacketBuffer::new(tx_meta, tx_buffer),
-            ))
+            i.add_socket(
+                InterfaceId::PRIMARY,
+                icmp::Socket::new(
+                    icmp::PacketBuffer::new(rx_meta, rx_buffer),
+                    icmp::PacketBuffer::new(tx_meta, tx_buffer),
+                ),
+            )
         });
 
         Self { stack, handle }
     }
 
+    /// Bind the socket to a network interface of the stack.
+    ///
+    /// Sockets are created on the primary interface, and only send and receive packets through
+    /// the interface they're on.
+    ///
+    /// Returns an error, leaving the socket where it is, if the interface has no room for it.
+    pub fn bind_to_interface(&mut self, interface: InterfaceId) -> Result<(), SocketSetFull> {
+        self.handle = self.stack.with_mut(|i| i.move_socket(self.handle, interface))?;
+        Ok(())
+    }
+
     /// Bind the socket to the given endpoint.
     pub fn bind<T>(&mut self, endpoint: T) -> Result<(), BindError>
     where
@@ -97,16 +111,16 @@ impl<'a> IcmpSocket<'a> {
 
     fn with<R>(&self, f: impl FnOnce(&icmp::Socket, &Interface) -> R) -> R {
         self.stack.with(|i| {
-            let socket = i.sockets.get::<icmp::Socket>(self.handle);
-            f(socket, &i.iface)
+            let (socket, iface) = i.socket::<icmp::Socket>(self.handle);
+            f(socket, iface)
         })
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut icmp::Socket, &mut Interface) -> R) -> R {
         self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<icmp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
+            let (socket, iface) = i.socket_mut::<icmp::Socket>(self.handle);
+            let res = f(socket, iface);
+            i.wake(self.handle);
             res
         })
     }
@@ -366,7 +380,7 @@ impl<'a> IcmpSocket<'a> {
 
 impl Drop for IcmpSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle));
     }
 }
 
@@ -434,6 +448,8 @@ pub mod ping {
         SocketSendError(SendError),
         /// Container error for [`icmp::RecvError`].
         SocketRecvError(RecvError),
+        /// The interface routing to the target has no room for the socket.
+        SocketSetFull,
     }
 
     /// Manages ICMP ping operations.
@@ -565,6 +581,11 @@ pub mod ping {
 
             // Create the socket and set hop limit and bind it to the endpoint with the ident
             let mut socket = IcmpSocket::new(self.stack, self.rx_meta, self.rx_buffer, self.tx_meta, self.tx_buffer);
+            if let Some(interface) = self.stack.lookup_route(params.target.unwrap()) {
+                socket
+                    .bind_to_interface(interface)
+                    .map_err(|_| PingError::SocketSetFull)?;
+            }
             socket.set_hop_limit(params.hop_limit);
             if let Err(e) = socket.bind(IcmpEndpoint::Ident(self.ident)) {
                 return Err(PingError::SocketBindError(e));
@@ -627,6 +648,11 @@ pub mod ping {
 
             // Create the socket and set hop limit and bind it to the endpoint with the ident
             let mut socket = IcmpSocket::new(self.stack, self.rx_meta, self.rx_buffer, self.tx_meta, self.tx_buffer);
+            if let Some(interface) = self.stack.lookup_route(params.target.unwrap()) {
+                socket
+                    .bind_to_interface(interface)
+                    .map_err(|_| PingError::SocketSetFull)?;
+            }
             socket.set_hop_limit(params.hop_limit);
             if let Err(e) = socket.bind(IcmpEndpoint::Ident(self.ident)) {
                 return Err(PingError::SocketBindError(e));
@@ -0,0 +1,700 @@
+#[cfg(feature = "stats")]
+use core::cell::Cell;
+use core::future::Future;
+use core::pin::pin;
+use core::task::Context;
+
+use embassy_net_driver::{Driver, LinkState};
+use embassy_sync::waitqueue::WakerRegistration;
+#[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+use embassy_time::Duration;
+use embassy_time::{Instant, Timer};
+use heapless::Vec;
+#[cfg(feature = "dhcpv4")]
+use smoltcp::iface::SocketHandle;
+use smoltcp::iface::{Interface, SocketSet, SocketStorage};
+use smoltcp::phy::Device as _;
+#[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
+use smoltcp::phy::Medium;
+#[cfg(feature = "dhcpv4")]
+use smoltcp::socket::dhcpv4;
+
+use crate::driver_util::DriverAdapter;
+#[cfg(feature = "raw-ethernet")]
+use crate::ethernet::EthernetSockets;
+#[cfg(feature = "ipv4-forwarding")]
+use crate::forward;
+#[cfg(feature = "proto-ipv4-fragmentation")]
+use crate::fragment::Fragments;
+#[cfg(feature = "pmtu")]
+use crate::pmtu::PathMtus;
+#[cfg(feature = "slaac")]
+use crate::slaac;
+#[cfg(feature = "stats")]
+use crate::stats::InterfaceStats;
+#[cfg(all(feature = "stats", feature = "tcp"))]
+use crate::stats::TcpTracker;
+use crate::time::{instant_from_smoltcp, instant_to_smoltcp};
+use crate::{to_smoltcp_hardware_address, HardwareAddress, InterfaceId, InterfaceResources, IpAddress, IpCidr, Route};
+#[cfg(feature = "proto-ipv4")]
+use crate::{ConfigV4, StaticConfigV4};
+#[cfg(feature = "proto-ipv6")]
+use crate::{ConfigV6, StaticConfigV6};
+#[cfg(feature = "dhcpv4-hostname")]
+use crate::{HostnameResources, MAX_HOSTNAME_LEN};
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+use crate::{Ipv4Address, MAX_NTP_SERVERS};
+
+/// Size of the buffer holding the last DHCP packet, to read options smoltcp doesn't handle.
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+pub(crate) const DHCP_PACKET_LEN: usize = 576;
+/// DHCP options requested from the server: subnet mask, router, DNS servers and NTP servers.
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+const DHCP_PARAMETER_REQUEST_LIST: &[u8] = &[1, 3, 6, DHCP_OPT_NTP_SERVERS];
+/// DHCP option with the addresses of NTP servers.
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+const DHCP_OPT_NTP_SERVERS: u8 = 42;
+
+/// State of one network interface of the stack.
+pub(crate) struct Iface {
+    pub(crate) sockets: SocketSet<'static>, // Lifetime type-erased.
+    pub(crate) iface: Interface,
+    /// Waker used for triggering polls.
+    pub(crate) waker: WakerRegistration,
+    pub(crate) hardware_address: HardwareAddress,
+    /// Number of sockets the socket set can hold.
+    capacity: usize,
+    pub(crate) link_up: bool,
+    pub(crate) metric: u32,
+    /// Largest IP packet the interface sends without fragmenting it.
+    pub(crate) mtu: usize,
+    #[cfg(feature = "pmtu")]
+    pub(crate) pmtu: PathMtus,
+    /// Fragmentation state, `None` if the medium doesn't carry IPv4 packets.
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    pub(crate) fragments: Option<Fragments>,
+    #[cfg(feature = "raw-ethernet")]
+    pub(crate) ethernet: EthernetSockets,
+    #[cfg(feature = "proto-ipv4")]
+    pub(crate) static_v4: Option<StaticConfigV4>,
+    #[cfg(feature = "proto-ipv6")]
+    pub(crate) static_v6: Option<StaticConfigV6>,
+    #[cfg(feature = "dhcpv4")]
+    dhcp_socket: Option<SocketHandle>,
+    #[cfg(feature = "dhcpv4-hostname")]
+    hostname: *mut HostnameResources,
+    #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+    dhcp_packet: *mut [u8; DHCP_PACKET_LEN],
+    /// NTP servers provided by DHCP.
+    #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+    pub(crate) ntp_servers: Vec<Ipv4Address, MAX_NTP_SERVERS>,
+    #[cfg(feature = "slaac")]
+    slaac: Option<slaac::Slaac>,
+    #[cfg(feature = "slaac")]
+    slaac_resources: *mut slaac::SlaacResources,
+    #[cfg(feature = "slaac")]
+    random_seed: u64,
+    #[cfg(feature = "ipv4-forwarding")]
+    pub(crate) forward: Option<forward::Forward>,
+    #[cfg(feature = "ipv4-forwarding")]
+    forward_resources: *mut forward::ForwardResources,
+    #[cfg(feature = "stats")]
+    pub(crate) stats: Cell<InterfaceStats>,
+    #[cfg(all(feature = "stats", feature = "tcp"))]
+    pub(crate) tcp_tracker: TcpTracker,
+}
+
+impl Iface {
+    /// Initialize an interface in `resources`.
+    ///
+    /// The returned interface holds pointers into `resources`, so it must not outlive them.
+    pub(crate) fn init<'r, D: Driver, const SOCK: usize, const FRAG: usize>(
+        driver: &mut D,
+        resources: &'r mut InterfaceResources<SOCK, FRAG>,
+        random_seed: u64,
+    ) -> &'r mut Self {
+        let (hardware_address, medium) = to_smoltcp_hardware_address(driver.hardware_address());
+        let mut iface_cfg = smoltcp::iface::Config::new(hardware_address);
+        iface_cfg.random_seed = random_seed;
+
+        #[cfg(feature = "pmtu")]
+        let mut pmtu = PathMtus::new();
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        let mut fragments = {
+            // safety: the interface doesn't outlive the resources.
+            let resources: &'static mut crate::fragment::FragmentResources<FRAG> =
+                unsafe { core::mem::transmute(&mut resources.fragments) };
+            let mtu = driver.capabilities().max_transmission_unit;
+            Fragments::new(resources, medium, mtu, random_seed as u16)
+        };
+        let mut device = DriverAdapter {
+            inner: driver,
+            cx: None,
+            medium,
+            #[cfg(feature = "ipv4-forwarding")]
+            forward: None,
+            #[cfg(feature = "raw-ethernet")]
+            ethernet: None,
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            fragments: None,
+            #[cfg(feature = "pmtu")]
+            pmtu: &mut pmtu,
+            #[cfg(feature = "stats")]
+            stats: &Cell::new(InterfaceStats::default()),
+            #[cfg(all(feature = "stats", feature = "tcp"))]
+            tcp: &mut TcpTracker::new(),
+            #[cfg(feature = "pcap")]
+            sink: None,
+        };
+        let mtu = device.capabilities().ip_mtu();
+        // 6LoWPAN fragments IPv6 packets of up to the IPv6 minimum MTU.
+        #[cfg(feature = "proto-sixlowpan-fragmentation")]
+        let mtu = if medium == Medium::Ieee802154 { 1280 } else { mtu };
+
+        // smoltcp sends packets as large as the fragmentation buffer.
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        {
+            device.fragments = fragments.as_mut();
+        }
+        let iface = Interface::new(iface_cfg, &mut device, instant_to_smoltcp(Instant::now()));
+
+        let sockets = resources.sockets.write([SocketStorage::EMPTY; SOCK]);
+        // safety: the interface doesn't outlive the resources.
+        let sockets: &'static mut [SocketStorage<'static>] = unsafe { core::mem::transmute(&mut sockets[..]) };
+        let sockets = SocketSet::new(sockets);
+
+        resources.iface.write(Self {
+            sockets,
+            iface,
+            waker: WakerRegistration::new(),
+            hardware_address,
+            capacity: SOCK,
+            link_up: false,
+            metric: 0,
+            mtu,
+            #[cfg(feature = "pmtu")]
+            pmtu,
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            fragments,
+            #[cfg(feature = "raw-ethernet")]
+            ethernet: EthernetSockets::new(),
+            #[cfg(feature = "proto-ipv4")]
+            static_v4: None,
+            #[cfg(feature = "proto-ipv6")]
+            static_v6: None,
+            #[cfg(feature = "dhcpv4")]
+            dhcp_socket: None,
+            #[cfg(feature = "dhcpv4-hostname")]
+            hostname: &mut resources.hostname,
+            #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+            dhcp_packet: &mut resources.dhcp_packet,
+            #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+            ntp_servers: Vec::new(),
+            #[cfg(feature = "slaac")]
+            slaac: None,
+            #[cfg(feature = "slaac")]
+            slaac_resources: &mut resources.slaac,
+            #[cfg(feature = "slaac")]
+            random_seed,
+            #[cfg(feature = "ipv4-forwarding")]
+            forward: None,
+            #[cfg(feature = "ipv4-forwarding")]
+            forward_resources: &mut resources.forward,
+            #[cfg(feature = "stats")]
+            stats: Cell::new(InterfaceStats::default()),
+            #[cfg(all(feature = "stats", feature = "tcp"))]
+            tcp_tracker: TcpTracker::new(),
+        })
+    }
+
+    /// Get the path MTU to `addr`, the learned one if it's lower than the interface MTU.
+    #[cfg(feature = "pmtu")]
+    pub(crate) fn path_mtu(&self, addr: IpAddress) -> usize {
+        self.pmtu.get(addr).map_or(self.mtu, |mtu| mtu.min(self.mtu))
+    }
+
+    /// Get the time fragments of a packet are kept while waiting for the others.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub(crate) fn reassembly_timeout(&self) -> Duration {
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if let Some(fragments) = &self.fragments {
+            return fragments.reassembler.timeout;
+        }
+        #[cfg(feature = "proto-sixlowpan-fragmentation")]
+        return crate::time::duration_from_smoltcp(self.iface.reassembly_timeout());
+        #[cfg(not(feature = "proto-sixlowpan-fragmentation"))]
+        crate::fragment::DEFAULT_TIMEOUT
+    }
+
+    /// Set the time fragments of a packet are kept while waiting for the others.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub(crate) fn set_reassembly_timeout(&mut self, timeout: Duration) {
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        if let Some(fragments) = &mut self.fragments {
+            fragments.reassembler.timeout = timeout;
+        }
+        #[cfg(feature = "proto-sixlowpan-fragmentation")]
+        self.iface
+            .set_reassembly_timeout(crate::time::duration_to_smoltcp(timeout));
+    }
+
+    /// Get the number of free socket slots.
+    pub(crate) fn free_slots(&self) -> usize {
+        self.capacity - self.sockets.iter().count()
+    }
+
+    pub(crate) fn is_config_up(&self) -> bool {
+        let v4_up;
+        let v6_up;
+
+        #[cfg(feature = "proto-ipv4")]
+        {
+            v4_up = self.static_v4.is_some();
+        }
+        #[cfg(not(feature = "proto-ipv4"))]
+        {
+            v4_up = false;
+        }
+
+        #[cfg(feature = "proto-ipv6")]
+        {
+            v6_up = self.static_v6.is_some();
+        }
+        #[cfg(not(feature = "proto-ipv6"))]
+        {
+            v6_up = false;
+        }
+
+        v4_up || v6_up
+    }
+
+    /// Check whether the interface has a default gateway for `addr`.
+    pub(crate) fn has_default_gateway(&self, addr: &IpAddress) -> bool {
+        match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddress::Ipv4(_) => self.static_v4.as_ref().is_some_and(|c| c.gateway.is_some()),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddress::Ipv6(_) => self.static_v6.as_ref().is_some_and(|c| c.gateway.is_some()),
+        }
+    }
+
+    /// Get the DNS servers of the interface.
+    #[cfg(feature = "dns")]
+    pub(crate) fn dns_servers(&self) -> Vec<IpAddress, 6> {
+        let mut dns_servers = Vec::new();
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(config) = &self.static_v4 {
+            for s in &config.dns_servers {
+                unwrap!(dns_servers.push(IpAddress::Ipv4(*s)).ok());
+            }
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = &self.static_v6 {
+            for s in &config.dns_servers {
+                unwrap!(dns_servers.push(IpAddress::Ipv6(*s)).ok());
+            }
+        }
+        dns_servers
+    }
+
+    #[cfg(feature = "proto-ipv4")]
+    pub(crate) fn set_config_v4(&mut self, config: ConfigV4) {
+        // Handle static config.
+        self.static_v4 = match config.clone() {
+            ConfigV4::None => None,
+            #[cfg(feature = "dhcpv4")]
+            ConfigV4::Dhcp(_) => None,
+            ConfigV4::Static(c) => Some(c),
+        };
+
+        // Handle DHCP config.
+        #[cfg(feature = "dhcpv4")]
+        match config {
+            ConfigV4::Dhcp(c) => {
+                // Create the socket if it doesn't exist.
+                if self.dhcp_socket.is_none() {
+                    #[allow(unused_mut)]
+                    let mut socket = smoltcp::socket::dhcpv4::Socket::new();
+                    #[cfg(feature = "sntp")]
+                    {
+                        socket.set_parameter_request_list(DHCP_PARAMETER_REQUEST_LIST);
+                        // safety: the buffer lives for as long as the stack exists, and only this socket uses it.
+                        socket.set_receive_packet_buffer(unsafe { &mut *self.dhcp_packet });
+                    }
+                    let handle = self.sockets.add(socket);
+                    self.dhcp_socket = Some(handle);
+                }
+
+                // Configure it
+                let socket = self.sockets.get_mut::<dhcpv4::Socket>(unwrap!(self.dhcp_socket));
+                socket.set_ignore_naks(c.ignore_naks);
+                socket.set_max_lease_duration(c.max_lease_duration.map(crate::time::duration_to_smoltcp));
+                socket.set_ports(c.server_port, c.client_port);
+                socket.set_retry_config(c.retry_config);
+
+                socket.set_outgoing_options(&[]);
+                #[cfg(feature = "dhcpv4-hostname")]
+                if let Some(h) = c.hostname {
+                    // safety:
+                    // - we just did set_outgoing_options([]) so we know the socket is no longer holding a reference.
+                    // - we know this pointer lives for as long as the stack exists, because the resources
+                    //   are borrowed for at least as long as the stack. Therefore it's OK to pass a reference
+                    //   to this to smoltcp.
+                    let hostname = unsafe { &mut *self.hostname };
+
+                    // create data
+                    let data = hostname.data.write([0; MAX_HOSTNAME_LEN]);
+                    data[..h.len()].copy_from_slice(h.as_bytes());
+                    let data: &[u8] = &data[..h.len()];
+
+                    // set the option.
+                    let option = hostname.option.write(smoltcp::wire::DhcpOption { data, kind: 12 });
+                    socket.set_outgoing_options(core::slice::from_ref(option));
+                }
+
+                socket.reset();
+            }
+            _ => {
+                // Remove DHCP socket if any.
+                if let Some(socket) = self.dhcp_socket {
+                    self.sockets.remove(socket);
+                    self.dhcp_socket = None;
+                }
+                #[cfg(feature = "sntp")]
+                self.ntp_servers.clear();
+            }
+        }
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    pub(crate) fn set_config_v6(&mut self, config: ConfigV6) {
+        // Handle static config.
+        self.static_v6 = match config.clone() {
+            ConfigV6::None => None,
+            #[cfg(feature = "slaac")]
+            ConfigV6::Slaac => None,
+            ConfigV6::Static(c) => Some(c),
+        };
+
+        // Handle SLAAC config.
+        #[cfg(feature = "slaac")]
+        match config {
+            ConfigV6::Slaac => {
+                // Start over with a new socket.
+                if let Some(slaac) = self.slaac.take() {
+                    self.sockets.remove(slaac.socket);
+                }
+
+                // safety:
+                // - we just removed the SLAAC socket, so nothing holds a reference to the resources.
+                // - we know this pointer lives for as long as the stack exists, because the resources
+                //   are borrowed for at least as long as the stack. Therefore it's OK to pass a reference
+                //   to this to smoltcp.
+                let resources = unsafe { &mut *self.slaac_resources };
+                self.slaac = Some(slaac::Slaac::new(
+                    &mut self.sockets,
+                    resources,
+                    self.hardware_address,
+                    self.random_seed,
+                ));
+            }
+            _ => {
+                // Remove SLAAC socket if any.
+                if let Some(slaac) = self.slaac.take() {
+                    self.sockets.remove(slaac.socket);
+                }
+            }
+        }
+    }
+
+    /// Enable or disable forwarding of IPv4 packets through this interface.
+    #[cfg(feature = "ipv4-forwarding")]
+    pub(crate) fn set_ipv4_forwarding(&mut self, enabled: bool) {
+        match (enabled, self.forward.take()) {
+            (true, None) => {
+                if self.free_slots() < forward::SOCKET_COUNT {
+                    warn!(
+                        "IPv4 forwarding needs {} free socket slots per interface",
+                        forward::SOCKET_COUNT
+                    );
+                    return;
+                }
+                // safety:
+                // - the forwarding sockets were removed, so nothing holds a reference to the resources.
+                // - we know this pointer lives for as long as the stack exists, because the resources
+                //   are borrowed for at least as long as the stack. Therefore it's OK to pass a reference
+                //   to this to smoltcp.
+                let resources = unsafe { &mut *self.forward_resources };
+                let mut forward = forward::Forward::new(&mut self.sockets, resources, self.hardware_address);
+                forward.set_addrs(self.iface.ip_addrs());
+                self.forward = Some(forward);
+            }
+            (true, Some(forward)) => self.forward = Some(forward),
+            (false, Some(forward)) => forward.remove(&mut self.sockets),
+            (false, None) => {}
+        }
+    }
+
+    /// Apply the current configuration to the smoltcp interface.
+    ///
+    /// `routes` are the static routes of the stack, the ones going through this interface are
+    /// added to the smoltcp routing table.
+    pub(crate) fn apply_config(&mut self, id: InterfaceId, routes: &[Route]) {
+        let mut addrs = Vec::new();
+        #[cfg(feature = "proto-ipv4")]
+        let mut gateway_v4 = None;
+        #[cfg(feature = "proto-ipv6")]
+        let mut gateway_v6 = None;
+
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(config) = &self.static_v4 {
+            debug!("IPv4: UP ({:?})", id);
+            debug!("   IP address:      {:?}", config.address);
+            debug!("   Default gateway: {:?}", config.gateway);
+
+            unwrap!(addrs.push(IpCidr::Ipv4(config.address)).ok());
+            gateway_v4 = config.gateway;
+            #[cfg(feature = "dns")]
+            for s in &config.dns_servers {
+                debug!("   DNS server:      {:?}", s);
+            }
+        } else {
+            info!("IPv4: DOWN ({:?})", id);
+        }
+
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = &self.static_v6 {
+            debug!("IPv6: UP ({:?})", id);
+            debug!("   IP address:      {:?}", config.address);
+            debug!("   Default gateway: {:?}", config.gateway);
+
+            unwrap!(addrs.push(IpCidr::Ipv6(config.address)).ok());
+            gateway_v6 = config.gateway;
+            #[cfg(feature = "dns")]
+            for s in &config.dns_servers {
+                debug!("   DNS server:      {:?}", s);
+            }
+        } else {
+            info!("IPv6: DOWN ({:?})", id);
+        }
+
+        #[cfg(feature = "slaac")]
+        if let Some(slaac) = &self.slaac {
+            if addrs.push(IpCidr::Ipv6(slaac.link_local())).is_err() {
+                debug!("IPv6: no room for the link-local address, increase IFACE_MAX_ADDR_COUNT");
+            }
+        }
+
+        // Apply addresses
+        self.iface.update_ip_addrs(|a| *a = addrs);
+        #[cfg(feature = "ipv4-forwarding")]
+        if let Some(forward) = &mut self.forward {
+            forward.set_addrs(self.iface.ip_addrs());
+        }
+
+        // Apply gateways
+        self.iface.routes_mut().update(|r| r.clear());
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(gateway) = gateway_v4 {
+            unwrap!(self.iface.routes_mut().add_default_ipv4_route(gateway));
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(gateway) = gateway_v6 {
+            unwrap!(self.iface.routes_mut().add_default_ipv6_route(gateway));
+        }
+
+        // Apply static routes
+        for route in routes.iter().filter(|r| r.interface == id) {
+            let mut full = false;
+            self.iface.routes_mut().update(|r| {
+                full = r
+                    .push(smoltcp::iface::Route {
+                        cidr: route.destination,
+                        via_router: route.gateway,
+                        preferred_until: None,
+                        expires_at: None,
+                    })
+                    .is_err();
+            });
+            if full {
+                warn!(
+                    "No room for route to {:?} on {:?}, increase IFACE_MAX_ROUTE_COUNT",
+                    route.destination, id
+                );
+            }
+        }
+    }
+
+    /// Poll the interface.
+    ///
+    /// Returns true if the link state or the configuration changed. In the latter case, the
+    /// new configuration has already been applied.
+    pub(crate) fn poll<D: Driver>(
+        &mut self,
+        id: InterfaceId,
+        #[allow(unused)] routes: &[Route],
+        cx: &mut Context<'_>,
+        driver: &mut D,
+        #[cfg(feature = "pcap")] sink: Option<&dyn crate::pcap::PacketSink>,
+    ) -> bool {
+        self.waker.register(cx.waker());
+
+        let (_hardware_addr, medium) = to_smoltcp_hardware_address(driver.hardware_address());
+
+        #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
+        {
+            let do_set = match medium {
+                #[cfg(feature = "medium-ethernet")]
+                Medium::Ethernet => true,
+                #[cfg(feature = "medium-ieee802154")]
+                Medium::Ieee802154 => true,
+                #[allow(unreachable_patterns)]
+                _ => false,
+            };
+            if do_set {
+                self.iface.set_hardware_addr(_hardware_addr);
+            }
+        }
+
+        let timestamp = instant_to_smoltcp(Instant::now());
+        let mut smoldev = DriverAdapter {
+            cx: Some(cx),
+            inner: driver,
+            medium,
+            #[cfg(feature = "ipv4-forwarding")]
+            forward: self.forward.as_mut(),
+            #[cfg(feature = "raw-ethernet")]
+            ethernet: None,
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            fragments: self.fragments.as_mut(),
+            #[cfg(feature = "pmtu")]
+            pmtu: &mut self.pmtu,
+            #[cfg(feature = "stats")]
+            stats: &self.stats,
+            #[cfg(all(feature = "stats", feature = "tcp"))]
+            tcp: &mut self.tcp_tracker,
+            #[cfg(feature = "pcap")]
+            sink,
+        };
+        #[cfg(feature = "raw-ethernet")]
+        {
+            self.ethernet.dispatch(&mut smoldev, timestamp);
+            smoldev.ethernet = Some(&mut self.ethernet);
+        }
+        self.iface.poll(timestamp, &mut smoldev, &mut self.sockets);
+        #[cfg(feature = "proto-ipv4-fragmentation")]
+        smoldev.send_fragments();
+
+        // Update link up
+        let old_link_up = self.link_up;
+        self.link_up = driver.link_state(cx) == LinkState::Up;
+
+        // Print when changed
+        let mut changed = false;
+        if old_link_up != self.link_up {
+            info!("link_up = {:?} ({:?})", self.link_up, id);
+            // The interface may have moved to another network.
+            #[cfg(feature = "pmtu")]
+            self.pmtu.clear();
+            changed = true;
+        }
+
+        #[cfg(feature = "dhcpv4")]
+        if let Some(dhcp_handle) = self.dhcp_socket {
+            let socket = self.sockets.get_mut::<dhcpv4::Socket>(dhcp_handle);
+
+            let configure = if self.link_up {
+                if old_link_up != self.link_up {
+                    socket.reset();
+                }
+                match socket.poll() {
+                    None => false,
+                    Some(dhcpv4::Event::Deconfigured) => {
+                        self.static_v4 = None;
+                        #[cfg(feature = "sntp")]
+                        self.ntp_servers.clear();
+                        true
+                    }
+                    Some(dhcpv4::Event::Configured(config)) => {
+                        #[cfg(feature = "sntp")]
+                        {
+                            let ntp_servers = config.packet.map(dhcp_ntp_servers).unwrap_or_default();
+                            if ntp_servers != self.ntp_servers {
+                                self.ntp_servers = ntp_servers;
+                                changed = true;
+                            }
+                        }
+                        self.static_v4 = Some(StaticConfigV4 {
+                            address: config.address,
+                            gateway: config.router,
+                            dns_servers: config.dns_servers,
+                        });
+                        true
+                    }
+                }
+            } else if old_link_up {
+                socket.reset();
+                self.static_v4 = None;
+                #[cfg(feature = "sntp")]
+                self.ntp_servers.clear();
+                true
+            } else {
+                false
+            };
+            if configure {
+                self.apply_config(id, routes);
+                changed = true;
+            }
+        }
+
+        #[cfg(feature = "slaac")]
+        if let Some(slaac) = &mut self.slaac {
+            if old_link_up != self.link_up {
+                slaac.reset();
+            }
+            match slaac.poll(&mut self.sockets, &self.iface, self.link_up) {
+                None => {}
+                Some(slaac::Event::Deconfigured) => {
+                    self.static_v6 = None;
+                    self.apply_config(id, routes);
+                    changed = true;
+                }
+                Some(slaac::Event::Configured(config)) => {
+                    self.static_v6 = Some(config);
+                    self.apply_config(id, routes);
+                    changed = true;
+                }
+            }
+        }
+
+        #[allow(unused_mut)]
+        let mut poll_at = self
+            .iface
+            .poll_at(timestamp, &mut self.sockets)
+            .map(instant_from_smoltcp);
+        #[cfg(feature = "slaac")]
+        if let Some(slaac_poll_at) = self.slaac.as_ref().and_then(|s| s.poll_at()) {
+            poll_at = Some(poll_at.map_or(slaac_poll_at, |t| t.min(slaac_poll_at)));
+        }
+        if let Some(poll_at) = poll_at {
+            let t = pin!(Timer::at(poll_at));
+            if t.poll(cx).is_ready() {
+                cx.waker().wake_by_ref();
+            }
+        }
+
+        changed
+    }
+}
+
+/// Get the NTP servers in a DHCP packet.
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+fn dhcp_ntp_servers(packet: smoltcp::wire::DhcpPacket<&[u8]>) -> Vec<Ipv4Address, MAX_NTP_SERVERS> {
+    packet
+        .options()
+        .filter(|option| option.kind == DHCP_OPT_NTP_SERVERS)
+        .flat_map(|option| option.data.chunks_exact(4))
+        .map(|addr| Ipv4Address::new(addr[0], addr[1], addr[2], addr[3]))
+        .take(MAX_NTP_SERVERS)
+        .collect()
+}
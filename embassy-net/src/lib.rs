@@ -9,43 +9,79 @@
 #[cfg(not(any(feature = "proto-ipv4", feature = "proto-ipv6")))]
 compile_error!("You must enable at least one of the following features: proto-ipv4, proto-ipv6");
 
+#[cfg(test)]
+extern crate std;
+
 // This mod MUST go first, so that the others see its macros.
 pub(crate) mod fmt;
 
+#[cfg(feature = "coap")]
+pub mod coap;
+#[cfg(feature = "dhcpv4-server")]
+pub mod dhcp_server;
 #[cfg(feature = "dns")]
 pub mod dns;
 mod driver_util;
+#[cfg(feature = "raw-ethernet")]
+pub mod ethernet;
+#[cfg(feature = "ipv4-forwarding")]
+mod forward;
+#[cfg(feature = "proto-ipv4-fragmentation")]
+mod fragment;
+#[cfg(feature = "http")]
+pub mod http;
 #[cfg(feature = "icmp")]
 pub mod icmp;
+mod iface;
+#[cfg(feature = "mdns-responder")]
+pub mod mdns;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "pcap")]
+pub mod pcap;
+#[cfg(feature = "pmtu")]
+mod pmtu;
+#[cfg(any(feature = "tcp", feature = "udp"))]
+mod pool;
 #[cfg(feature = "raw")]
 pub mod raw;
+#[cfg(any(feature = "http", feature = "coap"))]
+pub mod router;
+#[cfg(feature = "slaac")]
+mod slaac;
+#[cfg(feature = "sntp")]
+pub mod sntp;
+#[cfg(feature = "stats")]
+pub mod stats;
 #[cfg(feature = "tcp")]
 pub mod tcp;
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod test_util;
 mod time;
+#[cfg(feature = "tls")]
+pub mod tls;
 #[cfg(feature = "udp")]
 pub mod udp;
 
 use core::cell::RefCell;
 use core::future::{poll_fn, Future};
 use core::mem::MaybeUninit;
-use core::pin::pin;
 use core::task::{Context, Poll};
 
 pub use embassy_net_driver as driver;
-use embassy_net_driver::{Driver, LinkState};
+use embassy_net_driver::Driver;
 use embassy_sync::waitqueue::WakerRegistration;
-use embassy_time::{Instant, Timer};
 use heapless::Vec;
 #[cfg(feature = "dns")]
 pub use smoltcp::config::DNS_MAX_SERVER_COUNT;
 #[cfg(feature = "multicast")]
 pub use smoltcp::iface::MulticastError;
-#[cfg(any(feature = "dns", feature = "dhcpv4"))]
-use smoltcp::iface::SocketHandle;
-use smoltcp::iface::{Interface, SocketSet, SocketStorage};
+pub use smoltcp::iface::RouteTableFull;
+use smoltcp::iface::SocketStorage;
 use smoltcp::phy::Medium;
 #[cfg(feature = "dhcpv4")]
-use smoltcp::socket::dhcpv4::{self, RetryConfig};
+use smoltcp::socket::dhcpv4::RetryConfig;
+use smoltcp::socket::AnySocket;
 #[cfg(feature = "medium-ethernet")]
 pub use smoltcp::wire::EthernetAddress;
 #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154", feature = "medium-ip"))]
@@ -60,49 +96,140 @@ pub use smoltcp::wire::{Ipv4Address, Ipv4Cidr};
 #[cfg(feature = "proto-ipv6")]
 pub use smoltcp::wire::{Ipv6Address, Ipv6Cidr};
 
-use crate::driver_util::DriverAdapter;
-use crate::time::{instant_from_smoltcp, instant_to_smoltcp};
+use crate::iface::Iface;
 
 const LOCAL_PORT_MIN: u16 = 1025;
 const LOCAL_PORT_MAX: u16 = 65535;
 #[cfg(feature = "dns")]
 const MAX_QUERIES: usize = 4;
 #[cfg(feature = "dhcpv4-hostname")]
-const MAX_HOSTNAME_LEN: usize = 32;
+pub(crate) const MAX_HOSTNAME_LEN: usize = 32;
+#[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+pub(crate) const MAX_NTP_SERVERS: usize = 3;
+
+const MAX_INTERFACES: usize = 4;
+const MAX_ROUTES: usize = 4;
 
 /// Memory resources needed for a network stack.
-pub struct StackResources<const SOCK: usize> {
-    sockets: MaybeUninit<[SocketStorage<'static>; SOCK]>,
+///
+/// `SOCK` is the number of sockets. `FRAG` is the size of the IPv4 fragmentation and reassembly
+/// buffers with the `proto-ipv4-fragmentation` feature, see [`InterfaceResources`].
+pub struct StackResources<const SOCK: usize, const FRAG: usize = 0> {
+    iface: InterfaceResources<SOCK, FRAG>,
     inner: MaybeUninit<RefCell<Inner>>,
     #[cfg(feature = "dns")]
     queries: MaybeUninit<[Option<dns::DnsQuery>; MAX_QUERIES]>,
+}
+
+impl<const SOCK: usize, const FRAG: usize> StackResources<SOCK, FRAG> {
+    /// Create a new set of stack resources.
+    pub const fn new() -> Self {
+        Self {
+            iface: InterfaceResources::new(),
+            inner: MaybeUninit::uninit(),
+            #[cfg(feature = "dns")]
+            queries: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Memory resources needed for an additional network interface.
+///
+/// `SOCK` is the number of sockets. With the `proto-ipv4-fragmentation` feature, `FRAG` is the
+/// size of the buffers used to reassemble received IPv4 packets and fragment sent ones, one packet
+/// at a time in each direction. It includes the link-layer header: with 4110, IPv4 packets of up
+/// to 4096 bytes are sent and received on the Ethernet medium. 0, the default, disables
+/// fragmentation. The feature has no effect on the IEEE 802.15.4 medium, where
+/// `proto-sixlowpan-fragmentation` uses buffers of smoltcp sized for the IPv6 minimum MTU.
+///
+/// See [`Stack::add_interface`].
+pub struct InterfaceResources<const SOCK: usize, const FRAG: usize = 0> {
+    sockets: MaybeUninit<[SocketStorage<'static>; SOCK]>,
+    iface: MaybeUninit<Iface>,
     #[cfg(feature = "dhcpv4-hostname")]
     hostname: HostnameResources,
+    #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+    dhcp_packet: [u8; iface::DHCP_PACKET_LEN],
+    #[cfg(feature = "slaac")]
+    slaac: slaac::SlaacResources,
+    #[cfg(feature = "ipv4-forwarding")]
+    forward: forward::ForwardResources,
+    #[cfg(feature = "proto-ipv4-fragmentation")]
+    fragments: fragment::FragmentResources<FRAG>,
 }
 
 #[cfg(feature = "dhcpv4-hostname")]
-struct HostnameResources {
-    option: MaybeUninit<smoltcp::wire::DhcpOption<'static>>,
-    data: MaybeUninit<[u8; MAX_HOSTNAME_LEN]>,
+pub(crate) struct HostnameResources {
+    pub(crate) option: MaybeUninit<smoltcp::wire::DhcpOption<'static>>,
+    pub(crate) data: MaybeUninit<[u8; MAX_HOSTNAME_LEN]>,
 }
 
-impl<const SOCK: usize> StackResources<SOCK> {
-    /// Create a new set of stack resources.
+impl<const SOCK: usize, const FRAG: usize> InterfaceResources<SOCK, FRAG> {
+    /// Create a new set of interface resources.
     pub const fn new() -> Self {
         Self {
             sockets: MaybeUninit::uninit(),
-            inner: MaybeUninit::uninit(),
-            #[cfg(feature = "dns")]
-            queries: MaybeUninit::uninit(),
+            iface: MaybeUninit::uninit(),
             #[cfg(feature = "dhcpv4-hostname")]
             hostname: HostnameResources {
                 option: MaybeUninit::uninit(),
                 data: MaybeUninit::uninit(),
             },
+            #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+            dhcp_packet: [0; iface::DHCP_PACKET_LEN],
+            #[cfg(feature = "slaac")]
+            slaac: slaac::SlaacResources::new(),
+            #[cfg(feature = "ipv4-forwarding")]
+            forward: forward::ForwardResources::new(),
+            #[cfg(feature = "proto-ipv4-fragmentation")]
+            fragments: fragment::FragmentResources::new(),
         }
     }
 }
 
+impl<const SOCK: usize, const FRAG: usize> Default for InterfaceResources<SOCK, FRAG> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned when a socket can't be moved to a network interface.
+///
+/// Each interface has its own set of sockets, sized by its [`StackResources`] or
+/// [`InterfaceResources`], and it has no room left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SocketSetFull;
+
+/// Identifier of a network interface of a [`Stack`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct InterfaceId(u8);
+
+impl InterfaceId {
+    /// The interface the stack was created with.
+    pub const PRIMARY: Self = Self(0);
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A static route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Route {
+    /// Destination network.
+    pub destination: IpCidr,
+    /// Router to send the packets to.
+    pub gateway: IpAddress,
+    /// Interface the router is reachable through.
+    pub interface: InterfaceId,
+    /// Route metric. Among the routes with the longest matching prefix, the one with the
+    /// lowest metric is used.
+    pub metric: u32,
+}
+
 /// Static IP address configuration.
 #[cfg(feature = "proto-ipv4")]
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -215,6 +342,16 @@ impl Config {
             ipv6: ConfigV6::None,
         }
     }
+
+    /// IPv6 configuration with stateless address autoconfiguration.
+    #[cfg(feature = "slaac")]
+    pub const fn slaac() -> Self {
+        Self {
+            #[cfg(feature = "proto-ipv4")]
+            ipv4: ConfigV4::None,
+            ipv6: ConfigV6::Slaac,
+        }
+    }
 }
 
 /// Network stack IPv4 configuration.
@@ -240,6 +377,18 @@ pub enum ConfigV6 {
     None,
     /// Use a static IPv6 address configuration.
     Static(StaticConfigV6),
+    /// Use stateless address autoconfiguration (SLAAC) to obtain an IP address configuration.
+    ///
+    /// The address is formed from the first /64 prefix advertised for autonomous configuration in
+    /// Router Advertisements, the default gateway is the advertising router, and the DNS servers are
+    /// taken from the Recursive DNS Server option.
+    ///
+    /// The link-local address is also assigned to the interface, if `smoltcp`'s `IFACE_MAX_ADDR_COUNT`
+    /// leaves room for it.
+    ///
+    /// SLAAC uses one socket slot in [`StackResources`].
+    #[cfg(feature = "slaac")]
+    Slaac,
 }
 
 /// Network stack runner.
@@ -248,6 +397,9 @@ pub enum ConfigV6 {
 pub struct Runner<'d, D: Driver> {
     driver: D,
     stack: Stack<'d>,
+    id: InterfaceId,
+    #[cfg(feature = "pcap")]
+    sink: Option<&'d dyn pcap::PacketSink>,
 }
 
 /// Network stack handle
@@ -259,28 +411,37 @@ pub struct Stack<'d> {
     inner: &'d RefCell<Inner>,
 }
 
+/// Network interface handle
+///
+/// Use this to get and set the state of one of the network interfaces of a [`Stack`].
+/// It's `Copy`, so you can pass it by value instead of by reference.
+#[derive(Copy, Clone)]
+pub struct Interface<'d> {
+    stack: Stack<'d>,
+    id: InterfaceId,
+}
+
+/// Handle to a socket in the socket set of one of the stack's interfaces.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub(crate) struct SocketHandle {
+    pub(crate) iface: InterfaceId,
+    socket: smoltcp::iface::SocketHandle,
+}
+
 pub(crate) struct Inner {
-    pub(crate) sockets: SocketSet<'static>, // Lifetime type-erased.
-    pub(crate) iface: Interface,
-    /// Waker used for triggering polls.
-    pub(crate) waker: WakerRegistration,
+    /// The interfaces. They live at least as long as the stack.
+    ifaces: Vec<*mut Iface, MAX_INTERFACES>,
+    routes: Vec<Route, MAX_ROUTES>,
     /// Waker used for waiting for link up or config up.
     state_waker: WakerRegistration,
-    hardware_address: HardwareAddress,
     next_local_port: u16,
-    link_up: bool,
-    #[cfg(feature = "proto-ipv4")]
-    static_v4: Option<StaticConfigV4>,
-    #[cfg(feature = "proto-ipv6")]
-    static_v6: Option<StaticConfigV6>,
-    #[cfg(feature = "dhcpv4")]
-    dhcp_socket: Option<SocketHandle>,
+    random_seed: u64,
+    #[cfg(feature = "ipv4-forwarding")]
+    ipv4_forwarding: bool,
     #[cfg(feature = "dns")]
     dns_socket: SocketHandle,
     #[cfg(feature = "dns")]
     dns_waker: WakerRegistration,
-    #[cfg(feature = "dhcpv4-hostname")]
-    hostname: *mut HostnameResources,
 }
 
 fn _assert_covariant<'a, 'b: 'a>(x: Stack<'b>) -> Stack<'a> {
@@ -288,75 +449,57 @@ fn _assert_covariant<'a, 'b: 'a>(x: Stack<'b>) -> Stack<'a> {
 }
 
 /// Create a new network stack.
-pub fn new<'d, D: Driver, const SOCK: usize>(
+pub fn new<'d, D: Driver, const SOCK: usize, const FRAG: usize>(
     mut driver: D,
     config: Config,
-    resources: &'d mut StackResources<SOCK>,
+    resources: &'d mut StackResources<SOCK, FRAG>,
     random_seed: u64,
 ) -> (Stack<'d>, Runner<'d, D>) {
-    let (hardware_address, medium) = to_smoltcp_hardware_address(driver.hardware_address());
-    let mut iface_cfg = smoltcp::iface::Config::new(hardware_address);
-    iface_cfg.random_seed = random_seed;
-
-    let iface = Interface::new(
-        iface_cfg,
-        &mut DriverAdapter {
-            inner: &mut driver,
-            cx: None,
-            medium,
-        },
-        instant_to_smoltcp(Instant::now()),
-    );
-
-    unsafe fn transmute_slice<T>(x: &mut [T]) -> &'static mut [T] {
-        core::mem::transmute(x)
-    }
-
-    let sockets = resources.sockets.write([SocketStorage::EMPTY; SOCK]);
-    #[allow(unused_mut)]
-    let mut sockets: SocketSet<'static> = SocketSet::new(unsafe { transmute_slice(sockets) });
+    let iface = Iface::init(&mut driver, &mut resources.iface, random_seed);
 
     let next_local_port = (random_seed % (LOCAL_PORT_MAX - LOCAL_PORT_MIN) as u64) as u16 + LOCAL_PORT_MIN;
 
     #[cfg(feature = "dns")]
-    let dns_socket = sockets.add(dns::Socket::new(
-        &[],
-        managed::ManagedSlice::Borrowed(unsafe {
-            transmute_slice(resources.queries.write([const { None }; MAX_QUERIES]))
-        }),
-    ));
+    let dns_socket = SocketHandle {
+        iface: InterfaceId::PRIMARY,
+        socket: iface.sockets.add(dns::Socket::new(
+            &[],
+            managed::ManagedSlice::Borrowed(unsafe {
+                core::mem::transmute::<&mut [Option<dns::DnsQuery>], &'static mut [Option<dns::DnsQuery>]>(
+                    resources.queries.write([const { None }; MAX_QUERIES]),
+                )
+            }),
+        )),
+    };
 
     let mut inner = Inner {
-        sockets,
-        iface,
-        waker: WakerRegistration::new(),
+        ifaces: Vec::new(),
+        routes: Vec::new(),
         state_waker: WakerRegistration::new(),
         next_local_port,
-        hardware_address,
-        link_up: false,
-        #[cfg(feature = "proto-ipv4")]
-        static_v4: None,
-        #[cfg(feature = "proto-ipv6")]
-        static_v6: None,
-        #[cfg(feature = "dhcpv4")]
-        dhcp_socket: None,
+        random_seed,
+        #[cfg(feature = "ipv4-forwarding")]
+        ipv4_forwarding: false,
         #[cfg(feature = "dns")]
         dns_socket,
         #[cfg(feature = "dns")]
         dns_waker: WakerRegistration::new(),
-        #[cfg(feature = "dhcpv4-hostname")]
-        hostname: &mut resources.hostname,
     };
-
-    #[cfg(feature = "proto-ipv4")]
-    inner.set_config_v4(config.ipv4);
-    #[cfg(feature = "proto-ipv6")]
-    inner.set_config_v6(config.ipv6);
-    inner.apply_static_config();
+    unwrap!(inner.ifaces.push(iface).ok());
+    inner.set_config(InterfaceId::PRIMARY, config);
 
     let inner = &*resources.inner.write(RefCell::new(inner));
     let stack = Stack { inner };
-    (stack, Runner { driver, stack })
+    (
+        stack,
+        Runner {
+            driver,
+            stack,
+            id: InterfaceId::PRIMARY,
+            #[cfg(feature = "pcap")]
+            sink: None,
+        },
+    )
 }
 
 fn to_smoltcp_hardware_address(addr: driver::HardwareAddress) -> (HardwareAddress, Medium) {
@@ -388,60 +531,189 @@ impl<'d> Stack<'d> {
         f(&mut self.inner.borrow_mut())
     }
 
-    /// Get the hardware address of the network interface.
-    pub fn hardware_address(&self) -> HardwareAddress {
-        self.with(|i| i.hardware_address)
+    #[cfg(any(feature = "coap", feature = "tcp"))]
+    pub(crate) fn random_seed(&self) -> u64 {
+        self.with(|i| i.random_seed)
     }
 
-    /// Check whether the link is up.
-    pub fn is_link_up(&self) -> bool {
-        self.with(|i| i.link_up)
+    /// Add a network interface to the stack.
+    ///
+    /// The stack starts with one interface, the primary interface, which is the one the
+    /// methods of `Stack` such as [`Stack::is_link_up`] or [`Stack::config_v4`] refer to.
+    /// Use the returned [`Interface`] to manage the new interface, and call [`Runner::run`]
+    /// on the returned runner in a background task.
+    ///
+    /// Packets are sent through the interface given by the routing table:
+    ///
+    /// - the interfaces' own networks, from their IP address configuration,
+    /// - the static routes added with [`Stack::add_route`],
+    /// - the default routes given by the interfaces' default gateways.
+    ///
+    /// The route with the longest matching prefix wins. Among them, the one with the lowest
+    /// metric wins (see [`Interface::set_metric`]), and then the one added first. Only
+    /// interfaces whose link is up are considered.
+    ///
+    /// Sockets are created on the primary interface, and only send and receive packets through
+    /// the interface they are on. They can be bound to an interface explicitly. Otherwise, TCP
+    /// and UDP sockets are moved to the interface of the local address they're bound to, or to
+    /// the interface given by the routing table when connecting (TCP) or sending (UDP sockets set
+    /// to follow routes).
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack already has the maximum number of interfaces, 4.
+    pub fn add_interface<D: Driver, const SOCK: usize, const FRAG: usize>(
+        &self,
+        mut driver: D,
+        config: Config,
+        resources: &'static mut InterfaceResources<SOCK, FRAG>,
+    ) -> (Interface<'d>, Runner<'d, D>) {
+        let id = self.with_mut(|i| {
+            if i.ifaces.is_full() {
+                panic!("Too many network interfaces, the maximum is {}", MAX_INTERFACES);
+            }
+            let id = InterfaceId(i.ifaces.len() as u8);
+            let iface = Iface::init(&mut driver, resources, i.random_seed.wrapping_add(id.0 as u64));
+            unwrap!(i.ifaces.push(iface).ok());
+            #[cfg(feature = "ipv4-forwarding")]
+            if i.ipv4_forwarding {
+                i.iface_mut(id).set_ipv4_forwarding(true);
+            }
+            i.set_config(id, config);
+            id
+        });
+
+        let stack = *self;
+        let runner = Runner {
+            driver,
+            stack,
+            id,
+            #[cfg(feature = "pcap")]
+            sink: None,
+        };
+        (Interface { stack, id }, runner)
     }
 
-    /// Check whether the network stack has a valid IP configuration.
-    /// This is true if the network stack has a static IP configuration or if DHCP has completed
-    pub fn is_config_up(&self) -> bool {
-        let v4_up;
-        let v6_up;
+    /// Get a handle to one of the network interfaces of the stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the stack has no interface with this ID.
+    pub fn interface(&self, id: InterfaceId) -> Interface<'d> {
+        assert!(id.index() < self.with(|i| i.ifaces.len()), "No such network interface");
+        Interface { stack: *self, id }
+    }
 
-        #[cfg(feature = "proto-ipv4")]
-        {
-            v4_up = self.config_v4().is_some();
-        }
-        #[cfg(not(feature = "proto-ipv4"))]
-        {
-            v4_up = false;
-        }
+    /// Iterate over the network interfaces of the stack.
+    pub fn interfaces(&self) -> impl Iterator<Item = Interface<'d>> {
+        let stack = *self;
+        let count = self.with(|i| i.ifaces.len());
+        (0..count).map(move |n| Interface {
+            stack,
+            id: InterfaceId(n as u8),
+        })
+    }
 
-        #[cfg(feature = "proto-ipv6")]
-        {
-            v6_up = self.config_v6().is_some();
-        }
-        #[cfg(not(feature = "proto-ipv6"))]
-        {
-            v6_up = false;
+    fn primary(&self) -> Interface<'d> {
+        Interface {
+            stack: *self,
+            id: InterfaceId::PRIMARY,
         }
+    }
+
+    /// Add a static route.
+    ///
+    /// See [`Stack::add_interface`] for how routes are selected.
+    pub fn add_route(&self, route: Route) -> Result<(), RouteTableFull> {
+        self.with_mut(|i| {
+            assert!(route.interface.index() < i.ifaces.len(), "No such network interface");
+            i.routes.push(route).map_err(|_| RouteTableFull)?;
+            i.apply_config(route.interface);
+            Ok(())
+        })
+    }
 
-        v4_up || v6_up
+    /// Remove the static routes to `destination` through `interface`.
+    ///
+    /// Returns whether a route was removed.
+    pub fn remove_route(&self, destination: IpCidr, interface: InterfaceId) -> bool {
+        self.with_mut(|i| {
+            let len = i.routes.len();
+            i.routes
+                .retain(|r| !(r.destination == destination && r.interface == interface));
+            let removed = i.routes.len() != len;
+            if removed {
+                i.apply_config(interface);
+            }
+            removed
+        })
     }
 
-    /// Wait for the network device to obtain a link signal.
+    /// Get the interface packets sent to `addr` go through, if any.
+    pub fn lookup_route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        self.with(|i| i.route(addr))
+    }
+
+    /// Enable or disable forwarding of IPv4 packets between the interfaces of the stack.
+    ///
+    /// When enabled, IPv4 packets received on an interface that are not addressed to the stack
+    /// are sent through the interface given by the routing table, if it's not the interface
+    /// they came from.
+    ///
+    /// Only unfragmented TCP, UDP and ICMP packets of up to 1500 bytes are forwarded, and IP
+    /// options are dropped. No ICMP error is sent for packets that can't be forwarded.
+    ///
+    /// Forwarding uses three socket slots on each interface. While it's enabled, no ICMP port
+    /// unreachable message is sent in response to UDP packets sent to closed ports.
+    #[cfg(feature = "ipv4-forwarding")]
+    pub fn set_ipv4_forwarding(&self, enabled: bool) {
+        self.with_mut(|i| {
+            i.ipv4_forwarding = enabled;
+            for n in 0..i.ifaces.len() {
+                i.iface_mut(InterfaceId(n as u8)).set_ipv4_forwarding(enabled);
+            }
+        })
+    }
+
+    /// Get whether IPv4 forwarding is enabled.
+    #[cfg(feature = "ipv4-forwarding")]
+    pub fn ipv4_forwarding(&self) -> bool {
+        self.with(|i| i.ipv4_forwarding)
+    }
+
+    /// Get the hardware address of the primary network interface.
+    pub fn hardware_address(&self) -> HardwareAddress {
+        self.primary().hardware_address()
+    }
+
+    /// Check whether the link of the primary network interface is up.
+    pub fn is_link_up(&self) -> bool {
+        self.primary().is_link_up()
+    }
+
+    /// Check whether the primary network interface has a valid IP configuration.
+    /// This is true if the network stack has a static IP configuration or if DHCP has completed
+    pub fn is_config_up(&self) -> bool {
+        self.primary().is_config_up()
+    }
+
+    /// Wait for the primary network device to obtain a link signal.
     pub async fn wait_link_up(&self) {
-        self.wait(|| self.is_link_up()).await
+        self.primary().wait_link_up().await
     }
 
-    /// Wait for the network device to lose link signal.
+    /// Wait for the primary network device to lose link signal.
     pub async fn wait_link_down(&self) {
-        self.wait(|| !self.is_link_up()).await
+        self.primary().wait_link_down().await
     }
 
-    /// Wait for the network stack to obtain a valid IP configuration.
+    /// Wait for the network stack to obtain a valid IP configuration on the primary interface.
     ///
     /// ## Notes:
     /// - Ensure [`Runner::run`] has been started before using this function.
     ///
     /// - This function may never return (e.g. if no configuration is obtained through DHCP).
-    /// The caller is supposed to handle a timeout for this case.
+    ///   The caller is supposed to handle a timeout for this case.
     ///
     /// ## Example
     /// ```ignore
@@ -465,12 +737,12 @@ impl<'d> Stack<'d> {
     /// // ...
     /// ```
     pub async fn wait_config_up(&self) {
-        self.wait(|| self.is_config_up()).await
+        self.primary().wait_config_up().await
     }
 
-    /// Wait for the network stack to lose a valid IP configuration.
+    /// Wait for the network stack to lose a valid IP configuration on the primary interface.
     pub async fn wait_config_down(&self) {
-        self.wait(|| !self.is_config_up()).await
+        self.primary().wait_config_down().await
     }
 
     fn wait<'a>(&'a self, mut predicate: impl FnMut() -> bool + 'a) -> impl Future<Output = ()> + 'a {
@@ -491,40 +763,77 @@ impl<'d> Stack<'d> {
         })
     }
 
-    /// Get the current IPv4 configuration.
+    /// Get the current IPv4 configuration of the primary interface.
     ///
     /// If using DHCP, this will be None if DHCP hasn't been able to
     /// acquire an IP address, or Some if it has.
     #[cfg(feature = "proto-ipv4")]
     pub fn config_v4(&self) -> Option<StaticConfigV4> {
-        self.with(|i| i.static_v4.clone())
+        self.primary().config_v4()
     }
 
-    /// Get the current IPv6 configuration.
+    /// Get the current IPv6 configuration of the primary interface.
     #[cfg(feature = "proto-ipv6")]
     pub fn config_v6(&self) -> Option<StaticConfigV6> {
-        self.with(|i| i.static_v6.clone())
+        self.primary().config_v6()
     }
 
-    /// Set the IPv4 configuration.
+    /// Get the NTP servers provided by DHCP to the primary interface.
+    #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+    pub fn ntp_servers(&self) -> Vec<Ipv4Address, MAX_NTP_SERVERS> {
+        self.primary().ntp_servers()
+    }
+
+    /// Get the counters of the primary interface.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> stats::InterfaceStats {
+        self.primary().stats()
+    }
+
+    /// Get the MTU of the primary interface, see [`Interface::mtu`].
+    pub fn mtu(&self) -> usize {
+        self.primary().mtu()
+    }
+
+    /// Get the path MTU to `addr`, through the interface given by the routing table.
+    ///
+    /// Returns `None` if there's no route to `addr`. See [`Interface::path_mtu`].
+    #[cfg(feature = "pmtu")]
+    pub fn path_mtu(&self, addr: IpAddress) -> Option<usize> {
+        self.with(|i| {
+            let id = i.route(addr)?;
+            Some(i.iface(id).path_mtu(addr))
+        })
+    }
+
+    /// Get the time fragments of a packet are kept on the primary interface while waiting for the others.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub fn reassembly_timeout(&self) -> embassy_time::Duration {
+        self.primary().reassembly_timeout()
+    }
+
+    /// Set the time fragments of a packet are kept on the primary interface while waiting for the others.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub fn set_reassembly_timeout(&self, timeout: embassy_time::Duration) {
+        self.primary().set_reassembly_timeout(timeout)
+    }
+
+    /// Set the IPv4 configuration of the primary interface.
     #[cfg(feature = "proto-ipv4")]
     pub fn set_config_v4(&self, config: ConfigV4) {
-        self.with_mut(|i| {
-            i.set_config_v4(config);
-            i.apply_static_config();
-        })
+        self.primary().set_config_v4(config)
     }
 
-    /// Set the IPv6 configuration.
+    /// Set the IPv6 configuration of the primary interface.
     #[cfg(feature = "proto-ipv6")]
     pub fn set_config_v6(&self, config: ConfigV6) {
-        self.with_mut(|i| {
-            i.set_config_v6(config);
-            i.apply_static_config();
-        })
+        self.primary().set_config_v6(config)
     }
 
     /// Make a query for a given name and return the corresponding IP addresses.
+    ///
+    /// The query is sent to the DNS servers of the interface with the lowest metric among the
+    /// ones that have DNS servers, preferring interfaces whose link is up.
     #[cfg(feature = "dns")]
     pub async fn dns_query(
         &self,
@@ -550,10 +859,11 @@ impl<'d> Stack<'d> {
 
         let query = poll_fn(|cx| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
-                match socket.start_query(i.iface.context(), name, qtype) {
+                let handle = i.dns_socket;
+                let (socket, iface) = i.socket_mut::<dns::Socket>(handle);
+                match socket.start_query(iface.context(), name, qtype) {
                     Ok(handle) => {
-                        i.waker.wake();
+                        i.wake(i.dns_socket);
                         Poll::Ready(Ok(handle))
                     }
                     Err(dns::StartQueryError::NoFreeSlot) => {
@@ -591,16 +901,18 @@ impl<'d> Stack<'d> {
 
         let drop = OnDrop::new(|| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
+                let handle = i.dns_socket;
+                let (socket, _) = i.socket_mut::<dns::Socket>(handle);
                 socket.cancel_query(query);
-                i.waker.wake();
+                i.wake(handle);
                 i.dns_waker.wake();
             })
         });
 
         let res = poll_fn(|cx| {
             self.with_mut(|i| {
-                let socket = i.sockets.get_mut::<dns::Socket>(i.dns_socket);
+                let handle = i.dns_socket;
+                let (socket, _) = i.socket_mut::<dns::Socket>(handle);
                 match socket.get_query_result(query) {
                     Ok(addrs) => {
                         i.dns_waker.wake();
@@ -627,266 +939,613 @@ impl<'d> Stack<'d> {
 
 #[cfg(feature = "multicast")]
 impl<'d> Stack<'d> {
-    /// Join a multicast group.
+    /// Join a multicast group on the primary interface.
     pub fn join_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.join_multicast_group(addr))
+        self.with_mut(|i| i.iface_mut(InterfaceId::PRIMARY).iface.join_multicast_group(addr))
     }
 
-    /// Leave a multicast group.
+    /// Leave a multicast group on the primary interface.
     pub fn leave_multicast_group(&self, addr: impl Into<IpAddress>) -> Result<(), MulticastError> {
-        self.with_mut(|i| i.iface.leave_multicast_group(addr))
+        self.with_mut(|i| i.iface_mut(InterfaceId::PRIMARY).iface.leave_multicast_group(addr))
     }
 
-    /// Get whether the network stack has joined the given multicast group.
+    /// Get whether the primary interface has joined the given multicast group.
     pub fn has_multicast_group(&self, addr: impl Into<IpAddress>) -> bool {
-        self.with(|i| i.iface.has_multicast_group(addr))
+        self.with(|i| i.iface(InterfaceId::PRIMARY).iface.has_multicast_group(addr))
     }
 }
 
-impl Inner {
-    #[allow(clippy::absurd_extreme_comparisons)]
-    pub fn get_local_port(&mut self) -> u16 {
-        let res = self.next_local_port;
-        self.next_local_port = if res >= LOCAL_PORT_MAX { LOCAL_PORT_MIN } else { res + 1 };
-        res
+impl<'d> Interface<'d> {
+    /// Get the ID of the interface.
+    pub fn id(&self) -> InterfaceId {
+        self.id
+    }
+
+    /// Get the stack the interface belongs to.
+    pub fn stack(&self) -> Stack<'d> {
+        self.stack
+    }
+
+    /// Get the hardware address of the network interface.
+    pub fn hardware_address(&self) -> HardwareAddress {
+        self.stack.with(|i| i.iface(self.id).hardware_address)
+    }
+
+    /// Check whether the link is up.
+    pub fn is_link_up(&self) -> bool {
+        self.stack.with(|i| i.iface(self.id).link_up)
+    }
+
+    /// Check whether the interface has a valid IP configuration.
+    /// This is true if the interface has a static IP configuration or if DHCP has completed
+    pub fn is_config_up(&self) -> bool {
+        self.stack.with(|i| i.iface(self.id).is_config_up())
+    }
+
+    /// Wait for the network device to obtain a link signal.
+    pub async fn wait_link_up(&self) {
+        self.stack.wait(|| self.is_link_up()).await
+    }
+
+    /// Wait for the network device to lose link signal.
+    pub async fn wait_link_down(&self) {
+        self.stack.wait(|| !self.is_link_up()).await
+    }
+
+    /// Wait for the interface to obtain a valid IP configuration.
+    ///
+    /// This function may never return (e.g. if no configuration is obtained through DHCP).
+    /// The caller is supposed to handle a timeout for this case.
+    pub async fn wait_config_up(&self) {
+        self.stack.wait(|| self.is_config_up()).await
+    }
+
+    /// Wait for the interface to lose a valid IP configuration.
+    pub async fn wait_config_down(&self) {
+        self.stack.wait(|| !self.is_config_up()).await
     }
 
+    /// Get the current IPv4 configuration.
+    ///
+    /// If using DHCP, this will be None if DHCP hasn't been able to
+    /// acquire an IP address, or Some if it has.
     #[cfg(feature = "proto-ipv4")]
-    pub fn set_config_v4(&mut self, config: ConfigV4) {
-        // Handle static config.
-        self.static_v4 = match config.clone() {
-            ConfigV4::None => None,
-            #[cfg(feature = "dhcpv4")]
-            ConfigV4::Dhcp(_) => None,
-            ConfigV4::Static(c) => Some(c),
-        };
+    pub fn config_v4(&self) -> Option<StaticConfigV4> {
+        self.stack.with(|i| i.iface(self.id).static_v4.clone())
+    }
 
-        // Handle DHCP config.
-        #[cfg(feature = "dhcpv4")]
-        match config {
-            ConfigV4::Dhcp(c) => {
-                // Create the socket if it doesn't exist.
-                if self.dhcp_socket.is_none() {
-                    let socket = smoltcp::socket::dhcpv4::Socket::new();
-                    let handle = self.sockets.add(socket);
-                    self.dhcp_socket = Some(handle);
-                }
+    /// Get the current IPv6 configuration.
+    #[cfg(feature = "proto-ipv6")]
+    pub fn config_v6(&self) -> Option<StaticConfigV6> {
+        self.stack.with(|i| i.iface(self.id).static_v6.clone())
+    }
 
-                // Configure it
-                let socket = self.sockets.get_mut::<dhcpv4::Socket>(unwrap!(self.dhcp_socket));
-                socket.set_ignore_naks(c.ignore_naks);
-                socket.set_max_lease_duration(c.max_lease_duration.map(crate::time::duration_to_smoltcp));
-                socket.set_ports(c.server_port, c.client_port);
-                socket.set_retry_config(c.retry_config);
-
-                socket.set_outgoing_options(&[]);
-                #[cfg(feature = "dhcpv4-hostname")]
-                if let Some(h) = c.hostname {
-                    // safety:
-                    // - we just did set_outgoing_options([]) so we know the socket is no longer holding a reference.
-                    // - we know this pointer lives for as long as the stack exists, because `new()` borrows
-                    //   the resources for `'d`. Therefore it's OK to pass a reference to this to smoltcp.
-                    let hostname = unsafe { &mut *self.hostname };
-
-                    // create data
-                    let data = hostname.data.write([0; MAX_HOSTNAME_LEN]);
-                    data[..h.len()].copy_from_slice(h.as_bytes());
-                    let data: &[u8] = &data[..h.len()];
-
-                    // set the option.
-                    let option = hostname.option.write(smoltcp::wire::DhcpOption { data, kind: 12 });
-                    socket.set_outgoing_options(core::slice::from_ref(option));
-                }
+    /// Get the NTP servers provided by DHCP (option 42).
+    #[cfg(all(feature = "sntp", feature = "dhcpv4"))]
+    pub fn ntp_servers(&self) -> Vec<Ipv4Address, MAX_NTP_SERVERS> {
+        self.stack.with(|i| i.iface(self.id).ntp_servers.clone())
+    }
 
-                socket.reset();
-            }
-            _ => {
-                // Remove DHCP socket if any.
-                if let Some(socket) = self.dhcp_socket {
-                    self.sockets.remove(socket);
-                    self.dhcp_socket = None;
-                }
-            }
-        }
+    /// Get the counters of the interface.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> stats::InterfaceStats {
+        self.stack.with(|i| i.iface(self.id).stats.get())
+    }
+
+    /// Get the size of the largest IP packet the interface sends without fragmenting it.
+    ///
+    /// This is the MTU of the driver, without the link-layer header. On the IEEE 802.15.4 medium
+    /// with the `proto-sixlowpan-fragmentation` feature, it's the IPv6 minimum MTU of 1280 bytes.
+    /// With the `proto-ipv4-fragmentation` feature, larger IPv4 packets are sent as fragments.
+    pub fn mtu(&self) -> usize {
+        self.stack.with(|i| i.iface(self.id).mtu)
     }
 
+    /// Get the path MTU to `addr` through this interface.
+    ///
+    /// smoltcp doesn't do path MTU discovery, but the MTUs reported by the ICMPv4 "fragmentation
+    /// needed" and ICMPv6 "packet too big" errors the interface receives are remembered for 10
+    /// minutes, for up to 4 destinations. This is the lowest of that and [`Interface::mtu`].
+    ///
+    /// smoltcp doesn't use it to size the packets it sends, TCP segments are sized from the
+    /// interface MTU only. It's meant for applications sizing UDP datagrams.
+    #[cfg(feature = "pmtu")]
+    pub fn path_mtu(&self, addr: IpAddress) -> usize {
+        self.stack.with(|i| i.iface(self.id).path_mtu(addr))
+    }
+
+    /// Get the time fragments of a packet are kept while waiting for the others.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub fn reassembly_timeout(&self) -> embassy_time::Duration {
+        self.stack.with(|i| i.iface(self.id).reassembly_timeout())
+    }
+
+    /// Set the time fragments of a packet are kept while waiting for the others.
+    ///
+    /// The default is 60 seconds, which is also the maximum RFC 4944 allows for 6LoWPAN.
+    #[cfg(any(feature = "proto-ipv4-fragmentation", feature = "proto-sixlowpan-fragmentation"))]
+    pub fn set_reassembly_timeout(&self, timeout: embassy_time::Duration) {
+        self.stack
+            .with_mut(|i| i.iface_mut(self.id).set_reassembly_timeout(timeout))
+    }
+
+    /// Set the IPv4 configuration.
+    #[cfg(feature = "proto-ipv4")]
+    pub fn set_config_v4(&self, config: ConfigV4) {
+        self.stack.with_mut(|i| {
+            i.iface_mut(self.id).set_config_v4(config);
+            i.apply_config(self.id);
+        })
+    }
+
+    /// Set the IPv6 configuration.
     #[cfg(feature = "proto-ipv6")]
-    pub fn set_config_v6(&mut self, config: ConfigV6) {
-        self.static_v6 = match config {
-            ConfigV6::None => None,
-            ConfigV6::Static(c) => Some(c),
-        };
+    pub fn set_config_v6(&self, config: ConfigV6) {
+        self.stack.with_mut(|i| {
+            i.iface_mut(self.id).set_config_v6(config);
+            i.apply_config(self.id);
+        })
+    }
+
+    /// Get the metric of the interface's own networks and default routes.
+    pub fn metric(&self) -> u32 {
+        self.stack.with(|i| i.iface(self.id).metric)
+    }
+
+    /// Set the metric of the interface's own networks and default routes.
+    ///
+    /// When several interfaces have a default gateway, the one with the lowest metric is used
+    /// for the default route. All interfaces start with a metric of 0.
+    pub fn set_metric(&self, metric: u32) {
+        self.stack.with_mut(|i| {
+            i.iface_mut(self.id).metric = metric;
+            #[cfg(feature = "dns")]
+            i.update_dns();
+        })
     }
+}
 
-    fn apply_static_config(&mut self) {
-        let mut addrs = Vec::new();
+/// Add `socket`, removed from another socket set, to `sockets`.
+fn add_any_socket(
+    sockets: &mut smoltcp::iface::SocketSet<'static>,
+    socket: smoltcp::socket::Socket<'static>,
+) -> smoltcp::iface::SocketHandle {
+    use smoltcp::socket::Socket;
+
+    match socket {
+        #[cfg(feature = "tcp")]
+        Socket::Tcp(s) => sockets.add(s),
+        #[cfg(feature = "udp")]
+        Socket::Udp(s) => sockets.add(s),
+        #[cfg(feature = "icmp")]
+        Socket::Icmp(s) => sockets.add(s),
+        #[cfg(feature = "raw")]
+        Socket::Raw(s) => sockets.add(s),
         #[cfg(feature = "dns")]
-        let mut dns_servers: Vec<_, 6> = Vec::new();
-        #[cfg(feature = "proto-ipv4")]
-        let mut gateway_v4 = None;
-        #[cfg(feature = "proto-ipv6")]
-        let mut gateway_v6 = None;
+        Socket::Dns(s) => sockets.add(s),
+        #[allow(unreachable_patterns)]
+        _ => unreachable!(),
+    }
+}
 
-        #[cfg(feature = "proto-ipv4")]
-        if let Some(config) = &self.static_v4 {
-            debug!("IPv4: UP");
-            debug!("   IP address:      {:?}", config.address);
-            debug!("   Default gateway: {:?}", config.gateway);
+impl Inner {
+    pub(crate) fn iface(&self, id: InterfaceId) -> &Iface {
+        // safety: the interfaces live at least as long as the stack.
+        unsafe { &*self.ifaces[id.index()] }
+    }
 
-            unwrap!(addrs.push(IpCidr::Ipv4(config.address)).ok());
-            gateway_v4 = config.gateway;
-            #[cfg(feature = "dns")]
-            for s in &config.dns_servers {
-                debug!("   DNS server:      {:?}", s);
-                unwrap!(dns_servers.push(s.clone().into()).ok());
-            }
-        } else {
-            info!("IPv4: DOWN");
+    pub(crate) fn iface_mut(&mut self, id: InterfaceId) -> &mut Iface {
+        // safety: the interfaces live at least as long as the stack.
+        unsafe { &mut *self.ifaces[id.index()] }
+    }
+
+    fn ifaces(&self) -> impl Iterator<Item = (InterfaceId, &Iface)> {
+        (0..self.ifaces.len()).map(|n| {
+            let id = InterfaceId(n as u8);
+            (id, self.iface(id))
+        })
+    }
+
+    /// Add a socket to the socket set of interface `id`.
+    pub(crate) fn add_socket<T: AnySocket<'static>>(&mut self, id: InterfaceId, socket: T) -> SocketHandle {
+        SocketHandle {
+            iface: id,
+            socket: self.iface_mut(id).sockets.add(socket),
         }
+    }
 
-        #[cfg(feature = "proto-ipv6")]
-        if let Some(config) = &self.static_v6 {
-            debug!("IPv6: UP");
-            debug!("   IP address:      {:?}", config.address);
-            debug!("   Default gateway: {:?}", config.gateway);
+    pub(crate) fn remove_socket(&mut self, handle: SocketHandle) {
+        self.iface_mut(handle.iface).sockets.remove(handle.socket);
+    }
 
-            unwrap!(addrs.push(IpCidr::Ipv6(config.address)).ok());
-            gateway_v6 = config.gateway.into();
-            #[cfg(feature = "dns")]
-            for s in &config.dns_servers {
-                debug!("   DNS server:      {:?}", s);
-                unwrap!(dns_servers.push(s.clone().into()).ok());
+    pub(crate) fn socket<T: AnySocket<'static>>(&self, handle: SocketHandle) -> (&T, &smoltcp::iface::Interface) {
+        let iface = self.iface(handle.iface);
+        (iface.sockets.get::<T>(handle.socket), &iface.iface)
+    }
+
+    pub(crate) fn socket_mut<T: AnySocket<'static>>(
+        &mut self,
+        handle: SocketHandle,
+    ) -> (&mut T, &mut smoltcp::iface::Interface) {
+        let iface = self.iface_mut(handle.iface);
+        (iface.sockets.get_mut::<T>(handle.socket), &mut iface.iface)
+    }
+
+    /// Wake the runner of the interface the socket is on.
+    pub(crate) fn wake(&mut self, handle: SocketHandle) {
+        self.iface_mut(handle.iface).waker.wake();
+    }
+
+    /// Move a socket to the socket set of interface `to`.
+    ///
+    /// The socket stays where it is if `to` has no room for it.
+    pub(crate) fn move_socket(&mut self, handle: SocketHandle, to: InterfaceId) -> Result<SocketHandle, SocketSetFull> {
+        if handle.iface == to {
+            return Ok(handle);
+        }
+        if self.iface(to).free_slots() == 0 {
+            return Err(SocketSetFull);
+        }
+
+        let socket = self.iface_mut(handle.iface).sockets.remove(handle.socket);
+        let handle = SocketHandle {
+            iface: to,
+            socket: add_any_socket(&mut self.iface_mut(to).sockets, socket),
+        };
+        self.wake(handle);
+        Ok(handle)
+    }
+
+    /// Look up the interface packets sent to `addr` go through.
+    pub(crate) fn route(&self, addr: IpAddress) -> Option<InterfaceId> {
+        // (prefix length, metric, interface) of the best route so far.
+        let mut best: Option<(u8, u32, InterfaceId)> = None;
+        let mut consider = |prefix_len: u8, metric: u32, id: InterfaceId| {
+            if best.is_none_or(|(p, m, _)| prefix_len > p || (prefix_len == p && metric < m)) {
+                best = Some((prefix_len, metric, id));
+            }
+        };
+
+        for (id, iface) in self.ifaces().filter(|(_, iface)| iface.link_up) {
+            for cidr in iface.iface.ip_addrs() {
+                if cidr.contains_addr(&addr) {
+                    consider(cidr.prefix_len(), iface.metric, id);
+                }
+            }
+            if iface.has_default_gateway(&addr) {
+                consider(0, iface.metric, id);
+            }
+        }
+        for route in &self.routes {
+            if self.iface(route.interface).link_up && route.destination.contains_addr(&addr) {
+                consider(route.destination.prefix_len(), route.metric, route.interface);
             }
-        } else {
-            info!("IPv6: DOWN");
         }
 
-        // Apply addresses
-        self.iface.update_ip_addrs(|a| *a = addrs);
+        best.map(|(_, _, id)| id)
+    }
 
-        // Apply gateways
+    /// Get the interface that has address `addr`.
+    #[cfg(any(feature = "udp", feature = "tcp"))]
+    pub(crate) fn interface_with_addr(&self, addr: IpAddress) -> Option<InterfaceId> {
+        self.ifaces()
+            .find(|(_, iface)| iface.iface.has_ip_addr(addr))
+            .map(|(id, _)| id)
+    }
+
+    /// Get the address the stack sends from to reach `addr`.
+    #[cfg(feature = "udp")]
+    pub(crate) fn source_address(&self, addr: IpAddress) -> Option<IpAddress> {
+        let id = self.route(addr)?;
+        self.iface(id).iface.get_source_address(&addr)
+    }
+
+    #[allow(clippy::absurd_extreme_comparisons)]
+    pub fn get_local_port(&mut self) -> u16 {
+        let res = self.next_local_port;
+        self.next_local_port = if res >= LOCAL_PORT_MAX { LOCAL_PORT_MIN } else { res + 1 };
+        res
+    }
+
+    fn set_config(&mut self, id: InterfaceId, #[allow(unused)] config: Config) {
         #[cfg(feature = "proto-ipv4")]
-        if let Some(gateway) = gateway_v4 {
-            unwrap!(self.iface.routes_mut().add_default_ipv4_route(gateway));
-        } else {
-            self.iface.routes_mut().remove_default_ipv4_route();
-        }
+        self.iface_mut(id).set_config_v4(config.ipv4);
         #[cfg(feature = "proto-ipv6")]
-        if let Some(gateway) = gateway_v6 {
-            unwrap!(self.iface.routes_mut().add_default_ipv6_route(gateway));
-        } else {
-            self.iface.routes_mut().remove_default_ipv6_route();
-        }
+        self.iface_mut(id).set_config_v6(config.ipv6);
+        self.apply_config(id);
+    }
+
+    fn apply_config(&mut self, id: InterfaceId) {
+        // safety: the interfaces live at least as long as the stack.
+        let iface = unsafe { &mut *self.ifaces[id.index()] };
+        iface.apply_config(id, &self.routes);
 
-        // Apply DNS servers
         #[cfg(feature = "dns")]
-        if !dns_servers.is_empty() {
-            let count = if dns_servers.len() > DNS_MAX_SERVER_COUNT {
-                warn!("Number of DNS servers exceeds DNS_MAX_SERVER_COUNT, truncating list.");
-                DNS_MAX_SERVER_COUNT
-            } else {
-                dns_servers.len()
-            };
-            self.sockets
-                .get_mut::<smoltcp::socket::dns::Socket>(self.dns_socket)
-                .update_servers(&dns_servers[..count]);
-        }
+        self.update_dns();
 
         self.state_waker.wake();
     }
 
-    fn poll<D: Driver>(&mut self, cx: &mut Context<'_>, driver: &mut D) {
-        self.waker.register(cx.waker());
-
-        let (_hardware_addr, medium) = to_smoltcp_hardware_address(driver.hardware_address());
-
-        #[cfg(any(feature = "medium-ethernet", feature = "medium-ieee802154"))]
-        {
-            let do_set = match medium {
-                #[cfg(feature = "medium-ethernet")]
-                Medium::Ethernet => true,
-                #[cfg(feature = "medium-ieee802154")]
-                Medium::Ieee802154 => true,
-                #[allow(unreachable_patterns)]
-                _ => false,
-            };
-            if do_set {
-                self.iface.set_hardware_addr(_hardware_addr);
+    /// Use the DNS servers of the preferred interface that has some.
+    #[cfg(feature = "dns")]
+    fn update_dns(&mut self) {
+        let mut best = None;
+        let mut dns_servers = Vec::<IpAddress, 6>::new();
+        for (id, iface) in self.ifaces() {
+            let servers = iface.dns_servers();
+            let key = (!iface.link_up, iface.metric);
+            if !servers.is_empty() && best.is_none_or(|(k, _)| key < k) {
+                best = Some((key, id));
+                dns_servers = servers;
             }
         }
-
-        let timestamp = instant_to_smoltcp(Instant::now());
-        let mut smoldev = DriverAdapter {
-            cx: Some(cx),
-            inner: driver,
-            medium,
+        let Some((_, id)) = best else {
+            return;
         };
-        self.iface.poll(timestamp, &mut smoldev, &mut self.sockets);
-
-        // Update link up
-        let old_link_up = self.link_up;
-        self.link_up = driver.link_state(cx) == LinkState::Up;
 
-        // Print when changed
-        if old_link_up != self.link_up {
-            info!("link_up = {:?}", self.link_up);
-            self.state_waker.wake();
+        let count = if dns_servers.len() > DNS_MAX_SERVER_COUNT {
+            warn!("Number of DNS servers exceeds DNS_MAX_SERVER_COUNT, truncating list.");
+            DNS_MAX_SERVER_COUNT
+        } else {
+            dns_servers.len()
+        };
+        match self.move_socket(self.dns_socket, id) {
+            Ok(handle) => self.dns_socket = handle,
+            Err(SocketSetFull) => warn!("No room for the DNS socket on {:?}, increase its number of sockets", id),
         }
+        let handle = self.dns_socket;
+        let (socket, _) = self.socket_mut::<dns::Socket>(handle);
+        socket.update_servers(&dns_servers[..count]);
+        self.dns_waker.wake();
+    }
 
-        #[cfg(feature = "dhcpv4")]
-        if let Some(dhcp_handle) = self.dhcp_socket {
-            let socket = self.sockets.get_mut::<dhcpv4::Socket>(dhcp_handle);
-
-            let configure = if self.link_up {
-                if old_link_up != self.link_up {
-                    socket.reset();
-                }
-                match socket.poll() {
-                    None => false,
-                    Some(dhcpv4::Event::Deconfigured) => {
-                        self.static_v4 = None;
-                        true
-                    }
-                    Some(dhcpv4::Event::Configured(config)) => {
-                        self.static_v4 = Some(StaticConfigV4 {
-                            address: config.address,
-                            gateway: config.router,
-                            dns_servers: config.dns_servers,
-                        });
-                        true
+    /// Forward the packets received on interface `from`.
+    #[cfg(feature = "ipv4-forwarding")]
+    fn forward(&mut self, from: InterfaceId, cx: &mut Context<'_>) {
+        while let Some(dst) = self.iface_mut(from).forward.as_mut().and_then(|f| f.peek()) {
+            let to = self.route(IpAddress::Ipv4(dst));
+            // safety: the interfaces live at least as long as the stack, and `src` and `dst`
+            // are different interfaces.
+            let src = unsafe { &mut *self.ifaces[from.index()] };
+            let src_forward = unwrap!(src.forward.as_mut());
+            match to.filter(|to| *to != from) {
+                Some(to) => {
+                    let dst = unsafe { &mut *self.ifaces[to.index()] };
+                    match &dst.forward {
+                        Some(dst_forward) => {
+                            let sent = src_forward.forward_next(dst_forward, &mut dst.sockets, cx);
+                            dst.waker.wake();
+                            if !sent {
+                                break;
+                            }
+                        }
+                        None => src_forward.drop_next(),
                     }
                 }
-            } else if old_link_up {
-                socket.reset();
-                self.static_v4 = None;
-                true
-            } else {
-                false
-            };
-            if configure {
-                self.apply_static_config()
+                None => src_forward.drop_next(),
             }
         }
+    }
 
-        if let Some(poll_at) = self.iface.poll_at(timestamp, &mut self.sockets) {
-            let t = pin!(Timer::at(instant_from_smoltcp(poll_at)));
-            if t.poll(cx).is_ready() {
-                cx.waker().wake_by_ref();
-            }
+    fn poll<D: Driver>(
+        &mut self,
+        id: InterfaceId,
+        cx: &mut Context<'_>,
+        driver: &mut D,
+        #[cfg(feature = "pcap")] sink: Option<&dyn pcap::PacketSink>,
+    ) {
+        // safety: the interfaces live at least as long as the stack.
+        let iface = unsafe { &mut *self.ifaces[id.index()] };
+        #[cfg(feature = "pcap")]
+        let changed = iface.poll(id, &self.routes, cx, driver, sink);
+        #[cfg(not(feature = "pcap"))]
+        let changed = iface.poll(id, &self.routes, cx, driver);
+        if changed {
+            #[cfg(feature = "dns")]
+            self.update_dns();
+            self.state_waker.wake();
         }
+
+        #[cfg(feature = "ipv4-forwarding")]
+        self.forward(id, cx);
     }
 }
 
 impl<'d, D: Driver> Runner<'d, D> {
-    /// Run the network stack.
+    /// Capture the packets received and sent by the interface with `sink`.
+    ///
+    /// See the [`pcap`] module.
+    #[cfg(feature = "pcap")]
+    pub fn set_packet_sink(&mut self, sink: Option<&'d dyn pcap::PacketSink>) {
+        self.sink = sink;
+    }
+
+    /// Run the network interface.
     ///
     /// You must call this in a background task, to process network events.
     pub async fn run(&mut self) -> ! {
         poll_fn(|cx| {
-            self.stack.with_mut(|i| i.poll(cx, &mut self.driver));
+            #[cfg(feature = "pcap")]
+            self.stack
+                .with_mut(|i| i.poll(self.id, cx, &mut self.driver, self.sink));
+            #[cfg(not(feature = "pcap"))]
+            self.stack.with_mut(|i| i.poll(self.id, cx, &mut self.driver));
             Poll::<()>::Pending
         })
         .await;
         unreachable!()
     }
 }
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+
+    use embassy_net_driver::LinkState;
+
+    use super::*;
+    use crate::test_util::{self, static_config_with, wait_for};
+
+    const fn net(n: u8) -> Ipv4Address {
+        Ipv4Address::new(10, 0, n, 0)
+    }
+
+    const fn host(network: u8, n: u8) -> Ipv4Address {
+        Ipv4Address::new(10, 0, network, n)
+    }
+
+    fn route(destination: Ipv4Cidr, interface: InterfaceId, metric: u32) -> Route {
+        Route {
+            destination: destination.into(),
+            gateway: host(destination.address().octets()[2], 254).into(),
+            interface,
+            metric,
+        }
+    }
+
+    #[test]
+    fn routes_by_longest_prefix_then_metric() {
+        let (_, switch_a, [device_a, _]) = test_util::link();
+        let (control, switch_b, [device_b, _]) = test_util::link();
+        let (stack, runner_a) = test_util::stack(device_a, static_config_with(host(1, 1), 24, None));
+        let resources = Box::leak(Box::new(InterfaceResources::<2>::new()));
+        let (second, runner_b) = stack.add_interface(device_b, static_config_with(host(2, 1), 24, None), resources);
+        let second = second.id();
+
+        test_util::run_with([switch_a, switch_b], [runner_a, runner_b], async {
+            wait_for(|| stack.interfaces().all(|i| i.is_link_up())).await;
+            let lookup = |addr: Ipv4Address| stack.lookup_route(addr.into());
+
+            // Networks of the interfaces.
+            assert_eq!(lookup(host(1, 5)), Some(InterfaceId::PRIMARY));
+            assert_eq!(lookup(host(2, 5)), Some(second));
+            assert_eq!(lookup(host(3, 5)), None);
+
+            // A shorter prefix doesn't override the interface networks.
+            unwrap!(stack.add_route(route(Ipv4Cidr::new(net(0), 16), second, 0)));
+            assert_eq!(lookup(host(1, 5)), Some(InterfaceId::PRIMARY));
+            assert_eq!(lookup(host(3, 5)), Some(second));
+
+            // The longest prefix wins, then the lowest metric.
+            unwrap!(stack.add_route(route(Ipv4Cidr::new(net(3), 24), InterfaceId::PRIMARY, 10)));
+            assert_eq!(lookup(host(3, 5)), Some(InterfaceId::PRIMARY));
+            unwrap!(stack.add_route(route(Ipv4Cidr::new(net(3), 24), second, 5)));
+            assert_eq!(lookup(host(3, 5)), Some(second));
+
+            // Interfaces whose link is down are skipped.
+            control.set_link_state(0, LinkState::Down);
+            wait_for(|| !stack.interface(second).is_link_up()).await;
+            assert_eq!(lookup(host(3, 5)), Some(InterfaceId::PRIMARY));
+            assert_eq!(lookup(host(2, 5)), None);
+
+            assert!(stack.remove_route(Ipv4Cidr::new(net(3), 24).into(), InterfaceId::PRIMARY));
+            assert_eq!(lookup(host(3, 5)), None);
+        });
+    }
+
+    #[cfg(feature = "udp")]
+    mod udp {
+        use embassy_time::{with_timeout, Duration};
+
+        use super::*;
+        use crate::udp::{BindError, PacketMetadata, UdpSocket};
+
+        fn socket(stack: Stack<'static>) -> UdpSocket<'static> {
+            UdpSocket::new(
+                stack,
+                Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+                Box::leak(Box::new([0; 512])),
+                Box::leak(Box::new([PacketMetadata::EMPTY; 4])),
+                Box::leak(Box::new([0; 512])),
+            )
+        }
+
+        #[test]
+        fn sockets_follow_routes_only_when_enabled() {
+            // The stack has an interface on each network, and the peer is on the second one.
+            let (_, switch_a, [device_a, _]) = test_util::link();
+            let (_, switch_b, [device_b, device_peer]) = test_util::link();
+            let (stack, runner_a) = test_util::stack(device_a, static_config_with(host(1, 1), 24, None));
+            let resources = Box::leak(Box::new(InterfaceResources::<2>::new()));
+            let (second, runner_b) = stack.add_interface(device_b, static_config_with(host(2, 1), 24, None), resources);
+            let (peer, runner_peer) = test_util::stack(device_peer, static_config_with(host(2, 2), 24, None));
+
+            let runners = [runner_a, runner_b, runner_peer];
+            test_util::run_with([switch_a, switch_b], runners, async {
+                wait_for(|| second.is_link_up() && peer.is_link_up()).await;
+                let mut receiver = socket(peer);
+                unwrap!(receiver.bind(7));
+                let mut stays = socket(stack);
+                unwrap!(stays.bind(1234));
+                let mut follows = socket(stack);
+                unwrap!(follows.bind(1235));
+                follows.set_follow_route(true);
+
+                // The first socket stays on the primary interface, which has no route to the peer.
+                unwrap!(stays.send_to(b"lost", (host(2, 2), 7)).await);
+                let received = with_timeout(Duration::from_millis(100), receiver.recv_from(&mut [0; 8])).await;
+                assert!(received.is_err());
+
+                unwrap!(follows.send_to(b"routed", (host(2, 2), 7)).await);
+                let mut buf = [0; 8];
+                let (n, meta) = unwrap!(receiver.recv_from(&mut buf).await);
+                assert_eq!(&buf[..n], b"routed");
+                assert_eq!(meta.endpoint, (host(2, 1), 1235).into());
+            });
+        }
+
+        #[test]
+        fn bind_to_full_interface_fails() {
+            let (_, switch_a, [device_a, _]) = test_util::link();
+            let (_, switch_b, [device_b, _]) = test_util::link();
+            let (stack, runner_a) = test_util::stack(device_a, static_config_with(host(1, 1), 24, None));
+            let resources = Box::leak(Box::new(InterfaceResources::<1>::new()));
+            let (second, runner_b) = stack.add_interface(device_b, static_config_with(host(2, 1), 24, None), resources);
+
+            test_util::run_with([switch_a, switch_b], [runner_a, runner_b], async {
+                let mut first = socket(stack);
+                unwrap!(first.bind_to_interface(second.id()));
+                let mut other = socket(stack);
+                assert_eq!(other.bind_to_interface(second.id()), Err(SocketSetFull));
+                // The socket stays usable on the primary interface.
+                unwrap!(other.bind((host(1, 1), 1234)));
+                assert_eq!(other.bind((host(2, 1), 1234)), Err(BindError::SocketSetFull));
+            });
+        }
+
+        #[cfg(feature = "ipv4-forwarding")]
+        #[test]
+        fn forwards_between_interfaces() {
+            // host a <-> router <-> host b, each host using the router as gateway.
+            let (_, switch_a, [device_a, device_router_a]) = test_util::link();
+            let (_, switch_b, [device_b, device_router_b]) = test_util::link();
+            let (a, runner_a) = test_util::stack(device_a, static_config_with(host(1, 2), 24, Some(host(1, 1))));
+            let (b, runner_b) = test_util::stack(device_b, static_config_with(host(2, 2), 24, Some(host(2, 1))));
+            let (router, runner_router_a) = test_util::stack(device_router_a, static_config_with(host(1, 1), 24, None));
+            let resources = Box::leak(Box::new(InterfaceResources::<4>::new()));
+            let (_, runner_router_b) =
+                router.add_interface(device_router_b, static_config_with(host(2, 1), 24, None), resources);
+            router.set_ipv4_forwarding(true);
+
+            let runners = [runner_a, runner_b, runner_router_a, runner_router_b];
+            test_util::run_with([switch_a, switch_b], runners, async {
+                wait_for(|| router.interfaces().all(|i| i.is_link_up())).await;
+                let mut socket_a = socket(a);
+                unwrap!(socket_a.bind(1234));
+                let mut socket_b = socket(b);
+                unwrap!(socket_b.bind(7));
+
+                let mut buf = [0; 8];
+                unwrap!(socket_a.send_to(b"ping", (host(2, 2), 7)).await);
+                let (n, meta) = unwrap!(socket_b.recv_from(&mut buf).await);
+                assert_eq!(&buf[..n], b"ping");
+                assert_eq!(meta.endpoint.addr, host(1, 2).into());
+
+                unwrap!(socket_b.send_to(b"pong", meta.endpoint).await);
+                let (n, meta) = unwrap!(socket_a.recv_from(&mut buf).await);
+                assert_eq!(&buf[..n], b"pong");
+                assert_eq!(meta.endpoint.addr, host(2, 2).into());
+            });
+        }
+    }
+}
@@ -0,0 +1,1110 @@
+//! mDNS and DNS-SD responder.
+//!
+//! [`MdnsResponder`] makes the device reachable as `<hostname>.local` with multicast DNS (RFC 6762),
+//! and advertises its services with DNS-based service discovery (RFC 6763), so that browsing for
+//! `_http._tcp` finds them for example.
+//!
+//! Before answering, the responder probes the network to check its names are unique. When another
+//! host uses one of them, it's renamed, `device` becoming `device-2` and the service instance
+//! `My device` becoming `My device (2)`, and probed again. Services can be added and removed while
+//! the responder runs.
+//!
+//! The responder serves the primary interface, over IPv4 and IPv6 depending on the enabled features.
+//! It doesn't break ties between hosts probing the same name at the same time, and doesn't delay
+//! or suppress responses based on the answers already known to the querier.
+
+use core::cell::RefCell;
+use core::fmt::Write as _;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::WakerRegistration;
+use embassy_time::{with_deadline, Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::udp::{PacketMetadata, RecvError, UdpMetadata, UdpSocket};
+use crate::{IpAddress, IpEndpoint, Stack};
+
+/// mDNS port.
+const MDNS_PORT: u16 = 5353;
+/// mDNS IPv4 multicast group.
+#[cfg(feature = "proto-ipv4")]
+const MDNS_GROUP_V4: crate::Ipv4Address = crate::Ipv4Address::new(224, 0, 0, 251);
+/// mDNS IPv6 multicast group.
+#[cfg(feature = "proto-ipv6")]
+const MDNS_GROUP_V6: crate::Ipv6Address = crate::Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb);
+/// Maximum size of mDNS packets, to fit an Ethernet frame.
+const PACKET_LEN: usize = 1472;
+/// Maximum size of an encoded domain name.
+const MAX_NAME_LEN: usize = 255;
+/// Maximum size of a label of a domain name.
+const MAX_LABEL_LEN: usize = 63;
+
+/// TTL of the address records, in seconds.
+const HOST_TTL: u32 = 120;
+/// TTL of the other records, in seconds.
+const SERVICE_TTL: u32 = 4500;
+/// Maximum TTL of the records in responses to legacy unicast queries, in seconds.
+const LEGACY_TTL: u32 = 10;
+/// Interval between probes, and maximum delay before the first one.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// Number of probes sent before claiming names.
+const PROBE_COUNT: usize = 3;
+/// Interval between announcements.
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of announcements after probing.
+const ANNOUNCE_COUNT: usize = 2;
+/// Number of conflicts after which probing is rate limited.
+const MAX_CONFLICTS: u32 = 15;
+/// Delay between probes after too many conflicts.
+const CONFLICT_DELAY: Duration = Duration::from_secs(5);
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_AAAA: u16 = 28;
+const TYPE_SRV: u16 = 33;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+/// Class bit of unique records, telling caches to flush other records of the name.
+const CACHE_FLUSH: u16 = 0x8000;
+/// Class bit of questions, asking for a unicast response.
+const UNICAST_RESPONSE: u16 = 0x8000;
+/// Flags of a response: QR and AA.
+const RESPONSE_FLAGS: u16 = 0x8400;
+/// Name of the service type enumeration.
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+type Name = Vec<u8, MAX_NAME_LEN>;
+
+/// Service advertised with DNS-SD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Service<'a> {
+    /// Name of the instance, such as `My device`.
+    pub instance: &'a str,
+    /// Type of the service and its transport protocol, such as `_http._tcp`.
+    pub service_type: &'a str,
+    /// Port of the service.
+    pub port: u16,
+    /// Strings of the TXT record, such as `path=/`.
+    pub txt: &'a [&'a str],
+}
+
+/// State for [`MdnsResponder`].
+pub struct MdnsResponderState {
+    rx_meta: [PacketMetadata; 2],
+    rx_buffer: [u8; 2 * PACKET_LEN],
+    tx_meta: [PacketMetadata; 1],
+    tx_buffer: [u8; PACKET_LEN],
+}
+
+impl MdnsResponderState {
+    /// Create a new `MdnsResponderState`.
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx_buffer: [0; 2 * PACKET_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 1],
+            tx_buffer: [0; PACKET_LEN],
+        }
+    }
+}
+
+impl Default for MdnsResponderState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Inner<'d, const S: usize> {
+    /// Number appended to the host name after conflicts, 0 if none.
+    host_suffix: u16,
+    /// Services, with the number appended to their instance name after conflicts.
+    services: Vec<(Service<'d>, u16), S>,
+    /// Services removed since the last announcement.
+    removed: Vec<(Service<'d>, u16), S>,
+    changed: bool,
+    waker: WakerRegistration,
+}
+
+/// Records to write in a section of a message.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+struct Records {
+    address: bool,
+    /// Service types in the service type enumeration, by index of a service of the type.
+    types: u32,
+    ptr: u32,
+    srv: u32,
+    txt: u32,
+}
+
+impl Records {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn without(self, other: &Records) -> Records {
+        Records {
+            address: self.address && !other.address,
+            types: self.types & !other.types,
+            ptr: self.ptr & !other.ptr,
+            srv: self.srv & !other.srv,
+            txt: self.txt & !other.txt,
+        }
+    }
+}
+
+/// mDNS and DNS-SD responder, advertising up to `S` services.
+pub struct MdnsResponder<'d, const S: usize = 4> {
+    stack: Stack<'d>,
+    socket: UdpSocket<'d>,
+    hostname: &'d str,
+    inner: RefCell<Inner<'d, S>>,
+}
+
+impl<'d, const S: usize> MdnsResponder<'d, S> {
+    /// Create a new responder for `<hostname>.local`.
+    ///
+    /// The responder joins the mDNS multicast groups of the primary interface.
+    pub fn new(stack: Stack<'d>, hostname: &'d str, state: &'d mut MdnsResponderState) -> Self {
+        assert!(S <= 32, "at most 32 services are supported");
+
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut state.rx_meta,
+            &mut state.rx_buffer,
+            &mut state.tx_meta,
+            &mut state.tx_buffer,
+        );
+        unwrap!(socket.bind(MDNS_PORT));
+        // RFC 6762 asks for a hop limit of 255 on sent packets. The hop limit of received packets
+        // isn't checked, smoltcp doesn't report it.
+        socket.set_hop_limit(Some(255));
+
+        #[cfg(feature = "proto-ipv4")]
+        if let Err(e) = stack.join_multicast_group(MDNS_GROUP_V4) {
+            warn!("mdns: failed to join the IPv4 multicast group: {:?}", e);
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Err(e) = stack.join_multicast_group(MDNS_GROUP_V6) {
+            warn!("mdns: failed to join the IPv6 multicast group: {:?}", e);
+        }
+
+        Self {
+            stack,
+            socket,
+            hostname,
+            inner: RefCell::new(Inner {
+                host_suffix: 0,
+                services: Vec::new(),
+                removed: Vec::new(),
+                changed: false,
+                waker: WakerRegistration::new(),
+            }),
+        }
+    }
+
+    /// Get the host name, without the `.local` domain.
+    ///
+    /// It differs from the name given to [`new`](Self::new) after a conflict with another host.
+    pub fn hostname(&self) -> String<MAX_LABEL_LEN> {
+        label(self.hostname, self.inner.borrow().host_suffix, false)
+    }
+
+    /// Add a service, which is probed and announced.
+    ///
+    /// Returns the service back if there are already `S` services.
+    pub fn add_service(&self, service: Service<'d>) -> Result<(), Service<'d>> {
+        let mut inner = self.inner.borrow_mut();
+        inner.services.push((service, 0)).map_err(|(service, _)| service)?;
+        inner.changed = true;
+        inner.waker.wake();
+        Ok(())
+    }
+
+    /// Remove a service, telling other hosts it's gone.
+    ///
+    /// Returns whether the service was found.
+    pub fn remove_service(&self, instance: &str, service_type: &str) -> bool {
+        let mut inner = self.inner.borrow_mut();
+        let Some(i) = inner
+            .services
+            .iter()
+            .position(|(s, _)| s.instance == instance && s.service_type == service_type)
+        else {
+            return false;
+        };
+        let service = inner.services.remove(i);
+        // If too many services were removed at once, some of them expire from caches instead.
+        inner.removed.push(service).ok();
+        inner.changed = true;
+        inner.waker.wake();
+        true
+    }
+
+    /// Run the responder.
+    pub async fn run(&self) -> ! {
+        let mut rx = [0; PACKET_LEN];
+        let mut tx = [0; PACKET_LEN];
+        let mut conflicts = 0;
+        loop {
+            self.stack.wait_config_up().await;
+            if conflicts >= MAX_CONFLICTS {
+                Timer::after(CONFLICT_DELAY).await;
+            }
+            if !self.probe(&mut rx, &mut tx).await {
+                conflicts += 1;
+                continue;
+            }
+            conflicts = 0;
+            info!("mdns: responding as {}.local", self.hostname().as_str());
+            self.serve(&mut rx, &mut tx).await;
+        }
+    }
+
+    /// Probe the names, returns `false` if one of them is in use and was renamed.
+    async fn probe(&self, rx: &mut [u8], tx: &mut [u8]) -> bool {
+        // Random delay, so that hosts starting together don't probe at the same time.
+        let delay = Instant::now().as_ticks() % PROBE_INTERVAL.as_ticks();
+        Timer::after_ticks(delay).await;
+        self.inner.borrow_mut().changed = false;
+
+        for _ in 0..PROBE_COUNT {
+            if let Some(len) = self.write_probe(tx) {
+                self.send_multicast(&tx[..len]).await;
+            }
+            let deadline = Instant::now() + PROBE_INTERVAL;
+            while let Ok(received) = with_deadline(deadline, self.socket.recv_from(rx)).await {
+                if let Ok((n, _)) = received {
+                    if self.check_conflict(&rx[..n]) {
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Announce the names and answer queries, until a conflict or a change of the services.
+    async fn serve(&self, rx: &mut [u8], tx: &mut [u8]) {
+        if let Some(len) = self.write_goodbye(tx) {
+            self.send_multicast(&tx[..len]).await;
+        }
+
+        let mut announcements = 0;
+        let mut next_announcement = Instant::now();
+        loop {
+            if announcements < ANNOUNCE_COUNT && Instant::now() >= next_announcement {
+                if let Some(len) = self.write_announcement(tx) {
+                    self.send_multicast(&tx[..len]).await;
+                }
+                announcements += 1;
+                next_announcement += ANNOUNCE_INTERVAL;
+            }
+
+            let received = if announcements < ANNOUNCE_COUNT {
+                match with_deadline(next_announcement, self.recv_or_change(rx)).await {
+                    Ok(received) => received,
+                    Err(_) => continue,
+                }
+            } else {
+                self.recv_or_change(rx).await
+            };
+
+            match received {
+                // The services changed, the new ones must be probed.
+                None => {
+                    if let Some(len) = self.write_goodbye(tx) {
+                        self.send_multicast(&tx[..len]).await;
+                    }
+                    return;
+                }
+                Some(Ok((n, meta))) => {
+                    if self.check_conflict(&rx[..n]) {
+                        return;
+                    }
+                    // Queries from another port come from simple resolvers expecting a unicast response.
+                    let legacy = meta.endpoint.port != MDNS_PORT;
+                    if let Some(len) = self.write_response(&rx[..n], legacy, tx) {
+                        let destination = if legacy {
+                            Some(meta.endpoint)
+                        } else {
+                            group(&meta.endpoint.addr)
+                        };
+                        if let Some(destination) = destination {
+                            self.send(&tx[..len], destination).await;
+                        }
+                    }
+                }
+                Some(Err(RecvError::Truncated)) => {}
+            }
+        }
+    }
+
+    async fn recv_or_change(&self, buf: &mut [u8]) -> Option<Result<(usize, UdpMetadata), RecvError>> {
+        poll_fn(|cx| {
+            {
+                let mut inner = self.inner.borrow_mut();
+                if inner.changed {
+                    inner.changed = false;
+                    return Poll::Ready(None);
+                }
+                inner.waker.register(cx.waker());
+            }
+            self.socket.poll_recv_from(buf, cx).map(Some)
+        })
+        .await
+    }
+
+    async fn send_multicast(&self, packet: &[u8]) {
+        for addr in self.addresses() {
+            if let Some(destination) = group(&addr) {
+                self.send(packet, destination).await;
+            }
+        }
+    }
+
+    async fn send(&self, packet: &[u8], destination: IpEndpoint) {
+        if let Err(e) = self.socket.send_to(packet, destination).await {
+            warn!("mdns: failed to send to {}: {:?}", destination, e);
+        }
+    }
+
+    /// Get the addresses of the host.
+    fn addresses(&self) -> Vec<IpAddress, 2> {
+        let mut addresses = Vec::new();
+        #[cfg(feature = "proto-ipv4")]
+        if let Some(config) = self.stack.config_v4() {
+            unwrap!(addresses.push(IpAddress::Ipv4(config.address.address())));
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Some(config) = self.stack.config_v6() {
+            unwrap!(addresses.push(IpAddress::Ipv6(config.address.address())));
+        }
+        addresses
+    }
+
+    /// Check a message for records of other hosts using our names, and rename them.
+    fn check_conflict(&self, packet: &[u8]) -> bool {
+        let Some(header) = Header::parse(packet) else {
+            return false;
+        };
+        if header.flags & 0x8000 == 0 {
+            return false;
+        }
+
+        let mut inner = self.inner.borrow_mut();
+        let Some(host) = host_name(self.hostname, inner.host_suffix) else {
+            return false;
+        };
+        let addresses = self.addresses();
+
+        let mut pos = header.questions_end(packet);
+        for _ in 0..header.records() {
+            let Some((record, next)) = pos.and_then(|pos| Record::parse(packet, pos)) else {
+                break;
+            };
+            pos = Some(next);
+
+            // Another host has the name, with other addresses.
+            if record.name.eq_ignore_ascii_case(&host)
+                && matches!(record.rtype, TYPE_A | TYPE_AAAA)
+                && !addresses.iter().any(|a| address_data(a).1 == record.data)
+            {
+                inner.host_suffix = next_suffix(inner.host_suffix);
+                info!(
+                    "mdns: host name in use, renamed to {}",
+                    label(self.hostname, inner.host_suffix, false).as_str()
+                );
+                return true;
+            }
+
+            if record.rtype != TYPE_SRV {
+                continue;
+            }
+            for (service, suffix) in inner.services.iter_mut() {
+                if !instance_name(service, *suffix).is_some_and(|n| record.name.eq_ignore_ascii_case(&n)) {
+                    continue;
+                }
+                // Another host has the instance, on another host or port.
+                let target = read_name(packet, record.data_pos + 6).map(|(name, _)| name);
+                let ours = record.data.len() >= 6
+                    && record.data[4..6] == service.port.to_be_bytes()
+                    && target.is_some_and(|t| t.eq_ignore_ascii_case(&host));
+                if !ours {
+                    *suffix = next_suffix(*suffix);
+                    info!(
+                        "mdns: service instance in use, renamed to {}",
+                        label(service.instance, *suffix, true).as_str()
+                    );
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Write a probe for our names.
+    fn write_probe(&self, tx: &mut [u8]) -> Option<usize> {
+        let inner = self.inner.borrow();
+        let host = host_name(self.hostname, inner.host_suffix)?;
+        let mut msg = Message::new(tx, 0, 0);
+
+        msg.question(&host, TYPE_ANY, CLASS_IN | UNICAST_RESPONSE).ok()?;
+        for (service, suffix) in &inner.services {
+            if let Some(name) = instance_name(service, *suffix) {
+                msg.question(&name, TYPE_ANY, CLASS_IN | UNICAST_RESPONSE).ok()?;
+            }
+        }
+
+        // The records we would use, to break ties with other hosts probing the same names.
+        let records = Records {
+            address: true,
+            srv: u32::MAX,
+            txt: u32::MAX,
+            ..Default::default()
+        };
+        self.write_records(&mut msg, &inner, Section::Authority, &records, u32::MAX, false);
+        Some(msg.finish())
+    }
+
+    /// Write an announcement of all our records.
+    fn write_announcement(&self, tx: &mut [u8]) -> Option<usize> {
+        let inner = self.inner.borrow();
+        let mut msg = Message::new(tx, 0, RESPONSE_FLAGS);
+        let records = Records {
+            address: true,
+            ptr: u32::MAX,
+            srv: u32::MAX,
+            txt: u32::MAX,
+            ..Default::default()
+        };
+        self.write_records(&mut msg, &inner, Section::Answer, &records, u32::MAX, true);
+        Some(msg.finish())
+    }
+
+    /// Write the records of removed services, with a TTL of 0 telling caches to delete them.
+    fn write_goodbye(&self, tx: &mut [u8]) -> Option<usize> {
+        let mut inner = self.inner.borrow_mut();
+        if inner.removed.is_empty() {
+            return None;
+        }
+        let host = host_name(self.hostname, inner.host_suffix)?;
+        let mut msg = Message::new(tx, 0, RESPONSE_FLAGS);
+        for (service, suffix) in &inner.removed {
+            write_service_records(
+                &mut msg,
+                Section::Answer,
+                &host,
+                service,
+                *suffix,
+                (true, true, true),
+                0,
+                true,
+            );
+        }
+        inner.removed.clear();
+        Some(msg.finish())
+    }
+
+    /// Write the response to a query, if we have answers.
+    fn write_response(&self, packet: &[u8], legacy: bool, tx: &mut [u8]) -> Option<usize> {
+        let header = Header::parse(packet)?;
+        // Only answer standard queries.
+        if header.flags & 0xf800 != 0 {
+            return None;
+        }
+
+        let inner = self.inner.borrow();
+        let host = host_name(self.hostname, inner.host_suffix)?;
+        let services_name = encode_name(None, &[SERVICES])?;
+        let mut answers = Records::default();
+        let mut additionals = Records::default();
+
+        let mut pos = 12;
+        for _ in 0..header.questions {
+            let (name, next) = read_name(packet, pos)?;
+            let qtype = u16::from_be_bytes(packet.get(next..next + 2)?.try_into().ok()?);
+            pos = next + 4;
+            let is = |t: u16| qtype == t || qtype == TYPE_ANY;
+
+            if name.eq_ignore_ascii_case(&host) && (is(TYPE_A) || is(TYPE_AAAA)) {
+                answers.address = true;
+            }
+            for (i, (service, suffix)) in inner.services.iter().enumerate() {
+                let bit = 1 << i;
+                if name.eq_ignore_ascii_case(&services_name) && is(TYPE_PTR) {
+                    // One record per service type.
+                    if !inner.services[..i]
+                        .iter()
+                        .any(|(s, _)| s.service_type == service.service_type)
+                    {
+                        answers.types |= bit;
+                    }
+                }
+                if type_name(service).is_some_and(|n| name.eq_ignore_ascii_case(&n)) && is(TYPE_PTR) {
+                    answers.ptr |= bit;
+                    additionals.srv |= bit;
+                    additionals.txt |= bit;
+                    additionals.address = true;
+                }
+                if instance_name(service, *suffix).is_some_and(|n| name.eq_ignore_ascii_case(&n)) {
+                    if is(TYPE_SRV) {
+                        answers.srv |= bit;
+                        additionals.address = true;
+                    }
+                    if is(TYPE_TXT) {
+                        answers.txt |= bit;
+                    }
+                }
+            }
+        }
+        if answers.is_empty() {
+            return None;
+        }
+
+        let (id, ttl) = if legacy { (header.id, LEGACY_TTL) } else { (0, u32::MAX) };
+        let mut msg = Message::new(tx, id, RESPONSE_FLAGS);
+        if legacy {
+            // Pointers in the questions stay valid, as they are at the same place in the response.
+            msg.questions(&packet[12..pos], header.questions).ok()?;
+        }
+        self.write_records(&mut msg, &inner, Section::Answer, &answers, ttl, !legacy);
+        let additionals = additionals.without(&answers);
+        self.write_records(&mut msg, &inner, Section::Additional, &additionals, ttl, !legacy);
+        Some(msg.finish())
+    }
+
+    /// Write records, with a TTL of at most `max_ttl`, until the message is full.
+    fn write_records(
+        &self,
+        msg: &mut Message<'_>,
+        inner: &Inner<'d, S>,
+        section: Section,
+        records: &Records,
+        max_ttl: u32,
+        cache_flush: bool,
+    ) {
+        let Some(host) = host_name(self.hostname, inner.host_suffix) else {
+            return;
+        };
+        let flush = if cache_flush { CACHE_FLUSH } else { 0 };
+
+        if records.address {
+            for addr in self.addresses() {
+                let (rtype, data) = address_data(&addr);
+                let ok = msg.record(section, &host, rtype, CLASS_IN | flush, HOST_TTL.min(max_ttl), |m| {
+                    m.put(&data)
+                });
+                if ok.is_err() {
+                    return;
+                }
+            }
+        }
+
+        for (i, (service, suffix)) in inner.services.iter().enumerate() {
+            let bit = 1 << i;
+            if records.types & bit != 0 {
+                let (Some(services), Some(target)) = (encode_name(None, &[SERVICES]), type_name(service)) else {
+                    continue;
+                };
+                let ttl = SERVICE_TTL.min(max_ttl);
+                if msg
+                    .record(section, &services, TYPE_PTR, CLASS_IN, ttl, |m| m.put(&target))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            let selected = (records.ptr & bit != 0, records.srv & bit != 0, records.txt & bit != 0);
+            if !write_service_records(msg, section, &host, service, *suffix, selected, max_ttl, cache_flush) {
+                return;
+            }
+        }
+    }
+}
+
+/// Write the selected PTR, SRV and TXT records of a service, returns `false` if the message is full.
+#[allow(clippy::too_many_arguments)]
+fn write_service_records(
+    msg: &mut Message<'_>,
+    section: Section,
+    host: &Name,
+    service: &Service<'_>,
+    suffix: u16,
+    (ptr, srv, txt): (bool, bool, bool),
+    max_ttl: u32,
+    cache_flush: bool,
+) -> bool {
+    let (Some(type_name), Some(instance)) = (type_name(service), instance_name(service, suffix)) else {
+        return true;
+    };
+    let flush = if cache_flush { CACHE_FLUSH } else { 0 };
+    let ttl = SERVICE_TTL.min(max_ttl);
+
+    if ptr
+        && msg
+            .record(section, &type_name, TYPE_PTR, CLASS_IN, ttl, |m| m.put(&instance))
+            .is_err()
+    {
+        return false;
+    }
+    if srv {
+        let written = msg.record(section, &instance, TYPE_SRV, CLASS_IN | flush, ttl, |m| {
+            // Priority and weight
+            m.put(&[0, 0, 0, 0])?;
+            m.put(&service.port.to_be_bytes())?;
+            m.put(host)
+        });
+        if written.is_err() {
+            return false;
+        }
+    }
+    if txt {
+        let written = msg.record(section, &instance, TYPE_TXT, CLASS_IN | flush, ttl, |m| {
+            if service.txt.is_empty() {
+                return m.put(&[0]);
+            }
+            for s in service.txt {
+                let len: u8 = s.len().try_into().map_err(|_| ())?;
+                m.put(&[len])?;
+                m.put(s.as_bytes())?;
+            }
+            Ok(())
+        });
+        if written.is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Get the multicast endpoint of the family of `addr`.
+fn group(addr: &IpAddress) -> Option<IpEndpoint> {
+    match addr {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(_) => Some(IpEndpoint::new(MDNS_GROUP_V4.into(), MDNS_PORT)),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(_) => Some(IpEndpoint::new(MDNS_GROUP_V6.into(), MDNS_PORT)),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Get the record type and data of an address.
+fn address_data(addr: &IpAddress) -> (u16, Vec<u8, 16>) {
+    match addr {
+        #[cfg(feature = "proto-ipv4")]
+        IpAddress::Ipv4(addr) => (TYPE_A, unwrap!(Vec::from_slice(&addr.octets()))),
+        #[cfg(feature = "proto-ipv6")]
+        IpAddress::Ipv6(addr) => (TYPE_AAAA, unwrap!(Vec::from_slice(&addr.octets()))),
+    }
+}
+
+fn next_suffix(suffix: u16) -> u16 {
+    suffix.max(1).saturating_add(1)
+}
+
+/// Get a label with a number appended after conflicts.
+fn label(name: &str, suffix: u16, instance: bool) -> String<MAX_LABEL_LEN> {
+    let mut label = String::new();
+    // Names too long for the suffix are truncated.
+    let res = match (suffix, instance) {
+        (0, _) => write!(label, "{}", name),
+        (_, false) => write!(label, "{}-{}", name, suffix),
+        (_, true) => write!(label, "{} ({})", name, suffix),
+    };
+    res.ok();
+    label
+}
+
+fn host_name(hostname: &str, suffix: u16) -> Option<Name> {
+    encode_name(Some(&label(hostname, suffix, false)), &["local"])
+}
+
+fn type_name(service: &Service<'_>) -> Option<Name> {
+    encode_name(None, &[service.service_type, "local"])
+}
+
+fn instance_name(service: &Service<'_>, suffix: u16) -> Option<Name> {
+    encode_name(
+        Some(&label(service.instance, suffix, true)),
+        &[service.service_type, "local"],
+    )
+}
+
+/// Encode a domain name, from a first label that may contain dots, and dotted names.
+fn encode_name(first: Option<&str>, rest: &[&str]) -> Option<Name> {
+    let mut name = Name::new();
+    let labels = first.into_iter().chain(rest.iter().flat_map(|s| s.split('.')));
+    for label in labels {
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            return None;
+        }
+        name.push(label.len() as u8).ok()?;
+        name.extend_from_slice(label.as_bytes()).ok()?;
+    }
+    name.push(0).ok()?;
+    Some(name)
+}
+
+/// Read a possibly compressed name at `pos`, returns it uncompressed with the position after it.
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(Name, usize)> {
+    let mut name = Name::new();
+    let mut end = None;
+    for _ in 0..MAX_NAME_LEN {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            name.push(0).ok()?;
+            return Some((name, end.unwrap_or(pos + 1)));
+        } else if len & 0xc0 == 0xc0 {
+            let pointer = ((len & 0x3f) << 8) | *packet.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = pointer;
+        } else if len <= MAX_LABEL_LEN {
+            name.extend_from_slice(packet.get(pos..pos + 1 + len)?).ok()?;
+            pos += 1 + len;
+        } else {
+            return None;
+        }
+    }
+    // Pointer loop
+    None
+}
+
+struct Header {
+    id: u16,
+    flags: u16,
+    questions: u16,
+    answers: u16,
+    authorities: u16,
+    additionals: u16,
+}
+
+impl Header {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let field = |i: usize| Some(u16::from_be_bytes(packet.get(i..i + 2)?.try_into().ok()?));
+        Some(Self {
+            id: field(0)?,
+            flags: field(2)?,
+            questions: field(4)?,
+            answers: field(6)?,
+            authorities: field(8)?,
+            additionals: field(10)?,
+        })
+    }
+
+    /// Number of resource records, in all sections.
+    fn records(&self) -> u32 {
+        self.answers as u32 + self.authorities as u32 + self.additionals as u32
+    }
+
+    /// Get the position of the first resource record.
+    fn questions_end(&self, packet: &[u8]) -> Option<usize> {
+        let mut pos = 12;
+        for _ in 0..self.questions {
+            pos = read_name(packet, pos)?.1 + 4;
+        }
+        Some(pos)
+    }
+}
+
+struct Record<'p> {
+    name: Name,
+    rtype: u16,
+    data_pos: usize,
+    data: &'p [u8],
+}
+
+impl<'p> Record<'p> {
+    /// Parse the record at `pos`, returns it with the position of the next one.
+    fn parse(packet: &'p [u8], pos: usize) -> Option<(Self, usize)> {
+        let (name, pos) = read_name(packet, pos)?;
+        let fixed = packet.get(pos..pos + 10)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let data_pos = pos + 10;
+        let data = packet.get(data_pos..data_pos + len)?;
+        Some((
+            Self {
+                name,
+                rtype,
+                data_pos,
+                data,
+            },
+            data_pos + len,
+        ))
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Section {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// Writer of a DNS message.
+struct Message<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Message<'b> {
+    fn new(buf: &'b mut [u8], id: u16, flags: u16) -> Self {
+        buf[..12].fill(0);
+        buf[0..2].copy_from_slice(&id.to_be_bytes());
+        buf[2..4].copy_from_slice(&flags.to_be_bytes());
+        Self { buf, len: 12 }
+    }
+
+    fn put(&mut self, data: &[u8]) -> Result<(), ()> {
+        let dest = self.buf.get_mut(self.len..self.len + data.len()).ok_or(())?;
+        dest.copy_from_slice(data);
+        self.len += data.len();
+        Ok(())
+    }
+
+    fn add_count(&mut self, offset: usize, n: u16) {
+        let count = u16::from_be_bytes([self.buf[offset], self.buf[offset + 1]]) + n;
+        self.buf[offset..offset + 2].copy_from_slice(&count.to_be_bytes());
+    }
+
+    fn question(&mut self, name: &[u8], qtype: u16, class: u16) -> Result<(), ()> {
+        let start = self.len;
+        let res = self
+            .put(name)
+            .and_then(|_| self.put(&qtype.to_be_bytes()))
+            .and_then(|_| self.put(&class.to_be_bytes()));
+        if res.is_err() {
+            self.len = start;
+            return res;
+        }
+        self.add_count(4, 1);
+        Ok(())
+    }
+
+    /// Copy the raw questions of another message, which must be the first thing written.
+    fn questions(&mut self, questions: &[u8], count: u16) -> Result<(), ()> {
+        self.put(questions)?;
+        self.add_count(4, count);
+        Ok(())
+    }
+
+    /// Write a resource record, with the data written by `data`.
+    ///
+    /// Nothing is written if the record doesn't fit.
+    fn record(
+        &mut self,
+        section: Section,
+        name: &[u8],
+        rtype: u16,
+        class: u16,
+        ttl: u32,
+        data: impl FnOnce(&mut Self) -> Result<(), ()>,
+    ) -> Result<(), ()> {
+        let start = self.len;
+        let res = (|| {
+            self.put(name)?;
+            self.put(&rtype.to_be_bytes())?;
+            self.put(&class.to_be_bytes())?;
+            self.put(&ttl.to_be_bytes())?;
+            let len_pos = self.len;
+            self.put(&[0, 0])?;
+            data(self)?;
+            let len = (self.len - len_pos - 2) as u16;
+            self.buf[len_pos..len_pos + 2].copy_from_slice(&len.to_be_bytes());
+            Ok(())
+        })();
+        if res.is_err() {
+            self.len = start;
+            return res;
+        }
+        let offset = match section {
+            Section::Answer => 6,
+            Section::Authority => 8,
+            Section::Additional => 10,
+        };
+        self.add_count(offset, 1);
+        Ok(())
+    }
+
+    fn finish(self) -> usize {
+        self.len
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+
+    use embassy_futures::select::{select, Either};
+
+    use super::*;
+    use crate::test_util::{self, addr, static_config, wait_for};
+
+    #[test]
+    fn encode_and_read_names() {
+        let name = unwrap!(encode_name(Some("My device.x"), &["_http._tcp", "local"]));
+        assert_eq!(&name[..], b"\x0bMy device.x\x05_http\x04_tcp\x05local\x00");
+        assert_eq!(read_name(&name, 0), Some((name.clone(), name.len())));
+
+        assert_eq!(encode_name(None, &["a..local"]), None);
+        // Labels are at most 63 bytes long.
+        assert_eq!(encode_name(Some(&"a".repeat(64)), &[]), None);
+    }
+
+    #[test]
+    fn read_compressed_names() {
+        // `local` at 0, `device.local` at 7 pointing to it, and a pointer to `device.local` at 15.
+        let packet = b"\x05local\x00\x06device\xc0\x00\xc0\x07";
+        let expected = unwrap!(encode_name(Some("device"), &["local"]));
+        assert_eq!(read_name(packet, 7), Some((expected.clone(), 16)));
+        assert_eq!(read_name(packet, 16), Some((expected, 18)));
+
+        // Truncated and looping names.
+        assert_eq!(read_name(b"\x06devi", 0), None);
+        assert_eq!(read_name(b"\x01a\xc0\x00", 0), None);
+    }
+
+    #[test]
+    fn write_and_parse_records() {
+        let mut buf = [0; 96];
+        let mut msg = Message::new(&mut buf, 0x1234, RESPONSE_FLAGS);
+        let name = unwrap!(encode_name(Some("device"), &["local"]));
+        unwrap!(msg.question(&name, TYPE_ANY, CLASS_IN | UNICAST_RESPONSE));
+        unwrap!(msg.record(Section::Answer, &name, TYPE_A, CLASS_IN, HOST_TTL, |m| m
+            .put(&[10, 0, 0, 1])));
+        unwrap!(
+            msg.record(Section::Additional, &name, TYPE_TXT, CLASS_IN, SERVICE_TTL, |m| m
+                .put(&[0]))
+        );
+        // Records that don't fit aren't written.
+        assert!(msg
+            .record(Section::Authority, &name, TYPE_TXT, CLASS_IN, 0, |m| m.put(&[0; 32]))
+            .is_err());
+        let len = msg.finish();
+        let packet = &buf[..len];
+
+        let header = unwrap!(Header::parse(packet));
+        assert_eq!((header.id, header.flags), (0x1234, RESPONSE_FLAGS));
+        assert_eq!(
+            (header.questions, header.answers, header.authorities, header.additionals),
+            (1, 1, 0, 1)
+        );
+        assert_eq!(header.records(), 2);
+
+        let pos = unwrap!(header.questions_end(packet));
+        let (record, pos) = unwrap!(Record::parse(packet, pos));
+        assert_eq!(
+            (&record.name, record.rtype, record.data),
+            (&name, TYPE_A, &[10, 0, 0, 1][..])
+        );
+        let (record, pos) = unwrap!(Record::parse(packet, pos));
+        assert_eq!((&record.name, record.rtype, record.data), (&name, TYPE_TXT, &[0][..]));
+        assert_eq!(pos, len);
+        assert!(Record::parse(packet, pos).is_none());
+    }
+
+    #[test]
+    fn labels_after_conflicts() {
+        assert_eq!(label("device", 0, false).as_str(), "device");
+        assert_eq!(label("device", next_suffix(0), false).as_str(), "device-2");
+        assert_eq!(label("My device", next_suffix(2), true).as_str(), "My device (3)");
+    }
+
+    fn responder(stack: Stack<'static>) -> MdnsResponder<'static, 1> {
+        MdnsResponder::new(stack, "device", Box::leak(Box::new(MdnsResponderState::new())))
+    }
+
+    /// Send a legacy unicast query until it's answered, returns the response.
+    async fn query(socket: &mut UdpSocket<'_>, name: &Name, qtype: u16, buf: &mut [u8]) -> usize {
+        let mut query = [0; 128];
+        let mut msg = Message::new(&mut query, 0x4242, 0);
+        unwrap!(msg.question(name, qtype, CLASS_IN));
+        let len = msg.finish();
+        loop {
+            unwrap!(socket.send_to(&query[..len], (MDNS_GROUP_V4, MDNS_PORT)).await);
+            if let Ok(Ok((n, _))) = embassy_time::with_timeout(PROBE_INTERVAL, socket.recv_from(buf)).await {
+                return n;
+            }
+        }
+    }
+
+    #[test]
+    fn answers_legacy_queries() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let responder = responder(net.b);
+            unwrap!(responder.add_service(Service {
+                instance: "My device",
+                service_type: "_http._tcp",
+                port: 80,
+                txt: &["path=/"],
+            }));
+
+            let mut socket = UdpSocket::new(
+                net.a,
+                Box::leak(Box::new([PacketMetadata::EMPTY; 1])),
+                Box::leak(Box::new([0; PACKET_LEN])),
+                Box::leak(Box::new([PacketMetadata::EMPTY; 1])),
+                Box::leak(Box::new([0; PACKET_LEN])),
+            );
+            unwrap!(socket.bind(10000));
+
+            let client = async {
+                let mut buf = [0; PACKET_LEN];
+                let host = unwrap!(encode_name(Some("device"), &["local"]));
+                let n = query(&mut socket, &host, TYPE_A, &mut buf).await;
+                let packet = &buf[..n];
+                let header = unwrap!(Header::parse(packet));
+                assert_eq!((header.id, header.questions, header.answers), (0x4242, 1, 1));
+                let (record, _) = unwrap!(Record::parse(packet, unwrap!(header.questions_end(packet))));
+                assert_eq!((&record.name, record.rtype), (&host, TYPE_A));
+                assert_eq!(record.data, &addr(2).octets()[..]);
+
+                // Browsing for the service gives its instance, with the SRV record in the additional section.
+                let service_type = unwrap!(encode_name(None, &["_http._tcp", "local"]));
+                let n = query(&mut socket, &service_type, TYPE_PTR, &mut buf).await;
+                let packet = &buf[..n];
+                let header = unwrap!(Header::parse(packet));
+                assert_eq!(header.answers, 1);
+                let (record, mut pos) = unwrap!(Record::parse(packet, unwrap!(header.questions_end(packet))));
+                let instance = unwrap!(encode_name(Some("My device"), &["_http._tcp", "local"]));
+                assert_eq!(read_name(packet, record.data_pos).map(|(n, _)| n), Some(instance));
+                let mut srv = false;
+                for _ in 0..header.additionals {
+                    let (record, next) = unwrap!(Record::parse(packet, pos));
+                    srv |= record.rtype == TYPE_SRV && record.data[4..6] == 80u16.to_be_bytes();
+                    pos = next;
+                }
+                assert!(srv);
+            };
+            match select(responder.run(), client).await {
+                Either::Second(()) => {}
+            }
+        });
+    }
+
+    #[test]
+    fn renames_on_conflict() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let first = responder(net.b);
+            let second = responder(net.a);
+            let test = async {
+                // Wait for the first responder to claim the name before starting the second.
+                Timer::after(PROBE_INTERVAL * (PROBE_COUNT as u32 + 2)).await;
+                let renamed = async {
+                    wait_for(|| second.hostname().as_str() == "device-2").await;
+                };
+                match select(second.run(), renamed).await {
+                    Either::Second(()) => {}
+                }
+            };
+            match select(first.run(), test).await {
+                Either::Second(()) => {}
+            }
+            assert_eq!(first.hostname().as_str(), "device");
+        });
+    }
+}
@@ -0,0 +1,1544 @@
+//! MQTT 3.1.1 and 5 client.
+//!
+//! [`new`] creates a [`MqttClient`], a handle to publish messages and manage subscriptions from any task,
+//! and a [`MqttRunner`], which must be run in its own task to keep the connection to the broker.
+//!
+//! The runner connects when the network is configured, sends keep-alive pings, and reconnects with
+//! an exponential backoff when the connection, the link or the configuration is lost. Messages
+//! published with QoS 1 and 2 are kept in the outbox until the broker acknowledges them, and sent
+//! again after reconnecting. Subscriptions are renewed when the broker didn't keep the session.
+//! A [`SessionStore`] can persist the unacknowledged messages and the subscriptions across reboots.
+//!
+//! Messages received on the subscribed topics are published to a [`PubSubChannel`](embassy_sync::pubsub::PubSubChannel)
+//! of [`Message`]s, whose subscribers can pick the topics they're interested in with [`topic_matches`].
+//!
+//! Only the features of MQTT 5 also available in MQTT 3.1.1 are supported: properties sent by the broker
+//! are ignored, and reason codes are logged.
+
+use core::cell::RefCell;
+use core::future::{poll_fn, Future};
+use core::pin::pin;
+use core::task::Poll;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::pubsub::DynPublisher;
+use embassy_sync::waitqueue::{MultiWakerRegistration, WakerRegistration};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::{String, Vec};
+
+use crate::tcp::TcpSocket;
+use crate::{IpEndpoint, Stack};
+
+/// Maximum number of packets waiting in the outbox, to be sent or acknowledged.
+pub const MAX_INFLIGHT: usize = 8;
+/// Maximum number of subscriptions.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+/// Maximum length of a topic filter.
+pub const MAX_FILTER_LEN: usize = 64;
+/// Maximum number of tasks waiting for room in the outbox, or for the connection, at the same time.
+const MAX_WAITERS: usize = 4;
+/// Size of the queue of acknowledgments and pings, sent between the packets of the outbox.
+const CONTROL_LEN: usize = 4 * MAX_INFLIGHT;
+
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const PUBACK: u8 = 4;
+const PUBREC: u8 = 5;
+const PUBREL: u8 = 6;
+const PUBCOMP: u8 = 7;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const UNSUBSCRIBE: u8 = 10;
+const UNSUBACK: u8 = 11;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+const DISCONNECT: u8 = 14;
+
+/// DUP flag of a PUBLISH packet, set when it's sent again.
+const DUP: u8 = 0x08;
+/// Format version of the saved sessions.
+const SESSION_FORMAT: u8 = 1;
+
+/// MQTT protocol version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Version {
+    /// MQTT 3.1.1
+    Mqtt311,
+    /// MQTT 5
+    Mqtt5,
+}
+
+/// Quality of service of a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum QoS {
+    /// The message is delivered at most once.
+    AtMostOnce = 0,
+    /// The message is delivered at least once.
+    AtLeastOnce = 1,
+    /// The message is delivered exactly once.
+    ExactlyOnce = 2,
+}
+
+impl QoS {
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(Self::AtMostOnce),
+            1 => Some(Self::AtLeastOnce),
+            2 => Some(Self::ExactlyOnce),
+            _ => None,
+        }
+    }
+}
+
+/// Message published by the broker when the client disconnects unexpectedly.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Will<'a> {
+    /// Topic of the message.
+    pub topic: &'a str,
+    /// Payload of the message.
+    pub payload: &'a [u8],
+    /// Quality of service of the message.
+    pub qos: QoS,
+    /// Whether the broker retains the message.
+    pub retain: bool,
+}
+
+impl<'a> Will<'a> {
+    /// Create a new `Will`, delivered at most once and not retained.
+    pub const fn new(topic: &'a str, payload: &'a [u8]) -> Self {
+        Self {
+            topic,
+            payload,
+            qos: QoS::AtMostOnce,
+            retain: false,
+        }
+    }
+}
+
+/// MQTT client configuration.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Address and port of the broker.
+    pub broker: IpEndpoint,
+    /// Client identifier, which must be unique among the clients of the broker.
+    pub client_id: &'a str,
+    /// Protocol version.
+    pub version: Version,
+    /// Interval of the keep-alive pings, when nothing else is sent. Zero disables them.
+    pub keep_alive: Duration,
+    /// Whether the broker discards the previous session when connecting.
+    pub clean_session: bool,
+    /// With MQTT 5, how long the broker keeps the session after disconnecting, in seconds.
+    ///
+    /// MQTT 3.1.1 brokers keep it until the next connection with `clean_session`.
+    pub session_expiry_interval: u32,
+    /// User name.
+    pub username: Option<&'a str>,
+    /// Password.
+    pub password: Option<&'a [u8]>,
+    /// Will message.
+    pub will: Option<Will<'a>>,
+    /// How long to wait for the connection to the broker, and its responses to the connection and to pings.
+    pub timeout: Duration,
+    /// Delay before reconnecting after a failure, doubled after each failed attempt.
+    pub min_reconnect_delay: Duration,
+    /// Maximum delay before reconnecting.
+    pub max_reconnect_delay: Duration,
+}
+
+impl<'a> Config<'a> {
+    /// Create a new `Config`, connecting to `broker` with a clean session.
+    pub const fn new(broker: IpEndpoint, client_id: &'a str) -> Self {
+        Self {
+            broker,
+            client_id,
+            version: Version::Mqtt311,
+            keep_alive: Duration::from_secs(60),
+            clean_session: true,
+            session_expiry_interval: 0,
+            username: None,
+            password: None,
+            will: None,
+            timeout: Duration::from_secs(10),
+            min_reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Error returned by [`MqttClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The packet doesn't fit in the outbox.
+    TooLarge,
+    /// The topic or topic filter is empty, or has misplaced wildcards.
+    InvalidTopic,
+    /// There are already [`MAX_SUBSCRIPTIONS`] subscriptions.
+    TooManySubscriptions,
+}
+
+/// Message received from the broker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Message<const TOPIC_LEN: usize, const PAYLOAD_LEN: usize> {
+    /// Topic the message was published to.
+    pub topic: String<TOPIC_LEN>,
+    /// Payload of the message.
+    pub payload: Vec<u8, PAYLOAD_LEN>,
+    /// Quality of service the message was delivered with.
+    pub qos: QoS,
+    /// Whether the message was retained by the broker.
+    pub retain: bool,
+}
+
+/// Check whether `topic` matches the topic `filter`, which can have `+` and `#` wildcards.
+pub fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut levels = topic.split('/');
+    for (i, part) in filter.split('/').enumerate() {
+        // Topics starting with `$` are reserved, and not matched by a leading wildcard.
+        let reserved = i == 0 && topic.starts_with('$');
+        match (part, levels.next()) {
+            ("#", _) => return !reserved,
+            ("+", Some(_)) if !reserved => {}
+            (part, Some(level)) if part == level => {}
+            _ => return false,
+        }
+    }
+    levels.next().is_none()
+}
+
+/// Storage of the session of the client, to survive reboots.
+///
+/// The session holds the messages published with QoS 1 and 2 that the broker didn't acknowledge yet,
+/// and the subscriptions. The runner saves it every time they change, so writes should be cheap, to
+/// RAM surviving resets or wear-leveled flash for example.
+pub trait SessionStore {
+    /// Load the saved session into `buf`, and return its length, or 0 if there is none.
+    async fn load(&mut self, buf: &mut [u8]) -> usize;
+
+    /// Save the session, replacing the previous one.
+    async fn save(&mut self, session: &[u8]);
+}
+
+/// No storage, the session is lost on reboot.
+impl SessionStore for () {
+    async fn load(&mut self, _buf: &mut [u8]) -> usize {
+        0
+    }
+
+    async fn save(&mut self, _session: &[u8]) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Kind {
+    /// PUBLISH with QoS 0, dropped once sent.
+    Publish0,
+    /// PUBLISH with QoS 1, waiting for PUBACK.
+    Publish1,
+    /// PUBLISH with QoS 2, waiting for PUBREC.
+    Publish2,
+    /// PUBREL, waiting for PUBCOMP.
+    Release,
+    /// SUBSCRIBE, waiting for SUBACK.
+    Subscribe,
+    /// UNSUBSCRIBE, waiting for UNSUBACK.
+    Unsubscribe,
+}
+
+impl Kind {
+    /// Whether the packet is part of the saved session.
+    fn persistent(self) -> bool {
+        matches!(self, Self::Publish1 | Self::Publish2 | Self::Release)
+    }
+}
+
+/// Packet of the outbox.
+#[derive(Clone, Copy)]
+struct Entry {
+    kind: Kind,
+    id: u16,
+    start: usize,
+    len: usize,
+    /// Bytes of the packet written to the socket.
+    sent: usize,
+}
+
+#[derive(Clone)]
+struct Subscription {
+    filter: String<MAX_FILTER_LEN>,
+    qos: QoS,
+}
+
+/// State shared by the client and the runner.
+struct Shared<const N: usize> {
+    version: Version,
+    /// Encoded packets, in the order of `entries`.
+    outbox: [u8; N],
+    outbox_len: usize,
+    entries: Vec<Entry, MAX_INFLIGHT>,
+    /// Acknowledgments and pings, which don't wait for a response.
+    control: Vec<u8, CONTROL_LEN>,
+    subscriptions: Vec<Subscription, MAX_SUBSCRIPTIONS>,
+    next_id: u16,
+    connected: bool,
+    /// Number of received messages dropped.
+    dropped: u32,
+    /// Whether the session changed since it was saved.
+    dirty: bool,
+    runner_waker: WakerRegistration,
+    client_wakers: MultiWakerRegistration<MAX_WAITERS>,
+}
+
+impl<const N: usize> Shared<N> {
+    const fn new() -> Self {
+        Self {
+            version: Version::Mqtt311,
+            outbox: [0; N],
+            outbox_len: 0,
+            entries: Vec::new(),
+            control: Vec::new(),
+            subscriptions: Vec::new(),
+            next_id: 1,
+            connected: false,
+            dropped: 0,
+            dirty: false,
+            runner_waker: WakerRegistration::new(),
+            client_wakers: MultiWakerRegistration::new(),
+        }
+    }
+
+    /// Allocate a packet identifier not used by the outbox.
+    fn alloc_id(&mut self) -> u16 {
+        loop {
+            let id = self.next_id;
+            self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+            if !self.entries.iter().any(|e| e.id == id) {
+                return id;
+            }
+        }
+    }
+
+    /// Append a packet of `len` bytes encoded by `f` to the outbox.
+    ///
+    /// Returns `None` if there is no room for it.
+    fn push(&mut self, kind: Kind, id: u16, len: usize, f: impl FnOnce(&mut Encoder)) -> Option<()> {
+        if self.entries.is_full() || self.outbox_len + len > N {
+            return None;
+        }
+        let start = self.outbox_len;
+        let mut encoder = Encoder::new(&mut self.outbox[start..start + len]);
+        f(&mut encoder);
+        debug_assert_eq!(encoder.pos, len);
+        unwrap!(self
+            .entries
+            .push(Entry {
+                kind,
+                id,
+                start,
+                len,
+                sent: 0,
+            })
+            .ok());
+        self.outbox_len += len;
+        self.dirty |= kind.persistent();
+        self.runner_waker.wake();
+        Some(())
+    }
+
+    fn remove(&mut self, index: usize) {
+        let entry = self.entries.remove(index);
+        self.outbox
+            .copy_within(entry.start + entry.len..self.outbox_len, entry.start);
+        self.outbox_len -= entry.len;
+        for e in &mut self.entries[index..] {
+            e.start -= entry.len;
+        }
+        self.dirty |= entry.kind.persistent();
+        self.client_wakers.wake();
+    }
+
+    /// Remove the packet of `kind` with `id`, once it's acknowledged.
+    fn acknowledge(&mut self, kind: Kind, id: u16) -> bool {
+        match self.entries.iter().position(|e| e.kind == kind && e.id == id) {
+            Some(index) => {
+                self.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Replace the PUBLISH with `id` by a PUBREL, once the broker received it.
+    fn release(&mut self, id: u16) {
+        self.acknowledge(Kind::Publish2, id);
+        if !self.entries.iter().any(|e| e.kind == Kind::Release && e.id == id) {
+            // There is room, the PUBLISH was larger than the PUBREL. Otherwise, it was released already.
+            let _ = self.push(Kind::Release, id, 4, |e| {
+                e.header((PUBREL << 4) | 0x02, 2);
+                e.u16(id);
+            });
+        }
+    }
+
+    /// Queue a control packet, sent before the packets of the outbox not started yet.
+    ///
+    /// Returns `None` if there is no room for it.
+    fn push_control(&mut self, packet: &[u8]) -> Option<()> {
+        self.control.extend_from_slice(packet).ok()?;
+        self.runner_waker.wake();
+        Some(())
+    }
+
+    /// Whether there is room for an acknowledgment, keeping room for a ping.
+    fn can_acknowledge(&self) -> bool {
+        self.control.len() + 4 + 2 <= CONTROL_LEN
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.control.is_empty() || self.entries.iter().any(|e| e.sent < e.len)
+    }
+
+    /// Copy the bytes not sent yet to `buf`, and return how many were copied.
+    ///
+    /// Control packets are sent before the next packet of the outbox is started, so that they aren't
+    /// inserted in the middle of it.
+    fn take_pending(&mut self, buf: &mut [u8]) -> usize {
+        let mut copied = 0;
+        while copied < buf.len() {
+            let index = self.entries.iter().position(|e| e.sent < e.len);
+            let started = index.is_some_and(|i| self.entries[i].sent > 0);
+            if !started && !self.control.is_empty() {
+                let n = self.control.len().min(buf.len() - copied);
+                buf[copied..copied + n].copy_from_slice(&self.control[..n]);
+                self.control.copy_within(n.., 0);
+                self.control.truncate(self.control.len() - n);
+                copied += n;
+                continue;
+            }
+            let Some(index) = index else {
+                break;
+            };
+            let entry = &mut self.entries[index];
+            let n = (entry.len - entry.sent).min(buf.len() - copied);
+            let start = entry.start + entry.sent;
+            buf[copied..copied + n].copy_from_slice(&self.outbox[start..start + n]);
+            entry.sent += n;
+            copied += n;
+            if entry.kind == Kind::Publish0 && entry.sent == entry.len {
+                self.remove(index);
+            }
+        }
+        copied
+    }
+
+    /// Prepare the outbox for a new connection.
+    ///
+    /// Without a session, the queued (un)subscriptions are dropped as the subscriptions are renewed anyway.
+    fn reset(&mut self, session_present: bool) {
+        // The broker sends the unacknowledged packets again.
+        self.control.clear();
+        if !session_present {
+            while let Some(index) = self
+                .entries
+                .iter()
+                .position(|e| matches!(e.kind, Kind::Subscribe | Kind::Unsubscribe))
+            {
+                self.remove(index);
+            }
+        }
+        for entry in &mut self.entries {
+            if matches!(entry.kind, Kind::Publish1 | Kind::Publish2) && entry.sent > 0 {
+                self.outbox[entry.start] |= DUP;
+            }
+            entry.sent = 0;
+        }
+    }
+
+    fn set_connected(&mut self, connected: bool) {
+        self.connected = connected;
+        self.client_wakers.wake();
+    }
+
+    /// Serialize the session to `buf`, and return its length, or `None` if it doesn't fit.
+    fn save(&self, buf: &mut [u8]) -> Option<usize> {
+        let mut encoder = Encoder::new(buf);
+        encoder.try_bytes(&[SESSION_FORMAT])?;
+        encoder.try_bytes(&self.next_id.to_be_bytes())?;
+        encoder.try_bytes(&[self.subscriptions.len() as u8])?;
+        for sub in &self.subscriptions {
+            encoder.try_bytes(&[sub.qos as u8, sub.filter.len() as u8])?;
+            encoder.try_bytes(sub.filter.as_bytes())?;
+        }
+        let entries = self.entries.iter().filter(|e| e.kind.persistent());
+        encoder.try_bytes(&[entries.clone().count() as u8])?;
+        for entry in entries {
+            encoder.try_bytes(&[entry.kind as u8])?;
+            encoder.try_bytes(&entry.id.to_be_bytes())?;
+            encoder.try_bytes(&(entry.len as u16).to_be_bytes())?;
+            encoder.try_bytes(&self.outbox[entry.start..entry.start + entry.len])?;
+        }
+        Some(encoder.pos)
+    }
+
+    /// Restore a session serialized by [`save`](Self::save).
+    fn restore(&mut self, session: &[u8]) -> Option<()> {
+        let mut decoder = Decoder::new(session);
+        if decoder.u8()? != SESSION_FORMAT {
+            return None;
+        }
+        self.next_id = decoder.u16()?.max(1);
+        for _ in 0..decoder.u8()? {
+            let qos = QoS::from_bits(decoder.u8()?)?;
+            let len = decoder.u8()? as usize;
+            let filter = core::str::from_utf8(decoder.bytes(len)?).ok()?;
+            let filter = String::try_from(filter).ok()?;
+            self.subscriptions.push(Subscription { filter, qos }).ok()?;
+        }
+        for _ in 0..decoder.u8()? {
+            let kind = match decoder.u8()? {
+                k if k == Kind::Publish1 as u8 => Kind::Publish1,
+                k if k == Kind::Publish2 as u8 => Kind::Publish2,
+                k if k == Kind::Release as u8 => Kind::Release,
+                _ => return None,
+            };
+            let id = decoder.u16()?;
+            let len = decoder.u16()? as usize;
+            let packet = decoder.bytes(len)?;
+            self.push(kind, id, len, |e| e.bytes(packet))?;
+            // The packet may have been sent before the reboot.
+            if kind != Kind::Release {
+                self.outbox[self.outbox_len - len] |= DUP;
+            }
+        }
+        self.dirty = false;
+        Some(())
+    }
+}
+
+/// State of the MQTT client, with buffers of N bytes.
+///
+/// The outbox, the socket buffers and the buffer receiving packets have N bytes each. Larger packets
+/// can't be published, and larger received messages are dropped.
+pub struct MqttState<M: RawMutex, const N: usize = 1024> {
+    socket_rx: [u8; N],
+    socket_tx: [u8; N],
+    packet: [u8; N],
+    shared: Mutex<M, RefCell<Shared<N>>>,
+}
+
+impl<M: RawMutex, const N: usize> MqttState<M, N> {
+    /// Create a new `MqttState`.
+    pub const fn new() -> Self {
+        Self {
+            socket_rx: [0; N],
+            socket_tx: [0; N],
+            packet: [0; N],
+            shared: Mutex::new(RefCell::new(Shared::new())),
+        }
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default for MqttState<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Create a new MQTT client and its runner.
+///
+/// Messages received on the subscribed topics are published with `publisher`.
+pub fn new<'d, M: RawMutex, const N: usize, const TOPIC_LEN: usize, const PAYLOAD_LEN: usize>(
+    stack: Stack<'d>,
+    state: &'d mut MqttState<M, N>,
+    config: Config<'d>,
+    publisher: DynPublisher<'d, Message<TOPIC_LEN, PAYLOAD_LEN>>,
+) -> (MqttClient<'d, M, N>, MqttRunner<'d, M, N, TOPIC_LEN, PAYLOAD_LEN>) {
+    let shared = &state.shared;
+    shared.lock(|s| s.borrow_mut().version = config.version);
+    let client = MqttClient { shared };
+    let runner = MqttRunner {
+        stack,
+        config,
+        socket: TcpSocket::new(stack, &mut state.socket_rx, &mut state.socket_tx),
+        packet: &mut state.packet,
+        shared,
+        publisher,
+        received: Vec::new(),
+    };
+    (client, runner)
+}
+
+/// Handle to publish messages and manage subscriptions.
+///
+/// The messages and subscriptions are queued in the outbox, and sent by the [`MqttRunner`]
+/// once connected.
+pub struct MqttClient<'d, M: RawMutex, const N: usize> {
+    shared: &'d Mutex<M, RefCell<Shared<N>>>,
+}
+
+impl<M: RawMutex, const N: usize> Clone for MqttClient<'_, M, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M: RawMutex, const N: usize> Copy for MqttClient<'_, M, N> {}
+
+impl<M: RawMutex, const N: usize> MqttClient<'_, M, N> {
+    /// Publish `payload` to `topic`.
+    ///
+    /// This waits until there is room for the message in the outbox, and returns once it's queued.
+    pub async fn publish(&self, topic: &str, payload: &[u8], qos: QoS, retain: bool) -> Result<(), Error> {
+        if topic.is_empty() || topic.contains(['+', '#']) || topic.len() > u16::MAX as usize {
+            return Err(Error::InvalidTopic);
+        }
+        let version = self.shared.lock(|s| s.borrow().version);
+        let id_len = if qos == QoS::AtMostOnce { 0 } else { 2 };
+        let remaining = 2 + topic.len() + id_len + properties_len(version) + payload.len();
+        let len = packet_len(remaining);
+        if len > N {
+            return Err(Error::TooLarge);
+        }
+        let kind = match qos {
+            QoS::AtMostOnce => Kind::Publish0,
+            QoS::AtLeastOnce => Kind::Publish1,
+            QoS::ExactlyOnce => Kind::Publish2,
+        };
+        self.push(len, |s| {
+            let id = if qos == QoS::AtMostOnce { 0 } else { s.alloc_id() };
+            s.push(kind, id, len, |e| {
+                e.header((PUBLISH << 4) | ((qos as u8) << 1) | retain as u8, remaining);
+                e.str(topic);
+                if qos != QoS::AtMostOnce {
+                    e.u16(id);
+                }
+                e.properties(version);
+                e.bytes(payload);
+            })
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Subscribe to the topics matching `filter`, with a maximum `qos`.
+    ///
+    /// The subscription is renewed after reconnecting if the broker didn't keep the session.
+    /// Subscribing again to the same filter replaces its QoS.
+    pub async fn subscribe(&self, filter: &str, qos: QoS) -> Result<(), Error> {
+        check_filter(filter)?;
+        let version = self.shared.lock(|s| s.borrow().version);
+        let (len, remaining) = subscribe_len(version, filter);
+        let filter_string = String::try_from(filter).map_err(|_| Error::InvalidTopic)?;
+        self.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            match s.subscriptions.iter_mut().find(|sub| sub.filter == filter) {
+                Some(sub) => sub.qos = qos,
+                None => {
+                    let sub = Subscription {
+                        filter: filter_string,
+                        qos,
+                    };
+                    s.subscriptions.push(sub).map_err(|_| Error::TooManySubscriptions)?;
+                }
+            }
+            s.dirty = true;
+            Ok(())
+        })?;
+        self.push(len, |s| {
+            let id = s.alloc_id();
+            s.push(Kind::Subscribe, id, len, |e| {
+                encode_subscribe(e, version, id, filter, qos, remaining)
+            })
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Unsubscribe from `filter`.
+    pub async fn unsubscribe(&self, filter: &str) -> Result<(), Error> {
+        check_filter(filter)?;
+        let version = self.shared.lock(|s| s.borrow().version);
+        let remaining = 2 + properties_len(version) + 2 + filter.len();
+        let len = packet_len(remaining);
+        self.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.subscriptions.retain(|sub| sub.filter != filter);
+            s.dirty = true;
+        });
+        self.push(len, |s| {
+            let id = s.alloc_id();
+            s.push(Kind::Unsubscribe, id, len, |e| {
+                e.header((UNSUBSCRIBE << 4) | 0x02, remaining);
+                e.u16(id);
+                e.properties(version);
+                e.str(filter);
+            })
+        })
+        .await;
+        Ok(())
+    }
+
+    /// Check whether the client is connected to the broker.
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock(|s| s.borrow().connected)
+    }
+
+    /// Wait until the client is connected to the broker.
+    pub async fn wait_connected(&self) {
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let mut s = s.borrow_mut();
+                if s.connected {
+                    return Poll::Ready(());
+                }
+                s.client_wakers.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+
+    /// Check whether all the queued packets were sent, and acknowledged by the broker if needed.
+    pub fn is_flushed(&self) -> bool {
+        self.shared.lock(|s| s.borrow().entries.is_empty())
+    }
+
+    /// Number of messages received from the broker and dropped.
+    ///
+    /// Messages are dropped when they're larger than the packet buffer or than a [`Message`], or when the
+    /// channel is full.
+    pub fn dropped_messages(&self) -> u32 {
+        self.shared.lock(|s| s.borrow().dropped)
+    }
+
+    /// Queue a packet of `len` bytes with `f`, waiting for room in the outbox.
+    async fn push(&self, len: usize, mut f: impl FnMut(&mut Shared<N>) -> Option<()>) {
+        debug_assert!(len <= N);
+        poll_fn(|cx| {
+            self.shared.lock(|s| {
+                let mut s = s.borrow_mut();
+                if f(&mut s).is_some() {
+                    return Poll::Ready(());
+                }
+                s.client_wakers.register(cx.waker());
+                Poll::Pending
+            })
+        })
+        .await
+    }
+}
+
+fn check_filter(filter: &str) -> Result<(), Error> {
+    let valid = !filter.is_empty()
+        && filter.len() <= MAX_FILTER_LEN
+        && filter.split('/').enumerate().all(|(i, level)| match level {
+            "+" => true,
+            // `#` must be the last level.
+            "#" => i == filter.split('/').count() - 1,
+            level => !level.contains(['+', '#']),
+        });
+    valid.then_some(()).ok_or(Error::InvalidTopic)
+}
+
+/// Length of the properties sent with packets: none, with MQTT 5.
+fn properties_len(version: Version) -> usize {
+    match version {
+        Version::Mqtt311 => 0,
+        Version::Mqtt5 => 1,
+    }
+}
+
+/// Length of a packet with `remaining` bytes after its fixed header.
+fn packet_len(remaining: usize) -> usize {
+    let len_bytes = match remaining {
+        0..=127 => 1,
+        128..=16_383 => 2,
+        16_384..=2_097_151 => 3,
+        _ => 4,
+    };
+    1 + len_bytes + remaining
+}
+
+/// Length of a SUBSCRIBE packet, and its remaining length.
+fn subscribe_len(version: Version, filter: &str) -> (usize, usize) {
+    let remaining = 2 + properties_len(version) + 2 + filter.len() + 1;
+    (packet_len(remaining), remaining)
+}
+
+fn encode_subscribe(e: &mut Encoder, version: Version, id: u16, filter: &str, qos: QoS, remaining: usize) {
+    e.header((SUBSCRIBE << 4) | 0x02, remaining);
+    e.u16(id);
+    e.properties(version);
+    e.str(filter);
+    e.u8(qos as u8);
+}
+
+/// Reason the connection ended.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+// The fields are only read by the logs.
+#[allow(unused)]
+enum ConnError {
+    Connect(crate::tcp::ConnectError),
+    Tcp(crate::tcp::Error),
+    /// The broker closed the connection.
+    Closed,
+    /// The broker refused the connection, with this return code or reason code.
+    Refused(u8),
+    /// The broker disconnected, with this reason code.
+    Disconnected(u8),
+    /// The broker sent an invalid packet.
+    Protocol,
+    Timeout,
+    /// The link or the IP configuration was lost.
+    NetworkDown,
+}
+
+impl From<crate::tcp::Error> for ConnError {
+    fn from(err: crate::tcp::Error) -> Self {
+        Self::Tcp(err)
+    }
+}
+
+enum Event {
+    Readable,
+    Writable,
+    Timer,
+    NetworkDown,
+}
+
+/// Runner keeping the connection to the broker.
+pub struct MqttRunner<'d, M: RawMutex, const N: usize, const TOPIC_LEN: usize, const PAYLOAD_LEN: usize> {
+    stack: Stack<'d>,
+    config: Config<'d>,
+    socket: TcpSocket<'d>,
+    packet: &'d mut [u8; N],
+    shared: &'d Mutex<M, RefCell<Shared<N>>>,
+    publisher: DynPublisher<'d, Message<TOPIC_LEN, PAYLOAD_LEN>>,
+    /// Identifiers of the QoS 2 messages received and not released yet.
+    received: Vec<u16, MAX_INFLIGHT>,
+}
+
+impl<M: RawMutex, const N: usize, const TOPIC_LEN: usize, const PAYLOAD_LEN: usize>
+    MqttRunner<'_, M, N, TOPIC_LEN, PAYLOAD_LEN>
+{
+    /// Run the client, without persisting the session.
+    pub async fn run(&mut self) -> ! {
+        self.run_with_store(&mut ()).await
+    }
+
+    /// Run the client, persisting the session in `store`.
+    ///
+    /// The session saved in `store` is restored first.
+    pub async fn run_with_store<S: SessionStore>(&mut self, store: &mut S) -> ! {
+        let len = store.load(self.packet).await;
+        if len > 0 {
+            let restored = self.shared.lock(|s| s.borrow_mut().restore(&self.packet[..len]));
+            if restored.is_none() {
+                warn!("mqtt: invalid saved session");
+            }
+        }
+
+        let mut delay = self.config.min_reconnect_delay;
+        loop {
+            self.stack.wait_config_up().await;
+            let mut connected = false;
+            let err = self.connection(store, &mut connected).await;
+            self.socket.abort();
+            let _ = with_timeout(self.config.timeout, self.socket.flush()).await;
+            self.shared.lock(|s| s.borrow_mut().set_connected(false));
+            warn!("mqtt: disconnected: {:?}", err);
+
+            if connected {
+                delay = self.config.min_reconnect_delay;
+            }
+            Timer::after(delay).await;
+            delay = (delay * 2).min(self.config.max_reconnect_delay);
+        }
+    }
+
+    /// Connect to the broker, and serve the connection until it fails.
+    async fn connection<S: SessionStore>(&mut self, store: &mut S, connected: &mut bool) -> ConnError {
+        let timeout = self.config.timeout;
+        match with_timeout(timeout, self.socket.connect(self.config.broker)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return ConnError::Connect(e),
+            Err(_) => return ConnError::Timeout,
+        }
+
+        let len = encode_connect(self.packet, &self.config);
+        if let Err(e) = write_all(&mut self.socket, &self.packet[..len]).await {
+            return e;
+        }
+        let session_present = match with_timeout(timeout, self.read_packet()).await {
+            Ok(Ok((header, len, _))) if header >> 4 == CONNACK && len >= 2 => {
+                let code = self.packet[1];
+                if code != 0 {
+                    return ConnError::Refused(code);
+                }
+                self.packet[0] & 0x01 != 0
+            }
+            Ok(Ok(_)) => return ConnError::Protocol,
+            Ok(Err(e)) => return e,
+            Err(_) => return ConnError::Timeout,
+        };
+        info!("mqtt: connected, session present: {}", session_present);
+        *connected = true;
+
+        // The broker doesn't know about the messages received before, unless it kept the session.
+        if !session_present {
+            self.received.clear();
+        }
+        let subscriptions = self.shared.lock(|s| {
+            let mut s = s.borrow_mut();
+            s.reset(session_present);
+            if session_present {
+                Vec::new()
+            } else {
+                s.subscriptions.clone()
+            }
+        });
+        for sub in &subscriptions {
+            let version = self.config.version;
+            let (len, remaining) = subscribe_len(version, &sub.filter);
+            let id = self.shared.lock(|s| s.borrow_mut().alloc_id());
+            encode_subscribe(
+                &mut Encoder::new(&mut self.packet[..len]),
+                version,
+                id,
+                &sub.filter,
+                sub.qos,
+                remaining,
+            );
+            if let Err(e) = write_all(&mut self.socket, &self.packet[..len]).await {
+                return e;
+            }
+        }
+        self.shared.lock(|s| s.borrow_mut().set_connected(true));
+
+        match self.serve(store).await {
+            Ok(never) => match never {},
+            Err(e) => e,
+        }
+    }
+
+    async fn serve<S: SessionStore>(&mut self, store: &mut S) -> Result<core::convert::Infallible, ConnError> {
+        let keep_alive = self.config.keep_alive;
+        let mut last_sent = Instant::now();
+        let mut ping_sent: Option<Instant> = None;
+        loop {
+            if self.shared.lock(|s| core::mem::take(&mut s.borrow_mut().dirty)) {
+                let len = self.shared.lock(|s| s.borrow().save(self.packet));
+                match len {
+                    Some(len) => store.save(&self.packet[..len]).await,
+                    None => warn!("mqtt: session too large to be saved"),
+                }
+            }
+
+            let deadline = match ping_sent {
+                Some(sent) => sent + self.config.timeout,
+                None if keep_alive.as_ticks() == 0 => Instant::MAX,
+                None => last_sent + keep_alive,
+            };
+            let event = {
+                let mut readable = pin!(self.socket.wait_read_ready());
+                let mut writable = pin!(self.socket.wait_write_ready());
+                let mut timer = pin!(Timer::at(deadline));
+                let mut link_down = pin!(self.stack.wait_link_down());
+                let mut config_down = pin!(self.stack.wait_config_down());
+                poll_fn(|cx| {
+                    let (pending, can_read) = self.shared.lock(|s| {
+                        let mut s = s.borrow_mut();
+                        s.runner_waker.register(cx.waker());
+                        (s.has_pending(), s.can_acknowledge())
+                    });
+                    // Packets aren't read while their acknowledgment can't be queued.
+                    // A closed connection isn't readable, but reading reports it.
+                    if can_read && (readable.as_mut().poll(cx).is_ready() || !self.socket.may_recv()) {
+                        return Poll::Ready(Event::Readable);
+                    }
+                    if pending && writable.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Event::Writable);
+                    }
+                    if timer.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Event::Timer);
+                    }
+                    if link_down.as_mut().poll(cx).is_ready() || config_down.as_mut().poll(cx).is_ready() {
+                        return Poll::Ready(Event::NetworkDown);
+                    }
+                    Poll::Pending
+                })
+                .await
+            };
+
+            match event {
+                Event::Readable => {
+                    let (header, len, truncated) = self.read_packet().await?;
+                    if header >> 4 == PINGRESP {
+                        ping_sent = None;
+                    }
+                    self.handle(header, len, truncated)?;
+                }
+                Event::Writable => {
+                    self.socket
+                        .write_with(|buf| {
+                            let n = self.shared.lock(|s| s.borrow_mut().take_pending(buf));
+                            (n, ())
+                        })
+                        .await?;
+                    last_sent = Instant::now();
+                }
+                Event::Timer if ping_sent.is_some() => return Err(ConnError::Timeout),
+                Event::Timer => {
+                    // There is always room for a ping, see `can_acknowledge`.
+                    self.shared
+                        .lock(|s| unwrap!(s.borrow_mut().push_control(&[PINGREQ << 4, 0])));
+                    last_sent = Instant::now();
+                    ping_sent = Some(last_sent);
+                }
+                Event::NetworkDown => return Err(ConnError::NetworkDown),
+            }
+        }
+    }
+
+    /// Read a packet into the packet buffer, and return its first byte, the length stored, and whether
+    /// it was truncated.
+    ///
+    /// The end of packets larger than the buffer is discarded.
+    async fn read_packet(&mut self) -> Result<(u8, usize, bool), ConnError> {
+        let mut byte = [0];
+        read_exact(&mut self.socket, &mut byte).await?;
+        let header = byte[0];
+        let mut remaining = 0usize;
+        for shift in (0..28).step_by(7) {
+            read_exact(&mut self.socket, &mut byte).await?;
+            remaining |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+            if shift == 21 {
+                return Err(ConnError::Protocol);
+            }
+        }
+
+        let len = remaining.min(N);
+        read_exact(&mut self.socket, &mut self.packet[..len]).await?;
+        let mut discard = remaining - len;
+        while discard > 0 {
+            let mut buf = [0; 64];
+            let n = discard.min(buf.len());
+            read_exact(&mut self.socket, &mut buf[..n]).await?;
+            discard -= n;
+        }
+        Ok((header, len, len < remaining))
+    }
+
+    /// Handle a packet received from the broker, `truncated` if it didn't fit in the packet buffer.
+    ///
+    /// There must be room for the acknowledgment, see [`Shared::can_acknowledge`].
+    fn handle(&mut self, header: u8, len: usize, truncated: bool) -> Result<(), ConnError> {
+        let version = self.config.version;
+        let mut decoder = Decoder::new(&self.packet[..len]);
+        match header >> 4 {
+            PUBLISH => {
+                let qos = QoS::from_bits((header >> 1) & 0x03).ok_or(ConnError::Protocol)?;
+                let topic = decoder.str().ok_or(ConnError::Protocol)?;
+                let id = match qos {
+                    QoS::AtMostOnce => 0,
+                    _ => decoder.u16().ok_or(ConnError::Protocol)?,
+                };
+                if version == Version::Mqtt5 {
+                    decoder.skip_properties().ok_or(ConnError::Protocol)?;
+                }
+                let payload = decoder.rest();
+
+                let duplicate = qos == QoS::ExactlyOnce && self.received.contains(&id);
+                if !duplicate {
+                    let message = match (truncated, String::try_from(topic), Vec::from_slice(payload)) {
+                        (false, Ok(topic), Ok(payload)) => Ok(Message {
+                            topic,
+                            payload,
+                            qos,
+                            retain: header & 0x01 != 0,
+                        }),
+                        _ => {
+                            warn!("mqtt: message too large, dropped");
+                            Err(())
+                        }
+                    };
+                    // With MQTT 5, the broker doesn't send more messages than the receive maximum.
+                    if qos == QoS::ExactlyOnce && self.received.push(id).is_err() {
+                        warn!("mqtt: too many QoS 2 messages in flight, it may be delivered twice");
+                    }
+                    // Waiting for the subscribers would block the connection, and the acknowledgments.
+                    let published = message.and_then(|message| {
+                        self.publisher.try_publish(message).map_err(|_| {
+                            warn!("mqtt: channel full, message dropped");
+                        })
+                    });
+                    if published.is_err() {
+                        self.shared.lock(|s| {
+                            let mut s = s.borrow_mut();
+                            s.dropped = s.dropped.wrapping_add(1);
+                        });
+                    }
+                }
+                match qos {
+                    QoS::AtMostOnce => {}
+                    QoS::AtLeastOnce => self.acknowledge(PUBACK << 4, id),
+                    QoS::ExactlyOnce => self.acknowledge(PUBREC << 4, id),
+                }
+            }
+            // Other packets from the broker are small, a truncated one is invalid.
+            _ if truncated => return Err(ConnError::Protocol),
+            kind @ (PUBACK | PUBREC | PUBCOMP | SUBACK | UNSUBACK) => {
+                let id = decoder.u16().ok_or(ConnError::Protocol)?;
+                let reason = match (kind, version) {
+                    (SUBACK | UNSUBACK, Version::Mqtt5) => {
+                        decoder.skip_properties();
+                        decoder.u8()
+                    }
+                    (SUBACK, Version::Mqtt311) => decoder.u8(),
+                    (UNSUBACK, Version::Mqtt311) => None,
+                    _ => decoder.u8(),
+                };
+                // Failures have the high bit set, with MQTT 3.1.1 for subscriptions and MQTT 5 for all acks.
+                if reason.is_some_and(|r| r >= 0x80) {
+                    warn!(
+                        "mqtt: packet {} with id {} failed: reason {}",
+                        kind,
+                        id,
+                        reason.unwrap_or(0)
+                    );
+                }
+                self.shared.lock(|s| {
+                    let mut s = s.borrow_mut();
+                    match kind {
+                        PUBACK => {
+                            s.acknowledge(Kind::Publish1, id);
+                        }
+                        PUBREC if reason.is_some_and(|r| r >= 0x80) => {
+                            // The message was refused, there is nothing to release.
+                            s.acknowledge(Kind::Publish2, id);
+                        }
+                        PUBREC => s.release(id),
+                        PUBCOMP => {
+                            s.acknowledge(Kind::Release, id);
+                        }
+                        SUBACK => {
+                            s.acknowledge(Kind::Subscribe, id);
+                        }
+                        _ => {
+                            s.acknowledge(Kind::Unsubscribe, id);
+                        }
+                    }
+                });
+            }
+            PUBREL => {
+                let id = decoder.u16().ok_or(ConnError::Protocol)?;
+                self.received.retain(|r| *r != id);
+                self.acknowledge(PUBCOMP << 4, id);
+            }
+            PINGRESP => {}
+            DISCONNECT => return Err(ConnError::Disconnected(decoder.u8().unwrap_or(0))),
+            _ => return Err(ConnError::Protocol),
+        }
+        Ok(())
+    }
+
+    /// Queue an acknowledgment of the packet with `id`.
+    fn acknowledge(&self, header: u8, id: u16) {
+        let [hi, lo] = id.to_be_bytes();
+        self.shared
+            .lock(|s| unwrap!(s.borrow_mut().push_control(&[header, 2, hi, lo])));
+    }
+}
+
+/// Encode the CONNECT packet for `config` into `buf`, and return its length.
+fn encode_connect(buf: &mut [u8], config: &Config) -> usize {
+    let version = config.version;
+    let session_expiry = version == Version::Mqtt5 && config.session_expiry_interval != 0;
+    // With MQTT 5, the receive maximum, and the session expiry interval if any.
+    let connect_properties_len = match (version, session_expiry) {
+        (Version::Mqtt311, _) => 0,
+        (Version::Mqtt5, false) => 4,
+        (Version::Mqtt5, true) => 9,
+    };
+    let mut remaining = 10 + connect_properties_len + 2 + config.client_id.len();
+    let mut flags = 0;
+    if config.clean_session {
+        flags |= 0x02;
+    }
+    if let Some(will) = &config.will {
+        flags |= 0x04 | ((will.qos as u8) << 3) | ((will.retain as u8) << 5);
+        remaining += properties_len(version) + 2 + will.topic.len() + 2 + will.payload.len();
+    }
+    if let Some(username) = config.username {
+        flags |= 0x80;
+        remaining += 2 + username.len();
+    }
+    if let Some(password) = config.password {
+        flags |= 0x40;
+        remaining += 2 + password.len();
+    }
+
+    let mut e = Encoder::new(buf);
+    e.header(CONNECT << 4, remaining);
+    e.str("MQTT");
+    e.u8(match version {
+        Version::Mqtt311 => 4,
+        Version::Mqtt5 => 5,
+    });
+    e.u8(flags);
+    e.u16(config.keep_alive.as_secs().min(u16::MAX as u64) as u16);
+    if version == Version::Mqtt5 {
+        e.u8(connect_properties_len as u8 - 1);
+        // Receive Maximum, so that the broker doesn't send more QoS 2 messages than can be tracked.
+        e.u8(0x21);
+        e.u16(MAX_INFLIGHT as u16);
+        if session_expiry {
+            // Session Expiry Interval.
+            e.u8(0x11);
+            e.bytes(&config.session_expiry_interval.to_be_bytes());
+        }
+    }
+    e.str(config.client_id);
+    if let Some(will) = &config.will {
+        e.properties(version);
+        e.str(will.topic);
+        e.u16(will.payload.len() as u16);
+        e.bytes(will.payload);
+    }
+    if let Some(username) = config.username {
+        e.str(username);
+    }
+    if let Some(password) = config.password {
+        e.u16(password.len() as u16);
+        e.bytes(password);
+    }
+    e.pos
+}
+
+async fn write_all(socket: &mut TcpSocket<'_>, mut buf: &[u8]) -> Result<(), ConnError> {
+    while !buf.is_empty() {
+        let n = socket.write(buf).await?;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+async fn read_exact(socket: &mut TcpSocket<'_>, mut buf: &mut [u8]) -> Result<(), ConnError> {
+    while !buf.is_empty() {
+        match socket.read(buf).await? {
+            0 => return Err(ConnError::Closed),
+            n => buf = &mut buf[n..],
+        }
+    }
+    Ok(())
+}
+
+/// Writer of packets into a buffer of the right size.
+struct Encoder<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn bytes(&mut self, data: &[u8]) {
+        self.buf[self.pos..self.pos + data.len()].copy_from_slice(data);
+        self.pos += data.len();
+    }
+
+    fn try_bytes(&mut self, data: &[u8]) -> Option<()> {
+        if self.pos + data.len() > self.buf.len() {
+            return None;
+        }
+        self.bytes(data);
+        Some(())
+    }
+
+    fn u8(&mut self, value: u8) {
+        self.bytes(&[value]);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes(&value.to_be_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u16(value.len() as u16);
+        self.bytes(value.as_bytes());
+    }
+
+    /// Fixed header of a packet.
+    fn header(&mut self, first: u8, mut remaining: usize) {
+        self.u8(first);
+        loop {
+            let byte = (remaining & 0x7f) as u8;
+            remaining >>= 7;
+            if remaining == 0 {
+                self.u8(byte);
+                return;
+            }
+            self.u8(byte | 0x80);
+        }
+    }
+
+    /// Empty properties, with MQTT 5.
+    fn properties(&mut self, version: Version) {
+        if version == Version::Mqtt5 {
+            self.u8(0);
+        }
+    }
+}
+
+/// Reader of received packets.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.buf.len() {
+            return None;
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn str(&mut self) -> Option<&'a str> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).ok()
+    }
+
+    fn var_int(&mut self) -> Option<usize> {
+        let mut value = 0;
+        for shift in (0..28).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Skip the properties of an MQTT 5 packet.
+    fn skip_properties(&mut self) -> Option<()> {
+        let len = self.var_int()?;
+        self.bytes(len)?;
+        Some(())
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        core::mem::take(&mut self.buf)
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+    use std::vec;
+
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::pubsub::PubSubChannel;
+
+    use super::*;
+    use crate::test_util::{self, addr, static_config, wait_for};
+
+    const PORT: u16 = 1883;
+    const N: usize = 64;
+
+    type Channel = PubSubChannel<NoopRawMutex, Message<16, 16>, 1, 1, 1>;
+
+    #[test]
+    fn control_packets_are_sent_between_outbox_packets() {
+        let mut shared = Shared::<N>::new();
+        let mut buf = [0; 32];
+        unwrap!(shared.push(Kind::Publish1, 1, 10, |e| e.bytes(&[1; 10])));
+        assert_eq!(shared.take_pending(&mut buf[..4]), 4);
+
+        // The PUBLISH is finished before the PUBACK.
+        unwrap!(shared.push_control(&[PUBACK << 4, 2, 0, 7]));
+        unwrap!(shared.push(Kind::Publish1, 2, 3, |e| e.bytes(&[2; 3])));
+        assert_eq!(shared.take_pending(&mut buf[..8]), 8);
+        assert_eq!(buf[..8], [1, 1, 1, 1, 1, 1, PUBACK << 4, 2]);
+        // The rest of the PUBACK comes before the next PUBLISH.
+        assert_eq!(shared.take_pending(&mut buf), 5);
+        assert_eq!(buf[..5], [0, 7, 2, 2, 2]);
+        assert!(!shared.has_pending());
+
+        while shared.can_acknowledge() {
+            unwrap!(shared.push_control(&[PUBACK << 4, 2, 0, 7]));
+        }
+        // There is still room for a ping.
+        unwrap!(shared.push_control(&[PINGREQ << 4, 0]));
+    }
+
+    /// Read a packet sent by the client, and return its first byte and what follows its length.
+    async fn read(socket: &mut TcpSocket<'_>) -> (u8, vec::Vec<u8>) {
+        let mut byte = [0];
+        unwrap!(read_exact(socket, &mut byte).await);
+        let header = byte[0];
+        let mut len = 0;
+        for shift in (0..28).step_by(7) {
+            unwrap!(read_exact(socket, &mut byte).await);
+            len |= ((byte[0] & 0x7f) as usize) << shift;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; len];
+        unwrap!(read_exact(socket, &mut body).await);
+        (header, body)
+    }
+
+    /// Accept the connection of the client, and acknowledge its subscription if it has one.
+    async fn accept(broker: &mut TcpSocket<'_>, subscribed: bool) {
+        unwrap!(broker.accept(PORT).await);
+        assert_eq!(read(broker).await.0, CONNECT << 4);
+        unwrap!(write_all(broker, &[CONNACK << 4, 2, 0, 0]).await);
+        if subscribed {
+            let (header, body) = read(broker).await;
+            assert_eq!(header, (SUBSCRIBE << 4) | 0x02);
+            unwrap!(write_all(broker, &[SUBACK << 4, 3, body[0], body[1], 1]).await);
+        }
+    }
+
+    /// Run an MQTT client on `a` with the broker socket on `b`, and a channel of one message.
+    fn run<F, Fut>(keep_alive: Duration, test: F)
+    where
+        F: FnOnce(MqttClient<'static, NoopRawMutex, N>, &'static Channel, TcpSocket<'static>) -> Fut + 'static,
+        Fut: Future<Output = ()>,
+    {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let state = Box::leak(Box::new(MqttState::<NoopRawMutex, N>::new()));
+            let channel: &'static Channel = Box::leak(Box::new(PubSubChannel::new()));
+            let mut config = Config::new((addr(2), PORT).into(), "test");
+            config.keep_alive = keep_alive;
+            let (client, mut runner) = new(net.a, state, config, unwrap!(channel.dyn_publisher()));
+            let rx = Box::leak(Box::new([0; 1024]));
+            let tx = Box::leak(Box::new([0; 1024]));
+            let broker = TcpSocket::new(net.b, rx, tx);
+
+            match select(runner.run(), test(client, channel, broker)).await {
+                Either::Second(()) => {}
+            }
+        })
+    }
+
+    #[test]
+    fn delivers_and_acknowledges_messages() {
+        run(Duration::from_secs(60), |client, channel, mut broker| async move {
+            let mut subscriber = unwrap!(channel.subscriber());
+            unwrap!(client.subscribe("a/#", QoS::AtLeastOnce).await);
+            accept(&mut broker, true).await;
+            client.wait_connected().await;
+
+            // QoS 1 message, delivered and acknowledged.
+            unwrap!(write_all(&mut broker, &[0x32, 9, 0, 3, b'a', b'/', b'b', 0, 7, b'h', b'i']).await);
+            assert_eq!(read(&mut broker).await, (PUBACK << 4, vec![0, 7]));
+            // QoS 0 message, dropped as the channel is full.
+            unwrap!(write_all(&mut broker, &[0x30, 7, 0, 3, b'a', b'/', b'c', b'o', b'k']).await);
+            // QoS 1 message larger than the packet buffer, dropped and acknowledged.
+            let mut large = vec![0x32, 107, 0, 3, b'a', b'/', b'd', 0, 8];
+            large.extend_from_slice(&[b'x'; 100]);
+            unwrap!(write_all(&mut broker, &large).await);
+            assert_eq!(read(&mut broker).await, (PUBACK << 4, vec![0, 8]));
+            assert_eq!(client.dropped_messages(), 2);
+
+            let message = subscriber.next_message_pure().await;
+            assert_eq!(message.topic, "a/b");
+            assert_eq!(message.payload, b"hi");
+            assert_eq!(message.qos, QoS::AtLeastOnce);
+
+            // QoS 2 message, delivered once.
+            let publish = [0x34, 8, 0, 3, b'a', b'/', b'e', 0, 9, b'!'];
+            unwrap!(write_all(&mut broker, &publish).await);
+            assert_eq!(read(&mut broker).await, (PUBREC << 4, vec![0, 9]));
+            publish_again(&mut broker, &publish).await;
+            unwrap!(write_all(&mut broker, &[(PUBREL << 4) | 0x02, 2, 0, 9]).await);
+            assert_eq!(read(&mut broker).await, (PUBCOMP << 4, vec![0, 9]));
+            assert_eq!(subscriber.next_message_pure().await.topic, "a/e");
+            assert_eq!(subscriber.try_next_message_pure(), None);
+            assert_eq!(client.dropped_messages(), 2);
+        });
+    }
+
+    /// Send the QoS 2 `publish` again with the DUP flag, and check it's only acknowledged.
+    async fn publish_again(broker: &mut TcpSocket<'_>, publish: &[u8]) {
+        let mut dup = publish.to_vec();
+        dup[0] |= DUP;
+        unwrap!(write_all(broker, &dup).await);
+        assert_eq!(read(broker).await, (PUBREC << 4, dup[7..9].to_vec()));
+    }
+
+    #[test]
+    fn publishes_until_acknowledged() {
+        run(Duration::from_secs(60), |client, _, mut broker| async move {
+            let publish = async {
+                unwrap!(client.publish("t", b"x", QoS::AtLeastOnce, false).await);
+                unwrap!(client.publish("t", b"y", QoS::ExactlyOnce, true).await);
+            };
+            join(publish, accept(&mut broker, false)).await;
+
+            let (header, first) = read(&mut broker).await;
+            assert_eq!(header, (PUBLISH << 4) | 0x02);
+            assert_eq!(first[..3], [0, 1, b't']);
+            assert_eq!(first[5..], *b"x");
+            let (header, second) = read(&mut broker).await;
+            assert_eq!(header, (PUBLISH << 4) | 0x05);
+            assert_eq!(second[5..], *b"y");
+
+            unwrap!(write_all(&mut broker, &[PUBACK << 4, 2, first[3], first[4]]).await);
+            unwrap!(write_all(&mut broker, &[PUBREC << 4, 2, second[3], second[4]]).await);
+            assert_eq!(read(&mut broker).await, ((PUBREL << 4) | 0x02, second[3..5].to_vec()));
+            assert!(!client.is_flushed());
+            unwrap!(write_all(&mut broker, &[PUBCOMP << 4, 2, second[3], second[4]]).await);
+            wait_for(|| client.is_flushed()).await;
+        });
+    }
+
+    #[test]
+    fn pings_broker() {
+        run(Duration::from_millis(200), |client, _, mut broker| async move {
+            accept(&mut broker, false).await;
+            client.wait_connected().await;
+            for _ in 0..2 {
+                assert_eq!(read(&mut broker).await, (PINGREQ << 4, vec![]));
+                unwrap!(write_all(&mut broker, &[PINGRESP << 4, 0]).await);
+            }
+            assert!(client.is_connected());
+        });
+    }
+}
@@ -0,0 +1,363 @@
+//! Packet capture in the pcapng format.
+//!
+//! A [`PacketSink`] set with [`Runner::set_packet_sink`](crate::Runner::set_packet_sink) sees every packet
+//! received from and sent to the driver of an interface, with its direction and a timestamp.
+//!
+//! [`PcapPipe`] is a sink buffering the packets as pcapng blocks, which [`PcapPipe::write_to`] writes to any
+//! [`embedded_io_async::Write`], such as a file, a [`Pipe`] or a [`TcpSocket`](crate::tcp::TcpSocket).
+//! The output can be opened directly in Wireshark, or streamed to it:
+//!
+//! ```text
+//! nc <device address> <port> | wireshark -k -i -
+//! ```
+//!
+//! Timestamps are the [`Instant`]s the packets were captured, so they count from the boot of the device.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::pipe::Pipe;
+use embassy_time::Instant;
+
+/// pcapng Section Header Block type.
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D_0D0A;
+/// pcapng Interface Description Block type.
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x0000_0001;
+/// pcapng Enhanced Packet Block type.
+const ENHANCED_PACKET_BLOCK: u32 = 0x0000_0006;
+/// Magic number telling the byte order of a section.
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B_3C4D;
+/// `epb_flags` option code of an Enhanced Packet Block.
+const OPT_EPB_FLAGS: u16 = 2;
+/// Length of an Enhanced Packet Block, without the packet data.
+const PACKET_BLOCK_OVERHEAD: usize = 28 + 12 + 4;
+/// Size of the chunks copied from the pipe to the writer.
+const CHUNK_LEN: usize = 256;
+
+/// Link-layer type of captured packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinkType {
+    /// Ethernet frames.
+    Ethernet,
+    /// Bare IPv4 or IPv6 packets.
+    Ip,
+    /// IEEE 802.15.4 frames, without the FCS.
+    Ieee802154,
+}
+
+impl LinkType {
+    /// Value of the link type in the pcap and pcapng formats.
+    pub const fn to_pcap(self) -> u16 {
+        match self {
+            Self::Ethernet => 1,
+            Self::Ip => 101,
+            Self::Ieee802154 => 230,
+        }
+    }
+}
+
+/// Direction of a captured packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// Received from the driver.
+    Rx,
+    /// Sent to the driver.
+    Tx,
+}
+
+/// Packet seen by a [`PacketSink`].
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Packet<'a> {
+    /// Link-layer type of the packet.
+    pub link_type: LinkType,
+    /// Whether the packet was received or sent.
+    pub direction: Direction,
+    /// When the packet was captured.
+    pub timestamp: Instant,
+    /// Raw packet, including the link-layer header.
+    pub data: &'a [u8],
+}
+
+/// Receiver of the packets captured on an interface.
+///
+/// The sink is called from the task running the interface, while the stack is locked, so it must not
+/// block or use the stack.
+pub trait PacketSink {
+    /// Capture a packet.
+    fn capture(&self, packet: &Packet<'_>);
+}
+
+#[derive(Clone, Copy)]
+struct State {
+    link_type: Option<LinkType>,
+    dropped: u32,
+}
+
+/// Packet sink buffering captured packets as pcapng blocks in a [`Pipe`] of `N` bytes.
+///
+/// Packets that don't fit in the pipe are dropped. All packets must have the same link type, so use one
+/// `PcapPipe` per interface.
+pub struct PcapPipe<M: RawMutex, const N: usize> {
+    pipe: Pipe<M, N>,
+    state: Mutex<M, Cell<State>>,
+}
+
+impl<M: RawMutex, const N: usize> PcapPipe<M, N> {
+    /// Create a new `PcapPipe`.
+    pub const fn new() -> Self {
+        Self {
+            pipe: Pipe::new(),
+            state: Mutex::new(Cell::new(State {
+                link_type: None,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Number of packets dropped because the pipe was full.
+    pub fn dropped(&self) -> u32 {
+        self.state.lock(|s| s.get().dropped)
+    }
+
+    /// Write the captured packets to `writer` as a pcapng stream.
+    ///
+    /// The stream starts with the pcapng headers, which are written when the first packet is available,
+    /// followed by the packets captured so far and all the packets captured afterwards.
+    /// This only returns if writing fails, and can then be called again with a new writer,
+    /// to start a new stream with the packets that weren't written yet.
+    pub async fn write_to<W: embedded_io_async::Write>(&self, writer: &mut W) -> Result<Infallible, W::Error> {
+        let mut buf = [0; CHUNK_LEN];
+        let mut header = [0; 8];
+        self.read_exact(&mut header).await;
+
+        // The link type is known once a packet was captured.
+        let link_type = unwrap!(self.state.lock(|s| s.get().link_type));
+        let (section, interface) = stream_header(link_type);
+        let mut remaining = block_len(&header) - header.len();
+        let res = async {
+            writer.write_all(&section).await?;
+            writer.write_all(&interface).await?;
+            writer.write_all(&header).await?;
+            loop {
+                while remaining > 0 {
+                    let n = self.pipe.read(&mut buf[..remaining.min(CHUNK_LEN)]).await;
+                    writer.write_all(&buf[..n]).await?;
+                    remaining -= n;
+                }
+                if self.pipe.is_empty() {
+                    writer.flush().await?;
+                }
+                self.read_exact(&mut header).await;
+                writer.write_all(&header).await?;
+                remaining = block_len(&header) - header.len();
+            }
+        }
+        .await;
+
+        // Drop the rest of the block being written, so the next stream starts with a whole block.
+        while remaining > 0 {
+            let n = self.pipe.read(&mut buf[..remaining.min(CHUNK_LEN)]).await;
+            remaining -= n;
+        }
+        res
+    }
+
+    async fn read_exact(&self, mut buf: &mut [u8]) {
+        while !buf.is_empty() {
+            let n = self.pipe.read(buf).await;
+            buf = &mut buf[n..];
+        }
+    }
+
+    /// Write all of `buf` to the pipe, returns `false` if it's full.
+    fn write(&self, mut buf: &[u8]) -> bool {
+        while !buf.is_empty() {
+            // This only writes part of `buf` when wrapping around.
+            match self.pipe.try_write(buf) {
+                Ok(n) => buf = &buf[n..],
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+impl<M: RawMutex, const N: usize> Default for PcapPipe<M, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: RawMutex, const N: usize> PacketSink for PcapPipe<M, N> {
+    fn capture(&self, packet: &Packet<'_>) {
+        let padding = (4 - packet.data.len() % 4) % 4;
+        let len = PACKET_BLOCK_OVERHEAD + packet.data.len() + padding;
+
+        let micros = packet.timestamp.as_micros();
+        let mut header = [0; 28];
+        header[0..4].copy_from_slice(&ENHANCED_PACKET_BLOCK.to_le_bytes());
+        header[4..8].copy_from_slice(&(len as u32).to_le_bytes());
+        // The interface ID is left at 0, the only interface of the section.
+        header[12..16].copy_from_slice(&((micros >> 32) as u32).to_le_bytes());
+        header[16..20].copy_from_slice(&(micros as u32).to_le_bytes());
+        header[20..24].copy_from_slice(&(packet.data.len() as u32).to_le_bytes());
+        header[24..28].copy_from_slice(&(packet.data.len() as u32).to_le_bytes());
+
+        let flags: u32 = match packet.direction {
+            Direction::Rx => 1,
+            Direction::Tx => 2,
+        };
+        let mut trailer = [0; 16];
+        trailer[0..2].copy_from_slice(&OPT_EPB_FLAGS.to_le_bytes());
+        trailer[2..4].copy_from_slice(&4u16.to_le_bytes());
+        trailer[4..8].copy_from_slice(&flags.to_le_bytes());
+        // End of options is all zeros.
+        trailer[12..16].copy_from_slice(&(len as u32).to_le_bytes());
+
+        self.state.lock(|s| {
+            let mut state = s.get();
+            let link_type = *state.link_type.get_or_insert(packet.link_type);
+            // Other captures wait for the lock and the reader only frees space, so the block can't be
+            // cut short once there's room for it.
+            let written = link_type == packet.link_type
+                && self.pipe.free_capacity() >= len
+                && self.write(&header)
+                && self.write(packet.data)
+                && self.write(&[0; 3][..padding])
+                && self.write(&trailer);
+            if !written {
+                state.dropped = state.dropped.wrapping_add(1);
+            }
+            s.set(state);
+        });
+    }
+}
+
+/// Total length of the block starting with `header`.
+fn block_len(header: &[u8; 8]) -> usize {
+    u32::from_le_bytes(unwrap!(header[4..8].try_into())) as usize
+}
+
+/// Section Header and Interface Description blocks starting a stream of packets of `link_type`.
+fn stream_header(link_type: LinkType) -> ([u8; 28], [u8; 20]) {
+    let mut section = [0; 28];
+    section[0..4].copy_from_slice(&SECTION_HEADER_BLOCK.to_le_bytes());
+    section[4..8].copy_from_slice(&28u32.to_le_bytes());
+    section[8..12].copy_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+    // Version 1.0.
+    section[12..14].copy_from_slice(&1u16.to_le_bytes());
+    // Unknown section length.
+    section[16..24].copy_from_slice(&(-1i64).to_le_bytes());
+    section[24..28].copy_from_slice(&28u32.to_le_bytes());
+
+    // Timestamps are in microseconds, the default resolution, and the snapshot length is unlimited.
+    let mut interface = [0; 20];
+    interface[0..4].copy_from_slice(&INTERFACE_DESCRIPTION_BLOCK.to_le_bytes());
+    interface[4..8].copy_from_slice(&20u32.to_le_bytes());
+    interface[8..10].copy_from_slice(&link_type.to_pcap().to_le_bytes());
+    interface[16..20].copy_from_slice(&20u32.to_le_bytes());
+
+    (section, interface)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use embassy_futures::poll_once;
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    use super::*;
+
+    /// Writer collecting a stream in memory.
+    #[derive(Default)]
+    struct Output(Vec<u8>);
+
+    impl embedded_io_async::ErrorType for Output {
+        type Error = Infallible;
+    }
+
+    impl embedded_io_async::Write for Output {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.0.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn packet(direction: Direction, data: &[u8]) -> Packet<'_> {
+        Packet {
+            link_type: LinkType::Ethernet,
+            direction,
+            timestamp: Instant::from_micros(0x1_0000_0002),
+            data,
+        }
+    }
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(unwrap!(buf[pos..pos + 4].try_into()))
+    }
+
+    /// Write the blocks captured so far to a stream.
+    fn stream<const N: usize>(pipe: &PcapPipe<NoopRawMutex, N>) -> Vec<u8> {
+        let mut output = Output::default();
+        assert!(poll_once(pipe.write_to(&mut output)).is_pending());
+        output.0
+    }
+
+    #[test]
+    fn encodes_blocks() {
+        let pipe = PcapPipe::<NoopRawMutex, 512>::new();
+        pipe.capture(&packet(Direction::Rx, &[1, 2, 3, 4, 5]));
+        pipe.capture(&packet(Direction::Tx, &[6, 7, 8, 9]));
+        let stream = stream(&pipe);
+
+        let (section, interface) = stream_header(LinkType::Ethernet);
+        assert_eq!(&stream[..28], &section);
+        assert_eq!(&stream[28..48], &interface);
+        assert_eq!(u32_at(&stream, 28 + 8) & 0xffff, 1);
+
+        // The first packet is padded to 8 bytes.
+        let block = &stream[48..48 + 52];
+        assert_eq!(u32_at(block, 0), ENHANCED_PACKET_BLOCK);
+        assert_eq!(u32_at(block, 4), 52);
+        assert_eq!(u32_at(block, 48), 52);
+        assert_eq!((u32_at(block, 12), u32_at(block, 16)), (1, 2));
+        assert_eq!((u32_at(block, 20), u32_at(block, 24)), (5, 5));
+        assert_eq!(&block[28..36], &[1, 2, 3, 4, 5, 0, 0, 0]);
+        assert_eq!(&block[36..48], &[2, 0, 4, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+        let block = &stream[100..];
+        assert_eq!(block.len(), 48);
+        assert_eq!(u32_at(block, 4), 48);
+        assert_eq!(&block[28..32], &[6, 7, 8, 9]);
+        assert_eq!(u32_at(block, 36), 2);
+    }
+
+    #[test]
+    fn drops_packets_that_dont_fit() {
+        let pipe = PcapPipe::<NoopRawMutex, 128>::new();
+        // Two blocks of 60 bytes fit, not a third one.
+        for _ in 0..3 {
+            pipe.capture(&packet(Direction::Rx, &[0xaa; 16]));
+        }
+        // Packets of another link type are dropped too.
+        pipe.capture(&Packet {
+            link_type: LinkType::Ip,
+            ..packet(Direction::Rx, &[0; 4])
+        });
+        assert_eq!(pipe.dropped(), 2);
+
+        let stream = stream(&pipe);
+        assert_eq!(stream.len(), 48 + 2 * 60);
+        assert_eq!(u32_at(&stream, 48 + 60 + 4), 60);
+
+        // There's room again once the blocks were written.
+        pipe.capture(&packet(Direction::Rx, &[0xaa; 16]));
+        assert_eq!(pipe.dropped(), 2);
+    }
+}
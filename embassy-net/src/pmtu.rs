@@ -0,0 +1,343 @@
+//! Path MTUs learned from ICMP errors.
+//!
+//! smoltcp doesn't do path MTU discovery, so the ICMPv4 "fragmentation needed" and ICMPv6
+//! "packet too big" errors routers send back are captured from the driver before smoltcp sees
+//! them. The MTU they report is remembered for the destination of the packet that was too big,
+//! for 10 minutes as recommended by RFC 1191 and RFC 8201.
+
+use embassy_time::{Duration, Instant};
+use smoltcp::phy::Medium;
+#[cfg(feature = "proto-ipv4")]
+use smoltcp::wire::{Icmpv4DstUnreachable, Icmpv4Message, Icmpv4Packet, Ipv4Packet};
+#[cfg(feature = "proto-ipv6")]
+use smoltcp::wire::{Icmpv6Message, Icmpv6Packet, Ipv6Packet};
+use smoltcp::wire::{IpAddress, IpProtocol};
+
+/// Number of destinations whose path MTU is remembered on each interface.
+const CACHE_LEN: usize = 4;
+/// How long a learned path MTU is used, before trying the interface MTU again.
+const EXPIRATION: Duration = Duration::from_secs(10 * 60);
+/// Smallest path MTU accepted for IPv4, smaller ones are ignored (RFC 1191).
+#[cfg(feature = "proto-ipv4")]
+const IPV4_MIN_MTU: u16 = 68;
+/// Smallest path MTU accepted for IPv6, smaller ones are ignored (RFC 8201).
+#[cfg(feature = "proto-ipv6")]
+const IPV6_MIN_MTU: u16 = 1280;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    destination: IpAddress,
+    mtu: u16,
+    expires_at: Instant,
+}
+
+pub(crate) struct PathMtus {
+    entries: [Option<Entry>; CACHE_LEN],
+}
+
+impl PathMtus {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: [None; CACHE_LEN],
+        }
+    }
+
+    /// Get the path MTU learned for `destination`, if any.
+    pub(crate) fn get(&self, destination: IpAddress) -> Option<usize> {
+        let now = Instant::now();
+        self.entries
+            .iter()
+            .flatten()
+            .find(|e| e.destination == destination && e.expires_at > now)
+            .map(|e| usize::from(e.mtu))
+    }
+
+    /// Forget all learned path MTUs.
+    pub(crate) fn clear(&mut self) {
+        self.entries = [None; CACHE_LEN];
+    }
+
+    /// Learn the path MTU reported by `frame`, if it's an ICMP error saying a packet was too big.
+    #[cfg_attr(
+        not(any(feature = "medium-ethernet", feature = "medium-ip")),
+        allow(unreachable_code, unused_variables)
+    )]
+    pub(crate) fn capture(&mut self, medium: Medium, frame: &[u8]) {
+        let packet: &[u8] = match medium {
+            #[cfg(feature = "medium-ethernet")]
+            Medium::Ethernet => {
+                let Ok(frame) = smoltcp::wire::EthernetFrame::new_checked(frame) else {
+                    return;
+                };
+                match frame.ethertype() {
+                    smoltcp::wire::EthernetProtocol::Ipv4 | smoltcp::wire::EthernetProtocol::Ipv6 => {}
+                    _ => return,
+                }
+                &frame.into_inner()[smoltcp::wire::EthernetFrame::<&[u8]>::header_len()..]
+            }
+            #[cfg(feature = "medium-ip")]
+            Medium::Ip => frame,
+            #[allow(unreachable_patterns)]
+            _ => return,
+        };
+
+        let learned = match packet.first().map(|b| b >> 4) {
+            #[cfg(feature = "proto-ipv4")]
+            Some(4) => too_big_v4(packet),
+            #[cfg(feature = "proto-ipv6")]
+            Some(6) => too_big_v6(packet),
+            _ => None,
+        };
+        if let Some((destination, mtu)) = learned {
+            self.insert(destination, mtu);
+        }
+    }
+
+    fn insert(&mut self, destination: IpAddress, mtu: u16) {
+        let now = Instant::now();
+        let expires_at = now + EXPIRATION;
+
+        if let Some(entry) = self.entries.iter_mut().flatten().find(|e| e.destination == destination) {
+            // Errors for packets sent before the previous one was handled report a larger MTU.
+            if entry.expires_at <= now || mtu < entry.mtu {
+                debug!("path MTU to {:?}: {}", destination, mtu);
+                entry.mtu = mtu;
+                entry.expires_at = expires_at;
+            }
+            return;
+        }
+
+        debug!("path MTU to {:?}: {}", destination, mtu);
+        // Use a free slot, or replace the entry expiring first.
+        let slot = unwrap!(self
+            .entries
+            .iter_mut()
+            .min_by_key(|e| e.map(|e| e.expires_at.as_ticks()).unwrap_or(0)));
+        *slot = Some(Entry {
+            destination,
+            mtu,
+            expires_at,
+        });
+    }
+}
+
+/// Parse an ICMPv4 "fragmentation needed" error, returning the destination and next-hop MTU.
+#[cfg(feature = "proto-ipv4")]
+fn too_big_v4(packet: &[u8]) -> Option<(IpAddress, u16)> {
+    let packet = Ipv4Packet::new_checked(packet).ok()?;
+    if packet.next_header() != IpProtocol::Icmp || packet.more_frags() || packet.frag_offset() != 0 {
+        return None;
+    }
+    let icmp = Icmpv4Packet::new_checked(packet.payload()).ok()?;
+    if icmp.msg_type() != Icmpv4Message::DstUnreachable
+        || icmp.msg_code() != u8::from(Icmpv4DstUnreachable::FragRequired)
+        || !icmp.verify_checksum()
+    {
+        return None;
+    }
+    // The next-hop MTU is in the second half of the otherwise unused header word (RFC 1191).
+    // Routers predating it send 0, their errors are ignored.
+    let header = icmp.into_inner();
+    let mtu = u16::from_be_bytes([header[6], header[7]]);
+    let original = &header[8..];
+    if mtu < IPV4_MIN_MTU || original.len() < 20 {
+        return None;
+    }
+    // The original packet is truncated, so only its fixed header is read.
+    let original = Ipv4Packet::new_unchecked(original);
+    Some((original.dst_addr().into(), mtu))
+}
+
+/// Parse an ICMPv6 "packet too big" error, returning the destination and MTU.
+#[cfg(feature = "proto-ipv6")]
+fn too_big_v6(packet: &[u8]) -> Option<(IpAddress, u16)> {
+    let packet = Ipv6Packet::new_checked(packet).ok()?;
+    // Extension headers aren't walked, errors using them are ignored.
+    if packet.next_header() != IpProtocol::Icmpv6 {
+        return None;
+    }
+    let icmp = Icmpv6Packet::new_checked(packet.payload()).ok()?;
+    if icmp.msg_type() != Icmpv6Message::PktTooBig || !icmp.verify_checksum(&packet.src_addr(), &packet.dst_addr()) {
+        return None;
+    }
+    let mtu = u16::try_from(icmp.pkt_too_big_mtu()).unwrap_or(u16::MAX);
+    let original = icmp.payload();
+    if mtu < IPV6_MIN_MTU || original.len() < 40 {
+        return None;
+    }
+    // The original packet is truncated, so only its fixed header is read.
+    let original = Ipv6Packet::new_unchecked(original);
+    Some((original.dst_addr().into(), mtu))
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::vec::Vec;
+
+    use smoltcp::phy::ChecksumCapabilities;
+    use smoltcp::wire::{EthernetAddress, EthernetFrame, EthernetProtocol, EthernetRepr, Ipv4Address, Ipv4Repr};
+
+    use super::*;
+    use crate::test_util::addr;
+
+    /// Address of the router sending the errors.
+    const ROUTER: Ipv4Address = addr(254);
+
+    /// Ethernet frame carrying `packet`.
+    fn ethernet(ethertype: EthernetProtocol, packet: &[u8]) -> Vec<u8> {
+        let repr = EthernetRepr {
+            src_addr: EthernetAddress([0x02, 0, 0, 0, 0, 2]),
+            dst_addr: EthernetAddress([0x02, 0, 0, 0, 0, 1]),
+            ethertype,
+        };
+        let mut frame = std::vec![0; repr.buffer_len() + packet.len()];
+        repr.emit(&mut EthernetFrame::new_unchecked(&mut frame[..]));
+        frame[repr.buffer_len()..].copy_from_slice(packet);
+        frame
+    }
+
+    /// ICMPv4 error of type `code` (4 is "fragmentation needed") for a packet sent to `destination`.
+    fn dst_unreachable(destination: Ipv4Address, code: u8, mtu: u16) -> Vec<u8> {
+        let caps = ChecksumCapabilities::default();
+        let original = Ipv4Repr {
+            src_addr: addr(1),
+            dst_addr: destination,
+            next_header: IpProtocol::Udp,
+            payload_len: 1472,
+            hop_limit: 64,
+        };
+        // The header of the original packet and 8 bytes of its payload.
+        let mut icmp = std::vec![0; 8 + original.buffer_len() + 8];
+        icmp[0] = 3;
+        icmp[1] = code;
+        icmp[6..8].copy_from_slice(&mtu.to_be_bytes());
+        original.emit(&mut Ipv4Packet::new_unchecked(&mut icmp[8..]), &caps);
+        Icmpv4Packet::new_unchecked(&mut icmp[..]).fill_checksum();
+
+        let ip = Ipv4Repr {
+            src_addr: ROUTER,
+            dst_addr: addr(1),
+            next_header: IpProtocol::Icmp,
+            payload_len: icmp.len(),
+            hop_limit: 64,
+        };
+        let mut packet = std::vec![0; ip.buffer_len() + icmp.len()];
+        ip.emit(&mut Ipv4Packet::new_unchecked(&mut packet[..]), &caps);
+        packet[ip.buffer_len()..].copy_from_slice(&icmp);
+        ethernet(EthernetProtocol::Ipv4, &packet)
+    }
+
+    fn frag_needed(destination: Ipv4Address, mtu: u16) -> Vec<u8> {
+        dst_unreachable(destination, 4, mtu)
+    }
+
+    #[test]
+    fn learns_path_mtus() {
+        let mut pmtu = PathMtus::new();
+        assert_eq!(pmtu.get(addr(9).into()), None);
+
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(9), 1400));
+        assert_eq!(pmtu.get(addr(9).into()), Some(1400));
+        assert_eq!(pmtu.get(addr(10).into()), None);
+
+        // Errors for packets sent before the lower MTU was learned don't raise it.
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(9), 1450));
+        assert_eq!(pmtu.get(addr(9).into()), Some(1400));
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(9), 1300));
+        assert_eq!(pmtu.get(addr(9).into()), Some(1300));
+
+        pmtu.clear();
+        assert_eq!(pmtu.get(addr(9).into()), None);
+    }
+
+    #[test]
+    fn ignores_other_packets() {
+        let mut pmtu = PathMtus::new();
+
+        // Other errors, MTUs that are too small or missing, and bad checksums.
+        pmtu.capture(Medium::Ethernet, &dst_unreachable(addr(9), 3, 1400));
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(9), 67));
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(9), 0));
+        let mut frame = frag_needed(addr(9), 1400);
+        frame[14 + 20 + 7] ^= 1;
+        pmtu.capture(Medium::Ethernet, &frame);
+        // Truncated errors.
+        let frame = frag_needed(addr(9), 1400);
+        let mut truncated = frame[..14 + 20 + 8 + 19].to_vec();
+        let mut packet = Ipv4Packet::new_unchecked(&mut truncated[14..]);
+        packet.set_total_len(20 + 8 + 19);
+        packet.fill_checksum();
+        pmtu.capture(Medium::Ethernet, &truncated);
+        // Errors that aren't IP packets.
+        pmtu.capture(Medium::Ethernet, &ethernet(EthernetProtocol::Arp, &frame[14..]));
+
+        assert_eq!(pmtu.get(addr(9).into()), None);
+    }
+
+    #[test]
+    fn forgets_path_mtus() {
+        let mut pmtu = PathMtus::new();
+        for n in 10..10 + CACHE_LEN as u8 {
+            pmtu.capture(Medium::Ethernet, &frag_needed(addr(n), 1400));
+        }
+        // The entry expiring first is replaced.
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(20), 1300));
+        assert_eq!(pmtu.get(addr(10).into()), None);
+        assert_eq!(pmtu.get(addr(11).into()), Some(1400));
+        assert_eq!(pmtu.get(addr(20).into()), Some(1300));
+
+        // Expired entries aren't used, and are replaced even by larger MTUs.
+        for entry in pmtu.entries.iter_mut().flatten() {
+            entry.expires_at = Instant::now();
+        }
+        assert_eq!(pmtu.get(addr(20).into()), None);
+        pmtu.capture(Medium::Ethernet, &frag_needed(addr(20), 1400));
+        assert_eq!(pmtu.get(addr(20).into()), Some(1400));
+    }
+
+    #[cfg(feature = "proto-ipv6")]
+    #[test]
+    fn learns_ipv6_path_mtus() {
+        use smoltcp::wire::{Icmpv6Repr, Ipv6Address, Ipv6Repr};
+
+        let caps = ChecksumCapabilities::default();
+        let local = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 1);
+        let router = Ipv6Address::new(0xfd00, 0, 0, 0, 0, 0, 0, 0xfe);
+        let destination = Ipv6Address::new(0xfd00, 0, 0, 1, 0, 0, 0, 9);
+        let too_big = |mtu: u32| {
+            let icmp = Icmpv6Repr::PktTooBig {
+                mtu,
+                header: Ipv6Repr {
+                    src_addr: local,
+                    dst_addr: destination,
+                    next_header: IpProtocol::Udp,
+                    payload_len: 1452,
+                    hop_limit: 64,
+                },
+                data: &[0; 8],
+            };
+            let ip = Ipv6Repr {
+                src_addr: router,
+                dst_addr: local,
+                next_header: IpProtocol::Icmpv6,
+                payload_len: icmp.buffer_len(),
+                hop_limit: 64,
+            };
+            let mut packet = std::vec![0; ip.buffer_len() + icmp.buffer_len()];
+            ip.emit(&mut Ipv6Packet::new_unchecked(&mut packet[..]));
+            icmp.emit(
+                &router,
+                &local,
+                &mut Icmpv6Packet::new_unchecked(&mut packet[ip.buffer_len()..]),
+                &caps,
+            );
+            ethernet(EthernetProtocol::Ipv6, &packet)
+        };
+
+        let mut pmtu = PathMtus::new();
+        pmtu.capture(Medium::Ethernet, &too_big(1279));
+        assert_eq!(pmtu.get(destination.into()), None);
+        pmtu.capture(Medium::Ethernet, &too_big(1280));
+        assert_eq!(pmtu.get(destination.into()), Some(1280));
+    }
+}
@@ -0,0 +1,45 @@
+//! Pool of buffers for the sockets of `embedded-nal-async` clients.
+
+use core::cell::{Cell, UnsafeCell};
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+pub(crate) struct Pool<T, const N: usize> {
+    used: [Cell<bool>; N],
+    data: [UnsafeCell<MaybeUninit<T>>; N],
+}
+
+impl<T, const N: usize> Pool<T, N> {
+    const VALUE: Cell<bool> = Cell::new(false);
+    const UNINIT: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
+
+    pub(crate) const fn new() -> Self {
+        Self {
+            used: [Self::VALUE; N],
+            data: [Self::UNINIT; N],
+        }
+    }
+}
+
+impl<T, const N: usize> Pool<T, N> {
+    pub(crate) fn alloc(&self) -> Option<NonNull<T>> {
+        for n in 0..N {
+            // this can't race because Pool is not Sync.
+            if !self.used[n].get() {
+                self.used[n].set(true);
+                let p = self.data[n].get() as *mut T;
+                return Some(unsafe { NonNull::new_unchecked(p) });
+            }
+        }
+        None
+    }
+
+    /// safety: p must be a pointer obtained from self.alloc that hasn't been freed yet.
+    pub(crate) unsafe fn free(&self, p: NonNull<T>) {
+        let origin = self.data.as_ptr() as *mut T;
+        let n = p.as_ptr().offset_from(origin);
+        assert!(n >= 0);
+        assert!((n as usize) < N);
+        self.used[n as usize].set(false);
+    }
+}
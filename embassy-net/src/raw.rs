@@ -5,12 +5,12 @@ use core::mem;
 use core::task::{Context, Poll};
 
 use embassy_net_driver::Driver;
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 use smoltcp::socket::raw;
 pub use smoltcp::socket::raw::PacketMetadata;
 pub use smoltcp::wire::{IpProtocol, IpVersion};
 
-use crate::Stack;
+use crate::{InterfaceId, SocketHandle, SocketSetFull, Stack};
 
 /// Error returned by [`RawSocket::recv`] and [`RawSocket::send`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -42,22 +42,36 @@ impl<'a> RawSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(raw::Socket::new(
-                ip_version,
-                ip_protocol,
-                raw::PacketBuffer::new(rx_meta, rx_buffer),
-                raw::PacketBuffer::new(tx_meta, tx_buffer),
-            ))
+            i.add_socket(
+                InterfaceId::PRIMARY,
+                raw::Socket::new(
+                    ip_version,
+                    ip_protocol,
+                    raw::PacketBuffer::new(rx_meta, rx_buffer),
+                    raw::PacketBuffer::new(tx_meta, tx_buffer),
+                ),
+            )
         });
 
         Self { stack, handle }
     }
 
+    /// Bind the socket to a network interface of the stack.
+    ///
+    /// Sockets are created on the primary interface, and only send and receive packets through
+    /// the interface they're on.
+    ///
+    /// Returns an error, leaving the socket where it is, if the interface has no room for it.
+    pub fn bind_to_interface(&mut self, interface: InterfaceId) -> Result<(), SocketSetFull> {
+        self.handle = self.stack.with_mut(|i| i.move_socket(self.handle, interface))?;
+        Ok(())
+    }
+
     fn with_mut<R>(&self, f: impl FnOnce(&mut raw::Socket, &mut Interface) -> R) -> R {
         self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<raw::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
+            let (socket, iface) = i.socket_mut::<raw::Socket>(self.handle);
+            let res = f(socket, iface);
+            i.wake(self.handle);
             res
         })
     }
@@ -181,7 +195,7 @@ impl<'a> RawSocket<'a> {
 
 impl Drop for RawSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle));
     }
 }
 
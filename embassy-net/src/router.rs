@@ -0,0 +1,129 @@
+//! Routing of requests by method and path, shared by the [HTTP](crate::http) and [CoAP](crate::coap)
+//! servers.
+//!
+//! A [`Router`] holds a list of [`Route`]s, each with a method, a path pattern and a handler. Patterns
+//! are paths whose segments can be parameters, such as `/leds/{id}`, which match any non-empty segment.
+//! A trailing `/*` matches the rest of the path, possibly empty, and is captured as the `*` parameter.
+//!
+//! The servers implement their `Handler` trait for routers of their methods and handlers, dispatching
+//! requests to the first route matching their method and path.
+
+use heapless::Vec;
+
+/// Maximum number of parameters captured from the path of a request.
+pub const MAX_PARAMS: usize = 4;
+
+/// Parameters captured from a path, by name.
+pub(crate) type Params<'r> = Vec<(&'static str, &'r str), MAX_PARAMS>;
+
+/// Route of a [`Router`], with a method of type `M` and a handler of type `H`.
+#[derive(Debug, Clone, Copy)]
+pub struct Route<M, H> {
+    pub(crate) method: M,
+    pub(crate) pattern: &'static str,
+    pub(crate) handler: H,
+}
+
+impl<M, H> Route<M, H> {
+    /// Create a new `Route`.
+    pub const fn new(method: M, pattern: &'static str, handler: H) -> Self {
+        Self {
+            method,
+            pattern,
+            handler,
+        }
+    }
+}
+
+/// Handler dispatching requests to the first [`Route`] matching their method and path.
+pub struct Router<'a, M, H> {
+    pub(crate) routes: &'a [Route<M, H>],
+}
+
+impl<'a, M, H> Router<'a, M, H> {
+    /// Create a new `Router`.
+    pub const fn new(routes: &'a [Route<M, H>]) -> Self {
+        Self { routes }
+    }
+
+    /// Get the routes matching `path`, in order, with the parameters they capture.
+    pub(crate) fn matching<'r>(&self, path: &'r str) -> impl Iterator<Item = (&'a Route<M, H>, Params<'r>)> {
+        self.routes
+            .iter()
+            .filter_map(move |route| match_path(route.pattern, path).map(|params| (route, params)))
+    }
+}
+
+/// Match `path` against a route `pattern`, and return the parameters it captures.
+fn match_path<'r>(pattern: &'static str, path: &'r str) -> Option<Params<'r>> {
+    let mut params = Vec::new();
+    let mut segments = path.strip_prefix('/')?.split('/');
+    let mut rest = path;
+    for part in pattern.strip_prefix('/')?.split('/') {
+        if part == "*" {
+            params.push(("*", rest.strip_prefix('/').unwrap_or(rest))).ok()?;
+            return Some(params);
+        }
+        let segment = segments.next()?;
+        rest = &rest[1 + segment.len()..];
+        match part.strip_prefix('{').and_then(|p| p.strip_suffix('}')) {
+            Some(name) if !segment.is_empty() => params.push((name, segment)).ok()?,
+            Some(_) => return None,
+            None if part == segment => {}
+            None => return None,
+        }
+    }
+    segments.next().is_none().then_some(params)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec;
+
+    use super::*;
+
+    fn params<'r>(pattern: &'static str, path: &'r str) -> Option<std::vec::Vec<(&'static str, &'r str)>> {
+        match_path(pattern, path).map(|p| p.to_vec())
+    }
+
+    #[test]
+    fn matches_literal_paths() {
+        assert_eq!(params("/", "/"), Some(vec![]));
+        assert_eq!(params("/status", "/status"), Some(vec![]));
+        assert_eq!(params("/status", "/status/"), None);
+        assert_eq!(params("/status", "/stat"), None);
+        assert_eq!(params("/a/b", "/a"), None);
+        assert_eq!(params("/status", "status"), None);
+    }
+
+    #[test]
+    fn captures_parameters() {
+        assert_eq!(params("/leds/{id}", "/leds/3"), Some(vec![("id", "3")]));
+        assert_eq!(params("/{a}/x/{b}", "/1/x/2"), Some(vec![("a", "1"), ("b", "2")]));
+        // Parameters match non-empty segments only.
+        assert_eq!(params("/leds/{id}", "/leds/"), None);
+        assert_eq!(params("/{a}/{b}/{c}/{d}/{e}", "/1/2/3/4/5"), None);
+    }
+
+    #[test]
+    fn matches_rest_of_path() {
+        assert_eq!(params("/files/*", "/files/a/b.txt"), Some(vec![("*", "a/b.txt")]));
+        assert_eq!(params("/files/*", "/files"), Some(vec![("*", "")]));
+        assert_eq!(params("/files/*", "/files/"), Some(vec![("*", "")]));
+        assert_eq!(params("/*", "/"), Some(vec![("*", "")]));
+        assert_eq!(params("/files/*", "/other"), None);
+    }
+
+    #[test]
+    fn router_yields_matching_routes_in_order() {
+        let routes = [
+            Route::new(1, "/leds/{id}", 'a'),
+            Route::new(2, "/status", 'b'),
+            Route::new(3, "/leds/*", 'c'),
+        ];
+        let router = Router::new(&routes);
+        let handlers: std::vec::Vec<_> = router.matching("/leds/1").map(|(r, _)| r.handler).collect();
+        assert_eq!(handlers, ['a', 'c']);
+        assert_eq!(router.matching("/nothing").count(), 0);
+    }
+}
@@ -0,0 +1,631 @@
+//! IPv6 stateless address autoconfiguration (SLAAC).
+//!
+//! Router Solicitations are sent and Router Advertisements are received through a raw ICMPv6
+//! socket. The address is formed from the first autonomous /64 prefix advertised, the default
+//! gateway is the advertising router, and DNS servers come from the RDNSS option (RFC 8106).
+
+use core::mem::MaybeUninit;
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+use smoltcp::iface::{Interface, SocketHandle, SocketSet};
+use smoltcp::socket::raw;
+use smoltcp::wire::{
+    HardwareAddress, Icmpv6Message, Icmpv6Packet, IpAddress, IpProtocol, IpVersion, Ipv6Address, Ipv6Cidr, Ipv6Packet,
+    Ipv6Repr,
+};
+
+use crate::StaticConfigV6;
+
+const RX_META_LEN: usize = 4;
+const RX_LEN: usize = 1280;
+const TX_META_LEN: usize = 1;
+const TX_LEN: usize = 64;
+
+const ALL_ROUTERS: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+
+/// Interval between the first Router Solicitations, doubled after each one.
+const RS_INTERVAL: Duration = Duration::from_secs(4);
+/// Maximum interval between Router Solicitations (RFC 7559).
+const RS_MAX_INTERVAL: Duration = Duration::from_secs(3600);
+/// Minimum valid lifetime an unauthenticated Router Advertisement can set for an existing address.
+const MIN_VALID_LIFETIME: Duration = Duration::from_secs(2 * 60 * 60);
+
+const OPT_SOURCE_LINK_LAYER_ADDR: u8 = 1;
+const OPT_PREFIX_INFORMATION: u8 = 3;
+const OPT_RDNSS: u8 = 25;
+const PREFIX_FLAG_AUTONOMOUS: u8 = 0x40;
+
+/// Memory for the SLAAC raw socket.
+pub(crate) struct SlaacResources {
+    rx_meta: MaybeUninit<[raw::PacketMetadata; RX_META_LEN]>,
+    rx: MaybeUninit<[u8; RX_LEN]>,
+    tx_meta: MaybeUninit<[raw::PacketMetadata; TX_META_LEN]>,
+    tx: MaybeUninit<[u8; TX_LEN]>,
+}
+
+impl SlaacResources {
+    pub(crate) const fn new() -> Self {
+        Self {
+            rx_meta: MaybeUninit::uninit(),
+            rx: MaybeUninit::uninit(),
+            tx_meta: MaybeUninit::uninit(),
+            tx: MaybeUninit::uninit(),
+        }
+    }
+}
+
+/// Configuration change.
+pub(crate) enum Event {
+    Configured(StaticConfigV6),
+    Deconfigured,
+}
+
+/// Expiration time, `None` meaning infinite.
+type Expiry = Option<Instant>;
+
+pub(crate) struct Slaac {
+    pub(crate) socket: SocketHandle,
+    iid: [u8; 8],
+    /// Ethernet address, sent in Router Solicitations.
+    lladdr: Option<[u8; 6]>,
+    /// When to send the next Router Solicitation, `None` once a router has been found.
+    next_rs: Option<Instant>,
+    rs_interval: Duration,
+    address: Option<(Ipv6Cidr, Expiry)>,
+    gateway: Option<(Ipv6Address, Instant)>,
+    dns_servers: Vec<Ipv6Address, 3>,
+    dns_expiry: Expiry,
+    /// Last configuration reported with an [`Event`].
+    reported: Option<StaticConfigV6>,
+}
+
+impl Slaac {
+    pub(crate) fn new(
+        sockets: &mut SocketSet<'static>,
+        resources: &'static mut SlaacResources,
+        hardware_address: HardwareAddress,
+        random_seed: u64,
+    ) -> Self {
+        let socket = sockets.add(raw::Socket::new(
+            IpVersion::Ipv6,
+            IpProtocol::Icmpv6,
+            raw::PacketBuffer::new(
+                &mut resources.rx_meta.write([raw::PacketMetadata::EMPTY; RX_META_LEN])[..],
+                &mut resources.rx.write([0; RX_LEN])[..],
+            ),
+            raw::PacketBuffer::new(
+                &mut resources.tx_meta.write([raw::PacketMetadata::EMPTY; TX_META_LEN])[..],
+                &mut resources.tx.write([0; TX_LEN])[..],
+            ),
+        ));
+
+        let (iid, lladdr) = match hardware_address {
+            #[cfg(feature = "medium-ethernet")]
+            HardwareAddress::Ethernet(addr) => {
+                // Modified EUI-64, RFC 4291 appendix A.
+                let m = addr.0;
+                let iid = [m[0] ^ 0x02, m[1], m[2], 0xff, 0xfe, m[3], m[4], m[5]];
+                (iid, Some(m))
+            }
+            #[cfg(feature = "medium-ieee802154")]
+            HardwareAddress::Ieee802154(smoltcp::wire::Ieee802154Address::Extended(mut iid)) => {
+                iid[0] ^= 0x02;
+                (iid, None)
+            }
+            #[allow(unreachable_patterns)]
+            _ => {
+                // No stable link-layer identifier, use a random one with the universal/local bit cleared.
+                let mut iid = random_seed.wrapping_mul(0x9e37_79b9_7f4a_7c15).to_be_bytes();
+                iid[0] &= !0x02;
+                (iid, None)
+            }
+        };
+
+        Self {
+            socket,
+            iid,
+            lladdr,
+            next_rs: Some(Instant::now()),
+            rs_interval: RS_INTERVAL,
+            address: None,
+            gateway: None,
+            dns_servers: Vec::new(),
+            dns_expiry: None,
+            reported: None,
+        }
+    }
+
+    /// Link-local address of the interface.
+    pub(crate) fn link_local(&self) -> Ipv6Cidr {
+        Ipv6Cidr::new(
+            self.address_with_prefix(&Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0)),
+            64,
+        )
+    }
+
+    fn address_with_prefix(&self, prefix: &Ipv6Address) -> Ipv6Address {
+        let mut octets = prefix.octets();
+        octets[8..].copy_from_slice(&self.iid);
+        Ipv6Address::from(octets)
+    }
+
+    /// Forget the current configuration, and start soliciting routers again.
+    pub(crate) fn reset(&mut self) {
+        self.next_rs = Some(Instant::now());
+        self.rs_interval = RS_INTERVAL;
+        self.address = None;
+        self.gateway = None;
+        self.dns_servers.clear();
+        self.dns_expiry = None;
+    }
+
+    /// Process received Router Advertisements, expire stale information and send Router Solicitations.
+    ///
+    /// Returns an event if the configuration changed since the last one.
+    pub(crate) fn poll(&mut self, sockets: &mut SocketSet<'static>, iface: &Interface, link_up: bool) -> Option<Event> {
+        let socket = sockets.get_mut::<raw::Socket>(self.socket);
+        let now = Instant::now();
+
+        while let Ok(packet) = socket.recv() {
+            if let Some((src, message)) = parse_router_advert(packet) {
+                self.process_router_advert(src, message, now);
+            }
+        }
+
+        if self.address.is_some_and(|(_, expiry)| expiry.is_some_and(|t| t <= now)) {
+            debug!("SLAAC: address expired");
+            self.reset();
+        }
+        if self.gateway.is_some_and(|(_, expiry)| expiry <= now) {
+            self.gateway = None;
+        }
+        if self.dns_expiry.is_some_and(|t| t <= now) {
+            self.dns_servers.clear();
+            self.dns_expiry = None;
+        }
+
+        if !link_up {
+            // Routers will be solicited again once the link is up.
+            self.reset();
+            self.next_rs = None;
+        } else if let Some(next_rs) = self.next_rs {
+            if next_rs <= now && socket.can_send() {
+                self.send_router_solicit(socket, iface);
+                self.next_rs = Some(now + self.rs_interval);
+                self.rs_interval = (self.rs_interval * 2).min(RS_MAX_INTERVAL);
+            }
+        }
+
+        let config = self.address.map(|(address, _)| StaticConfigV6 {
+            address,
+            gateway: self.gateway.map(|(gateway, _)| gateway),
+            dns_servers: self.dns_servers.clone(),
+        });
+        if config == self.reported {
+            return None;
+        }
+        self.reported = config.clone();
+        Some(match config {
+            Some(config) => Event::Configured(config),
+            None => Event::Deconfigured,
+        })
+    }
+
+    /// When `poll` has to be called next, if no packets are received in the meantime.
+    pub(crate) fn poll_at(&self) -> Option<Instant> {
+        [
+            self.next_rs,
+            self.address.and_then(|(_, expiry)| expiry),
+            self.gateway.map(|(_, expiry)| expiry),
+            self.dns_expiry,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    fn send_router_solicit(&self, socket: &mut raw::Socket, iface: &Interface) {
+        // Solicit from the link-local address if there was room for it in the interface, or from the
+        // unspecified address otherwise, in which case the link-layer address option must be omitted.
+        let link_local = self.link_local().address();
+        let (src_addr, lladdr) = if iface.has_ip_addr(IpAddress::Ipv6(link_local)) {
+            (link_local, self.lladdr)
+        } else {
+            (Ipv6Address::UNSPECIFIED, None)
+        };
+
+        // RFC 4861 section 4.1.
+        let payload_len = if lladdr.is_some() { 16 } else { 8 };
+        let ip_repr = Ipv6Repr {
+            src_addr,
+            dst_addr: ALL_ROUTERS,
+            next_header: IpProtocol::Icmpv6,
+            payload_len,
+            hop_limit: 255,
+        };
+
+        match socket.send(ip_repr.buffer_len() + payload_len) {
+            Ok(buf) => {
+                trace!("SLAAC: sending router solicitation");
+                let mut packet = Ipv6Packet::new_unchecked(buf);
+                ip_repr.emit(&mut packet);
+                let mut message = Icmpv6Packet::new_unchecked(packet.payload_mut());
+                message.set_msg_type(Icmpv6Message::RouterSolicit);
+                message.set_msg_code(0);
+                message.clear_reserved();
+                if let Some(lladdr) = lladdr {
+                    let option = &mut message.payload_mut()[..8];
+                    option[0] = OPT_SOURCE_LINK_LAYER_ADDR;
+                    option[1] = 1;
+                    option[2..].copy_from_slice(&lladdr);
+                }
+                message.fill_checksum(&src_addr, &ALL_ROUTERS);
+            }
+            Err(_) => warn!("SLAAC: failed to send router solicitation"),
+        }
+    }
+
+    fn process_router_advert(&mut self, router: Ipv6Address, message: &[u8], now: Instant) {
+        trace!("SLAAC: received router advertisement from {:?}", router);
+
+        // A router was found, stop soliciting.
+        self.next_rs = None;
+
+        let router_lifetime = u16::from_be_bytes([message[6], message[7]]);
+        if router_lifetime != 0 {
+            self.gateway = Some((router, now + Duration::from_secs(router_lifetime as u64)));
+        } else if self.gateway.is_some_and(|(gateway, _)| gateway == router) {
+            self.gateway = None;
+        }
+
+        let mut options = &message[16..];
+        while options.len() >= 8 {
+            let len = options[1] as usize * 8;
+            if len == 0 || len > options.len() {
+                break;
+            }
+            let (option, rest) = options.split_at(len);
+            options = rest;
+
+            match option[0] {
+                OPT_PREFIX_INFORMATION if len == 32 => self.process_prefix_information(option, now),
+                OPT_RDNSS if len >= 24 => {
+                    let lifetime = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+                    if lifetime == 0 {
+                        self.dns_servers.clear();
+                        self.dns_expiry = None;
+                        continue;
+                    }
+                    self.dns_servers.clear();
+                    for server in option[8..].chunks_exact(16) {
+                        let server: [u8; 16] = unwrap!(server.try_into());
+                        if self.dns_servers.push(Ipv6Address::from(server)).is_err() {
+                            break;
+                        }
+                    }
+                    self.dns_expiry = expiry(now, lifetime);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn process_prefix_information(&mut self, option: &[u8], now: Instant) {
+        let prefix_len = option[2];
+        let flags = option[3];
+        let valid_lifetime = u32::from_be_bytes([option[4], option[5], option[6], option[7]]);
+        let preferred_lifetime = u32::from_be_bytes([option[8], option[9], option[10], option[11]]);
+        let prefix: [u8; 16] = unwrap!(option[16..32].try_into());
+        let prefix = Ipv6Address::from(prefix);
+
+        // RFC 4862 section 5.5.3.
+        if flags & PREFIX_FLAG_AUTONOMOUS == 0
+            || is_link_local(&prefix)
+            || preferred_lifetime > valid_lifetime
+            || prefix_len != 64
+        {
+            return;
+        }
+
+        let address = Ipv6Cidr::new(self.address_with_prefix(&prefix), prefix_len);
+        let valid_until = expiry(now, valid_lifetime);
+
+        match self.address {
+            None if valid_lifetime != 0 => self.address = Some((address, valid_until)),
+            Some((current, current_until)) if current == address => {
+                // Protect against unauthenticated advertisements shortening the lifetime too much.
+                let min_until = now + MIN_VALID_LIFETIME;
+                let updated = if valid_until.is_none_or(|t| t > min_until) || outlives(valid_until, current_until) {
+                    valid_until
+                } else if current_until.is_some_and(|t| t <= min_until) {
+                    current_until
+                } else {
+                    Some(min_until)
+                };
+                self.address = Some((address, updated));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Check that `packet` is a valid Router Advertisement, and return its source and ICMPv6 message.
+fn parse_router_advert(packet: &[u8]) -> Option<(Ipv6Address, &[u8])> {
+    let packet = Ipv6Packet::new_checked(packet).ok()?;
+    let repr = Ipv6Repr::parse(&packet).ok()?;
+    let message = Icmpv6Packet::new_checked(packet.payload()).ok()?;
+
+    // RFC 4861 section 6.1.2.
+    if repr.next_header != IpProtocol::Icmpv6
+        || repr.hop_limit != 255
+        || !is_link_local(&repr.src_addr)
+        || message.msg_type() != Icmpv6Message::RouterAdvert
+        || message.msg_code() != 0
+        || !message.verify_checksum(&repr.src_addr, &repr.dst_addr)
+    {
+        return None;
+    }
+
+    let message = &packet.payload()[..repr.payload_len];
+    if message.len() < 16 {
+        return None;
+    }
+    Some((repr.src_addr, message))
+}
+
+fn is_link_local(addr: &Ipv6Address) -> bool {
+    addr.segments()[0] & 0xffc0 == 0xfe80
+}
+
+fn expiry(now: Instant, lifetime: u32) -> Expiry {
+    match lifetime {
+        u32::MAX => None,
+        secs => Some(now + Duration::from_secs(secs as u64)),
+    }
+}
+
+/// Whether expiration time `a` is later than `b`.
+fn outlives(a: Expiry, b: Expiry) -> bool {
+    match (a, b) {
+        (_, None) => false,
+        (None, Some(_)) => true,
+        (Some(a), Some(b)) => a > b,
+    }
+}
+
+#[cfg(all(test, feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    use smoltcp::iface::SocketStorage;
+    use smoltcp::wire::EthernetAddress;
+
+    use super::*;
+
+    const ALL_NODES: Ipv6Address = Ipv6Address::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+    const ROUTER: Ipv6Address = Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+    const PREFIX: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0, 0, 0, 0);
+    /// Address formed from [`PREFIX`] by [`slaac`].
+    const ADDRESS: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 1, 0, 0x0000, 0x5eff, 0xfe10, 0x2030);
+    const DNS_1: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x53);
+    const DNS_2: Ipv6Address = Ipv6Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x54);
+
+    fn slaac_with(hardware_address: HardwareAddress, random_seed: u64) -> Slaac {
+        let storage = Box::leak(Box::new([SocketStorage::EMPTY; 1]));
+        let mut sockets = SocketSet::new(&mut storage[..]);
+        Slaac::new(
+            &mut sockets,
+            Box::leak(Box::new(SlaacResources::new())),
+            hardware_address,
+            random_seed,
+        )
+    }
+
+    fn slaac() -> Slaac {
+        let mac = EthernetAddress([0x02, 0x00, 0x5e, 0x10, 0x20, 0x30]);
+        slaac_with(HardwareAddress::Ethernet(mac), 0)
+    }
+
+    /// Router Advertisement from `src`, as an IPv6 packet.
+    fn router_advert(src: Ipv6Address, hop_limit: u8, router_lifetime: u16, options: &[u8]) -> Vec<u8> {
+        let repr = Ipv6Repr {
+            src_addr: src,
+            dst_addr: ALL_NODES,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: 16 + options.len(),
+            hop_limit,
+        };
+        let mut buf = vec![0; repr.buffer_len() + repr.payload_len];
+        let mut packet = Ipv6Packet::new_unchecked(&mut buf[..]);
+        repr.emit(&mut packet);
+        let mut message = Icmpv6Packet::new_unchecked(packet.payload_mut());
+        message.set_msg_type(Icmpv6Message::RouterAdvert);
+        message.set_msg_code(0);
+        let message = message.into_inner();
+        // Current hop limit, no flags, router lifetime, reachable time and retransmission timer unspecified.
+        message[4] = 64;
+        message[6..8].copy_from_slice(&router_lifetime.to_be_bytes());
+        message[16..].copy_from_slice(options);
+        Icmpv6Packet::new_unchecked(message).fill_checksum(&src, &ALL_NODES);
+        buf
+    }
+
+    /// Prefix Information option.
+    fn prefix_information(prefix: Ipv6Address, prefix_len: u8, flags: u8, valid: u32, preferred: u32) -> Vec<u8> {
+        let mut option = vec![OPT_PREFIX_INFORMATION, 4, prefix_len, flags];
+        option.extend_from_slice(&valid.to_be_bytes());
+        option.extend_from_slice(&preferred.to_be_bytes());
+        option.extend_from_slice(&[0; 4]);
+        option.extend_from_slice(&prefix.octets());
+        option
+    }
+
+    fn autonomous_prefix(valid: u32) -> Vec<u8> {
+        prefix_information(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, valid, valid.min(1800))
+    }
+
+    /// Recursive DNS Server option.
+    fn rdnss(lifetime: u32, servers: &[Ipv6Address]) -> Vec<u8> {
+        let mut option = vec![OPT_RDNSS, 1 + 2 * servers.len() as u8, 0, 0];
+        option.extend_from_slice(&lifetime.to_be_bytes());
+        for server in servers {
+            option.extend_from_slice(&server.octets());
+        }
+        option
+    }
+
+    /// Process the Router Advertisement `packet`, received at `now`.
+    fn receive(slaac: &mut Slaac, packet: &[u8], now: Instant) {
+        let (src, message) = unwrap!(parse_router_advert(packet));
+        slaac.process_router_advert(src, message, now);
+    }
+
+    #[test]
+    fn parses_router_adverts() {
+        let options = autonomous_prefix(3600);
+        let packet = router_advert(ROUTER, 255, 1800, &options);
+        let (src, message) = unwrap!(parse_router_advert(&packet));
+        assert_eq!(src, ROUTER);
+        assert_eq!(message.len(), 16 + options.len());
+        assert_eq!(message[16..], options[..]);
+
+        // Forwarded, or not from a link-local address.
+        assert!(parse_router_advert(&router_advert(ROUTER, 254, 1800, &options)).is_none());
+        assert!(parse_router_advert(&router_advert(PREFIX, 255, 1800, &options)).is_none());
+        // Bad checksum.
+        let mut corrupted = packet.clone();
+        *unwrap!(corrupted.last_mut()) ^= 1;
+        assert!(parse_router_advert(&corrupted).is_none());
+        // Other ICMPv6 messages.
+        let mut solicit = packet.clone();
+        let mut message = Icmpv6Packet::new_unchecked(&mut solicit[40..]);
+        message.set_msg_type(Icmpv6Message::RouterSolicit);
+        message.fill_checksum(&ROUTER, &ALL_NODES);
+        assert!(parse_router_advert(&solicit).is_none());
+        // Truncated.
+        assert!(parse_router_advert(&packet[..40 + 8]).is_none());
+    }
+
+    #[test]
+    fn interface_id_from_ethernet_address() {
+        // Modified EUI-64: the universal/local bit is flipped, and ff:fe inserted in the middle.
+        let slaac = slaac();
+        assert_eq!(
+            slaac.link_local(),
+            Ipv6Cidr::new(Ipv6Address::new(0xfe80, 0, 0, 0, 0x0000, 0x5eff, 0xfe10, 0x2030), 64)
+        );
+        let mac = EthernetAddress([0x00, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let slaac = slaac_with(HardwareAddress::Ethernet(mac), 0);
+        assert_eq!(
+            slaac.link_local().address(),
+            Ipv6Address::new(0xfe80, 0, 0, 0, 0x0211, 0x22ff, 0xfe33, 0x4455)
+        );
+    }
+
+    #[cfg(feature = "medium-ip")]
+    #[test]
+    fn random_interface_id_without_link_layer_address() {
+        let a = slaac_with(HardwareAddress::Ip, 1);
+        let b = slaac_with(HardwareAddress::Ip, 2);
+        assert_ne!(a.link_local(), b.link_local());
+        for slaac in [a, b] {
+            // The universal/local bit is cleared, as the identifier isn't universally unique.
+            assert_eq!(slaac.link_local().address().octets()[8] & 0x02, 0);
+        }
+    }
+
+    #[test]
+    fn configures_address_gateway_and_dns_servers() {
+        let mut slaac = slaac();
+        let now = Instant::from_secs(1000);
+        let mut options = autonomous_prefix(3600);
+        options.extend(rdnss(600, &[DNS_1, DNS_2]));
+        receive(&mut slaac, &router_advert(ROUTER, 255, 1800, &options), now);
+
+        assert_eq!(slaac.next_rs, None);
+        assert_eq!(
+            slaac.address,
+            Some((Ipv6Cidr::new(ADDRESS, 64), Some(now + Duration::from_secs(3600))))
+        );
+        assert_eq!(slaac.gateway, Some((ROUTER, now + Duration::from_secs(1800))));
+        assert_eq!(slaac.dns_servers[..], [DNS_1, DNS_2]);
+        assert_eq!(slaac.dns_expiry, Some(now + Duration::from_secs(600)));
+        assert_eq!(slaac.poll_at(), Some(now + Duration::from_secs(600)));
+
+        // A zero router lifetime removes the gateway, and a zero RDNSS lifetime the DNS servers.
+        receive(&mut slaac, &router_advert(ROUTER, 255, 0, &rdnss(0, &[DNS_1])), now);
+        assert_eq!(slaac.gateway, None);
+        assert!(slaac.dns_servers.is_empty());
+        assert_eq!(slaac.dns_expiry, None);
+        // The address is kept.
+        assert!(slaac.address.is_some());
+    }
+
+    #[test]
+    fn keeps_at_most_three_dns_servers() {
+        let mut slaac = slaac();
+        let servers = [DNS_1, DNS_2, DNS_1, DNS_2];
+        receive(
+            &mut slaac,
+            &router_advert(ROUTER, 255, 0, &rdnss(u32::MAX, &servers)),
+            Instant::from_secs(1),
+        );
+        assert_eq!(slaac.dns_servers[..], servers[..3]);
+        // Infinite lifetime.
+        assert_eq!(slaac.dns_expiry, None);
+    }
+
+    #[test]
+    fn ignores_unusable_prefixes() {
+        let now = Instant::from_secs(1000);
+        for option in [
+            // Not autonomous.
+            prefix_information(PREFIX, 64, 0x80, 3600, 1800),
+            // Link-local.
+            prefix_information(ROUTER, 64, PREFIX_FLAG_AUTONOMOUS, 3600, 1800),
+            // Preferred lifetime longer than the valid lifetime.
+            prefix_information(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, 1800, 3600),
+            // Not a /64.
+            prefix_information(PREFIX, 48, PREFIX_FLAG_AUTONOMOUS, 3600, 1800),
+            // Zero valid lifetime, for an address that isn't configured.
+            prefix_information(PREFIX, 64, PREFIX_FLAG_AUTONOMOUS, 0, 0),
+        ] {
+            let mut slaac = slaac();
+            receive(&mut slaac, &router_advert(ROUTER, 255, 1800, &option), now);
+            assert_eq!(slaac.address, None);
+        }
+    }
+
+    #[test]
+    fn valid_lifetime_two_hour_rule() {
+        const HOUR: u32 = 3600;
+        let now = Instant::from_secs(1000);
+        let at = |secs: u32| Some(now + Duration::from_secs(secs as u64));
+        let mut slaac = slaac();
+        let mut advertise = |valid: u32| {
+            receive(
+                &mut slaac,
+                &router_advert(ROUTER, 255, 0, &autonomous_prefix(valid)),
+                now,
+            );
+            unwrap!(slaac.address).1
+        };
+
+        assert_eq!(advertise(3 * HOUR), at(3 * HOUR));
+        // Lifetimes longer than 2 hours are accepted, even if shorter than the remaining one.
+        assert_eq!(advertise(4 * HOUR), at(4 * HOUR));
+        assert_eq!(advertise(3 * HOUR), at(3 * HOUR));
+        // Shorter ones only bring the remaining lifetime down to 2 hours.
+        assert_eq!(advertise(HOUR), at(2 * HOUR));
+        assert_eq!(advertise(0), at(2 * HOUR));
+        // Once the remaining lifetime is 2 hours or less, shorter lifetimes are ignored.
+        assert_eq!(advertise(HOUR / 2), at(2 * HOUR));
+        // Longer ones are accepted.
+        assert_eq!(advertise(2 * HOUR + 1), at(2 * HOUR + 1));
+        assert_eq!(advertise(u32::MAX), None);
+        assert_eq!(advertise(HOUR), at(2 * HOUR));
+    }
+}
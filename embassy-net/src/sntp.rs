@@ -0,0 +1,584 @@
+//! SNTP client.
+//!
+//! [`SntpClient`] gets the time from NTP servers with the Simple Network Time Protocol (RFC 4330),
+//! and keeps a [`WallClock`] synchronized with it. The wall clock is an offset to [`embassy_time::Instant`],
+//! corrected for the drift of the local clock, so reading it doesn't need the network.
+//!
+//! Servers are given by address, by name, or taken from the NTP servers option (42) of DHCP.
+//! Servers answering with a kiss-o'-death packet asking not to be queried again are skipped from then on,
+//! and the poll interval is increased when they ask to reduce the rate.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::CriticalSectionMutex;
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+use heapless::Vec;
+
+use crate::dns::DnsQueryType;
+use crate::udp::{PacketMetadata, SendError, UdpSocket};
+use crate::{IpAddress, IpEndpoint, Stack};
+
+/// NTP server port.
+const NTP_PORT: u16 = 123;
+/// Size of an NTP packet without extensions.
+const PACKET_LEN: usize = 48;
+/// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+const NTP_UNIX_OFFSET: i64 = 2_208_988_800;
+/// Number of samples the clock filter keeps.
+const FILTER_LEN: usize = 4;
+/// Maximum number of servers that asked not to be queried again.
+const MAX_DENIED: usize = 4;
+/// Maximum number of addresses tried for a server name.
+const MAX_ADDRS: usize = 4;
+/// First delay before querying again after a failure.
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+/// Maximum drift of the local clock that is corrected, in parts per billion.
+const MAX_DRIFT: i64 = 500_000;
+
+/// NTP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Server<'a> {
+    /// Server address.
+    Addr(IpAddress),
+    /// Server name, resolved with DNS before each query.
+    Name(&'a str),
+    /// Servers provided by DHCP (option 42) to the interfaces of the stack.
+    #[cfg(feature = "dhcpv4")]
+    Dhcp,
+}
+
+/// SNTP client configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct Config<'a> {
+    /// Servers to query, in order of preference.
+    pub servers: &'a [Server<'a>],
+    /// How long to wait for the response of a server.
+    pub timeout: Duration,
+    /// Interval between queries after the first synchronization.
+    ///
+    /// The interval doubles after each successful query, up to `max_poll_interval`.
+    pub min_poll_interval: Duration,
+    /// Maximum interval between queries.
+    pub max_poll_interval: Duration,
+}
+
+impl<'a> Config<'a> {
+    /// Create a new configuration querying `servers`.
+    pub const fn new(servers: &'a [Server<'a>]) -> Self {
+        Self {
+            servers,
+            timeout: Duration::from_secs(5),
+            min_poll_interval: Duration::from_secs(64),
+            max_poll_interval: Duration::from_secs(1024),
+        }
+    }
+}
+
+/// Error returned by [`SntpClient::query`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// No server address could be found.
+    NoServer,
+    /// No route to the server.
+    NoRoute,
+    /// The server didn't respond in time.
+    Timeout,
+    /// The server isn't synchronized, or sent an invalid response.
+    InvalidResponse,
+    /// The server sent a kiss-o'-death packet with this code.
+    KissOfDeath([u8; 4]),
+    /// The transmit buffer of the socket can't hold a request.
+    PacketTooLarge,
+}
+
+/// Result of a query to an NTP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Sample {
+    /// Address of the server.
+    pub server: IpAddress,
+    /// Stratum of the server.
+    pub stratum: u8,
+    /// Unix time in microseconds minus [`Instant::as_micros`], at `instant`.
+    pub offset: i64,
+    /// Round-trip delay of the query.
+    pub delay: Duration,
+    /// When the sample was taken.
+    pub instant: Instant,
+}
+
+/// Wall clock time, as an offset to [`Instant`].
+///
+/// It can be shared between tasks, and is typically kept synchronized by [`SntpClient::run`].
+pub struct WallClock {
+    state: CriticalSectionMutex<Cell<Option<ClockState>>>,
+}
+
+#[derive(Clone, Copy)]
+struct ClockState {
+    /// Offset at `instant`.
+    offset: i64,
+    instant: Instant,
+    /// Drift of the local clock, in parts per billion.
+    drift: i64,
+}
+
+impl ClockState {
+    fn offset_at(&self, instant: Instant) -> i64 {
+        let elapsed = instant.as_micros() as i64 - self.instant.as_micros() as i64;
+        self.offset + (elapsed as i128 * self.drift as i128 / 1_000_000_000) as i64
+    }
+}
+
+impl WallClock {
+    /// Create a new, unsynchronized wall clock.
+    pub const fn new() -> Self {
+        Self {
+            state: CriticalSectionMutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Get the offset of Unix time to [`Instant`] in microseconds at `instant`, or `None` if the
+    /// clock isn't synchronized.
+    ///
+    /// The Unix time in microseconds at `instant` is `instant.as_micros() + offset`.
+    pub fn offset_at(&self, instant: Instant) -> Option<i64> {
+        self.state.lock(|s| s.get()).map(|s| s.offset_at(instant))
+    }
+
+    /// Get the Unix time in microseconds at `instant`, or `None` if the clock isn't synchronized.
+    pub fn unix_micros_at(&self, instant: Instant) -> Option<u64> {
+        self.offset_at(instant)
+            .map(|offset| (instant.as_micros() as i64 + offset) as u64)
+    }
+
+    /// Get the current Unix time in microseconds, or `None` if the clock isn't synchronized.
+    pub fn now_unix_micros(&self) -> Option<u64> {
+        self.unix_micros_at(Instant::now())
+    }
+
+    /// Get the current Unix time in seconds, or `None` if the clock isn't synchronized.
+    pub fn now_unix_secs(&self) -> Option<u64> {
+        self.now_unix_micros().map(|t| t / 1_000_000)
+    }
+
+    /// Get the estimated drift of the local clock, in parts per billion.
+    ///
+    /// A positive drift means the local clock is slow.
+    pub fn drift(&self) -> Option<i64> {
+        self.state.lock(|s| s.get()).map(|s| s.drift)
+    }
+
+    /// Set the current Unix time in microseconds, from another time source.
+    pub fn set_unix_micros(&self, unix_micros: u64) {
+        let instant = Instant::now();
+        let drift = self.drift().unwrap_or(0);
+        self.state.lock(|s| {
+            s.set(Some(ClockState {
+                offset: unix_micros as i64 - instant.as_micros() as i64,
+                instant,
+                drift,
+            }))
+        });
+    }
+
+    /// Forget the time, until the clock is synchronized again.
+    pub fn reset(&self) {
+        self.state.lock(|s| s.set(None));
+    }
+
+    /// Update the clock with a new sample, correcting the drift since the `previous` sample.
+    fn update(&self, sample: &Sample, previous: Option<&Sample>) {
+        self.state.lock(|s| {
+            let mut drift = s.get().map_or(0, |s| s.drift);
+            if let (Some(state), Some(previous)) = (s.get(), previous) {
+                let elapsed = sample.instant.as_micros() as i64 - previous.instant.as_micros() as i64;
+                if elapsed > 0 {
+                    // Error of the prediction since the previous sample, corrected by half to smooth out jitter.
+                    let error = sample.offset - state.offset_at(sample.instant);
+                    let correction = error as i128 * 1_000_000_000 / elapsed as i128 / 2;
+                    drift = (drift as i128 + correction).clamp(-MAX_DRIFT as i128, MAX_DRIFT as i128) as i64;
+                }
+            }
+            s.set(Some(ClockState {
+                offset: sample.offset,
+                instant: sample.instant,
+                drift,
+            }))
+        });
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// State for [`SntpClient`].
+pub struct SntpClientState {
+    rx_meta: [PacketMetadata; 2],
+    rx_buffer: [u8; 2 * PACKET_LEN],
+    tx_meta: [PacketMetadata; 1],
+    tx_buffer: [u8; PACKET_LEN],
+}
+
+impl SntpClientState {
+    /// Create a new `SntpClientState`.
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; 2],
+            rx_buffer: [0; 2 * PACKET_LEN],
+            tx_meta: [PacketMetadata::EMPTY; 1],
+            tx_buffer: [0; PACKET_LEN],
+        }
+    }
+}
+
+impl Default for SntpClientState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// SNTP client.
+pub struct SntpClient<'d> {
+    stack: Stack<'d>,
+    socket: UdpSocket<'d>,
+    config: Config<'d>,
+    poll_interval: Duration,
+    /// Latest samples, oldest first.
+    samples: Vec<Sample, FILTER_LEN>,
+    /// Sample the clock was last updated with.
+    selected: Option<Sample>,
+    /// Servers that asked not to be queried again.
+    denied: Vec<IpAddress, MAX_DENIED>,
+}
+
+impl<'d> SntpClient<'d> {
+    /// Create a new SNTP client.
+    pub fn new(stack: Stack<'d>, config: Config<'d>, state: &'d mut SntpClientState) -> Self {
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut state.rx_meta,
+            &mut state.rx_buffer,
+            &mut state.tx_meta,
+            &mut state.tx_buffer,
+        );
+        // The socket is new, and the port is allocated by `bind`.
+        unwrap!(socket.bind(0));
+        Self {
+            stack,
+            socket,
+            config,
+            poll_interval: config.min_poll_interval,
+            samples: Vec::new(),
+            selected: None,
+            denied: Vec::new(),
+        }
+    }
+
+    /// Query the servers in order, until one of them responds.
+    ///
+    /// If no server responds, the error of the last one is returned.
+    pub async fn query(&mut self) -> Result<Sample, Error> {
+        let mut result = Err(Error::NoServer);
+        for server in self.config.servers {
+            for addr in self.resolve(server).await {
+                if self.denied.contains(&addr) {
+                    continue;
+                }
+                result = self.query_addr(addr).await;
+                match result {
+                    Ok(sample) => return Ok(sample),
+                    Err(e) => debug!("sntp: query to {} failed: {:?}", addr, e),
+                }
+            }
+        }
+        result
+    }
+
+    /// Keep `clock` synchronized with the servers.
+    pub async fn run(&mut self, clock: &WallClock) -> ! {
+        let mut retry_interval = MIN_RETRY_INTERVAL;
+        loop {
+            match self.query().await {
+                Ok(sample) => {
+                    debug!(
+                        "sntp: offset {} us, delay {} us from {}",
+                        sample.offset,
+                        sample.delay.as_micros(),
+                        sample.server
+                    );
+                    if self.samples.is_full() {
+                        self.samples.remove(0);
+                    }
+                    unwrap!(self.samples.push(sample).ok());
+
+                    // The sample with the lowest delay is the most accurate.
+                    let best = *unwrap!(self.samples.iter().min_by_key(|s| s.delay));
+                    if self.selected.is_none_or(|s| s.instant < best.instant) {
+                        clock.update(&best, self.selected.as_ref());
+                        self.selected = Some(best);
+                    }
+
+                    retry_interval = MIN_RETRY_INTERVAL;
+                    Timer::after(self.poll_interval).await;
+                    self.poll_interval = (self.poll_interval * 2).min(self.config.max_poll_interval);
+                }
+                Err(e) => {
+                    warn!("sntp: no server responded: {:?}", e);
+                    Timer::after(retry_interval).await;
+                    retry_interval = (retry_interval * 2).min(self.poll_interval);
+                }
+            }
+        }
+    }
+
+    async fn resolve(&self, server: &Server<'_>) -> Vec<IpAddress, MAX_ADDRS> {
+        match server {
+            Server::Addr(addr) => [*addr].into_iter().collect(),
+            Server::Name(name) => {
+                #[cfg(feature = "proto-ipv4")]
+                let qtype = DnsQueryType::A;
+                #[cfg(not(feature = "proto-ipv4"))]
+                let qtype = DnsQueryType::Aaaa;
+                match self.stack.dns_query(name, qtype).await {
+                    Ok(addrs) => addrs.into_iter().take(MAX_ADDRS).collect(),
+                    Err(e) => {
+                        debug!("sntp: failed to resolve {}: {:?}", name, e);
+                        Vec::new()
+                    }
+                }
+            }
+            #[cfg(feature = "dhcpv4")]
+            Server::Dhcp => self
+                .stack
+                .interfaces()
+                .flat_map(|i| i.ntp_servers())
+                .map(IpAddress::Ipv4)
+                .take(MAX_ADDRS)
+                .collect(),
+        }
+    }
+
+    async fn query_addr(&mut self, addr: IpAddress) -> Result<Sample, Error> {
+        let server = IpEndpoint::new(addr, NTP_PORT);
+
+        // The server copies our transmit timestamp to its response, so it identifies the response.
+        let t1 = Instant::now();
+        let transmit = to_ntp_timestamp(t1.as_micros() as i64 + self.selected.map_or(0, |s| s.offset));
+        let mut request = [0; PACKET_LEN];
+        // LI = 0, VN = 4, Mode = 3 (client)
+        request[0] = 0x23;
+        request[40..48].copy_from_slice(&transmit.to_be_bytes());
+        self.socket.send_to(&request, server).await.map_err(|e| match e {
+            SendError::NoRoute | SendError::SocketNotBound | SendError::SocketSetFull => Error::NoRoute,
+            SendError::PacketTooLarge => Error::PacketTooLarge,
+        })?;
+
+        let deadline = t1 + self.config.timeout;
+        let mut response = [0; PACKET_LEN];
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let Ok(received) = with_timeout(timeout, self.socket.recv_from(&mut response)).await else {
+                return Err(Error::Timeout);
+            };
+            let t4 = Instant::now();
+            match received {
+                Ok((n, meta)) if n == PACKET_LEN && meta.endpoint == server => {}
+                // Not a response to this query.
+                _ => continue,
+            }
+            if response[24..32] != transmit.to_be_bytes() {
+                continue;
+            }
+            return self.parse_response(addr, &response, t1, t4);
+        }
+    }
+
+    fn parse_response(
+        &mut self,
+        addr: IpAddress,
+        response: &[u8; PACKET_LEN],
+        t1: Instant,
+        t4: Instant,
+    ) -> Result<Sample, Error> {
+        let leap = response[0] >> 6;
+        let version = (response[0] >> 3) & 0x07;
+        let mode = response[0] & 0x07;
+        let stratum = response[1];
+
+        if stratum == 0 {
+            let code = [response[12], response[13], response[14], response[15]];
+            match &code {
+                b"DENY" | b"RSTR" => {
+                    warn!("sntp: server {} denied access", addr);
+                    if self.denied.push(addr).is_err() {
+                        warn!("sntp: too many servers denied access");
+                    }
+                }
+                b"RATE" => {
+                    self.poll_interval = (self.poll_interval * 2).min(self.config.max_poll_interval);
+                }
+                _ => {}
+            }
+            return Err(Error::KissOfDeath(code));
+        }
+
+        let receive = u64::from_be_bytes(unwrap!(response[32..40].try_into()));
+        let transmit = u64::from_be_bytes(unwrap!(response[40..48].try_into()));
+        if leap == 3 || !(1..=4).contains(&version) || mode != 4 || stratum > 15 || transmit == 0 {
+            return Err(Error::InvalidResponse);
+        }
+
+        let t1_us = t1.as_micros() as i64;
+        let t4_us = t4.as_micros() as i64;
+        let t2_us = from_ntp_timestamp(receive);
+        let t3_us = from_ntp_timestamp(transmit);
+        let offset = ((t2_us - t1_us) + (t3_us - t4_us)) / 2;
+        let delay = (t4_us - t1_us) - (t3_us - t2_us);
+
+        Ok(Sample {
+            server: addr,
+            stratum,
+            offset,
+            delay: Duration::from_micros(delay.max(0) as u64),
+            instant: Instant::from_micros((t1_us + t4_us) as u64 / 2),
+        })
+    }
+}
+
+/// Convert Unix time in microseconds to an NTP timestamp.
+fn to_ntp_timestamp(unix_micros: i64) -> u64 {
+    let secs = unix_micros.div_euclid(1_000_000) + NTP_UNIX_OFFSET;
+    let micros = unix_micros.rem_euclid(1_000_000) as u64;
+    ((secs as u64) << 32) | ((micros << 32) / 1_000_000)
+}
+
+/// Convert an NTP timestamp to Unix time in microseconds.
+fn from_ntp_timestamp(timestamp: u64) -> i64 {
+    let mut secs = (timestamp >> 32) as i64;
+    // Timestamps with the high bit clear are in the era starting in 2036 (RFC 4330, section 3).
+    if secs & 0x8000_0000 == 0 {
+        secs += 1 << 32;
+    }
+    // Round to the nearest microsecond, so that converting back and forth gives the same time.
+    let micros = ((timestamp & 0xffff_ffff) * 1_000_000 + (1 << 31)) >> 32;
+    (secs - NTP_UNIX_OFFSET) * 1_000_000 + micros as i64
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+
+    use embassy_futures::join::join;
+
+    use super::*;
+    use crate::test_util::{self, addr, static_config};
+
+    #[test]
+    fn ntp_timestamps() {
+        assert_eq!(to_ntp_timestamp(0), (NTP_UNIX_OFFSET as u64) << 32);
+        assert_eq!(
+            to_ntp_timestamp(1_500_000),
+            ((NTP_UNIX_OFFSET as u64 + 1) << 32) | 0x8000_0000
+        );
+        assert_eq!(from_ntp_timestamp((NTP_UNIX_OFFSET as u64) << 32), 0);
+        assert_eq!(
+            from_ntp_timestamp(((NTP_UNIX_OFFSET as u64 + 1) << 32) | 0x8000_0000),
+            1_500_000
+        );
+
+        // Before the Unix epoch, in 2024, and after the NTP era rolls over in 2036.
+        for unix_micros in [-1_000_000, 1_700_000_000_123_456, 2_100_000_000_654_321] {
+            assert_eq!(from_ntp_timestamp(to_ntp_timestamp(unix_micros)), unix_micros);
+        }
+        // 2036-02-07 06:28:16 is the start of NTP era 1, where the seconds wrap to 0.
+        assert_eq!(to_ntp_timestamp(2_085_978_496_000_000), 0);
+    }
+
+    fn sample(instant_secs: u64, offset: i64) -> Sample {
+        Sample {
+            server: addr(2).into(),
+            stratum: 1,
+            offset,
+            delay: Duration::from_millis(1),
+            instant: Instant::from_secs(instant_secs),
+        }
+    }
+
+    #[test]
+    fn wall_clock_corrects_drift() {
+        let clock = WallClock::new();
+        assert_eq!(clock.offset_at(Instant::from_secs(1)), None);
+
+        let first = sample(1, 1_000_000);
+        clock.update(&first, None);
+        assert_eq!(clock.drift(), Some(0));
+        assert_eq!(clock.unix_micros_at(Instant::from_secs(2)), Some(3_000_000));
+
+        // The local clock is 10 ppm slow: the offset grew by 100 us in 10 s. Half of it is corrected.
+        let second = sample(11, 1_000_100);
+        clock.update(&second, Some(&first));
+        assert_eq!(clock.drift(), Some(5_000));
+        assert_eq!(clock.offset_at(Instant::from_secs(21)), Some(1_000_150));
+
+        // Large errors are clamped.
+        let third = sample(12, 2_000_000);
+        clock.update(&third, Some(&second));
+        assert_eq!(clock.drift(), Some(MAX_DRIFT));
+
+        clock.reset();
+        assert_eq!(clock.drift(), None);
+    }
+
+    /// Answer one request on `socket` with `stratum`, as a server whose clock is ahead by `offset` microseconds.
+    async fn respond(socket: &mut UdpSocket<'_>, stratum: u8, offset: i64, kiss_code: &[u8; 4]) {
+        let mut request = [0; PACKET_LEN];
+        let (_, meta) = unwrap!(socket.recv_from(&mut request).await);
+        let now = to_ntp_timestamp(Instant::now().as_micros() as i64 + offset);
+        let mut response = [0; PACKET_LEN];
+        // LI = 0, VN = 4, Mode = 4 (server)
+        response[0] = 0x24;
+        response[1] = stratum;
+        response[12..16].copy_from_slice(kiss_code);
+        response[24..32].copy_from_slice(&request[40..48]);
+        response[32..40].copy_from_slice(&now.to_be_bytes());
+        response[40..48].copy_from_slice(&now.to_be_bytes());
+        unwrap!(socket.send_to(&response, meta.endpoint).await);
+    }
+
+    #[test]
+    fn query_measures_offset_and_honors_kiss_of_death() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let servers = Box::leak(Box::new([Server::Addr(addr(2).into())]));
+            let state = Box::leak(Box::new(SntpClientState::new()));
+            let mut client = SntpClient::new(net.a, Config::new(servers), state);
+            let mut server = UdpSocket::new(
+                net.b,
+                Box::leak(Box::new([PacketMetadata::EMPTY; 2])),
+                Box::leak(Box::new([0; 2 * PACKET_LEN])),
+                Box::leak(Box::new([PacketMetadata::EMPTY; 2])),
+                Box::leak(Box::new([0; 2 * PACKET_LEN])),
+            );
+            unwrap!(server.bind(NTP_PORT));
+
+            const OFFSET: i64 = 1_700_000_000_000_000;
+            let (sample, ()) = join(client.query(), respond(&mut server, 2, OFFSET, b"\0\0\0\0")).await;
+            let sample = unwrap!(sample);
+            assert_eq!(sample.server, addr(2).into());
+            assert_eq!(sample.stratum, 2);
+            assert!((sample.offset - OFFSET).abs() < 10_000, "offset {}", sample.offset);
+
+            let (result, ()) = join(client.query(), respond(&mut server, 0, OFFSET, b"DENY")).await;
+            assert_eq!(result, Err(Error::KissOfDeath(*b"DENY")));
+            // The server isn't queried again.
+            assert_eq!(client.query().await, Err(Error::NoServer));
+        });
+    }
+}
@@ -0,0 +1,495 @@
+//! Interface and socket statistics.
+//!
+//! Counters wrap around on overflow.
+
+#[cfg(feature = "stats-checksums")]
+use embassy_net_driver::{Checksum, ChecksumCapabilities};
+#[cfg(any(feature = "tcp", feature = "stats-checksums"))]
+use smoltcp::phy::Medium;
+#[cfg(all(feature = "proto-ipv4", any(feature = "tcp", feature = "stats-checksums")))]
+use smoltcp::wire::Ipv4Packet;
+#[cfg(all(feature = "proto-ipv6", any(feature = "tcp", feature = "stats-checksums")))]
+use smoltcp::wire::Ipv6Packet;
+#[cfg(any(feature = "tcp", feature = "stats-checksums"))]
+use smoltcp::wire::{IpAddress, IpProtocol};
+#[cfg(feature = "tcp")]
+use smoltcp::wire::{IpEndpoint, TcpPacket, TcpSeqNumber};
+
+/// Number of TCP connections whose sequence numbers are tracked to count retransmissions, per interface.
+#[cfg(feature = "tcp")]
+const TRACKED_CONNECTIONS: usize = 8;
+
+/// Counters of a network interface, returned by [`Interface::stats`](crate::Interface::stats).
+///
+/// The counters are kept from when the interface is created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct InterfaceStats {
+    /// Packets received from the driver.
+    pub rx_packets: u32,
+    /// Bytes received from the driver, including link-layer headers.
+    pub rx_bytes: u64,
+    /// Packets sent to the driver.
+    pub tx_packets: u32,
+    /// Bytes sent to the driver, including link-layer headers.
+    pub tx_bytes: u64,
+    /// Received packets dropped because the IPv4 forwarding queue or an Ethernet socket's receive
+    /// buffer was full.
+    ///
+    /// Packets smoltcp drops because a UDP, ICMP or raw socket's receive buffer is full aren't
+    /// counted, smoltcp doesn't report them.
+    pub rx_dropped: u32,
+    /// Times a packet was ready to be sent but the driver had no free transmit buffer.
+    ///
+    /// Socket data is sent again later, but other packets, such as ARP requests, may be lost.
+    pub tx_buffer_full: u32,
+    /// TCP segments sent again, on all connections.
+    #[cfg(feature = "tcp")]
+    pub tcp_retransmissions: u32,
+    /// Received packets with an invalid IPv4 header, TCP, UDP or ICMP checksum.
+    ///
+    /// Only checksums the driver doesn't verify itself are counted, see
+    /// [`ChecksumCapabilities`](crate::driver::ChecksumCapabilities). smoltcp drops these packets.
+    #[cfg(feature = "stats-checksums")]
+    pub rx_checksum_errors: u32,
+}
+
+/// Counters of a TCP socket, returned by [`TcpSocket::stats`](crate::tcp::TcpSocket::stats).
+///
+/// The counters are reset when the socket connects or accepts a connection.
+#[cfg(feature = "tcp")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct TcpStats {
+    /// Bytes written to the transmit buffer.
+    pub bytes_sent: u64,
+    /// Bytes read from the receive buffer.
+    pub bytes_received: u64,
+    /// Segments sent again, after a timeout or duplicate acknowledgments.
+    ///
+    /// Retransmissions are tracked for the last few connections of each interface, connections
+    /// forgotten in the meantime keep the count they had the last time it was read.
+    pub retransmissions: u32,
+}
+
+/// Sequence numbers sent on the last TCP connections of an interface, to count retransmissions.
+#[cfg(feature = "tcp")]
+pub(crate) struct TcpTracker {
+    connections: [Option<Connection>; TRACKED_CONNECTIONS],
+    /// Entry replaced by the next new connection.
+    next: usize,
+}
+
+#[cfg(feature = "tcp")]
+#[derive(Clone, Copy)]
+struct Connection {
+    local: IpEndpoint,
+    remote: IpEndpoint,
+    /// Initial sequence number, telling connections using the same endpoints apart.
+    isn: TcpSeqNumber,
+    /// Sequence number after the last byte sent.
+    end: TcpSeqNumber,
+    retransmissions: u32,
+}
+
+#[cfg(feature = "tcp")]
+impl TcpTracker {
+    pub(crate) const fn new() -> Self {
+        Self {
+            connections: [None; TRACKED_CONNECTIONS],
+            next: 0,
+        }
+    }
+
+    /// Record a sent frame, returns whether it's a TCP retransmission.
+    pub(crate) fn capture(&mut self, medium: Medium, frame: &[u8]) -> bool {
+        let Some(packet) = ip_packet(medium, frame).and_then(transport) else {
+            return false;
+        };
+        if packet.protocol != IpProtocol::Tcp {
+            return false;
+        }
+        let Ok(segment) = TcpPacket::new_checked(packet.payload) else {
+            return false;
+        };
+        // Segments without data, SYN or FIN, such as acknowledgments and keep-alives, aren't retransmitted.
+        let len = segment.payload().len() + usize::from(segment.syn()) + usize::from(segment.fin());
+        if len == 0 {
+            return false;
+        }
+
+        let local = IpEndpoint::new(packet.src, segment.src_port());
+        let remote = IpEndpoint::new(packet.dst, segment.dst_port());
+        let seq = segment.seq_number();
+        let end = seq + len;
+        let found = self
+            .connections
+            .iter_mut()
+            .flatten()
+            .find(|c| c.local == local && c.remote == remote && !(segment.syn() && c.isn != seq));
+        match found {
+            Some(connection) => {
+                let retransmission = seq < connection.end;
+                if retransmission {
+                    connection.retransmissions = connection.retransmissions.wrapping_add(1);
+                }
+                if end > connection.end {
+                    connection.end = end;
+                }
+                retransmission
+            }
+            None => {
+                // A new connection, or one that was forgotten.
+                let slot = match self
+                    .connections
+                    .iter()
+                    .position(|c| c.is_some_and(|c| c.local == local && c.remote == remote))
+                {
+                    Some(i) => i,
+                    None => {
+                        let i = self.next;
+                        self.next = (self.next + 1) % TRACKED_CONNECTIONS;
+                        i
+                    }
+                };
+                self.connections[slot] = Some(Connection {
+                    local,
+                    remote,
+                    isn: seq,
+                    end,
+                    retransmissions: 0,
+                });
+                false
+            }
+        }
+    }
+
+    /// Get the number of retransmissions of the connection between `local` and `remote`, if it's tracked.
+    pub(crate) fn retransmissions(&self, local: IpEndpoint, remote: IpEndpoint) -> Option<u32> {
+        self.connections
+            .iter()
+            .flatten()
+            .find(|c| c.local == local && c.remote == remote)
+            .map(|c| c.retransmissions)
+    }
+}
+
+/// Get the IP packet in a frame.
+#[cfg(any(feature = "tcp", feature = "stats-checksums"))]
+#[cfg_attr(
+    not(any(feature = "medium-ethernet", feature = "medium-ip")),
+    allow(unreachable_code, unused_variables)
+)]
+fn ip_packet(medium: Medium, frame: &[u8]) -> Option<&[u8]> {
+    match medium {
+        #[cfg(feature = "medium-ethernet")]
+        Medium::Ethernet => {
+            let frame = smoltcp::wire::EthernetFrame::new_checked(frame).ok()?;
+            match frame.ethertype() {
+                smoltcp::wire::EthernetProtocol::Ipv4 | smoltcp::wire::EthernetProtocol::Ipv6 => {}
+                _ => return None,
+            }
+            Some(&frame.into_inner()[smoltcp::wire::EthernetFrame::<&[u8]>::header_len()..])
+        }
+        #[cfg(feature = "medium-ip")]
+        Medium::Ip => Some(frame),
+        #[allow(unreachable_patterns)]
+        _ => None,
+    }
+}
+
+/// Transport segment of an IP packet.
+#[cfg(any(feature = "tcp", feature = "stats-checksums"))]
+struct Transport<'p> {
+    src: IpAddress,
+    dst: IpAddress,
+    protocol: IpProtocol,
+    payload: &'p [u8],
+}
+
+/// Get the transport segment of an IP packet, if it isn't fragmented.
+#[cfg(any(feature = "tcp", feature = "stats-checksums"))]
+fn transport(packet: &[u8]) -> Option<Transport<'_>> {
+    match packet.first().map(|b| b >> 4) {
+        #[cfg(feature = "proto-ipv4")]
+        Some(4) => {
+            let packet = Ipv4Packet::new_checked(packet).ok()?;
+            if packet.more_frags() || packet.frag_offset() != 0 {
+                return None;
+            }
+            Some(Transport {
+                src: packet.src_addr().into(),
+                dst: packet.dst_addr().into(),
+                protocol: packet.next_header(),
+                payload: packet.payload(),
+            })
+        }
+        #[cfg(feature = "proto-ipv6")]
+        Some(6) => {
+            // Extension headers aren't walked, packets using them are skipped.
+            let packet = Ipv6Packet::new_checked(packet).ok()?;
+            Some(Transport {
+                src: packet.src_addr().into(),
+                dst: packet.dst_addr().into(),
+                protocol: packet.next_header(),
+                payload: packet.payload(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Check the checksums of a received frame that the driver doesn't verify.
+///
+/// Returns `false` if one of them is invalid. Frames that can't be parsed are left to smoltcp.
+#[cfg(feature = "stats-checksums")]
+pub(crate) fn verify_checksums(medium: Medium, caps: &ChecksumCapabilities, frame: &[u8]) -> bool {
+    let Some(packet) = ip_packet(medium, frame) else {
+        return true;
+    };
+    #[cfg(feature = "proto-ipv4")]
+    if verify(caps.ipv4) && packet.first().map(|b| b >> 4) == Some(4) {
+        if let Ok(packet) = Ipv4Packet::new_checked(packet) {
+            if !packet.verify_checksum() {
+                return false;
+            }
+        }
+    }
+    // Transport checksums cover the whole datagram, fragments are left to reassembly.
+    let Some(Transport {
+        src,
+        dst,
+        protocol,
+        payload,
+    }) = transport(packet)
+    else {
+        return true;
+    };
+
+    match protocol {
+        IpProtocol::Tcp if verify(caps.tcp) => {
+            smoltcp::wire::TcpPacket::new_checked(payload).map_or(true, |p| p.verify_checksum(&src, &dst))
+        }
+        IpProtocol::Udp if verify(caps.udp) => {
+            smoltcp::wire::UdpPacket::new_checked(payload).map_or(true, |p| p.verify_checksum(&src, &dst))
+        }
+        #[cfg(feature = "proto-ipv4")]
+        IpProtocol::Icmp if verify(caps.icmpv4) => {
+            smoltcp::wire::Icmpv4Packet::new_checked(payload).map_or(true, |p| p.verify_checksum())
+        }
+        #[cfg(feature = "proto-ipv6")]
+        IpProtocol::Icmpv6 if verify(caps.icmpv6) => match (src, dst) {
+            (IpAddress::Ipv6(src), IpAddress::Ipv6(dst)) => {
+                smoltcp::wire::Icmpv6Packet::new_checked(payload).map_or(true, |p| p.verify_checksum(&src, &dst))
+            }
+            #[allow(unreachable_patterns)]
+            _ => true,
+        },
+        _ => true,
+    }
+}
+
+/// Whether checksums of received packets must be verified in software.
+#[cfg(feature = "stats-checksums")]
+fn verify(checksum: Checksum) -> bool {
+    matches!(checksum, Checksum::Both | Checksum::Rx)
+}
+
+#[cfg(all(
+    test,
+    feature = "tcp",
+    feature = "proto-ipv4",
+    feature = "medium-ethernet",
+    feature = "medium-ip"
+))]
+mod tests {
+    use std::boxed::Box;
+    use std::vec;
+    use std::vec::Vec;
+
+    use embassy_futures::join::join;
+    use embassy_net_driver_channel::virtual_link::LinkConfig;
+    use smoltcp::phy::ChecksumCapabilities as SmolChecksums;
+    use smoltcp::wire::{Ipv4Repr, TcpControl, TcpRepr};
+
+    use super::*;
+    use crate::tcp::TcpSocket;
+    use crate::test_util::{self, addr, static_config, wait_for};
+    use crate::Stack;
+
+    const PORT: u16 = 1234;
+
+    /// Build an IPv4 packet with a TCP segment from 10.0.0.1:1000 to 10.0.0.2:80.
+    fn segment(seq: i32, syn: bool, payload: &[u8]) -> Vec<u8> {
+        let tcp = TcpRepr {
+            src_port: 1000,
+            dst_port: 80,
+            control: if syn { TcpControl::Syn } else { TcpControl::None },
+            seq_number: TcpSeqNumber(seq),
+            ack_number: None,
+            window_len: 1024,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None; 3],
+            timestamp: None,
+            payload,
+        };
+        let ip = Ipv4Repr {
+            src_addr: addr(1),
+            dst_addr: addr(2),
+            next_header: IpProtocol::Tcp,
+            payload_len: tcp.buffer_len(),
+            hop_limit: 64,
+        };
+        let mut buf = vec![0; ip.buffer_len() + tcp.buffer_len()];
+        let mut packet = Ipv4Packet::new_unchecked(&mut buf);
+        ip.emit(&mut packet, &SmolChecksums::default());
+        let (src, dst) = (addr(1).into(), addr(2).into());
+        tcp.emit(
+            &mut TcpPacket::new_unchecked(packet.payload_mut()),
+            &src,
+            &dst,
+            &SmolChecksums::default(),
+        );
+        buf
+    }
+
+    #[test]
+    fn tracker_counts_retransmissions() {
+        let mut tracker = TcpTracker::new();
+        let local = IpEndpoint::new(addr(1).into(), 1000);
+        let remote = IpEndpoint::new(addr(2).into(), 80);
+
+        assert!(!tracker.capture(Medium::Ip, &segment(100, true, b"")));
+        // The SYN is sent again.
+        assert!(tracker.capture(Medium::Ip, &segment(100, true, b"")));
+        assert!(!tracker.capture(Medium::Ip, &segment(101, false, b"hello")));
+        // Acknowledgments don't use sequence numbers.
+        assert!(!tracker.capture(Medium::Ip, &segment(106, false, b"")));
+        assert!(tracker.capture(Medium::Ip, &segment(101, false, b"hello world")));
+        assert!(!tracker.capture(Medium::Ip, &segment(112, false, b"!")));
+        assert_eq!(tracker.retransmissions(local, remote), Some(2));
+        assert_eq!(tracker.retransmissions(remote, local), None);
+
+        // A new connection between the same endpoints starts from zero.
+        assert!(!tracker.capture(Medium::Ip, &segment(5000, true, b"")));
+        assert_eq!(tracker.retransmissions(local, remote), Some(0));
+    }
+
+    #[test]
+    fn tracker_forgets_oldest_connections() {
+        let mut tracker = TcpTracker::new();
+        let remote = IpEndpoint::new(addr(2).into(), 80);
+        for port in 0..=TRACKED_CONNECTIONS as u16 {
+            let mut packet = segment(100, true, b"");
+            TcpPacket::new_unchecked(&mut packet[20..]).set_src_port(port);
+            tracker.capture(Medium::Ip, &packet);
+        }
+        let local = |port| IpEndpoint::new(addr(1).into(), port);
+        assert_eq!(tracker.retransmissions(local(0), remote), None);
+        assert_eq!(
+            tracker.retransmissions(local(TRACKED_CONNECTIONS as u16), remote),
+            Some(0)
+        );
+    }
+
+    #[cfg(feature = "stats-checksums")]
+    #[test]
+    fn detects_invalid_checksums() {
+        let caps = ChecksumCapabilities::default();
+        let mut packet = segment(100, false, b"hello");
+        assert!(verify_checksums(Medium::Ip, &caps, &packet));
+
+        // Checksums the driver verified aren't checked again.
+        let last = packet.len() - 1;
+        packet[last] ^= 0xff;
+        assert!(!verify_checksums(Medium::Ip, &caps, &packet));
+        let mut offloaded = ChecksumCapabilities::default();
+        offloaded.tcp = Checksum::Tx;
+        assert!(verify_checksums(Medium::Ip, &offloaded, &packet));
+
+        packet[last] ^= 0xff;
+        packet[8] -= 1; // TTL
+        assert!(!verify_checksums(Medium::Ip, &caps, &packet));
+    }
+
+    fn socket(stack: Stack<'static>) -> TcpSocket<'static> {
+        let rx = Box::leak(Box::new([0; 1024]));
+        let tx = Box::leak(Box::new([0; 1024]));
+        TcpSocket::new(stack, rx, tx)
+    }
+
+    #[test]
+    fn counts_traffic() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut client = socket(net.a);
+            let mut server = socket(net.b);
+            let serve = async {
+                unwrap!(server.accept(PORT).await);
+                let mut buf = [0; 16];
+                let n = unwrap!(server.read(&mut buf).await);
+                unwrap!(server.write(&buf[..n]).await);
+                unwrap!(server.flush().await);
+            };
+            let connect = async {
+                unwrap!(client.connect((addr(2), PORT)).await);
+                let (mut reader, mut writer) = client.split();
+                unwrap!(writer.write(b"hello").await);
+                let mut buf = [0; 16];
+                let n = unwrap!(reader.read(&mut buf).await);
+                assert_eq!(&buf[..n], b"hello");
+            };
+            join(serve, connect).await;
+
+            let expected = TcpStats {
+                bytes_sent: 5,
+                bytes_received: 5,
+                retransmissions: 0,
+            };
+            assert_eq!(client.stats(), expected);
+            assert_eq!(server.stats(), expected);
+
+            let (a, b) = (net.a.stats(), net.b.stats());
+            // The ARP request, SYN, ACK and data, and their answers with the echoed data.
+            assert!(a.tx_packets >= 4 && a.rx_packets >= 3);
+            assert_eq!((a.tx_packets, a.tx_bytes), (b.rx_packets, b.rx_bytes));
+            assert_eq!((a.rx_dropped, a.tx_buffer_full, a.tcp_retransmissions), (0, 0, 0));
+            #[cfg(feature = "stats-checksums")]
+            assert_eq!(a.rx_checksum_errors, 0);
+        });
+    }
+
+    #[test]
+    fn counts_retransmissions() {
+        let (control, switch, [device_a, device_b]) = test_util::link();
+        let (a, runner_a) = test_util::stack(device_a, static_config(1));
+        let (b, runner_b) = test_util::stack(device_b, static_config(2));
+        test_util::run_with([switch], [runner_a, runner_b], async {
+            let mut client = socket(a);
+            let mut server = socket(b);
+            let serve = async {
+                unwrap!(server.accept(PORT).await);
+                let mut buf = [0; 16];
+                unwrap!(server.read(&mut buf).await);
+            };
+            let send = async {
+                unwrap!(client.connect((addr(2), PORT)).await);
+                // Lose the segments sent by the client, until it sent the data a few times.
+                let mut lossy = LinkConfig::new();
+                lossy.loss_percent = 100;
+                control.set_config(0, lossy);
+                unwrap!(client.write(b"hello").await);
+                wait_for(|| a.stats().tcp_retransmissions >= 2).await;
+                control.set_config(0, LinkConfig::new());
+                unwrap!(client.flush().await);
+            };
+            join(serve, send).await;
+
+            assert!(client.stats().retransmissions >= 2);
+            assert_eq!(client.stats().retransmissions, a.stats().tcp_retransmissions);
+        });
+    }
+}
@@ -2,24 +2,29 @@
 //!
 //! # Listening
 //!
-//! `embassy-net` does not have a `TcpListener`. Instead, individual `TcpSocket`s can be put into
-//! listening mode by calling [`TcpSocket::accept`].
+//! Individual `TcpSocket`s can be put into listening mode by calling [`TcpSocket::accept`].
 //!
 //! Incoming connections when no socket is listening are rejected. To accept many incoming
-//! connections, create many sockets and put them all into listening mode.
+//! connections, create many sockets and put them all into listening mode, or use a
+//! [`TcpListener`](listener::TcpListener), which manages a pool of sockets listening on the same port.
 
+#[cfg(feature = "stats")]
+use core::cell::Cell;
 use core::future::{poll_fn, Future};
 use core::mem;
+use core::pin::pin;
 use core::task::{Context, Poll};
 
-use embassy_time::Duration;
-use smoltcp::iface::{Interface, SocketHandle};
+use embassy_time::{with_timeout, Duration, Timer};
+use smoltcp::iface::Interface;
 use smoltcp::socket::tcp;
 pub use smoltcp::socket::tcp::State;
-use smoltcp::wire::{IpEndpoint, IpListenEndpoint};
+use smoltcp::wire::{IpAddress, IpEndpoint, IpListenEndpoint};
 
+#[cfg(feature = "stats")]
+use crate::stats::TcpStats;
 use crate::time::duration_to_smoltcp;
-use crate::Stack;
+use crate::{InterfaceId, SocketHandle, SocketSetFull, Stack};
 
 /// Error returned by TcpSocket read/write functions.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -43,6 +48,8 @@ pub enum ConnectError {
     TimedOut,
     /// No route to host.
     NoRoute,
+    /// The interface routing to the remote host has no room for the socket.
+    SocketSetFull,
 }
 
 /// Error returned by [`TcpSocket::accept`].
@@ -55,21 +62,33 @@ pub enum AcceptError {
     InvalidPort,
     /// The remote host rejected the connection with a RST packet.
     ConnectionReset,
+    /// The interface that has the local address has no room for the socket.
+    SocketSetFull,
 }
 
 /// A TCP socket.
 pub struct TcpSocket<'a> {
     io: TcpIo<'a>,
+    interface: Option<InterfaceId>,
+    #[cfg(feature = "stats")]
+    stats: Cell<TcpStats>,
+    /// Local and remote endpoints of the connection the counters are for.
+    #[cfg(feature = "stats")]
+    connection: Cell<Option<(IpEndpoint, IpEndpoint)>>,
 }
 
 /// The reader half of a TCP socket.
 pub struct TcpReader<'a> {
     io: TcpIo<'a>,
+    #[cfg(feature = "stats")]
+    stats: &'a Cell<TcpStats>,
 }
 
 /// The writer half of a TCP socket.
 pub struct TcpWriter<'a> {
     io: TcpIo<'a>,
+    #[cfg(feature = "stats")]
+    stats: &'a Cell<TcpStats>,
 }
 
 impl<'a> TcpReader<'a> {
@@ -97,7 +116,10 @@ impl<'a> TcpReader<'a> {
     /// the socket you split this reader off the send half needs to be closed using
     /// [`abort()`](TcpSocket::abort).
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
-        self.io.read(buf).await
+        let n = self.io.read(buf).await?;
+        #[cfg(feature = "stats")]
+        count_received(self.stats, n);
+        Ok(n)
     }
 
     /// Call `f` with the largest contiguous slice of octets in the receive buffer,
@@ -108,6 +130,8 @@ impl<'a> TcpReader<'a> {
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
     {
+        #[cfg(feature = "stats")]
+        let f = counted(self.stats, f, count_received);
         self.io.read_with(f).await
     }
 
@@ -140,7 +164,15 @@ impl<'a> TcpWriter<'a> {
     /// Returns how many bytes were written, or an error. If the socket is not ready to
     /// accept data, it waits until it is.
     pub fn write<'s>(&'s mut self, buf: &'s [u8]) -> impl Future<Output = Result<usize, Error>> + 's {
-        self.io.write(buf)
+        #[cfg(feature = "stats")]
+        let stats = self.stats;
+        let write = self.io.write(buf);
+        async move {
+            let n = write.await?;
+            #[cfg(feature = "stats")]
+            count_sent(stats, n);
+            Ok(n)
+        }
     }
 
     /// Flushes the written data to the socket.
@@ -159,6 +191,8 @@ impl<'a> TcpWriter<'a> {
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
     {
+        #[cfg(feature = "stats")]
+        let f = counted(self.stats, f, count_sent);
         self.io.write_with(f).await
     }
 
@@ -179,17 +213,46 @@ impl<'a> TcpSocket<'a> {
         let handle = stack.with_mut(|i| {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(tcp::Socket::new(
-                tcp::SocketBuffer::new(rx_buffer),
-                tcp::SocketBuffer::new(tx_buffer),
-            ))
+            i.add_socket(
+                InterfaceId::PRIMARY,
+                tcp::Socket::new(tcp::SocketBuffer::new(rx_buffer), tcp::SocketBuffer::new(tx_buffer)),
+            )
         });
 
         Self {
             io: TcpIo { stack, handle },
+            interface: None,
+            #[cfg(feature = "stats")]
+            stats: Cell::new(TcpStats::default()),
+            #[cfg(feature = "stats")]
+            connection: Cell::new(None),
         }
     }
 
+    /// Bind the socket to a network interface of the stack.
+    ///
+    /// The socket is moved to the interface the next time it connects or starts listening.
+    /// Otherwise, the socket is moved to the interface given by the routing table when
+    /// connecting, or to the interface that has the local address given when listening.
+    /// See [`Stack::add_interface`] for details.
+    pub fn bind_to_interface(&mut self, interface: InterfaceId) {
+        self.interface = Some(interface);
+    }
+
+    /// Move the closed socket to the interface it's bound to, or to `interface`.
+    fn move_to(&mut self, interface: Option<InterfaceId>) -> Result<(), SocketSetFull> {
+        let Some(interface) = self.interface.or(interface) else {
+            return Ok(());
+        };
+        self.io.stack.with_mut(|i| {
+            let (s, _) = i.socket::<tcp::Socket>(self.io.handle);
+            if !s.is_open() {
+                self.io.handle = i.move_socket(self.io.handle, interface)?;
+            }
+            Ok(())
+        })
+    }
+
     /// Return the maximum number of bytes inside the recv buffer.
     pub fn recv_capacity(&self) -> usize {
         self.io.recv_capacity()
@@ -220,6 +283,8 @@ impl<'a> TcpSocket<'a> {
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
     {
+        #[cfg(feature = "stats")]
+        let f = counted(&self.stats, f, count_sent);
         self.io.write_with(f).await
     }
 
@@ -231,12 +296,51 @@ impl<'a> TcpSocket<'a> {
     where
         F: FnOnce(&mut [u8]) -> (usize, R),
     {
+        #[cfg(feature = "stats")]
+        let f = counted(&self.stats, f, count_received);
         self.io.read_with(f).await
     }
 
     /// Split the socket into reader and a writer halves.
     pub fn split(&mut self) -> (TcpReader<'_>, TcpWriter<'_>) {
-        (TcpReader { io: self.io }, TcpWriter { io: self.io })
+        (
+            TcpReader {
+                io: self.io,
+                #[cfg(feature = "stats")]
+                stats: &self.stats,
+            },
+            TcpWriter {
+                io: self.io,
+                #[cfg(feature = "stats")]
+                stats: &self.stats,
+            },
+        )
+    }
+
+    /// Get the counters of the socket.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> TcpStats {
+        let mut stats = self.stats.get();
+        if let Some((local, remote)) = self.connection.get() {
+            let iface = self.io.handle.iface;
+            let retransmissions = self
+                .io
+                .stack
+                .with(|i| i.iface(iface).tcp_tracker.retransmissions(local, remote));
+            if let Some(retransmissions) = retransmissions {
+                stats.retransmissions = retransmissions;
+                self.stats.set(stats);
+            }
+        }
+        stats
+    }
+
+    /// Reset the counters for a new connection.
+    #[cfg(feature = "stats")]
+    fn reset_stats(&self) {
+        self.stats.set(TcpStats::default());
+        let connection = self.io.with(|s, _| s.local_endpoint().zip(s.remote_endpoint()));
+        self.connection.set(connection);
     }
 
     /// Connect to a remote host.
@@ -244,13 +348,20 @@ impl<'a> TcpSocket<'a> {
     where
         T: Into<IpEndpoint>,
     {
+        let remote_endpoint = remote_endpoint.into();
+        let interface = self.io.stack.with(|i| i.route(remote_endpoint.addr));
+        self.move_to(interface).map_err(|_| ConnectError::SocketSetFull)?;
+
         let local_port = self.io.stack.with_mut(|i| i.get_local_port());
 
         match {
             self.io
                 .with_mut(|s, i| s.connect(i.context(), remote_endpoint, local_port))
         } {
-            Ok(()) => {}
+            Ok(()) => {
+                #[cfg(feature = "stats")]
+                self.reset_stats();
+            }
             Err(tcp::ConnectError::InvalidState) => return Err(ConnectError::InvalidState),
             Err(tcp::ConnectError::Unaddressable) => return Err(ConnectError::NoRoute),
         }
@@ -276,6 +387,12 @@ impl<'a> TcpSocket<'a> {
     where
         T: Into<IpListenEndpoint>,
     {
+        let local_endpoint = local_endpoint.into();
+        let interface = local_endpoint
+            .addr
+            .and_then(|addr| self.io.stack.with(|i| i.interface_with_addr(addr)));
+        self.move_to(interface).map_err(|_| AcceptError::SocketSetFull)?;
+
         match self.io.with_mut(|s, _| s.listen(local_endpoint)) {
             Ok(()) => {}
             Err(tcp::ListenError::InvalidState) => return Err(AcceptError::InvalidState),
@@ -291,7 +408,10 @@ impl<'a> TcpSocket<'a> {
                 _ => Poll::Ready(Ok(())),
             })
         })
-        .await
+        .await?;
+        #[cfg(feature = "stats")]
+        self.reset_stats();
+        Ok(())
     }
 
     /// Wait until the socket becomes readable.
@@ -312,7 +432,15 @@ impl<'a> TcpSocket<'a> {
     /// A return value of Ok(0) means that the socket was closed and is longer
     /// able to receive any data.
     pub fn read<'s>(&'s mut self, buf: &'s mut [u8]) -> impl Future<Output = Result<usize, Error>> + 's {
-        self.io.read(buf)
+        #[cfg(feature = "stats")]
+        let stats = &self.stats;
+        let read = self.io.read(buf);
+        async move {
+            let n = read.await?;
+            #[cfg(feature = "stats")]
+            count_received(stats, n);
+            Ok(n)
+        }
     }
 
     /// Wait until the socket becomes writable.
@@ -330,7 +458,15 @@ impl<'a> TcpSocket<'a> {
     /// Returns how many bytes were written, or an error. If the socket is not ready to
     /// accept data, it waits until it is.
     pub fn write<'s>(&'s mut self, buf: &'s [u8]) -> impl Future<Output = Result<usize, Error>> + 's {
-        self.io.write(buf)
+        #[cfg(feature = "stats")]
+        let stats = &self.stats;
+        let write = self.io.write(buf);
+        async move {
+            let n = write.await?;
+            #[cfg(feature = "stats")]
+            count_sent(stats, n);
+            Ok(n)
+        }
     }
 
     /// Flushes the written data to the socket.
@@ -451,7 +587,7 @@ impl<'a> TcpSocket<'a> {
 
 impl<'a> Drop for TcpSocket<'a> {
     fn drop(&mut self) {
-        self.io.stack.with_mut(|i| i.sockets.remove(self.io.handle));
+        self.io.stack.with_mut(|i| i.remove_socket(self.io.handle));
     }
 }
 
@@ -467,6 +603,37 @@ fn _assert_covariant_writer<'a, 'b: 'a>(x: TcpWriter<'b>) -> TcpWriter<'a> {
 
 // =======================
 
+#[cfg(feature = "stats")]
+fn count_sent(stats: &Cell<TcpStats>, n: usize) {
+    let mut s = stats.get();
+    s.bytes_sent = s.bytes_sent.wrapping_add(n as u64);
+    stats.set(s);
+}
+
+#[cfg(feature = "stats")]
+fn count_received(stats: &Cell<TcpStats>, n: usize) {
+    let mut s = stats.get();
+    s.bytes_received = s.bytes_received.wrapping_add(n as u64);
+    stats.set(s);
+}
+
+/// Wrap a `read_with`/`write_with` closure so the bytes it consumes are counted with `count`.
+#[cfg(feature = "stats")]
+fn counted<'c, F, R>(
+    stats: &'c Cell<TcpStats>,
+    f: F,
+    count: fn(&Cell<TcpStats>, usize),
+) -> impl FnOnce(&mut [u8]) -> (usize, R) + 'c
+where
+    F: FnOnce(&mut [u8]) -> (usize, R) + 'c,
+{
+    move |buf| {
+        let (n, r) = f(buf);
+        count(stats, n);
+        (n, r)
+    }
+}
+
 #[derive(Copy, Clone)]
 struct TcpIo<'a> {
     stack: Stack<'a>,
@@ -476,16 +643,16 @@ struct TcpIo<'a> {
 impl<'d> TcpIo<'d> {
     fn with<R>(&self, f: impl FnOnce(&tcp::Socket, &Interface) -> R) -> R {
         self.stack.with(|i| {
-            let socket = i.sockets.get::<tcp::Socket>(self.handle);
-            f(socket, &i.iface)
+            let (socket, iface) = i.socket::<tcp::Socket>(self.handle);
+            f(socket, iface)
         })
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut tcp::Socket, &mut Interface) -> R) -> R {
         self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<tcp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
+            let (socket, iface) = i.socket_mut::<tcp::Socket>(self.handle);
+            let res = f(socket, iface);
+            i.wake(self.handle);
             res
         })
     }
@@ -663,6 +830,7 @@ mod embedded_io_impls {
                 ConnectError::TimedOut => embedded_io_async::ErrorKind::TimedOut,
                 ConnectError::NoRoute => embedded_io_async::ErrorKind::NotConnected,
                 ConnectError::InvalidState => embedded_io_async::ErrorKind::Other,
+                ConnectError::SocketSetFull => embedded_io_async::ErrorKind::OutOfMemory,
             }
         }
     }
@@ -681,7 +849,7 @@ mod embedded_io_impls {
 
     impl<'d> embedded_io_async::Read for TcpSocket<'d> {
         async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-            self.io.read(buf).await
+            TcpSocket::read(self, buf).await
         }
     }
 
@@ -693,7 +861,7 @@ mod embedded_io_impls {
 
     impl<'d> embedded_io_async::Write for TcpSocket<'d> {
         async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-            self.io.write(buf).await
+            TcpSocket::write(self, buf).await
         }
 
         async fn flush(&mut self) -> Result<(), Self::Error> {
@@ -713,7 +881,7 @@ mod embedded_io_impls {
 
     impl<'d> embedded_io_async::Read for TcpReader<'d> {
         async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-            self.io.read(buf).await
+            TcpReader::read(self, buf).await
         }
     }
 
@@ -729,7 +897,7 @@ mod embedded_io_impls {
 
     impl<'d> embedded_io_async::Write for TcpWriter<'d> {
         async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-            self.io.write(buf).await
+            TcpWriter::write(self, buf).await
         }
 
         async fn flush(&mut self) -> Result<(), Self::Error> {
@@ -746,12 +914,11 @@ mod embedded_io_impls {
 
 /// TCP client compatible with `embedded-nal-async` traits.
 pub mod client {
-    use core::cell::{Cell, UnsafeCell};
-    use core::mem::MaybeUninit;
     use core::net::IpAddr;
     use core::ptr::NonNull;
 
     use super::*;
+    use crate::pool::Pool;
 
     /// TCP client connection pool compatible with `embedded-nal-async` traits.
     ///
@@ -877,44 +1044,807 @@ pub mod client {
             Self { pool: Pool::new() }
         }
     }
+}
+
+/// TCP listener with an accept backlog.
+pub mod listener {
+    use core::cell::{Cell, RefCell};
+    use core::ops::Deref;
+
+    use embassy_sync::waitqueue::MultiWakerRegistration;
+
+    use super::*;
+
+    /// TCP listener accepting up to N concurrent connections on a local endpoint.
+    ///
+    /// All the listener's sockets listen on the same endpoint, so up to N handshakes can be in progress
+    /// at the same time. [`accept`](Self::accept) returns whichever connection is established first.
+    /// When the returned [`TcpConnection`] is dropped, its socket goes back to listening.
+    pub struct TcpListener<'d, const N: usize> {
+        sockets: [TcpSocket<'d>; N],
+        accepted: [Cell<bool>; N],
+        wakers: RefCell<MultiWakerRegistration<N>>,
+        local_endpoint: IpListenEndpoint,
+    }
+
+    impl<'d, const N: usize> TcpListener<'d, N> {
+        /// Create a new `TcpListener`, and start listening on `local_endpoint`.
+        ///
+        /// The sockets listen on the interface that has the address of `local_endpoint`, or on
+        /// the primary interface if it has no address.
+        ///
+        /// # Panics
+        ///
+        /// Panics if the port of `local_endpoint` is 0, or if the interface that has its address has
+        /// no room for the sockets.
+        pub fn new<T, const TX_SZ: usize, const RX_SZ: usize>(
+            stack: Stack<'d>,
+            state: &'d mut TcpListenerState<N, TX_SZ, RX_SZ>,
+            local_endpoint: T,
+        ) -> Self
+        where
+            T: Into<IpListenEndpoint>,
+        {
+            let local_endpoint = local_endpoint.into();
+            assert!(local_endpoint.port != 0, "listen port must not be 0");
+            let interface = local_endpoint
+                .addr
+                .and_then(|addr| stack.with(|i| i.interface_with_addr(addr)));
+
+            let mut bufs = state.bufs.iter_mut();
+            let this = Self {
+                sockets: core::array::from_fn(|_| {
+                    let (tx, rx) = unwrap!(bufs.next());
+                    let mut socket = TcpSocket::new(stack, rx, tx);
+                    unwrap!(socket.move_to(interface));
+                    socket
+                }),
+                accepted: [const { Cell::new(false) }; N],
+                wakers: RefCell::new(MultiWakerRegistration::new()),
+                local_endpoint,
+            };
+            for socket in &this.sockets {
+                this.relisten(socket);
+            }
+            this
+        }
+
+        /// Set the timeout for each socket of this `TcpListener`.
+        ///
+        /// If the timeout is set, the socket will be closed if no data is received for the
+        /// specified duration.
+        pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+            for socket in &mut self.sockets {
+                socket.set_timeout(timeout);
+            }
+        }
+
+        /// Set the keep-alive interval for each socket of this `TcpListener`.
+        pub fn set_keep_alive(&mut self, interval: Option<Duration>) {
+            for socket in &mut self.sockets {
+                socket.set_keep_alive(interval);
+            }
+        }
 
-    struct Pool<T, const N: usize> {
-        used: [Cell<bool>; N],
-        data: [UnsafeCell<MaybeUninit<T>>; N],
+        /// Get the local endpoint the listener is listening on.
+        pub fn local_endpoint(&self) -> IpListenEndpoint {
+            self.local_endpoint
+        }
+
+        /// Wait for an incoming connection.
+        ///
+        /// Returns the first connection whose handshake completes. If all the listener's sockets are
+        /// handling accepted connections, this waits until one of them is dropped.
+        ///
+        /// Multiple tasks can wait for connections on the same listener concurrently.
+        pub async fn accept(&self) -> TcpConnection<'_, 'd, N> {
+            poll_fn(|cx| {
+                for (index, socket) in self.sockets.iter().enumerate() {
+                    if self.accepted[index].get() {
+                        continue;
+                    }
+                    let established = socket.io.with_mut(|s, _| match s.state() {
+                        tcp::State::Closed | tcp::State::TimeWait => {
+                            // The handshake was aborted, or the socket was closed after its connection was dropped.
+                            let _ = s.listen(self.local_endpoint);
+                            s.register_send_waker(cx.waker());
+                            false
+                        }
+                        tcp::State::Established | tcp::State::CloseWait => true,
+                        // Handshake in progress, or closing after its connection was dropped.
+                        _ => {
+                            s.register_send_waker(cx.waker());
+                            false
+                        }
+                    });
+                    if established {
+                        self.accepted[index].set(true);
+                        #[cfg(feature = "stats")]
+                        socket.reset_stats();
+                        // Other tasks waiting for connections may have been woken instead of this one, and
+                        // their wakers replaced in the sockets. Let them register again.
+                        self.wakers.borrow_mut().wake();
+                        return Poll::Ready(TcpConnection { listener: self, index });
+                    }
+                }
+                self.wakers.borrow_mut().register(cx.waker());
+                Poll::Pending
+            })
+            .await
+        }
+
+        fn relisten(&self, socket: &TcpSocket<'d>) {
+            socket.io.with_mut(|s, _| {
+                if matches!(s.state(), tcp::State::Closed | tcp::State::TimeWait) {
+                    // The endpoint was checked on creation, and the socket is not open.
+                    unwrap!(s.listen(self.local_endpoint));
+                }
+            })
+        }
+
+        fn release(&self, index: usize) {
+            let socket = &self.sockets[index];
+            socket.io.with_mut(|s, _| s.close());
+            // If the connection was already closed the socket can listen right away. Otherwise, it
+            // starts listening again once it's done closing, in `accept`.
+            self.relisten(socket);
+            self.accepted[index].set(false);
+            self.wakers.borrow_mut().wake();
+        }
+    }
+
+    /// Connection accepted by a [`TcpListener`].
+    ///
+    /// The connection is closed when dropped, and its socket goes back to listening for new connections.
+    /// It dereferences to the underlying [`TcpSocket`] to query the connection's state and endpoints.
+    pub struct TcpConnection<'l, 'd, const N: usize> {
+        listener: &'l TcpListener<'d, N>,
+        index: usize,
+    }
+
+    impl<'l, 'd, const N: usize> TcpConnection<'l, 'd, N> {
+        fn io(&self) -> TcpIo<'d> {
+            self.listener.sockets[self.index].io
+        }
+
+        /// Read data from the connection.
+        ///
+        /// See [`TcpSocket::read`].
+        pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let n = self.io().read(buf).await?;
+            #[cfg(feature = "stats")]
+            count_received(&self.stats, n);
+            Ok(n)
+        }
+
+        /// Write data to the connection.
+        ///
+        /// See [`TcpSocket::write`].
+        pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+            let n = self.io().write(buf).await?;
+            #[cfg(feature = "stats")]
+            count_sent(&self.stats, n);
+            Ok(n)
+        }
+
+        /// Flush the data written to the connection.
+        ///
+        /// See [`TcpSocket::flush`].
+        pub async fn flush(&mut self) -> Result<(), Error> {
+            self.io().flush().await
+        }
+
+        /// Split the connection into reader and a writer halves.
+        pub fn split(&mut self) -> (TcpReader<'_>, TcpWriter<'_>) {
+            let io = self.io();
+            (
+                TcpReader {
+                    io,
+                    #[cfg(feature = "stats")]
+                    stats: &self.listener.sockets[self.index].stats,
+                },
+                TcpWriter {
+                    io,
+                    #[cfg(feature = "stats")]
+                    stats: &self.listener.sockets[self.index].stats,
+                },
+            )
+        }
+    }
+
+    impl<'l, 'd, const N: usize> Deref for TcpConnection<'l, 'd, N> {
+        type Target = TcpSocket<'d>;
+
+        fn deref(&self) -> &Self::Target {
+            &self.listener.sockets[self.index]
+        }
+    }
+
+    impl<'l, 'd, const N: usize> Drop for TcpConnection<'l, 'd, N> {
+        fn drop(&mut self) {
+            self.listener.release(self.index);
+        }
     }
 
-    impl<T, const N: usize> Pool<T, N> {
-        const VALUE: Cell<bool> = Cell::new(false);
-        const UNINIT: UnsafeCell<MaybeUninit<T>> = UnsafeCell::new(MaybeUninit::uninit());
+    impl<'l, 'd, const N: usize> embedded_io_async::ErrorType for TcpConnection<'l, 'd, N> {
+        type Error = Error;
+    }
 
-        const fn new() -> Self {
+    impl<'l, 'd, const N: usize> embedded_io_async::Read for TcpConnection<'l, 'd, N> {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            TcpConnection::read(self, buf).await
+        }
+    }
+
+    impl<'l, 'd, const N: usize> embedded_io_async::ReadReady for TcpConnection<'l, 'd, N> {
+        fn read_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.io().with(|s, _| s.can_recv() || !s.may_recv()))
+        }
+    }
+
+    impl<'l, 'd, const N: usize> embedded_io_async::Write for TcpConnection<'l, 'd, N> {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            TcpConnection::write(self, buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.io().flush().await
+        }
+    }
+
+    impl<'l, 'd, const N: usize> embedded_io_async::WriteReady for TcpConnection<'l, 'd, N> {
+        fn write_ready(&mut self) -> Result<bool, Self::Error> {
+            Ok(self.io().with(|s, _| s.can_send()))
+        }
+    }
+
+    /// State for TcpListener
+    pub struct TcpListenerState<const N: usize, const TX_SZ: usize = 1024, const RX_SZ: usize = 1024> {
+        bufs: [([u8; TX_SZ], [u8; RX_SZ]); N],
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> TcpListenerState<N, TX_SZ, RX_SZ> {
+        /// Create a new `TcpListenerState`.
+        pub const fn new() -> Self {
             Self {
-                used: [Self::VALUE; N],
-                data: [Self::UNINIT; N],
+                bufs: [([0; TX_SZ], [0; RX_SZ]); N],
             }
         }
     }
 
-    impl<T, const N: usize> Pool<T, N> {
-        fn alloc(&self) -> Option<NonNull<T>> {
-            for n in 0..N {
-                // this can't race because Pool is not Sync.
-                if !self.used[n].get() {
-                    self.used[n].set(true);
-                    let p = self.data[n].get() as *mut T;
-                    return Some(unsafe { NonNull::new_unchecked(p) });
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize> Default for TcpListenerState<N, TX_SZ, RX_SZ> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+/// Remote host of a [`ReconnectingClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Remote<'a> {
+    /// Remote endpoint.
+    Endpoint(IpEndpoint),
+    /// Host name or IP address, and port.
+    ///
+    /// The name is resolved again before each connection. Without the `dns` feature, only IP
+    /// addresses are supported.
+    Host(&'a str, u16),
+}
+
+impl From<IpEndpoint> for Remote<'_> {
+    fn from(endpoint: IpEndpoint) -> Self {
+        Self::Endpoint(endpoint)
+    }
+}
+
+/// Configuration of a [`ReconnectingClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub struct ReconnectConfig {
+    /// Time to wait for a connection to be established.
+    pub connect_timeout: Duration,
+    /// Timeout of the socket, see [`TcpSocket::set_timeout`].
+    pub timeout: Option<Duration>,
+    /// Keep-alive interval of the socket, see [`TcpSocket::set_keep_alive`].
+    pub keep_alive: Option<Duration>,
+    /// Delay before the first reconnection attempt.
+    pub min_reconnect_delay: Duration,
+    /// Maximum delay between reconnection attempts.
+    pub max_reconnect_delay: Duration,
+}
+
+impl ReconnectConfig {
+    /// Create a new `ReconnectConfig`.
+    ///
+    /// Keep-alive packets are sent after 30 seconds of inactivity, and the connection is
+    /// considered lost if the remote doesn't answer them for 90 seconds.
+    pub const fn new() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            timeout: Some(Duration::from_secs(90)),
+            keep_alive: Some(Duration::from_secs(30)),
+            min_reconnect_delay: Duration::from_secs(1),
+            max_reconnect_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error returned by [`ReconnectingClient`] read/write functions.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReconnectError {
+    /// The connection was lost, and a new one was established.
+    ///
+    /// Data written to the previous connection may not have been received by the remote host,
+    /// and any protocol state bound to the connection must be set up again.
+    Reconnected,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ConnState {
+    /// Never connected.
+    Idle,
+    /// Connected from the local address.
+    Connected(IpAddress),
+    /// The connection was lost.
+    Lost,
+}
+
+/// Time to wait for the reset of a lost connection to be sent.
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// TCP client that reconnects to its remote host when the connection is lost.
+///
+/// The client connects on the first read or write, once the network is configured. The connection
+/// is lost when the socket fails, the remote host closes it, the link goes down or the local address
+/// is no longer assigned, such as after DHCP leases a different address. The client then reconnects,
+/// with an exponential backoff between attempts, and the read or write returns
+/// [`ReconnectError::Reconnected`], for the application to start over on the new connection.
+pub struct ReconnectingClient<'d> {
+    stack: Stack<'d>,
+    socket: TcpSocket<'d>,
+    remote: Remote<'d>,
+    config: ReconnectConfig,
+    state: ConnState,
+    /// State of the pseudo-random generator jittering the reconnection delays.
+    jitter: u32,
+}
+
+impl<'d> ReconnectingClient<'d> {
+    /// Create a new `ReconnectingClient`.
+    pub fn new(
+        stack: Stack<'d>,
+        rx_buffer: &'d mut [u8],
+        tx_buffer: &'d mut [u8],
+        remote: impl Into<Remote<'d>>,
+        config: ReconnectConfig,
+    ) -> Self {
+        let seed = stack.random_seed();
+        Self {
+            stack,
+            socket: TcpSocket::new(stack, rx_buffer, tx_buffer),
+            remote: remote.into(),
+            config,
+            state: ConnState::Idle,
+            jitter: (seed as u32 ^ (seed >> 32) as u32) | 1,
+        }
+    }
+
+    /// Whether the client is connected.
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ConnState::Connected(_))
+    }
+
+    /// Get the remote endpoint of the connection.
+    ///
+    /// Returns `None` if the client is not connected.
+    pub fn remote_endpoint(&self) -> Option<IpEndpoint> {
+        self.is_connected().then(|| self.socket.remote_endpoint()).flatten()
+    }
+
+    /// Wait until the client is connected, connecting if needed.
+    ///
+    /// Returns [`ReconnectError::Reconnected`] if an earlier connection was lost.
+    pub async fn connect(&mut self) -> Result<(), ReconnectError> {
+        self.connected().await.map(|_| ())
+    }
+
+    /// Close the connection, gracefully. The next read or write connects again.
+    pub async fn disconnect(&mut self) {
+        if self.is_connected() {
+            self.socket.close();
+            let _ = with_timeout(self.config.connect_timeout, self.socket.flush()).await;
+            self.socket.abort();
+            let _ = with_timeout(RESET_TIMEOUT, self.socket.flush()).await;
+            self.state = ConnState::Idle;
+        }
+    }
+
+    /// Read data from the connection, connecting first if needed.
+    ///
+    /// The end of the stream is a lost connection, `Ok(0)` is only returned if `buf` is empty.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReconnectError> {
+        let local = self.connected().await?;
+        let read = supervised(self.stack, local, self.socket.read(buf)).await;
+        match read {
+            Some(Ok(n)) if n > 0 || buf.is_empty() => Ok(n),
+            _ => Err(self.reconnect().await),
+        }
+    }
+
+    /// Write data to the connection, connecting first if needed.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, ReconnectError> {
+        let local = self.writable().await?;
+        match supervised(self.stack, local, self.socket.write(buf)).await {
+            Some(Ok(n)) => Ok(n),
+            _ => Err(self.reconnect().await),
+        }
+    }
+
+    /// Wait until the written data has been sent and acknowledged by the remote host.
+    pub async fn flush(&mut self) -> Result<(), ReconnectError> {
+        let local = self.writable().await?;
+        match supervised(self.stack, local, self.socket.flush()).await {
+            Some(Ok(())) => Ok(()),
+            _ => Err(self.reconnect().await),
+        }
+    }
+
+    /// Connect if needed, and return the local address of the connection.
+    async fn connected(&mut self) -> Result<IpAddress, ReconnectError> {
+        match self.state {
+            ConnState::Connected(local) => Ok(local),
+            ConnState::Idle => Ok(self.establish().await),
+            ConnState::Lost => {
+                self.establish().await;
+                Err(ReconnectError::Reconnected)
+            }
+        }
+    }
+
+    /// Connect if needed, and return the local address of a connection the remote host didn't close.
+    async fn writable(&mut self) -> Result<IpAddress, ReconnectError> {
+        let local = self.connected().await?;
+        // Once the remote host closed the connection, it doesn't read what's written anymore.
+        if self.socket.state() != State::Established {
+            return Err(self.reconnect().await);
+        }
+        Ok(local)
+    }
+
+    /// Drop the lost connection and establish a new one.
+    async fn reconnect(&mut self) -> ReconnectError {
+        warn!("tcp: connection to {:?} lost", self.remote);
+        self.state = ConnState::Lost;
+        self.reset().await;
+        self.establish().await;
+        ReconnectError::Reconnected
+    }
+
+    /// Connect, retrying with an exponential backoff until it succeeds, and return the local address.
+    async fn establish(&mut self) -> IpAddress {
+        let mut delay = self.config.min_reconnect_delay;
+        loop {
+            self.stack.wait_config_up().await;
+            match self.try_connect().await {
+                Ok(local) => {
+                    debug!("tcp: connected to {:?}", self.remote);
+                    self.state = ConnState::Connected(local);
+                    return local;
+                }
+                Err(e) => {
+                    warn!("tcp: failed to connect to {:?}: {:?}", self.remote, e);
+                    self.reset().await;
                 }
             }
+            Timer::after(self.jittered(delay)).await;
+            delay = (delay * 2).min(self.config.max_reconnect_delay);
+        }
+    }
+
+    async fn try_connect(&mut self) -> Result<IpAddress, ConnectError> {
+        let endpoint = self.resolve().await.ok_or(ConnectError::NoRoute)?;
+        self.socket.set_timeout(self.config.timeout);
+        self.socket.set_keep_alive(self.config.keep_alive);
+        match with_timeout(self.config.connect_timeout, self.socket.connect(endpoint)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Err(ConnectError::TimedOut),
+        }
+        self.socket
+            .local_endpoint()
+            .map(|local| local.addr)
+            .ok_or(ConnectError::ConnectionReset)
+    }
+
+    async fn resolve(&self) -> Option<IpEndpoint> {
+        let (name, port) = match self.remote {
+            Remote::Endpoint(endpoint) => return Some(endpoint),
+            Remote::Host(name, port) => (name, port),
+        };
+        #[cfg(feature = "proto-ipv4")]
+        if let Ok(addr) = name.parse() {
+            return Some(IpEndpoint::new(IpAddress::Ipv4(addr), port));
+        }
+        #[cfg(feature = "proto-ipv6")]
+        if let Ok(addr) = name.parse() {
+            return Some(IpEndpoint::new(IpAddress::Ipv6(addr), port));
+        }
+
+        // IPv6 addresses are looked up when the name has no IPv4 address.
+        #[cfg(all(feature = "dns", feature = "proto-ipv4"))]
+        if let Some(addr) = self.lookup(name, crate::dns::DnsQueryType::A).await {
+            return Some(IpEndpoint::new(addr, port));
+        }
+        #[cfg(all(feature = "dns", feature = "proto-ipv6"))]
+        if let Some(addr) = self.lookup(name, crate::dns::DnsQueryType::Aaaa).await {
+            return Some(IpEndpoint::new(addr, port));
+        }
+        #[cfg(feature = "dns")]
+        {
+            debug!("tcp: failed to resolve {}", name);
             None
         }
+        #[cfg(not(feature = "dns"))]
+        {
+            debug!("tcp: {} is not an IP address", name);
+            None
+        }
+    }
+
+    /// Look up the first address of `qtype` of `name`.
+    #[cfg(feature = "dns")]
+    async fn lookup(&self, name: &str, qtype: crate::dns::DnsQueryType) -> Option<IpAddress> {
+        match self.stack.dns_query(name, qtype).await {
+            Ok(addrs) => addrs.first().copied(),
+            Err(e) => {
+                debug!("tcp: failed to resolve {} ({:?}): {:?}", name, qtype, e);
+                None
+            }
+        }
+    }
+
+    /// Abort the connection, so the socket can connect again.
+    async fn reset(&mut self) {
+        self.socket.abort();
+        let _ = with_timeout(RESET_TIMEOUT, self.socket.flush()).await;
+    }
+
+    /// Randomize `delay` between half and all of it, so that clients don't reconnect in lockstep.
+    fn jittered(&mut self, delay: Duration) -> Duration {
+        // xorshift32
+        let mut x = self.jitter;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.jitter = x;
+        let half = delay.as_ticks() / 2;
+        Duration::from_ticks(half + x as u64 % (half + 1))
+    }
+}
+
+/// Run `io`, or return `None` if the connection from `local` is lost before it completes.
+async fn supervised<F: Future>(stack: Stack<'_>, local: IpAddress, io: F) -> Option<F::Output> {
+    let mut io = pin!(io);
+    let mut lost = pin!(stack.wait(move || {
+        stack.with(|i| match i.interface_with_addr(local) {
+            Some(id) => !i.iface(id).link_up,
+            None => true,
+        })
+    }));
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = io.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        lost.as_mut().poll(cx).map(|()| None)
+    })
+    .await
+}
 
-        /// safety: p must be a pointer obtained from self.alloc that hasn't been freed yet.
-        unsafe fn free(&self, p: NonNull<T>) {
-            let origin = self.data.as_ptr() as *mut T;
-            let n = p.as_ptr().offset_from(origin);
-            assert!(n >= 0);
-            assert!((n as usize) < N);
-            self.used[n as usize].set(false);
+impl embedded_io_async::Error for ReconnectError {
+    fn kind(&self) -> embedded_io_async::ErrorKind {
+        match self {
+            ReconnectError::Reconnected => embedded_io_async::ErrorKind::ConnectionReset,
         }
     }
 }
+
+impl embedded_io_async::ErrorType for ReconnectingClient<'_> {
+    type Error = ReconnectError;
+}
+
+impl embedded_io_async::Read for ReconnectingClient<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        ReconnectingClient::read(self, buf).await
+    }
+}
+
+impl embedded_io_async::Write for ReconnectingClient<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        ReconnectingClient::write(self, buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        ReconnectingClient::flush(self).await
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use std::boxed::Box;
+
+    use embassy_futures::join::join;
+    use embassy_time::Instant;
+
+    use super::listener::{TcpListener, TcpListenerState};
+    use super::*;
+    use crate::test_util::{self, addr, static_config, wait_for};
+
+    const PORT: u16 = 1234;
+
+    fn socket(stack: Stack<'static>) -> TcpSocket<'static> {
+        let rx = Box::leak(Box::new([0; 1024]));
+        let tx = Box::leak(Box::new([0; 1024]));
+        TcpSocket::new(stack, rx, tx)
+    }
+
+    async fn echo(socket: &mut TcpSocket<'_>, data: &[u8]) {
+        let mut buf = [0; 16];
+        unwrap!(socket.write(data).await);
+        let n = unwrap!(socket.read(&mut buf).await);
+        assert_eq!(&buf[..n], data);
+    }
+
+    #[test]
+    fn listener_accepts_connection() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let state = Box::leak(Box::new(TcpListenerState::<2, 1024, 1024>::default()));
+            let listener = TcpListener::new(net.b, state, PORT);
+            let mut client = socket(net.a);
+
+            let server = async {
+                let mut conn = listener.accept().await;
+                assert_eq!(conn.remote_endpoint().map(|e| e.addr), Some(addr(1).into()));
+                let mut buf = [0; 16];
+                let n = unwrap!(conn.read(&mut buf).await);
+                unwrap!(conn.write(&buf[..n]).await);
+                unwrap!(conn.flush().await);
+            };
+            let client = async {
+                unwrap!(client.connect((addr(2), PORT)).await);
+                echo(&mut client, b"hello").await;
+            };
+            join(server, client).await;
+        });
+    }
+
+    #[test]
+    fn listener_accepts_concurrent_connections() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let state = Box::leak(Box::new(TcpListenerState::<2, 1024, 1024>::new()));
+            let listener = TcpListener::new(net.b, state, PORT);
+            let mut first = socket(net.a);
+            let mut second = socket(net.a);
+
+            let server = async {
+                let a = listener.accept().await;
+                let b = listener.accept().await;
+                assert_ne!(a.remote_endpoint(), b.remote_endpoint());
+                (a, b)
+            };
+            let clients = async {
+                unwrap!(first.connect((addr(2), PORT)).await);
+                unwrap!(second.connect((addr(2), PORT)).await);
+            };
+            let ((a, b), ()) = join(server, clients).await;
+            assert_eq!(a.state(), State::Established);
+            assert_eq!(b.state(), State::Established);
+        });
+    }
+
+    #[test]
+    fn listener_relistens_after_connection_is_dropped() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let state = Box::leak(Box::new(TcpListenerState::<1, 1024, 1024>::new()));
+            let listener = TcpListener::new(net.b, state, PORT);
+            let mut first = socket(net.a);
+            let mut second = socket(net.a);
+
+            let server = async {
+                drop(listener.accept().await);
+                let mut conn = listener.accept().await;
+                let mut buf = [0; 16];
+                let n = unwrap!(conn.read(&mut buf).await);
+                unwrap!(conn.write(&buf[..n]).await);
+                unwrap!(conn.flush().await);
+            };
+            let clients = async {
+                unwrap!(first.connect((addr(2), PORT)).await);
+                // The listener closes the first connection as soon as it's accepted.
+                assert_eq!(first.read(&mut [0; 16]).await, Ok(0));
+                first.close();
+                wait_for(|| first.state() == State::Closed).await;
+                unwrap!(second.connect((addr(2), PORT)).await);
+                echo(&mut second, b"again").await;
+            };
+            join(server, clients).await;
+        });
+    }
+
+    fn reconnecting_client(stack: Stack<'static>, remote: Remote<'static>) -> ReconnectingClient<'static> {
+        let rx = Box::leak(Box::new([0; 1024]));
+        let tx = Box::leak(Box::new([0; 1024]));
+        let mut config = ReconnectConfig::new();
+        config.min_reconnect_delay = Duration::from_millis(50);
+        config.max_reconnect_delay = Duration::from_millis(200);
+        ReconnectingClient::new(stack, rx, tx, remote, config)
+    }
+
+    #[test]
+    fn reconnecting_client_reconnects_after_connection_is_lost() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut server = socket(net.b);
+            let mut client = reconnecting_client(net.a, Remote::Host("10.0.0.2", PORT));
+
+            let serve = async {
+                let mut buf = [0; 16];
+                unwrap!(server.accept(PORT).await);
+                let n = unwrap!(server.read(&mut buf).await);
+                assert_eq!(&buf[..n], b"hello");
+                server.abort();
+                unwrap!(server.flush().await);
+
+                unwrap!(server.accept(PORT).await);
+                let n = unwrap!(server.read(&mut buf).await);
+                unwrap!(server.write(&buf[..n]).await);
+                unwrap!(server.flush().await);
+            };
+            let connect = async {
+                let mut buf = [0; 16];
+                assert_eq!(client.write(b"hello").await, Ok(5));
+                assert_eq!(client.remote_endpoint(), Some((addr(2), PORT).into()));
+                // The client reconnects, once the server listens again.
+                assert_eq!(client.read(&mut buf).await, Err(ReconnectError::Reconnected));
+                assert!(client.is_connected());
+                assert_eq!(client.write(b"again").await, Ok(5));
+                let n = unwrap!(client.read(&mut buf).await);
+                assert_eq!(&buf[..n], b"again");
+            };
+            join(serve, connect).await;
+        });
+    }
+
+    #[test]
+    fn reconnecting_client_backs_off() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let mut server = socket(net.b);
+            let mut client = reconnecting_client(net.a, IpEndpoint::new(addr(2).into(), PORT).into());
+
+            for delay in [Duration::from_ticks(1), Duration::from_millis(100)] {
+                for _ in 0..100 {
+                    let jittered = client.jittered(delay);
+                    assert!(jittered >= delay / 2 && jittered <= delay, "{:?}", jittered);
+                }
+            }
+
+            // Connections are refused until the server listens, after the delays doubled up to their maximum.
+            let start = Instant::now();
+            let listen = async {
+                Timer::after_millis(500).await;
+                unwrap!(server.accept(PORT).await);
+            };
+            let ((), connected) = join(listen, client.connect()).await;
+            assert_eq!(connected, Ok(()));
+            let elapsed = start.elapsed();
+            assert!(elapsed < Duration::from_millis(500 + 200 + 50), "{:?}", elapsed);
+        });
+    }
+}
@@ -0,0 +1,122 @@
+//! Helpers for host tests: stacks connected by in-memory virtual links.
+
+use core::future::{poll_fn, Future};
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::task::Poll;
+use std::boxed::Box;
+use std::vec::Vec;
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use embassy_net_driver_channel::virtual_link::{self, Control};
+use embassy_time::{with_timeout, Duration, Timer};
+
+use crate::driver::HardwareAddress;
+use crate::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+
+pub(crate) const MTU: usize = 1514;
+
+pub(crate) type Device = embassy_net_driver_channel::Device<'static, MTU>;
+pub(crate) type Runner = crate::Runner<'static, Device>;
+pub(crate) type Switch = virtual_link::Runner<'static, MTU, 2>;
+
+/// Two stacks connected by a virtual link.
+pub(crate) struct Net {
+    pub a: Stack<'static>,
+    pub b: Stack<'static>,
+}
+
+/// IPv4 address of stack `n` (1 or 2) on the test network of [`run`].
+pub(crate) const fn addr(n: u8) -> Ipv4Address {
+    Ipv4Address::new(10, 0, 0, n)
+}
+
+/// Static configuration giving stack `n` the address [`addr`]`(n)`.
+pub(crate) fn static_config(n: u8) -> Config {
+    static_config_with(addr(n), 24, None)
+}
+
+/// Static IPv4 configuration.
+pub(crate) fn static_config_with(address: Ipv4Address, prefix_len: u8, gateway: Option<Ipv4Address>) -> Config {
+    Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(address, prefix_len),
+        gateway,
+        dns_servers: Default::default(),
+    })
+}
+
+/// Create a virtual link between two devices with distinct Ethernet addresses.
+pub(crate) fn link() -> (Control<'static, 2>, Switch, [Device; 2]) {
+    static NEXT_ADDRESS: AtomicU8 = AtomicU8::new(1);
+
+    let state = Box::leak(Box::new(virtual_link::State::<MTU, 2, 16>::new()));
+    let addresses = [(); 2].map(|_| {
+        let n = NEXT_ADDRESS.fetch_add(1, Ordering::Relaxed);
+        HardwareAddress::Ethernet([0x02, 0, 0, 0, 0, n])
+    });
+    virtual_link::new(state, addresses, 1)
+}
+
+/// Create a stack on `device`.
+pub(crate) fn stack(device: Device, config: Config) -> (Stack<'static>, Runner) {
+    static SEED: AtomicU8 = AtomicU8::new(1);
+
+    let resources = Box::leak(Box::new(StackResources::<8>::new()));
+    crate::new(device, config, resources, SEED.fetch_add(1, Ordering::Relaxed).into())
+}
+
+/// Wait until `f` returns true, checking every millisecond.
+pub(crate) async fn wait_for(mut f: impl FnMut() -> bool) {
+    while !f() {
+        Timer::after_millis(1).await;
+    }
+}
+
+/// Run `test` while running the switches and stacks in the background.
+///
+/// Panics if the test doesn't finish within 10 seconds.
+pub(crate) fn run_with<Fut: Future>(
+    switches: impl IntoIterator<Item = Switch>,
+    runners: impl IntoIterator<Item = Runner>,
+    test: Fut,
+) -> Fut::Output {
+    let mut background: Vec<Pin<Box<dyn Future<Output = ()>>>> = Vec::new();
+    for switch in switches {
+        background.push(Box::pin(async move {
+            switch.run().await;
+        }));
+    }
+    for mut runner in runners {
+        background.push(Box::pin(async move {
+            runner.run().await;
+        }));
+    }
+    let background = poll_fn(|cx| {
+        for future in &mut background {
+            let _ = future.as_mut().poll(cx);
+        }
+        Poll::<()>::Pending
+    });
+
+    match block_on(select(background, with_timeout(Duration::from_secs(10), test))) {
+        Either::Second(Ok(output)) => output,
+        Either::Second(Err(_)) => panic!("test timed out"),
+        Either::First(()) => unreachable!(),
+    }
+}
+
+/// Run `test` on two stacks with the given configurations, connected by a perfect link.
+///
+/// Panics if the test doesn't finish within 10 seconds.
+pub(crate) fn run<F, Fut>(configs: [Config; 2], test: F) -> Fut::Output
+where
+    F: FnOnce(Net) -> Fut,
+    Fut: Future,
+{
+    let (_, switch, [device_a, device_b]) = link();
+    let [config_a, config_b] = configs;
+    let (a, runner_a) = stack(device_a, config_a);
+    let (b, runner_b) = stack(device_b, config_b);
+    run_with([switch], [runner_a, runner_b], test(Net { a, b }))
+}
@@ -0,0 +1,732 @@
+//! TLS 1.3 client connections.
+//!
+//! [`TlsConnection`] runs a TLS 1.3 client session, implemented by [`embedded-tls`](embedded_tls), over
+//! a connected [`TcpSocket`], or over any other connection implementing the `embedded-io-async` traits,
+//! such as the connections of a [`TcpClient`](crate::tcp::client::TcpClient) or
+//! [`TcpListener`](crate::tcp::listener::TcpListener). [`TlsClient`](client::TlsClient) wraps a `TcpClient`
+//! to implement the `embedded-nal-async` `TcpConnect` trait with TLS connections.
+//!
+//! The server can be authenticated with a pre-shared key (see [`TlsConfig::with_psk`]), or with its
+//! certificate, using `CertVerifier` (feature `tls-webpki`) and a CA certificate (see [`TlsConfig::with_ca`]).
+//!
+//! Only the client side of TLS is supported: accepting TLS connections as a server is out of scope of
+//! this module, as `embedded-tls` only implements clients.
+//!
+//! # Buffers
+//!
+//! Each connection needs two buffers, in addition to the buffers of its TCP socket:
+//!
+//! - The read record buffer holds a whole encrypted record, the connection can't receive records larger
+//!   than it. Servers send records up to [`MAX_RECORD_SIZE`] bytes unless the client negotiates a
+//!   smaller maximum fragment length (see [`TlsConfig::with_max_fragment_length`]), which not all servers support.
+//! - The write record buffer holds an encrypted record being sent. Writes larger than the buffer minus
+//!   [`TLS_RECORD_OVERHEAD`] bytes are split into several records.
+//!
+//! The handshake is encoded in the larger of the two buffers, so it must fit the client hello, which
+//! is a few hundred bytes, more with pre-shared keys.
+
+use embedded_io_async::{BufRead, ErrorType, Read, Write};
+#[cfg(feature = "tls-webpki")]
+pub use embedded_tls::webpki::CertVerifier;
+pub use embedded_tls::{
+    Aes128GcmSha256, Aes256GcmSha384, Certificate, MaxFragmentLength, NoClock, NoVerify, TlsCipherSuite, TlsClock,
+    TlsConfig, TlsError as Error, TlsVerifier, TLS_RECORD_OVERHEAD,
+};
+use rand_core::{CryptoRng, RngCore};
+
+use crate::tcp::TcpSocket;
+
+/// Maximum size of an encrypted TLS record.
+///
+/// A read record buffer of this size can receive any record.
+pub const MAX_RECORD_SIZE: usize = 16640;
+
+/// A TLS 1.3 client connection.
+///
+/// `T` is the underlying connection, a [`TcpSocket`] by default, and `S` the cipher suite.
+pub struct TlsConnection<'d, T = TcpSocket<'d>, S = Aes128GcmSha256>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    inner: embedded_tls::TlsConnection<'d, T, S>,
+}
+
+impl<'d, T, S> TlsConnection<'d, T, S>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    /// Create a new TLS connection over `transport`, which must be connected to the server.
+    ///
+    /// See the [module documentation](self#buffers) for how to size the buffers.
+    pub fn new(transport: T, read_record_buf: &'d mut [u8], write_record_buf: &'d mut [u8]) -> Self {
+        Self {
+            inner: embedded_tls::TlsConnection::new(transport, read_record_buf, write_record_buf),
+        }
+    }
+
+    /// Perform the TLS handshake.
+    ///
+    /// The server is authenticated by the verifier `V`. [`NoVerify`] skips certificate verification,
+    /// which is only secure if the server is authenticated with a pre-shared key.
+    ///
+    /// `rng` must be a cryptographically secure random number generator, such as the hardware RNG of the chip.
+    ///
+    /// If the handshake fails, the connection can't be used anymore.
+    pub async fn open<'v, V, R>(&mut self, config: &'v TlsConfig<'v, S>, rng: &'v mut R) -> Result<(), Error>
+    where
+        V: TlsVerifier<'v, S>,
+        R: CryptoRng + RngCore,
+    {
+        self.inner
+            .open::<R, V>(embedded_tls::TlsContext::new(config, rng))
+            .await
+    }
+
+    /// Read decrypted data.
+    ///
+    /// Returns how many bytes were read, or 0 if the server closed the connection.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        self.inner.read(buf).await
+    }
+
+    /// Write data.
+    ///
+    /// The data is buffered until the write record buffer is full or [`flush`](Self::flush) is called.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        self.inner.write(buf).await
+    }
+
+    /// Encrypt and send the buffered data.
+    pub async fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush().await
+    }
+
+    /// Close the TLS session, and return the underlying connection.
+    ///
+    /// The underlying connection is still open, it's up to the caller to close it.
+    pub async fn close(self) -> Result<T, (T, Error)> {
+        self.inner.close().await
+    }
+}
+
+impl<'d, T, S> ErrorType for TlsConnection<'d, T, S>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    type Error = Error;
+}
+
+impl<'d, T, S> Read for TlsConnection<'d, T, S>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.inner.read(buf).await
+    }
+}
+
+impl<'d, T, S> BufRead for TlsConnection<'d, T, S>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    async fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        self.inner.fill_buf().await
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt)
+    }
+}
+
+impl<'d, T, S> Write for TlsConnection<'d, T, S>
+where
+    T: Read + Write + 'd,
+    S: TlsCipherSuite + 'static,
+{
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.inner.write(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().await
+    }
+}
+
+/// TLS client connections compatible with `embedded-nal-async` traits.
+pub mod client {
+    use core::mem::ManuallyDrop;
+    use core::net::SocketAddr;
+    use core::ptr::NonNull;
+
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+    use embassy_sync::mutex::Mutex;
+    use embedded_io_async::Error as _;
+
+    use super::*;
+    use crate::pool::Pool;
+
+    /// TLS connection pool compatible with the `embedded-nal-async` [`TcpConnect`](embedded_nal_async::TcpConnect) trait.
+    ///
+    /// Connections are made with `T`, usually a [`TcpClient`](crate::tcp::client::TcpClient), and the TLS
+    /// handshake is performed before they are returned. The client can hold up to N TLS connections at the same
+    /// time, with read and write record buffers of READ_SZ and WRITE_SZ bytes, see the
+    /// [module documentation](super#buffers) for how to size them.
+    ///
+    /// The server is authenticated by the verifier `V`, created for each handshake. It must be valid for
+    /// any lifetime, like [`NoVerify`], which is only secure if the server is authenticated with a pre-shared
+    /// key. To verify certificates with `CertVerifier`, open a [`TlsConnection`] over a connection of `T` instead.
+    ///
+    /// `rng` must be a cryptographically secure random number generator. Handshakes are performed one at a time,
+    /// as they share it.
+    pub struct TlsClient<
+        'd,
+        T,
+        R,
+        const N: usize,
+        const READ_SZ: usize = MAX_RECORD_SIZE,
+        const WRITE_SZ: usize = 4096,
+        S = Aes128GcmSha256,
+        V = NoVerify,
+    >
+    where
+        T: embedded_nal_async::TcpConnect,
+        R: CryptoRng + RngCore,
+        S: TlsCipherSuite + 'static,
+    {
+        transport: T,
+        state: &'d TlsClientState<N, READ_SZ, WRITE_SZ>,
+        config: TlsConfig<'d, S>,
+        rng: Mutex<NoopRawMutex, R>,
+        _verifier: core::marker::PhantomData<V>,
+    }
+
+    impl<'d, T, R, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S, V>
+        TlsClient<'d, T, R, N, READ_SZ, WRITE_SZ, S, V>
+    where
+        T: embedded_nal_async::TcpConnect,
+        R: CryptoRng + RngCore,
+        S: TlsCipherSuite + 'static,
+    {
+        /// Create a new `TlsClient`, connecting with `transport`.
+        pub fn new(
+            transport: T,
+            state: &'d TlsClientState<N, READ_SZ, WRITE_SZ>,
+            config: TlsConfig<'d, S>,
+            rng: R,
+        ) -> Self {
+            Self {
+                transport,
+                state,
+                config,
+                rng: Mutex::new(rng),
+                _verifier: core::marker::PhantomData,
+            }
+        }
+    }
+
+    impl<T, R, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S, V> embedded_nal_async::TcpConnect
+        for TlsClient<'_, T, R, N, READ_SZ, WRITE_SZ, S, V>
+    where
+        T: embedded_nal_async::TcpConnect,
+        R: CryptoRng + RngCore,
+        S: TlsCipherSuite + 'static,
+        V: for<'v> TlsVerifier<'v, S>,
+    {
+        type Error = Error;
+        type Connection<'m>
+            = TlsClientConnection<'m, T::Connection<'m>, N, READ_SZ, WRITE_SZ, S>
+        where
+            Self: 'm;
+
+        async fn connect(&self, remote: SocketAddr) -> Result<Self::Connection<'_>, Self::Error> {
+            let transport = self.transport.connect(remote).await.map_err(|e| Error::Io(e.kind()))?;
+            let bufs = self.state.pool.alloc().ok_or(Error::OutOfMemory)?;
+            let (read_record_buf, write_record_buf) = unsafe { &mut *bufs.as_ptr() };
+            let mut connection = TlsClientConnection {
+                tls: ManuallyDrop::new(TlsConnection::new(transport, read_record_buf, write_record_buf)),
+                state: self.state,
+                bufs,
+            };
+            let mut rng = self.rng.lock().await;
+            connection.tls.open::<V, R>(&self.config, &mut *rng).await?;
+            Ok(connection)
+        }
+    }
+
+    /// Opened TLS connection in a [`TlsClient`].
+    ///
+    /// Dropping the connection closes the underlying connection without closing the TLS session.
+    pub struct TlsClientConnection<'d, T, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S>
+    where
+        T: Read + Write + 'd,
+        S: TlsCipherSuite + 'static,
+    {
+        tls: ManuallyDrop<TlsConnection<'d, T, S>>,
+        state: &'d TlsClientState<N, READ_SZ, WRITE_SZ>,
+        bufs: NonNull<([u8; READ_SZ], [u8; WRITE_SZ])>,
+    }
+
+    impl<'d, T, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S> Drop
+        for TlsClientConnection<'d, T, N, READ_SZ, WRITE_SZ, S>
+    where
+        T: Read + Write + 'd,
+        S: TlsCipherSuite + 'static,
+    {
+        fn drop(&mut self) {
+            unsafe {
+                // Drop the connection before its buffers can be reused.
+                ManuallyDrop::drop(&mut self.tls);
+                self.state.pool.free(self.bufs);
+            }
+        }
+    }
+
+    impl<'d, T, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S> ErrorType
+        for TlsClientConnection<'d, T, N, READ_SZ, WRITE_SZ, S>
+    where
+        T: Read + Write + 'd,
+        S: TlsCipherSuite + 'static,
+    {
+        type Error = Error;
+    }
+
+    impl<'d, T, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S> Read
+        for TlsClientConnection<'d, T, N, READ_SZ, WRITE_SZ, S>
+    where
+        T: Read + Write + 'd,
+        S: TlsCipherSuite + 'static,
+    {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.tls.read(buf).await
+        }
+    }
+
+    impl<'d, T, const N: usize, const READ_SZ: usize, const WRITE_SZ: usize, S> Write
+        for TlsClientConnection<'d, T, N, READ_SZ, WRITE_SZ, S>
+    where
+        T: Read + Write + 'd,
+        S: TlsCipherSuite + 'static,
+    {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.tls.write(buf).await
+        }
+
+        async fn flush(&mut self) -> Result<(), Self::Error> {
+            self.tls.flush().await
+        }
+    }
+
+    /// State for [`TlsClient`].
+    pub struct TlsClientState<const N: usize, const READ_SZ: usize, const WRITE_SZ: usize> {
+        pool: Pool<([u8; READ_SZ], [u8; WRITE_SZ]), N>,
+    }
+
+    impl<const N: usize, const READ_SZ: usize, const WRITE_SZ: usize> TlsClientState<N, READ_SZ, WRITE_SZ> {
+        /// Create a new `TlsClientState`.
+        pub const fn new() -> Self {
+            Self { pool: Pool::new() }
+        }
+    }
+
+    impl<const N: usize, const READ_SZ: usize, const WRITE_SZ: usize> Default for TlsClientState<N, READ_SZ, WRITE_SZ> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use core::net::SocketAddr;
+    use std::boxed::Box;
+
+    use embassy_futures::join::join;
+    use embedded_nal_async::TcpConnect;
+    use rand_core::impls;
+
+    use super::client::{TlsClient, TlsClientState};
+    use super::*;
+    use crate::tcp::client::{TcpClient, TcpClientState};
+    use crate::test_util::{self, addr, static_config};
+    use crate::Stack;
+
+    const PORT: u16 = 4433;
+    const PSK: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    /// Deterministic random number generator, only for the tests.
+    struct TestRng(u64);
+
+    impl RngCore for TestRng {
+        fn next_u32(&mut self) -> u32 {
+            self.next_u64() as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            // xorshift64*
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            impls::fill_bytes_via_next(self, dest)
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    impl CryptoRng for TestRng {}
+
+    /// Minimal TLS 1.3 server, supporting only pre-shared keys with an ECDHE key exchange
+    /// (`psk_dhe_ke`) on secp256r1, and `TLS_AES_128_GCM_SHA256`.
+    mod server {
+        use std::vec;
+        use std::vec::Vec;
+
+        use aes_gcm::aead::{AeadInPlace, KeyInit};
+        use aes_gcm::{Aes128Gcm, Nonce, Tag};
+        use embedded_io_async::{Read, Write};
+        use hkdf::Hkdf;
+        use hmac::{Hmac, Mac};
+        use p256::ecdh::EphemeralSecret;
+        use p256::{EncodedPoint, PublicKey};
+        use sha2::{Digest, Sha256};
+
+        use super::TestRng;
+        use crate::tcp::TcpSocket;
+
+        type Secret = [u8; 32];
+
+        const HANDSHAKE: u8 = 22;
+        const APPLICATION_DATA: u8 = 23;
+
+        fn extract(salt: &[u8], ikm: &[u8]) -> Secret {
+            Hkdf::<Sha256>::extract(Some(salt), ikm).0.into()
+        }
+
+        fn expand_label(secret: &[u8], label: &[u8], context: &[u8], out: &mut [u8]) {
+            let mut info = Vec::new();
+            info.extend_from_slice(&(out.len() as u16).to_be_bytes());
+            info.push(6 + label.len() as u8);
+            info.extend_from_slice(b"tls13 ");
+            info.extend_from_slice(label);
+            info.push(context.len() as u8);
+            info.extend_from_slice(context);
+            unwrap!(unwrap!(Hkdf::<Sha256>::from_prk(secret)).expand(&info, out));
+        }
+
+        fn derive_secret(secret: &Secret, label: &[u8], transcript: &Sha256) -> Secret {
+            let mut out = [0; 32];
+            expand_label(secret, label, &transcript.clone().finalize(), &mut out);
+            out
+        }
+
+        fn finished(secret: &Secret, transcript: &Sha256) -> Vec<u8> {
+            let mut key = [0; 32];
+            expand_label(secret, b"finished", &[], &mut key);
+            let mut mac = unwrap!(<Hmac<Sha256> as Mac>::new_from_slice(&key));
+            mac.update(&transcript.clone().finalize());
+            mac.finalize().into_bytes().to_vec()
+        }
+
+        /// Handshake message of type `kind`.
+        fn handshake(kind: u8, body: &[u8]) -> Vec<u8> {
+            let mut message = vec![kind];
+            message.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            message.extend_from_slice(body);
+            message
+        }
+
+        /// Traffic keys for one direction.
+        pub struct Keys {
+            cipher: Aes128Gcm,
+            iv: [u8; 12],
+            seq: u64,
+        }
+
+        impl Keys {
+            fn new(secret: &Secret) -> Self {
+                let mut key = [0; 16];
+                let mut iv = [0; 12];
+                expand_label(secret, b"key", &[], &mut key);
+                expand_label(secret, b"iv", &[], &mut iv);
+                Self {
+                    cipher: unwrap!(Aes128Gcm::new_from_slice(&key)),
+                    iv,
+                    seq: 0,
+                }
+            }
+
+            fn nonce(&mut self) -> [u8; 12] {
+                let mut nonce = self.iv;
+                for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+                    *n ^= s;
+                }
+                self.seq += 1;
+                nonce
+            }
+
+            fn encrypt(&mut self, content_type: u8, data: &[u8]) -> Vec<u8> {
+                let mut payload = data.to_vec();
+                payload.push(content_type);
+                let len = payload.len() + 16;
+                let mut record = vec![APPLICATION_DATA, 3, 3, (len >> 8) as u8, len as u8];
+                let nonce = self.nonce();
+                let tag =
+                    unwrap!(self
+                        .cipher
+                        .encrypt_in_place_detached(Nonce::from_slice(&nonce), &record, &mut payload));
+                record.extend_from_slice(&payload);
+                record.extend_from_slice(&tag);
+                record
+            }
+
+            /// Decrypt a record, returning its content type and content.
+            fn decrypt(&mut self, header: &[u8], mut body: Vec<u8>) -> Option<(u8, Vec<u8>)> {
+                let nonce = self.nonce();
+                let len = body.len().checked_sub(16)?;
+                let (data, tag) = body.split_at_mut(len);
+                self.cipher
+                    .decrypt_in_place_detached(Nonce::from_slice(&nonce), header, data, Tag::from_slice(tag))
+                    .ok()?;
+                body.truncate(len);
+                while body.last() == Some(&0) {
+                    body.pop();
+                }
+                let content_type = body.pop()?;
+                Some((content_type, body))
+            }
+        }
+
+        /// Read a record, returning its header and body, or `None` if the connection was closed.
+        async fn read_record(socket: &mut TcpSocket<'_>) -> Option<([u8; 5], Vec<u8>)> {
+            let mut header = [0; 5];
+            socket.read_exact(&mut header).await.ok()?;
+            let mut body = vec![0; u16::from_be_bytes([header[3], header[4]]) as usize];
+            socket.read_exact(&mut body).await.ok()?;
+            Some((header, body))
+        }
+
+        /// Read an encrypted record, skipping change cipher spec records.
+        async fn read_encrypted(socket: &mut TcpSocket<'_>, keys: &mut Keys) -> Option<(u8, Vec<u8>)> {
+            loop {
+                let (header, body) = read_record(socket).await?;
+                match header[0] {
+                    // Change cipher spec, for compatibility with middleboxes.
+                    20 => continue,
+                    APPLICATION_DATA => return keys.decrypt(&header, body),
+                    _ => return None,
+                }
+            }
+        }
+
+        /// Find the extension of type `kind` in a client hello.
+        fn extension(client_hello: &[u8], kind: u16) -> Option<&[u8]> {
+            // Skip the handshake header, the version and the random.
+            let mut pos = 4 + 2 + 32;
+            // Session ID, cipher suites and compression methods.
+            pos += 1 + *client_hello.get(pos)? as usize;
+            pos += 2 + u16::from_be_bytes([*client_hello.get(pos)?, *client_hello.get(pos + 1)?]) as usize;
+            pos += 1 + *client_hello.get(pos)? as usize;
+            let mut extensions = client_hello.get(pos + 2..)?;
+            while extensions.len() >= 4 {
+                let len = u16::from_be_bytes([extensions[2], extensions[3]]) as usize;
+                let data = extensions.get(4..4 + len)?;
+                if u16::from_be_bytes([extensions[0], extensions[1]]) == kind {
+                    return Some(data);
+                }
+                extensions = &extensions[4 + len..];
+            }
+            None
+        }
+
+        /// Application data keys of an established session.
+        pub struct Session {
+            read: Keys,
+            write: Keys,
+        }
+
+        impl Session {
+            /// Receive application data, or `None` if the connection was closed.
+            pub async fn read(&mut self, socket: &mut TcpSocket<'_>) -> Option<Vec<u8>> {
+                match read_encrypted(socket, &mut self.read).await? {
+                    (APPLICATION_DATA, data) => Some(data),
+                    _ => None,
+                }
+            }
+
+            pub async fn write(&mut self, socket: &mut TcpSocket<'_>, data: &[u8]) {
+                let record = self.write.encrypt(APPLICATION_DATA, data);
+                unwrap!(socket.write_all(&record).await);
+                unwrap!(socket.flush().await);
+            }
+        }
+
+        /// Perform the server side of the handshake on a connected socket.
+        ///
+        /// Returns `None` if the client aborts the handshake.
+        pub async fn accept(socket: &mut TcpSocket<'_>, psk: &[u8], rng: &mut TestRng) -> Option<Session> {
+            let (header, client_hello) = read_record(socket).await?;
+            assert_eq!(header[0], HANDSHAKE);
+            assert_eq!(client_hello[0], 1);
+            let mut transcript = Sha256::new();
+            transcript.update(&client_hello);
+
+            // The client must offer a pre-shared key.
+            assert!(extension(&client_hello, 0x29).is_some());
+            let key_share = unwrap!(extension(&client_hello, 0x33));
+            // A single secp256r1 key share.
+            assert_eq!(key_share[2..4], [0, 0x17]);
+            let client_key = unwrap!(PublicKey::from_sec1_bytes(&key_share[6..]));
+            let secret = EphemeralSecret::random(rng);
+            let shared = secret.diffie_hellman(&client_key);
+
+            let session_id = &client_hello[4 + 2 + 32 + 1..][..client_hello[4 + 2 + 32] as usize];
+            let mut server_hello = vec![3, 3];
+            server_hello.extend_from_slice(&[0x42; 32]);
+            server_hello.push(session_id.len() as u8);
+            server_hello.extend_from_slice(session_id);
+            // TLS_AES_128_GCM_SHA256, no compression.
+            server_hello.extend_from_slice(&[0x13, 0x01, 0]);
+            let public_key = EncodedPoint::from(secret.public_key());
+            let mut extensions = vec![];
+            // Supported versions: TLS 1.3.
+            extensions.extend_from_slice(&[0, 0x2b, 0, 2, 3, 4]);
+            // Key share.
+            extensions.extend_from_slice(&[0, 0x33, 0, 4 + 65, 0, 0x17, 0, 65]);
+            extensions.extend_from_slice(public_key.as_bytes());
+            // Pre-shared key: the first identity.
+            extensions.extend_from_slice(&[0, 0x29, 0, 2, 0, 0]);
+            server_hello.extend_from_slice(&(extensions.len() as u16).to_be_bytes());
+            server_hello.extend_from_slice(&extensions);
+            let server_hello = handshake(2, &server_hello);
+            transcript.update(&server_hello);
+            let mut record = vec![HANDSHAKE, 3, 3];
+            record.extend_from_slice(&(server_hello.len() as u16).to_be_bytes());
+            record.extend_from_slice(&server_hello);
+            unwrap!(socket.write_all(&record).await);
+
+            // Key schedule, RFC 8446 section 7.1.
+            let early_secret = extract(&[0; 32], psk);
+            let derived = derive_secret(&early_secret, b"derived", &Sha256::new());
+            let handshake_secret = extract(&derived, shared.raw_secret_bytes());
+            let client_handshake = derive_secret(&handshake_secret, b"c hs traffic", &transcript);
+            let server_handshake = derive_secret(&handshake_secret, b"s hs traffic", &transcript);
+            let mut read = Keys::new(&client_handshake);
+            let mut write = Keys::new(&server_handshake);
+
+            // Encrypted extensions, empty, and finished.
+            let encrypted_extensions = handshake(8, &[0, 0]);
+            transcript.update(&encrypted_extensions);
+            unwrap!(socket.write_all(&write.encrypt(HANDSHAKE, &encrypted_extensions)).await);
+            let server_finished = handshake(20, &finished(&server_handshake, &transcript));
+            transcript.update(&server_finished);
+            unwrap!(socket.write_all(&write.encrypt(HANDSHAKE, &server_finished)).await);
+            unwrap!(socket.flush().await);
+
+            let derived = derive_secret(&handshake_secret, b"derived", &Sha256::new());
+            let master_secret = extract(&derived, &[0; 32]);
+            let client_application = derive_secret(&master_secret, b"c ap traffic", &transcript);
+            let server_application = derive_secret(&master_secret, b"s ap traffic", &transcript);
+
+            match read_encrypted(socket, &mut read).await? {
+                (HANDSHAKE, message) => {
+                    assert_eq!(message, handshake(20, &finished(&client_handshake, &transcript)));
+                }
+                _ => return None,
+            }
+            Some(Session {
+                read: Keys::new(&client_application),
+                write: Keys::new(&server_application),
+            })
+        }
+    }
+
+    type Client = TlsClient<'static, TcpClient<'static, 1>, TestRng, 1, 2048, 2048>;
+
+    fn client(stack: Stack<'static>, psk: &'static [u8]) -> Client {
+        let tcp = TcpClient::new(stack, Box::leak(Box::new(TcpClientState::new())));
+        let config = TlsConfig::new().with_psk(psk, &[b"client"]);
+        TlsClient::new(tcp, Box::leak(Box::new(TlsClientState::new())), config, TestRng(1))
+    }
+
+    fn server_socket(stack: Stack<'static>) -> TcpSocket<'static> {
+        TcpSocket::new(stack, Box::leak(Box::new([0; 4096])), Box::leak(Box::new([0; 4096])))
+    }
+
+    #[test]
+    fn client_exchanges_data_with_psk() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client = client(net.a, PSK);
+            let mut socket = server_socket(net.b);
+
+            let server = async {
+                unwrap!(socket.accept(PORT).await);
+                let mut session = unwrap!(server::accept(&mut socket, PSK, &mut TestRng(2)).await);
+                let request = unwrap!(session.read(&mut socket).await);
+                assert_eq!(request, b"ping");
+                session.write(&mut socket, b"pong").await;
+            };
+            let client = async {
+                let mut connection = unwrap!(client.connect(SocketAddr::new(addr(2).into(), PORT)).await);
+                unwrap!(connection.write_all(b"ping").await);
+                unwrap!(connection.flush().await);
+                let mut buf = [0; 16];
+                let n = unwrap!(connection.read(&mut buf).await);
+                assert_eq!(&buf[..n], b"pong");
+            };
+            join(server, client).await;
+        });
+    }
+
+    #[test]
+    fn client_rejects_wrong_psk() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client = client(net.a, b"wrong key");
+            let mut socket = server_socket(net.b);
+
+            let server = async {
+                unwrap!(socket.accept(PORT).await);
+                server::accept(&mut socket, PSK, &mut TestRng(2)).await.is_none()
+            };
+            let client = async { client.connect(SocketAddr::new(addr(2).into(), PORT)).await.is_err() };
+            assert_eq!(join(server, client).await, (true, true));
+        });
+    }
+
+    #[test]
+    fn client_releases_buffers() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client = client(net.a, PSK);
+            let mut socket = server_socket(net.b);
+
+            for _ in 0..2 {
+                let server = async {
+                    unwrap!(socket.accept(PORT).await);
+                    let mut session = unwrap!(server::accept(&mut socket, PSK, &mut TestRng(2)).await);
+                    session.write(&mut socket, b"hello").await;
+                    // The client closes the connection.
+                    assert!(session.read(&mut socket).await.is_none());
+                    socket.close();
+                    test_util::wait_for(|| socket.state() == crate::tcp::State::Closed).await;
+                };
+                let client = async {
+                    let mut connection = unwrap!(client.connect(SocketAddr::new(addr(2).into(), PORT)).await);
+                    let mut buf = [0; 16];
+                    let n = unwrap!(connection.read(&mut buf).await);
+                    assert_eq!(&buf[..n], b"hello");
+                };
+                join(server, client).await;
+            }
+        });
+    }
+}
@@ -1,15 +1,16 @@
 //! UDP sockets.
 
+use core::cell::Cell;
 use core::future::{poll_fn, Future};
 use core::mem;
 use core::task::{Context, Poll};
 
-use smoltcp::iface::{Interface, SocketHandle};
+use smoltcp::iface::Interface;
 use smoltcp::socket::udp;
 pub use smoltcp::socket::udp::{PacketMetadata, UdpMetadata};
-use smoltcp::wire::IpListenEndpoint;
+use smoltcp::wire::{IpAddress, IpListenEndpoint};
 
-use crate::Stack;
+use crate::{InterfaceId, SocketHandle, SocketSetFull, Stack};
 
 /// Error returned by [`UdpSocket::bind`].
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -19,6 +20,8 @@ pub enum BindError {
     InvalidState,
     /// No route to host.
     NoRoute,
+    /// The interface that has the local address has no room for the socket.
+    SocketSetFull,
 }
 
 /// Error returned by [`UdpSocket::send_to`].
@@ -31,6 +34,10 @@ pub enum SendError {
     SocketNotBound,
     /// There is not enough transmit buffer capacity to ever send this packet.
     PacketTooLarge,
+    /// The interface routing to the remote endpoint has no room for the socket.
+    ///
+    /// Only returned by sockets following routes, see [`UdpSocket::set_follow_route`].
+    SocketSetFull,
 }
 
 /// Error returned by [`UdpSocket::recv_from`].
@@ -44,7 +51,9 @@ pub enum RecvError {
 /// An UDP socket.
 pub struct UdpSocket<'a> {
     stack: Stack<'a>,
-    handle: SocketHandle,
+    handle: Cell<SocketHandle>,
+    interface: Option<InterfaceId>,
+    follow_route: bool,
 }
 
 impl<'a> UdpSocket<'a> {
@@ -61,13 +70,69 @@ impl<'a> UdpSocket<'a> {
             let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
             let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
             let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-            i.sockets.add(udp::Socket::new(
-                udp::PacketBuffer::new(rx_meta, rx_buffer),
-                udp::PacketBuffer::new(tx_meta, tx_buffer),
-            ))
+            i.add_socket(
+                InterfaceId::PRIMARY,
+                udp::Socket::new(
+                    udp::PacketBuffer::new(rx_meta, rx_buffer),
+                    udp::PacketBuffer::new(tx_meta, tx_buffer),
+                ),
+            )
         });
 
-        Self { stack, handle }
+        Self {
+            stack,
+            handle: Cell::new(handle),
+            interface: None,
+            follow_route: false,
+        }
+    }
+
+    /// Bind the socket to a network interface of the stack.
+    ///
+    /// Otherwise, the socket is moved to the interface that has the local address given to
+    /// [`bind`](Self::bind), or stays on the primary interface unless it follows routes, see
+    /// [`set_follow_route`](Self::set_follow_route). See [`Stack::add_interface`] for details.
+    ///
+    /// Returns an error, leaving the socket where it is, if the interface has no room for it.
+    pub fn bind_to_interface(&mut self, interface: InterfaceId) -> Result<(), SocketSetFull> {
+        let handle = self.stack.with_mut(|i| i.move_socket(self.handle.get(), interface))?;
+        self.handle.set(handle);
+        self.interface = Some(interface);
+        Ok(())
+    }
+
+    /// Set whether the socket follows the routing table.
+    ///
+    /// When enabled, a socket that isn't bound to an interface is moved to the interface given by
+    /// the routing table for the destination of each datagram it sends, once the datagrams it
+    /// already queued are sent. Disabled by default.
+    pub fn set_follow_route(&mut self, follow: bool) {
+        self.follow_route = follow;
+    }
+
+    /// Move the socket to the interface routing to `addr`, if it follows routes.
+    ///
+    /// Datagrams already queued are sent before moving.
+    fn poll_follow_route(&self, addr: IpAddress, cx: &mut Context<'_>) -> Poll<Result<(), SendError>> {
+        if !self.follow_route || self.interface.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        self.stack.with_mut(|i| {
+            let handle = self.handle.get();
+            match i.route(addr) {
+                Some(interface) if interface != handle.iface => {
+                    let (s, _) = i.socket_mut::<udp::Socket>(handle);
+                    if s.send_queue() != 0 {
+                        s.register_send_waker(cx.waker());
+                        return Poll::Pending;
+                    }
+                    let handle = i.move_socket(handle, interface).map_err(|_| SendError::SocketSetFull)?;
+                    self.handle.set(handle);
+                    Poll::Ready(Ok(()))
+                }
+                _ => Poll::Ready(Ok(())),
+            }
+        })
     }
 
     /// Bind the socket to a local endpoint.
@@ -82,6 +147,13 @@ impl<'a> UdpSocket<'a> {
             endpoint.port = self.stack.with_mut(|i| i.get_local_port());
         }
 
+        if let Some(addr) = endpoint.addr {
+            if let Some(interface) = self.stack.with(|i| i.interface_with_addr(addr)) {
+                self.bind_to_interface(interface)
+                    .map_err(|_| BindError::SocketSetFull)?;
+            }
+        }
+
         match self.with_mut(|s, _| s.bind(endpoint)) {
             Ok(()) => Ok(()),
             Err(udp::BindError::InvalidState) => Err(BindError::InvalidState),
@@ -91,16 +163,17 @@ impl<'a> UdpSocket<'a> {
 
     fn with<R>(&self, f: impl FnOnce(&udp::Socket, &Interface) -> R) -> R {
         self.stack.with(|i| {
-            let socket = i.sockets.get::<udp::Socket>(self.handle);
-            f(socket, &i.iface)
+            let (socket, iface) = i.socket::<udp::Socket>(self.handle.get());
+            f(socket, iface)
         })
     }
 
     fn with_mut<R>(&self, f: impl FnOnce(&mut udp::Socket, &mut Interface) -> R) -> R {
         self.stack.with_mut(|i| {
-            let socket = i.sockets.get_mut::<udp::Socket>(self.handle);
-            let res = f(socket, &mut i.iface);
-            i.waker.wake();
+            let handle = self.handle.get();
+            let (socket, iface) = i.socket_mut::<udp::Socket>(handle);
+            let res = f(socket, iface);
+            i.wake(handle);
             res
         })
     }
@@ -257,6 +330,13 @@ impl<'a> UdpSocket<'a> {
             return Poll::Ready(Err(SendError::PacketTooLarge));
         }
 
+        let remote_endpoint: UdpMetadata = remote_endpoint.into();
+        match self.poll_follow_route(remote_endpoint.endpoint.addr, cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+            Poll::Pending => return Poll::Pending,
+        }
+
         self.with_mut(|s, _| match s.send_slice(buf, remote_endpoint) {
             // Entire datagram has been sent
             Ok(()) => Poll::Ready(Ok(())),
@@ -294,6 +374,9 @@ impl<'a> UdpSocket<'a> {
             return Err(SendError::PacketTooLarge);
         }
 
+        let remote_endpoint: UdpMetadata = remote_endpoint.into();
+        poll_fn(|cx| self.poll_follow_route(remote_endpoint.endpoint.addr, cx)).await?;
+
         let mut f = Some(f);
         poll_fn(move |cx| {
             self.with_mut(|s, _| {
@@ -339,7 +422,6 @@ impl<'a> UdpSocket<'a> {
     }
 
     /// Returns whether the socket is open.
-
     pub fn is_open(&self) -> bool {
         self.with(|s, _| s.is_open())
     }
@@ -387,10 +469,452 @@ impl<'a> UdpSocket<'a> {
 
 impl Drop for UdpSocket<'_> {
     fn drop(&mut self) {
-        self.stack.with_mut(|i| i.sockets.remove(self.handle));
+        self.stack.with_mut(|i| i.remove_socket(self.handle.get()));
     }
 }
 
 fn _assert_covariant<'a, 'b: 'a>(x: UdpSocket<'b>) -> UdpSocket<'a> {
     x
 }
+
+/// UDP client sockets compatible with `embedded-nal-async` traits.
+pub mod client {
+    use core::mem::ManuallyDrop;
+    use core::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use core::ptr::NonNull;
+
+    use smoltcp::wire::IpEndpoint;
+
+    use super::*;
+    use crate::pool::Pool;
+
+    /// Error returned by [`UdpClient`] and its sockets.
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
+    pub enum Error {
+        /// All the sockets of the client are in use.
+        NoFreeSocket,
+        /// No route to host.
+        NoRoute,
+        /// There is not enough transmit buffer capacity to ever send this packet.
+        PacketTooLarge,
+        /// The interface that has the local address has no room for the socket.
+        SocketSetFull,
+    }
+
+    impl From<BindError> for Error {
+        fn from(e: BindError) -> Self {
+            match e {
+                // Sockets of the client are bound only once, when they are created.
+                BindError::NoRoute | BindError::InvalidState => Error::NoRoute,
+                BindError::SocketSetFull => Error::SocketSetFull,
+            }
+        }
+    }
+
+    impl From<SendError> for Error {
+        fn from(e: SendError) -> Self {
+            match e {
+                // Sockets of the client are always bound.
+                SendError::NoRoute | SendError::SocketNotBound => Error::NoRoute,
+                SendError::PacketTooLarge => Error::PacketTooLarge,
+                SendError::SocketSetFull => Error::SocketSetFull,
+            }
+        }
+    }
+
+    impl embedded_io_async::Error for Error {
+        fn kind(&self) -> embedded_io_async::ErrorKind {
+            match self {
+                Error::NoFreeSocket | Error::SocketSetFull => embedded_io_async::ErrorKind::OutOfMemory,
+                Error::NoRoute => embedded_io_async::ErrorKind::AddrNotAvailable,
+                Error::PacketTooLarge => embedded_io_async::ErrorKind::InvalidInput,
+            }
+        }
+    }
+
+    /// UDP socket pool compatible with `embedded-nal-async` traits.
+    ///
+    /// The pool is capable of managing up to N concurrent sockets with tx and rx buffers according to TX_SZ and RX_SZ,
+    /// each holding up to M datagrams.
+    pub struct UdpClient<'d, const N: usize, const TX_SZ: usize = 1024, const RX_SZ: usize = 1024, const M: usize = 4> {
+        stack: Stack<'d>,
+        state: &'d UdpClientState<N, TX_SZ, RX_SZ, M>,
+    }
+
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> UdpClient<'d, N, TX_SZ, RX_SZ, M> {
+        /// Create a new `UdpClient`.
+        pub fn new(stack: Stack<'d>, state: &'d UdpClientState<N, TX_SZ, RX_SZ, M>) -> Self {
+            Self { stack, state }
+        }
+
+        /// Get a socket from the pool, and bind it to `local`.
+        fn bind(&self, local: SocketAddr) -> Result<PooledSocket<'d, N, TX_SZ, RX_SZ, M>, Error> {
+            let mut socket = PooledSocket::new(self.stack, self.state)?;
+            let endpoint = IpListenEndpoint {
+                addr: to_ip_address(local.ip()),
+                port: local.port(),
+            };
+            // `bind` allocates a port if it's 0. The socket is dropped, and its buffers returned to
+            // the pool, on error.
+            socket.socket.bind(endpoint)?;
+            Ok(socket)
+        }
+    }
+
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> embedded_nal_async::UdpStack
+        for UdpClient<'d, N, TX_SZ, RX_SZ, M>
+    {
+        type Error = Error;
+        type Connected = UdpConnection<'d, N, TX_SZ, RX_SZ, M>;
+        type UniquelyBound = UdpBoundSocket<'d, N, TX_SZ, RX_SZ, M>;
+        type MultiplyBound = UdpBoundSocket<'d, N, TX_SZ, RX_SZ, M>;
+
+        async fn connect_from(
+            &self,
+            local: SocketAddr,
+            remote: SocketAddr,
+        ) -> Result<(SocketAddr, Self::Connected), Self::Error> {
+            let remote = IpEndpoint {
+                addr: to_ip_address(remote.ip()).ok_or(Error::NoRoute)?,
+                port: remote.port(),
+            };
+            let socket = self.bind(local)?;
+            // Send from the address the stack would pick, so it's the same for all datagrams.
+            let local_address = match to_ip_address(local.ip()) {
+                Some(addr) => Some(addr),
+                None => self.stack.with(|i| i.source_address(remote.addr)),
+            };
+            let local = match local_address {
+                Some(addr) => SocketAddr::new(addr.into(), socket.socket.endpoint().port),
+                None => SocketAddr::new(local.ip(), socket.socket.endpoint().port),
+            };
+            Ok((
+                local,
+                UdpConnection {
+                    socket,
+                    local_address,
+                    remote,
+                },
+            ))
+        }
+
+        async fn bind_single(&self, local: SocketAddr) -> Result<(SocketAddr, Self::UniquelyBound), Self::Error> {
+            let mut local = local;
+            if local.ip().is_unspecified() {
+                if let Some(addr) = self.primary_address(local.ip()) {
+                    local.set_ip(addr);
+                }
+            }
+            let socket = self.bind(local)?;
+            local.set_port(socket.socket.endpoint().port);
+            Ok((local, UdpBoundSocket { socket }))
+        }
+
+        async fn bind_multiple(&self, local: SocketAddr) -> Result<Self::MultiplyBound, Self::Error> {
+            let socket = self.bind(local)?;
+            Ok(UdpBoundSocket { socket })
+        }
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> UdpClient<'_, N, TX_SZ, RX_SZ, M> {
+        /// Get the address of the primary interface of the same family as `addr`.
+        fn primary_address(&self, addr: IpAddr) -> Option<IpAddr> {
+            match addr {
+                #[cfg(feature = "proto-ipv4")]
+                IpAddr::V4(_) => self.stack.config_v4().map(|c| IpAddr::V4(c.address.address())),
+                #[cfg(feature = "proto-ipv6")]
+                IpAddr::V6(_) => self.stack.config_v6().map(|c| IpAddr::V6(c.address.address())),
+                #[allow(unreachable_patterns)]
+                _ => None,
+            }
+        }
+    }
+
+    /// Socket of a [`UdpClient`], connected to a remote endpoint.
+    ///
+    /// Datagrams received from other endpoints are dropped.
+    pub struct UdpConnection<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> {
+        socket: PooledSocket<'d, N, TX_SZ, RX_SZ, M>,
+        local_address: Option<IpAddress>,
+        remote: IpEndpoint,
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> embedded_nal_async::ConnectedUdp
+        for UdpConnection<'_, N, TX_SZ, RX_SZ, M>
+    {
+        type Error = Error;
+
+        async fn send(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+            let mut meta = UdpMetadata::from(self.remote);
+            meta.local_address = self.local_address;
+            self.socket.socket.send_to(data, meta).await?;
+            Ok(())
+        }
+
+        async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<usize, Self::Error> {
+            loop {
+                let (n, meta) = self.socket.receive_into(buffer).await;
+                if meta.endpoint == self.remote {
+                    return Ok(n);
+                }
+            }
+        }
+    }
+
+    /// Socket of a [`UdpClient`], bound to a local endpoint.
+    pub struct UdpBoundSocket<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> {
+        socket: PooledSocket<'d, N, TX_SZ, RX_SZ, M>,
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> embedded_nal_async::UnconnectedUdp
+        for UdpBoundSocket<'_, N, TX_SZ, RX_SZ, M>
+    {
+        type Error = Error;
+
+        async fn send(&mut self, local: SocketAddr, remote: SocketAddr, data: &[u8]) -> Result<(), Self::Error> {
+            let remote = IpEndpoint {
+                addr: to_ip_address(remote.ip()).ok_or(Error::NoRoute)?,
+                port: remote.port(),
+            };
+            let mut meta = UdpMetadata::from(remote);
+            meta.local_address = to_ip_address(local.ip());
+            self.socket.socket.send_to(data, meta).await?;
+            Ok(())
+        }
+
+        async fn receive_into(&mut self, buffer: &mut [u8]) -> Result<(usize, SocketAddr, SocketAddr), Self::Error> {
+            let (n, meta) = self.socket.receive_into(buffer).await;
+            let endpoint = self.socket.socket.endpoint();
+            let local = match meta.local_address.or(endpoint.addr) {
+                Some(addr) => SocketAddr::new(addr.into(), endpoint.port),
+                None => SocketAddr::new(unspecified(meta.endpoint.addr), endpoint.port),
+            };
+            let remote = SocketAddr::new(meta.endpoint.addr.into(), meta.endpoint.port);
+            Ok((n, local, remote))
+        }
+    }
+
+    /// UDP socket with buffers from the pool of a [`UdpClientState`].
+    struct PooledSocket<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> {
+        socket: ManuallyDrop<UdpSocket<'d>>,
+        state: &'d UdpClientState<N, TX_SZ, RX_SZ, M>,
+        bufs: NonNull<Buffers<TX_SZ, RX_SZ, M>>,
+    }
+
+    impl<'d, const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> PooledSocket<'d, N, TX_SZ, RX_SZ, M> {
+        fn new(stack: Stack<'d>, state: &'d UdpClientState<N, TX_SZ, RX_SZ, M>) -> Result<Self, Error> {
+            let mut bufs = state.pool.alloc().ok_or(Error::NoFreeSocket)?;
+            let bufs_mut = unsafe { bufs.as_mut() };
+            bufs_mut.rx_meta = [PacketMetadata::EMPTY; M];
+            bufs_mut.tx_meta = [PacketMetadata::EMPTY; M];
+            let socket = UdpSocket::new(
+                stack,
+                &mut bufs_mut.rx_meta,
+                &mut bufs_mut.rx,
+                &mut bufs_mut.tx_meta,
+                &mut bufs_mut.tx,
+            );
+            Ok(Self {
+                socket: ManuallyDrop::new(socket),
+                state,
+                bufs,
+            })
+        }
+
+        /// Receive a datagram, truncated to the size of `buffer`.
+        ///
+        /// Returns the size of the datagram before truncation.
+        async fn receive_into(&mut self, buffer: &mut [u8]) -> (usize, UdpMetadata) {
+            self.socket
+                .recv_from_with(|data, meta| {
+                    let n = data.len().min(buffer.len());
+                    buffer[..n].copy_from_slice(&data[..n]);
+                    (data.len(), meta)
+                })
+                .await
+        }
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> Drop
+        for PooledSocket<'_, N, TX_SZ, RX_SZ, M>
+    {
+        fn drop(&mut self) {
+            unsafe {
+                // Remove the socket from the stack before its buffers can be reused.
+                ManuallyDrop::drop(&mut self.socket);
+                self.state.pool.free(self.bufs);
+            }
+        }
+    }
+
+    struct Buffers<const TX_SZ: usize, const RX_SZ: usize, const M: usize> {
+        rx_meta: [PacketMetadata; M],
+        rx: [u8; RX_SZ],
+        tx_meta: [PacketMetadata; M],
+        tx: [u8; TX_SZ],
+    }
+
+    /// State for UdpClient
+    pub struct UdpClientState<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> {
+        pool: Pool<Buffers<TX_SZ, RX_SZ, M>, N>,
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> UdpClientState<N, TX_SZ, RX_SZ, M> {
+        /// Create a new `UdpClientState`.
+        pub const fn new() -> Self {
+            Self { pool: Pool::new() }
+        }
+    }
+
+    impl<const N: usize, const TX_SZ: usize, const RX_SZ: usize, const M: usize> Default
+        for UdpClientState<N, TX_SZ, RX_SZ, M>
+    {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Convert `addr` to an `IpAddress`, or `None` if it is unspecified.
+    fn to_ip_address(addr: IpAddr) -> Option<IpAddress> {
+        if addr.is_unspecified() {
+            return None;
+        }
+        Some(match addr {
+            #[cfg(feature = "proto-ipv4")]
+            IpAddr::V4(addr) => IpAddress::Ipv4(addr),
+            #[cfg(not(feature = "proto-ipv4"))]
+            IpAddr::V4(_) => panic!("ipv4 support not enabled"),
+            #[cfg(feature = "proto-ipv6")]
+            IpAddr::V6(addr) => IpAddress::Ipv6(addr),
+            #[cfg(not(feature = "proto-ipv6"))]
+            IpAddr::V6(_) => panic!("ipv6 support not enabled"),
+        })
+    }
+
+    /// Get the unspecified address of the same family as `addr`.
+    fn unspecified(addr: IpAddress) -> IpAddr {
+        match IpAddr::from(addr) {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "proto-ipv4", feature = "medium-ethernet"))]
+mod tests {
+    use core::net::{Ipv4Addr, SocketAddr};
+    use std::boxed::Box;
+
+    use embassy_time::Timer;
+    use embedded_nal_async::{ConnectedUdp, UdpStack, UnconnectedUdp};
+
+    use super::client::{Error, UdpClient, UdpClientState};
+    use crate::test_util::{self, addr, static_config, static_config_with};
+    use crate::{InterfaceResources, Stack};
+
+    const PORT: u16 = 1234;
+
+    fn socket_addr(ip: Ipv4Addr, port: u16) -> SocketAddr {
+        SocketAddr::new(ip.into(), port)
+    }
+
+    fn client<const N: usize>(stack: Stack<'static>) -> UdpClient<'static, N, 64, 64, 4> {
+        UdpClient::new(stack, Box::leak(Box::new(UdpClientState::new())))
+    }
+
+    #[test]
+    fn client_sends_and_receives() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client_a = client::<2>(net.a);
+            let client_b = client::<2>(net.b);
+
+            // An unspecified address is replaced by the address of the interface.
+            let (local_b, mut bound) = unwrap!(client_b.bind_single(socket_addr(Ipv4Addr::UNSPECIFIED, PORT)).await);
+            assert_eq!(local_b, socket_addr(addr(2), PORT));
+            let (local_a, mut connection) = unwrap!(client_a.connect(local_b).await);
+            assert_eq!(local_a.ip(), addr(1));
+            assert_ne!(local_a.port(), 0);
+
+            unwrap!(connection.send(b"hello").await);
+            let mut buf = [0; 16];
+            let (n, local, remote) = unwrap!(bound.receive_into(&mut buf).await);
+            assert_eq!(&buf[..n], b"hello");
+            assert_eq!((local, remote), (local_b, local_a));
+
+            // Datagrams from other endpoints are dropped by the connection.
+            let (_, mut other) = unwrap!(client_b.bind_single(socket_addr(addr(2), 0)).await);
+            unwrap!(other.send(socket_addr(addr(2), 0), local_a, b"other").await);
+            unwrap!(bound.send(local_b, remote, b"reply").await);
+            let n = unwrap!(connection.receive_into(&mut buf).await);
+            assert_eq!(&buf[..n], b"reply");
+        });
+    }
+
+    #[test]
+    fn client_truncates_datagrams() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client_a = client::<1>(net.a);
+            let client_b = client::<1>(net.b);
+            let mut bound = unwrap!(client_b.bind_multiple(socket_addr(Ipv4Addr::UNSPECIFIED, PORT)).await);
+            let (_, mut connection) = unwrap!(client_a.connect(socket_addr(addr(2), PORT)).await);
+
+            unwrap!(connection.send(b"truncated datagram").await);
+            let mut buf = [0; 9];
+            let (n, local, _) = unwrap!(bound.receive_into(&mut buf).await);
+            // The full size is returned, so that the truncation can be detected.
+            assert_eq!(n, 18);
+            assert_eq!(&buf, b"truncated");
+            // The local address is the one the datagram was sent to.
+            assert_eq!(local, socket_addr(addr(2), PORT));
+
+            // Datagrams larger than the transmit buffer can never be sent.
+            assert_eq!(connection.send(&[0; 65]).await, Err(Error::PacketTooLarge));
+        });
+    }
+
+    #[test]
+    fn client_reuses_sockets() {
+        test_util::run([static_config(1), static_config(2)], |net| async move {
+            let client_a = client::<1>(net.a);
+            let client_b = client::<1>(net.b);
+            let (_, mut connection) = unwrap!(client_a.connect(socket_addr(addr(2), PORT)).await);
+
+            let local = socket_addr(addr(2), PORT);
+            let (_, first) = unwrap!(client_b.bind_single(local).await);
+            assert!(matches!(client_b.bind_single(local).await, Err(Error::NoFreeSocket)));
+
+            // The datagram is left in the buffers of the first socket, but not seen by the next one.
+            unwrap!(connection.send(b"old").await);
+            Timer::after_millis(100).await;
+            drop(first);
+
+            let (_, mut second) = unwrap!(client_b.bind_single(local).await);
+            unwrap!(connection.send(b"new").await);
+            let mut buf = [0; 16];
+            let (n, _, _) = unwrap!(second.receive_into(&mut buf).await);
+            assert_eq!(&buf[..n], b"new");
+        });
+    }
+
+    #[test]
+    fn client_bind_to_full_interface_fails() {
+        let (_, switch_a, [device_a, _]) = test_util::link();
+        let (_, switch_b, [device_b, _]) = test_util::link();
+        let (stack, runner_a) = test_util::stack(device_a, static_config(1));
+        let resources = Box::leak(Box::new(InterfaceResources::<1>::new()));
+        let second_addr = Ipv4Addr::new(10, 0, 1, 1);
+        let (_, runner_b) = stack.add_interface(device_b, static_config_with(second_addr, 24, None), resources);
+
+        test_util::run_with([switch_a, switch_b], [runner_a, runner_b], async {
+            let client = client::<2>(stack);
+            let (_, _first) = unwrap!(client.bind_single(socket_addr(second_addr, 0)).await);
+            assert!(matches!(
+                client.bind_single(socket_addr(second_addr, 0)).await,
+                Err(Error::SocketSetFull)
+            ));
+            // The buffers of the failed socket are returned to the pool.
+            unwrap!(client.bind_single(socket_addr(addr(1), 0)).await);
+        });
+    }
+}
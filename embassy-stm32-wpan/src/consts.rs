@@ -59,6 +59,7 @@ impl TryFrom<u8> for TlPacketType {
 
 pub const TL_PACKET_HEADER_SIZE: usize = core::mem::size_of::<PacketHeader>();
 pub const TL_EVT_HEADER_SIZE: usize = 3;
+pub const TL_ACL_HEADER_SIZE: usize = 5;
 pub const TL_CS_EVT_SIZE: usize = core::mem::size_of::<CsEvt>();
 
 /**
@@ -2,7 +2,7 @@ use core::marker::PhantomData;
 use core::{ptr, slice};
 
 use super::PacketHeader;
-use crate::consts::TL_EVT_HEADER_SIZE;
+use crate::consts::{TlPacketType, TL_ACL_HEADER_SIZE, TL_EVT_HEADER_SIZE};
 
 /**
  * The payload of `Evt` for a command status event
@@ -137,7 +137,13 @@ impl<T: MemoryManager> EvtBox<T> {
             let evt_serial: *const EvtSerial = &(*self.ptr).evt_serial;
             let evt_serial_buf: *const u8 = evt_serial.cast();
 
-            let len = (*evt_serial).evt.payload_len as usize + TL_EVT_HEADER_SIZE;
+            let len = match (*evt_serial).kind {
+                // ACL data, received with the HCI layer firmware, has a 16-bit length after the handle
+                kind if kind == TlPacketType::AclData as u8 => {
+                    u16::from_le_bytes([*evt_serial_buf.add(3), *evt_serial_buf.add(4)]) as usize + TL_ACL_HEADER_SIZE
+                }
+                _ => (*evt_serial).evt.payload_len as usize + TL_EVT_HEADER_SIZE,
+            };
 
             slice::from_raw_parts(evt_serial_buf, len)
         }
@@ -10,6 +10,7 @@
 //!
 //! BLE commands are implemented via use of the [stm32wb_hci] crate, for which the
 //! [stm32wb_hci::Controller] trait has been implemented.
+//! The [bt_hci::transport::Transport] trait is implemented as well, for host stacks running on CPU1.
 
 #![no_std]
 #![allow(async_fn_in_trait)]
@@ -47,6 +48,8 @@ pub mod mac;
 
 #[cfg(feature = "ble")]
 pub use crate::sub::ble::hci;
+#[cfg(feature = "ble")]
+pub use bt_hci;
 
 type PacketHeader = LinkedListNode;
 
@@ -1,10 +1,14 @@
 use core::ptr;
 
+use bt_hci::transport::{Transport, WithIndicator};
+use bt_hci::{ControllerToHostPacket, FromHciBytes, HostToControllerPacket, PacketKind, WriteHci};
 use embassy_stm32::ipcc::Ipcc;
 use hci::Opcode;
 
-use crate::cmd::CmdPacket;
-use crate::consts::{TlPacketType, TL_BLEEVT_CC_OPCODE, TL_BLEEVT_CS_OPCODE};
+use crate::cmd::{CmdPacket, CmdSerial};
+use crate::consts::{
+    TlPacketType, TL_ACL_HEADER_SIZE, TL_BLEEVT_CC_OPCODE, TL_BLEEVT_CS_OPCODE, TL_PACKET_HEADER_SIZE,
+};
 use crate::evt::{EvtBox, EvtPacket, EvtStub};
 use crate::sub::mm;
 use crate::tables::{BleTable, BLE_CMD_BUFFER, CS_BUFFER, EVT_QUEUE, HCI_ACL_DATA_BUFFER, TL_BLE_TABLE};
@@ -122,3 +126,84 @@ impl hci::Controller for Ble {
         buf[..evt_serial.len()].copy_from_slice(evt_serial);
     }
 }
+
+/// Maximum length of the ACL data sent to CPU2.
+const ACL_DATA_MAX_LEN: usize = 251;
+
+/// Errors of the [`Transport`] implementation of [`Ble`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransportError {
+    /// The packet kind isn't carried by the transport layer: synchronous and isochronous data.
+    Unsupported,
+    /// The packet doesn't fit in the buffer.
+    BufferTooSmall,
+    /// The packet received from CPU2 is malformed.
+    InvalidPacket,
+}
+
+impl embedded_io::Error for TransportError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            Self::Unsupported => embedded_io::ErrorKind::Unsupported,
+            Self::BufferTooSmall => embedded_io::ErrorKind::OutOfMemory,
+            Self::InvalidPacket => embedded_io::ErrorKind::InvalidData,
+        }
+    }
+}
+
+impl embedded_io::ErrorType for Ble {
+    type Error = TransportError;
+}
+
+/// Runs host stacks written against [`bt_hci`] (such as TrouBLE) on CPU2, which must run the
+/// HCI layer only firmware (`stm32wb5x_BLE_HCILayer_fw.bin`).
+///
+/// Commands and ACL data are sent to CPU2, events and ACL data are received from it.
+impl Transport for Ble {
+    async fn read<'a>(&self, rx: &'a mut [u8]) -> Result<ControllerToHostPacket<'a>, Self::Error> {
+        let evt_box = self.tl_read().await;
+        let evt_serial = evt_box.serial();
+        let rx = rx.get_mut(..evt_serial.len()).ok_or(TransportError::BufferTooSmall)?;
+        rx.copy_from_slice(evt_serial);
+        drop(evt_box);
+
+        let kind = PacketKind::from_hci_bytes_complete(&rx[..1]).map_err(|_| TransportError::InvalidPacket)?;
+        let (packet, _) = ControllerToHostPacket::from_hci_bytes_with_kind(kind, &rx[1..])
+            .map_err(|_| TransportError::InvalidPacket)?;
+        Ok(packet)
+    }
+
+    async fn write<T: HostToControllerPacket>(&self, val: &T) -> Result<(), Self::Error> {
+        let (channel, dst, capacity) = match T::KIND {
+            PacketKind::Cmd => (
+                channels::cpu1::IPCC_BLE_CMD_CHANNEL,
+                unsafe { ptr::addr_of_mut!((*BLE_CMD_BUFFER.as_mut_ptr()).cmdserial).cast::<u8>() },
+                core::mem::size_of::<CmdSerial>(),
+            ),
+            PacketKind::AclData => (
+                channels::cpu1::IPCC_HCI_ACL_DATA_CHANNEL,
+                unsafe { HCI_ACL_DATA_BUFFER.as_mut_ptr().cast::<u8>().add(TL_PACKET_HEADER_SIZE) },
+                TL_ACL_HEADER_SIZE + ACL_DATA_MAX_LEN,
+            ),
+            _ => return Err(TransportError::Unsupported),
+        };
+
+        // The packet is serialized before waiting for CPU2 to release the buffer.
+        let mut buf = [0; core::mem::size_of::<CmdSerial>()];
+        let packet = WithIndicator::new(val);
+        let len = packet.size();
+        if len > capacity {
+            return Err(TransportError::BufferTooSmall);
+        }
+        packet
+            .write_hci(&mut buf[..len])
+            .map_err(|_| TransportError::BufferTooSmall)?;
+
+        Ipcc::send(channel, || unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), dst, len);
+        })
+        .await;
+        Ok(())
+    }
+}
@@ -24,7 +24,9 @@ impl Queue {
         let task = embassy_executor::raw::task_from_waker(waker);
         let item = task.timer_queue_item();
         if item.next.get().is_none() {
-            // If not in the queue, add it and update.
+            // If not in the queue, add it and update. The queue keeps a reference to the task,
+            // so that it's not freed while in the queue.
+            task.retain();
             let prev = self.head.replace(Some(task));
             item.next.set(if prev.is_none() {
                 Some(unsafe { TaskRef::dangling() })
@@ -83,6 +85,8 @@ impl Queue {
                 // Remove it
                 prev.set(item.next.get());
                 item.next.set(None);
+                // Safety: the reference was acquired when the task was added to the queue.
+                unsafe { p.release() };
             }
         }
     }
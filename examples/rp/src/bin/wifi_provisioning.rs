@@ -0,0 +1,147 @@
+//! This example uses the RP Pico W board Wifi chip (cyw43).
+//! Joins the network stored in flash, or serves a captive portal on the "pico-setup" access point
+//! to choose it. Connecting GPIO 15 to ground at boot forgets the stored network.
+
+#![no_std]
+#![no_main]
+#![allow(async_fn_in_trait)]
+
+use cyw43::provisioning::{self, CredentialStorage, Credentials, CREDENTIALS_LEN};
+use cyw43_pio::{PioSpi, DEFAULT_CLOCK_DIVIDER};
+use defmt::*;
+use embassy_executor::Spawner;
+use embassy_net::{Config, StackResources};
+use embassy_rp::bind_interrupts;
+use embassy_rp::clocks::RoscRng;
+use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+use embassy_rp::gpio::{Input, Level, Output, Pull};
+use embassy_rp::peripherals::{DMA_CH0, FLASH, PIO0};
+use embassy_rp::pio::{InterruptHandler, Pio};
+use rand::RngCore;
+use static_cell::StaticCell;
+use {defmt_rtt as _, panic_probe as _};
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => InterruptHandler<PIO0>;
+});
+
+const FLASH_SIZE: usize = 2 * 1024 * 1024;
+/// Offset of the flash sector holding the credentials, the last one.
+const CREDENTIALS_OFFSET: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+
+/// Credentials stored in the last sector of the flash.
+struct FlashStorage {
+    flash: Flash<'static, FLASH, Blocking, FLASH_SIZE>,
+}
+
+impl CredentialStorage for FlashStorage {
+    type Error = embassy_rp::flash::Error;
+
+    async fn load(&mut self) -> Result<Option<Credentials>, Self::Error> {
+        let mut buf = [0; CREDENTIALS_LEN];
+        self.flash.blocking_read(CREDENTIALS_OFFSET, &mut buf)?;
+        Ok(Credentials::from_bytes(&buf))
+    }
+
+    async fn store(&mut self, credentials: &Credentials) -> Result<(), Self::Error> {
+        self.clear().await?;
+        self.flash.blocking_write(CREDENTIALS_OFFSET, &credentials.to_bytes())
+    }
+
+    async fn clear(&mut self) -> Result<(), Self::Error> {
+        self.flash
+            .blocking_erase(CREDENTIALS_OFFSET, CREDENTIALS_OFFSET + ERASE_SIZE as u32)
+    }
+}
+
+#[embassy_executor::task]
+async fn cyw43_task(runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    info!("Hello World!");
+
+    let p = embassy_rp::init(Default::default());
+    let mut rng = RoscRng;
+
+    let fw = include_bytes!("../../../../cyw43-firmware/43439A0.bin");
+    let clm = include_bytes!("../../../../cyw43-firmware/43439A0_clm.bin");
+
+    let mut storage = FlashStorage {
+        flash: Flash::new_blocking(p.FLASH),
+    };
+    let reset = Input::new(p.PIN_15, Pull::Up);
+    if reset.is_low() {
+        info!("forgetting the stored network");
+        unwrap!(storage.clear().await);
+    }
+
+    let pwr = Output::new(p.PIN_23, Level::Low);
+    let cs = Output::new(p.PIN_25, Level::High);
+    let mut pio = Pio::new(p.PIO0, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        DEFAULT_CLOCK_DIVIDER,
+        pio.irq0,
+        cs,
+        p.PIN_24,
+        p.PIN_29,
+        p.DMA_CH0,
+    );
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+    unwrap!(spawner.spawn(cyw43_task(runner)));
+
+    control.init(clm).await;
+    control
+        .set_power_management(cyw43::PowerManagementMode::PowerSave)
+        .await;
+
+    // The configuration is set by the provisioning
+    let config = Config::default();
+
+    // Generate random seed
+    let seed = rng.next_u64();
+
+    // Init network stack, with 4 sockets for the portal
+    static RESOURCES: StaticCell<StackResources<6>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(net_device, config, RESOURCES.init(StackResources::new()), seed);
+
+    unwrap!(spawner.spawn(net_task(runner)));
+
+    static PROVISIONING: StaticCell<provisioning::State> = StaticCell::new();
+    let provisioning_state = PROVISIONING.init(provisioning::State::new());
+    let credentials = unwrap!(
+        provisioning::provision(
+            &mut control,
+            stack,
+            &mut storage,
+            provisioning_state,
+            &provisioning::Config::new("pico-setup"),
+        )
+        .await
+    );
+    info!("joined {}", credentials.ssid());
+
+    // Wait for DHCP, not necessary when using static IP
+    info!("waiting for DHCP...");
+    stack.wait_config_up().await;
+    info!("DHCP is now up!");
+
+    loop {
+        control.gpio_set(0, true).await;
+        embassy_time::Timer::after_secs(1).await;
+        control.gpio_set(0, false).await;
+        embassy_time::Timer::after_secs(1).await;
+    }
+}
@@ -108,7 +108,7 @@ async fn main(spawner: Spawner) {
         {
             Ok(_) => break,
             Err(err) => {
-                info!("join failed with status={}", err.status);
+                info!("join failed: {:?}", err);
             }
         }
     }
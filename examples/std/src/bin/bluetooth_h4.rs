@@ -0,0 +1,121 @@
+//! Runs a Bluetooth host over the UART (H4) HCI transport, talking to a fake controller through a
+//! pair of pipes. Real controllers are reached the same way through a UART, chip-specific ones
+//! (cyw43, embassy-stm32-wpan) implement the same `Transport` trait.
+
+use bt_hci::cmd::controller_baseband::Reset;
+use bt_hci::cmd::info::ReadBdAddr;
+use bt_hci::cmd::Cmd;
+use bt_hci::controller::{Controller, ControllerCmdSync, ExternalController};
+use bt_hci::data::{AclBroadcastFlag, AclPacket, AclPacketBoundary};
+use bt_hci::param::ConnHandle;
+use bt_hci::ControllerToHostPacket;
+use embassy_executor::Spawner;
+use embassy_hci_h4::H4Transport;
+use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::pipe::Pipe;
+use embedded_io_async::Read;
+use futures::future::join;
+use heapless::Vec;
+use log::*;
+
+/// H4 packet indicators.
+const H4_CMD: u8 = 0x01;
+const H4_ACL: u8 = 0x02;
+const H4_EVENT: u8 = 0x04;
+
+/// Event code of HCI Command Complete.
+const EVENT_COMMAND_COMPLETE: u8 = 0x0e;
+
+/// Address of the fake controller.
+const CONTROLLER_ADDR: [u8; 6] = [0x66, 0x55, 0x44, 0x33, 0x22, 0x11];
+
+static HOST_TO_CONTROLLER: Pipe<CriticalSectionRawMutex, 512> = Pipe::new();
+static CONTROLLER_TO_HOST: Pipe<CriticalSectionRawMutex, 512> = Pipe::new();
+
+/// Fake controller: completes every command, and echoes ACL data back.
+#[embassy_executor::task]
+async fn controller_task() {
+    let mut rx = &HOST_TO_CONTROLLER;
+    let mut buf = [0; 256];
+    loop {
+        let mut kind = [0; 1];
+        rx.read_exact(&mut kind).await.unwrap();
+        match kind[0] {
+            H4_CMD => {
+                let mut header = [0; 3];
+                rx.read_exact(&mut header).await.unwrap();
+                let opcode = u16::from_le_bytes([header[0], header[1]]);
+                rx.read_exact(&mut buf[..usize::from(header[2])]).await.unwrap();
+                debug!("controller: command {:04x}", opcode);
+
+                let ret: &[u8] = match opcode {
+                    op if op == ReadBdAddr::OPCODE.to_raw() => &CONTROLLER_ADDR,
+                    _ => &[],
+                };
+                // Number of allowed commands, opcode, success status and return parameters
+                let mut event = Vec::<u8, 16>::new();
+                event
+                    .extend_from_slice(&[H4_EVENT, EVENT_COMMAND_COMPLETE, 4 + ret.len() as u8, 1])
+                    .unwrap();
+                event.extend_from_slice(&opcode.to_le_bytes()).unwrap();
+                event.extend_from_slice(&[0]).unwrap();
+                event.extend_from_slice(ret).unwrap();
+                CONTROLLER_TO_HOST.write_all(&event).await;
+            }
+            H4_ACL => {
+                let mut header = [0; 4];
+                rx.read_exact(&mut header).await.unwrap();
+                let len = usize::from(u16::from_le_bytes([header[2], header[3]]));
+                rx.read_exact(&mut buf[..len]).await.unwrap();
+                debug!("controller: echoing {} bytes of ACL data", len);
+
+                CONTROLLER_TO_HOST.write_all(&[H4_ACL]).await;
+                CONTROLLER_TO_HOST.write_all(&header).await;
+                CONTROLLER_TO_HOST.write_all(&buf[..len]).await;
+            }
+            kind => panic!("unexpected H4 packet indicator {:02x}", kind),
+        }
+    }
+}
+
+#[embassy_executor::main]
+async fn main(spawner: Spawner) {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    spawner.spawn(controller_task()).unwrap();
+
+    let transport = H4Transport::<NoopRawMutex, _, _>::new(&CONTROLLER_TO_HOST, &HOST_TO_CONTROLLER);
+    let controller = ExternalController::<_, 4>::new(transport);
+
+    // Command completions are dispatched while reading from the controller
+    let host = async {
+        controller.exec(&Reset::new()).await.unwrap();
+        let addr = controller.exec(&ReadBdAddr::new()).await.unwrap();
+        info!("controller address: {:02x?}", addr.raw());
+
+        let acl = AclPacket::new(
+            ConnHandle::new(1),
+            AclPacketBoundary::FirstFlushable,
+            AclBroadcastFlag::PointToPoint,
+            b"hello",
+        );
+        controller.write_acl_data(&acl).await.unwrap();
+    };
+    let reader = async {
+        let mut buf = [0; 259];
+        loop {
+            match controller.read(&mut buf).await.unwrap() {
+                ControllerToHostPacket::Acl(acl) => {
+                    info!("received ACL data: {:?}", core::str::from_utf8(acl.data()));
+                    break;
+                }
+                packet => warn!("unexpected packet: {:?}", packet),
+            }
+        }
+    };
+    join(host, reader).await;
+    info!("done");
+}
@@ -0,0 +1,45 @@
+use std::thread;
+
+use embassy_executor::{MultiThreadExecutor, SendSpawner};
+use embassy_time::{Duration, Instant, Timer};
+use log::*;
+use static_cell::StaticCell;
+
+#[embassy_executor::task(pool_size = 4)]
+async fn busy(id: u32) {
+    loop {
+        // Simulate a CPU-heavy job. This blocks the worker thread, but the other
+        // workers keep running the remaining tasks.
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(200) {}
+        info!("busy {} finished a job on {:?}", id, thread::current().name());
+        Timer::after_millis(100).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn tick() {
+    loop {
+        info!("tick on {:?}", thread::current().name());
+        Timer::after_secs(1).await;
+    }
+}
+
+fn init(spawner: SendSpawner) {
+    spawner.spawn(tick()).unwrap();
+    for id in 0..4 {
+        spawner.spawn(busy(id)).unwrap();
+    }
+}
+
+static EXECUTOR: StaticCell<MultiThreadExecutor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(MultiThreadExecutor::new(4));
+    executor.run(init);
+}
@@ -0,0 +1,191 @@
+use core::fmt::Write as _;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::coap::client::{CoapClient, CoapClientState};
+use embassy_net::coap::{
+    self, CoapServer, CoapServerState, Code, ContentFormat, Handler, Method, Request, Response, Route, Router,
+};
+use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::{Instant, Timer};
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// CoAP server to send requests to, and to observe `/time` on
+    #[clap(long)]
+    peer: Option<std::net::Ipv4Addr>,
+}
+
+enum Resource {
+    Hello,
+    Time,
+    Large,
+    Data,
+}
+
+impl Handler for Resource {
+    async fn handle(&self, request: &mut Request<'_>, response: &mut Response<'_>) -> Code {
+        response.set_content_format(ContentFormat::TEXT_PLAIN);
+        match self {
+            Resource::Hello => {
+                let name = request.query_param("name").unwrap_or("world");
+                write!(response, "Hello, {}!", name).unwrap();
+                Code::CONTENT
+            }
+            Resource::Time => {
+                response.set_max_age(10);
+                write!(response, "{}", Instant::now().as_secs()).unwrap();
+                Code::CONTENT
+            }
+            Resource::Large => {
+                // Sent block-wise
+                for i in 0..100 {
+                    writeln!(response, "line {:02}", i).unwrap();
+                }
+                Code::CONTENT
+            }
+            Resource::Data => {
+                // Received block-wise if larger than a block
+                info!("PUT /data: {} bytes", request.payload().len());
+                Code::CHANGED
+            }
+        }
+    }
+}
+
+static ROUTES: [Route<Resource>; 4] = [
+    Route::new(Method::Get, "/hello", Resource::Hello),
+    Route::new(Method::Get, "/time", Resource::Time),
+    Route::new(Method::Get, "/large", Resource::Large),
+    Route::new(Method::Put, "/data", Resource::Data),
+];
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn server_task(server: &'static CoapServer<'static>) -> ! {
+    server.run(&Router::new(&ROUTES), &mut [0; 1024]).await
+}
+
+#[embassy_executor::task]
+async fn client_task(stack: Stack<'static>, peer: IpEndpoint) {
+    static STATE: StaticCell<CoapClientState> = StaticCell::new();
+    let mut client = CoapClient::new(stack, STATE.init(CoapClientState::new()), coap::Config::new());
+    let mut buf = [0; 2048];
+
+    match client.get(peer, "/hello?name=embassy", &mut buf).await {
+        Ok(response) => info!(
+            "GET /hello: {} {:?}",
+            response.code,
+            core::str::from_utf8(response.payload)
+        ),
+        Err(e) => warn!("GET /hello error: {:?}", e),
+    }
+
+    let payload = [b'x'; 1500];
+    match client.put(peer, "/data", &payload, None, &mut buf).await {
+        Ok(response) => info!("PUT /data: {}", response.code),
+        Err(e) => warn!("PUT /data error: {:?}", e),
+    }
+
+    let (mut observation, response) = match client.observe(peer, "/time", &mut buf).await {
+        Ok(observation) => observation,
+        Err(e) => return warn!("observe /time error: {:?}", e),
+    };
+    info!("/time: {:?}", core::str::from_utf8(response.payload));
+    for _ in 0..5 {
+        match observation.next(&mut buf).await {
+            Ok(response) => info!("/time: {:?}", core::str::from_utf8(response.payload)),
+            Err(e) => return warn!("observe /time error: {:?}", e),
+        }
+    }
+    match observation.cancel().await {
+        Ok(()) => info!("observation of /time cancelled"),
+        Err(e) => warn!("cancel error: {:?}", e),
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Launch CoAP server
+    static STATE: StaticCell<CoapServerState> = StaticCell::new();
+    static SERVER: StaticCell<CoapServer> = StaticCell::new();
+    let server = SERVER.init(CoapServer::new(
+        stack,
+        STATE.init(CoapServerState::new()),
+        coap::PORT,
+        coap::Config::new(),
+    ));
+    spawner.spawn(server_task(server)).unwrap();
+
+    if let Some(peer) = opts.peer {
+        stack.wait_config_up().await;
+        spawner
+            .spawn(client_task(stack, IpEndpoint::new(peer.into(), coap::PORT)))
+            .unwrap();
+    }
+
+    // Notify the observers of `/time`
+    loop {
+        Timer::after_secs(5).await;
+        server.notify("/time");
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
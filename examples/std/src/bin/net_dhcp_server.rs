@@ -0,0 +1,79 @@
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::dhcp_server::{Config as DhcpServerConfig, DhcpServer, DhcpServerState, Event};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // The server needs a static IP
+    let address = Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24);
+    let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
+        address,
+        dns_servers: Vec::new(),
+        gateway: None,
+    });
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Lease 192.168.69.50 to 192.168.69.59
+    let mut server_config = DhcpServerConfig::new(address, Ipv4Address::new(192, 168, 69, 50), 10);
+    server_config.dns_servers.push(Ipv4Address::new(8, 8, 8, 8)).unwrap();
+
+    let mut state = DhcpServerState::new();
+    let mut server: DhcpServer<'_, 8> = DhcpServer::new(stack, server_config, &mut state);
+
+    loop {
+        match server.next_event().await {
+            Event::Leased(lease) => info!("leased {} to {}", lease.address, lease.hardware_address),
+            Event::Released(lease) => info!("{} released {}", lease.hardware_address, lease.address),
+        }
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
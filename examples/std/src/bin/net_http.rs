@@ -0,0 +1,172 @@
+use core::cell::Cell;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::http::{
+    Config as HttpConfig, Error, Handler, HttpServer, Method, Request, Responded, Response, Route, Router, Status,
+};
+use embassy_net::tcp::listener::TcpListenerState;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+}
+
+/// Number of connections served at the same time.
+const WORKERS: usize = 3;
+
+type Server = HttpServer<'static, WORKERS>;
+
+static SHUTDOWN: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+enum Api {
+    Index,
+    Hello,
+    Echo,
+    Shutdown,
+}
+
+static ROUTES: [Route<Api>; 4] = [
+    Route::new(Method::Get, "/", Api::Index),
+    Route::new(Method::Get, "/hello/{name}", Api::Hello),
+    Route::new(Method::Post, "/echo", Api::Echo),
+    Route::new(Method::Post, "/shutdown", Api::Shutdown),
+];
+
+impl Handler for Api {
+    async fn handle(&self, request: &mut Request<'_>, response: Response<'_>) -> Result<Responded, Error> {
+        info!("{:?} {}", request.method(), request.path());
+        match self {
+            Api::Index => {
+                let body = b"<html><body><h1>Hello from embassy-net!</h1></body></html>";
+                response.send(Status::OK, &[("Content-Type", "text/html")], body).await
+            }
+            Api::Hello => {
+                let name = request.param("name").unwrap_or_default();
+                let mut body = response
+                    .start(Status::OK, &[("Content-Type", "text/plain")], None)
+                    .await?;
+                body.write_all(b"Hello, ").await?;
+                body.write_all(name.as_bytes()).await?;
+                body.write_all(b"!\n").await?;
+                body.finish().await
+            }
+            Api::Echo => {
+                // Stream the request body back, whatever its length.
+                let content_type = request.header("Content-Type").unwrap_or("application/octet-stream");
+                let mut body = response
+                    .start(Status::OK, &[("Content-Type", content_type)], None)
+                    .await?;
+                let mut buf = [0; 256];
+                loop {
+                    match request.body().read(&mut buf).await? {
+                        0 => break,
+                        n => body.write_all(&buf[..n]).await?,
+                    }
+                }
+                body.finish().await
+            }
+            Api::Shutdown => {
+                SHUTDOWN.signal(());
+                response.send(Status::ACCEPTED, &[], &[]).await
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task(pool_size = WORKERS)]
+async fn http_task(server: &'static Server, stopped: &'static Cell<u32>) {
+    let mut buf = [0; 1024];
+    server.run(&Router::new(&ROUTES), &mut buf).await;
+    stopped.set(stopped.get() + 1);
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<5>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Serve HTTP on port 80 with a few workers
+    static STATE: StaticCell<TcpListenerState<WORKERS>> = StaticCell::new();
+    static SERVER: StaticCell<Server> = StaticCell::new();
+    static STOPPED: StaticCell<Cell<u32>> = StaticCell::new();
+    let server: &'static Server = SERVER.init(HttpServer::new(
+        stack,
+        STATE.init(TcpListenerState::new()),
+        80,
+        HttpConfig::new(),
+    ));
+    let stopped: &'static Cell<u32> = STOPPED.init(Cell::new(0));
+    for _ in 0..WORKERS {
+        spawner.spawn(http_task(server, stopped)).unwrap();
+    }
+    info!("serving HTTP on port 80, POST /shutdown to stop");
+
+    SHUTDOWN.wait().await;
+    info!("shutting down");
+    server.shutdown();
+    while stopped.get() < WORKERS as u32 {
+        embassy_time::Timer::after_millis(100).await;
+    }
+    info!("all workers stopped");
+    std::process::exit(0);
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
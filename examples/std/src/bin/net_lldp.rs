@@ -0,0 +1,159 @@
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::ethernet::{EthernetSocket, Filter, PacketMetadata};
+use embassy_net::{Config, HardwareAddress, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::{Duration, Ticker};
+use futures::future::{select, Either};
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+/// EtherType of LLDP frames.
+const ETHERTYPE_LLDP: u16 = 0x88cc;
+/// Multicast address LLDP frames are sent to, not forwarded by bridges.
+const LLDP_MULTICAST: [u8; 6] = [0x01, 0x80, 0xc2, 0x00, 0x00, 0x0e];
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+/// Append a TLV to an LLDP frame.
+fn push_tlv(frame: &mut Vec<u8, 256>, ty: u8, value: &[u8]) {
+    let header = (u16::from(ty) << 9) | value.len() as u16;
+    frame.extend_from_slice(&header.to_be_bytes()).unwrap();
+    frame.extend_from_slice(value).unwrap();
+}
+
+/// Log the TLVs of a received LLDP frame.
+fn log_lldp(frame: &[u8]) {
+    info!("LLDP frame from {:02x?}", &frame[6..12]);
+    let mut tlvs = &frame[14..];
+    while tlvs.len() >= 2 {
+        let header = u16::from_be_bytes([tlvs[0], tlvs[1]]);
+        let (ty, len) = ((header >> 9) as u8, usize::from(header & 0x1ff));
+        if ty == 0 || tlvs.len() < 2 + len {
+            break;
+        }
+        let value = &tlvs[2..2 + len];
+        match ty {
+            5 => info!("  system name: {}", core::str::from_utf8(value).unwrap_or("?")),
+            _ => info!("  TLV {}: {:02x?}", ty, value),
+        }
+        tlvs = &tlvs[2 + len..];
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // LLDP runs next to the IP stack, on the same interface
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 1024];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 1024];
+    let socket = EthernetSocket::new(
+        stack,
+        Filter::EtherType(ETHERTYPE_LLDP),
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    let HardwareAddress::Ethernet(mac) = socket.hardware_address() else {
+        unreachable!()
+    };
+
+    // Build the LLDP frame announcing us
+    let mut frame = Vec::<u8, 256>::new();
+    frame.extend_from_slice(&LLDP_MULTICAST).unwrap();
+    frame.extend_from_slice(mac.as_bytes()).unwrap();
+    frame.extend_from_slice(&ETHERTYPE_LLDP.to_be_bytes()).unwrap();
+    // Chassis ID, subtype 4: MAC address
+    let mut chassis = [4; 7];
+    chassis[1..].copy_from_slice(mac.as_bytes());
+    push_tlv(&mut frame, 1, &chassis);
+    // Port ID, subtype 5: interface name
+    push_tlv(&mut frame, 2, b"\x05eth0");
+    // Time to live, in seconds
+    push_tlv(&mut frame, 3, &120u16.to_be_bytes());
+    // System name
+    push_tlv(&mut frame, 5, b"embassy");
+    // End of LLDPDU
+    push_tlv(&mut frame, 0, &[]);
+
+    let mut ticker = Ticker::every(Duration::from_secs(30));
+    let mut buf = [0; 1514];
+    loop {
+        socket.send(&frame).await.unwrap();
+        debug!("sent LLDP frame");
+
+        // Log the frames of our neighbors until the next announce
+        let mut tick = core::pin::pin!(ticker.next());
+        loop {
+            let received = match select(core::pin::pin!(socket.recv(&mut buf)), tick.as_mut()).await {
+                Either::Left((received, _)) => received,
+                Either::Right(_) => break,
+            };
+            match received {
+                Ok(n) => log_lldp(&buf[..n]),
+                Err(e) => warn!("recv error: {:?}", e),
+            }
+        }
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,156 @@
+use core::fmt::Write as _;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::mqtt::{self, Config as MqttConfig, Message, MqttClient, MqttRunner, MqttState, QoS, Version};
+use embassy_net::{Config, IpEndpoint, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pubsub::PubSubChannel;
+use embassy_time::Timer;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// broker address
+    #[clap(long, default_value = "192.168.69.100")]
+    broker: std::net::Ipv4Addr,
+    /// use MQTT 5 instead of MQTT 3.1.1
+    #[clap(long)]
+    v5: bool,
+}
+
+type M = CriticalSectionRawMutex;
+type Received = Message<64, 256>;
+
+static MESSAGES: PubSubChannel<M, Received, 4, 1, 1> = PubSubChannel::new();
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn mqtt_task(mut runner: MqttRunner<'static, M, 1024, 64, 256>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn receive_task(client: MqttClient<'static, M, 1024>) {
+    let mut subscriber = MESSAGES.subscriber().unwrap();
+    loop {
+        let message = subscriber.next_message_pure().await;
+        info!(
+            "received {:?} on {} ({:?}, retain: {})",
+            core::str::from_utf8(&message.payload),
+            message.topic,
+            message.qos,
+            message.retain
+        );
+        if mqtt::topic_matches("embassy/cmd/echo", &message.topic) {
+            unwrap_publish(
+                client
+                    .publish("embassy/echo", &message.payload, message.qos, false)
+                    .await,
+            );
+        }
+    }
+}
+
+fn unwrap_publish(res: Result<(), mqtt::Error>) {
+    if let Err(e) = res {
+        warn!("publish error: {:?}", e);
+    }
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Init MQTT client
+    let mut mqtt_config = MqttConfig::new(IpEndpoint::new(opts.broker.into(), 1883), "embassy-std");
+    mqtt_config.version = if opts.v5 { Version::Mqtt5 } else { Version::Mqtt311 };
+    mqtt_config.keep_alive = embassy_time::Duration::from_secs(10);
+    mqtt_config.will = Some(mqtt::Will::new("embassy/status", b"offline"));
+    static STATE: StaticCell<MqttState<M>> = StaticCell::new();
+    let (client, runner) = mqtt::new(
+        stack,
+        STATE.init(MqttState::new()),
+        mqtt_config,
+        MESSAGES.dyn_publisher().unwrap(),
+    );
+    spawner.spawn(mqtt_task(runner)).unwrap();
+    spawner.spawn(receive_task(client)).unwrap();
+
+    client.subscribe("embassy/cmd/#", QoS::ExactlyOnce).await.unwrap();
+    client.wait_connected().await;
+    unwrap_publish(
+        client
+            .publish("embassy/status", b"online", QoS::AtLeastOnce, true)
+            .await,
+    );
+
+    // Publish telemetry with each QoS in turn
+    let qos = [QoS::AtMostOnce, QoS::AtLeastOnce, QoS::ExactlyOnce];
+    for count in 0u32.. {
+        let mut payload: heapless::String<32> = heapless::String::new();
+        write!(payload, "count={}", count).unwrap();
+        unwrap_publish(
+            client
+                .publish("embassy/telemetry", payload.as_bytes(), qos[count as usize % 3], false)
+                .await,
+        );
+        Timer::after_secs(5).await;
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
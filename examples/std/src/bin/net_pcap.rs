@@ -0,0 +1,108 @@
+use std::fs::File;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::dns::DnsQueryType;
+use embassy_net::pcap::PcapPipe;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_time::Timer;
+use embedded_io_adapters::futures_03::FromFutures;
+use futures::io::AllowStdIo;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// pcapng file to write the captured packets to
+    #[clap(long, default_value = "capture.pcapng")]
+    output: String,
+}
+
+static PCAP: PcapPipe<CriticalSectionRawMutex, 16384> = PcapPipe::new();
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn pcap_task(file: File) {
+    // Writing to the file blocks, which is fine for an example.
+    let mut writer = FromFutures::new(AllowStdIo::new(file));
+    let Err(e) = PCAP.write_to(&mut writer).await;
+    warn!("capture write error: {:?}", e);
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::from_slice(&[Ipv4Address::new(8, 8, 4, 4).into(), Ipv4Address::new(8, 8, 8, 8).into()])
+                .unwrap(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, mut runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Capture the packets of the interface
+    runner.set_packet_sink(Some(&PCAP));
+    let file = File::create(&opts.output).unwrap();
+    info!("capturing packets to {}", opts.output);
+    spawner.spawn(pcap_task(file)).unwrap();
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Make some traffic
+    loop {
+        match stack.dns_query("example.com", DnsQueryType::A).await {
+            Ok(r) => info!("query response: {:?}", r),
+            Err(e) => warn!("query error: {:?}", e),
+        }
+        info!("{} packets dropped from the capture", PCAP.dropped());
+        Timer::after_secs(10).await;
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,111 @@
+use core::fmt::Write as _;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::tcp::{ReconnectConfig, ReconnectError, ReconnectingClient, Remote};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::Timer;
+use embedded_io_async::Write;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// host to send the telemetry to
+    #[clap(long, default_value = "192.168.69.100")]
+    host: String,
+    /// port to send the telemetry to
+    #[clap(long, default_value = "8000")]
+    port: u16,
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // The host name is resolved again on each connection
+    let host: &'static str = Box::leak(opts.host.into_boxed_str());
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut client = ReconnectingClient::new(
+        stack,
+        &mut rx_buffer,
+        &mut tx_buffer,
+        Remote::Host(host, opts.port),
+        ReconnectConfig::new(),
+    );
+
+    // Send a line of telemetry every second, starting each connection with a greeting
+    let mut greet = true;
+    for count in 0u32.. {
+        let mut line = heapless::String::<64>::new();
+        if greet {
+            line.push_str("hello from embassy\n").unwrap();
+        }
+        writeln!(line, "count={}", count).unwrap();
+        match client.write_all(line.as_bytes()).await {
+            Ok(()) => greet = false,
+            Err(ReconnectError::Reconnected) => {
+                info!("reconnected to {:?}", client.remote_endpoint());
+                greet = true;
+            }
+        }
+        Timer::after_secs(1).await;
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
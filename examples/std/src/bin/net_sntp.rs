@@ -0,0 +1,111 @@
+use std::net::IpAddr;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::sntp::{Config as SntpConfig, Server, SntpClient, SntpClientState, WallClock};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::Timer;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// NTP server address or name, in addition to the servers provided by DHCP
+    #[clap(long, default_value = "pool.ntp.org")]
+    server: String,
+}
+
+static CLOCK: WallClock = WallClock::new();
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn sntp_task(mut client: SntpClient<'static>) -> ! {
+    client.run(&CLOCK).await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::from_slice(&[Ipv4Address::new(8, 8, 4, 4).into(), Ipv4Address::new(8, 8, 8, 8).into()])
+                .unwrap(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 100)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<4>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Query the servers provided by DHCP first
+    let server = match opts.server.parse::<IpAddr>() {
+        Ok(addr) => Server::Addr(addr.into()),
+        Err(_) => Server::Name(opts.server.leak()),
+    };
+    static SERVERS: StaticCell<[Server<'static>; 2]> = StaticCell::new();
+    let servers = SERVERS.init([Server::Dhcp, server]);
+
+    // Launch SNTP task
+    static STATE: StaticCell<SntpClientState> = StaticCell::new();
+    let client = SntpClient::new(stack, SntpConfig::new(servers), STATE.init(SntpClientState::new()));
+    spawner.spawn(sntp_task(client)).unwrap();
+
+    loop {
+        match CLOCK.now_unix_micros() {
+            Some(t) => info!(
+                "unix time: {}.{:06} s, drift: {} ppb",
+                t / 1_000_000,
+                t % 1_000_000,
+                CLOCK.drift().unwrap()
+            ),
+            None => info!("waiting for synchronization..."),
+        }
+        Timer::after_secs(10).await;
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
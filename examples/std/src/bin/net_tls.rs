@@ -0,0 +1,162 @@
+//! TLS client, verifying the server certificate.
+//!
+//! Run a TLS 1.3 server on the host side of the TAP interface, with a P-256 certificate for `localhost`
+//! signed by `ca.pem`. Key exchange is only supported with P-256:
+//!
+//! ```sh
+//! openssl s_server -tls1_3 -groups P-256 -accept 4433 -cert server.pem -key server-key.pem
+//! ```
+//!
+//! and pass the CA certificate in DER format with `--ca ca.der`.
+
+use std::time::SystemTime;
+
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::tls::{
+    Aes128GcmSha256, CertVerifier, Certificate, TlsClock, TlsConfig, TlsConnection, MAX_RECORD_SIZE,
+};
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, StackResources};
+use embassy_net_tuntap::TunTapDevice;
+use embassy_time::Duration;
+use embedded_io_async::Write;
+use heapless::Vec;
+use log::*;
+use rand_core::{OsRng, RngCore};
+use static_cell::StaticCell;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// TAP device name
+    #[clap(long, default_value = "tap0")]
+    tap: String,
+    /// use a static IP instead of DHCP
+    #[clap(long)]
+    static_ip: bool,
+    /// CA certificate of the server, in DER format
+    #[clap(long)]
+    ca: String,
+    /// Name in the server certificate
+    #[clap(long, default_value = "localhost")]
+    server_name: String,
+}
+
+/// Clock checking the validity period of certificates.
+struct SystemClock;
+
+impl TlsClock for SystemClock {
+    fn now() -> Option<u64> {
+        Some(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        )
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: embassy_net::Runner<'static, TunTapDevice>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Init network device
+    let device = TunTapDevice::new(&opts.tap).unwrap();
+
+    // Choose between dhcp or static ip
+    let config = if opts.static_ip {
+        Config::ipv4_static(embassy_net::StaticConfigV4 {
+            address: Ipv4Cidr::new(Ipv4Address::new(192, 168, 69, 2), 24),
+            dns_servers: Vec::new(),
+            gateway: Some(Ipv4Address::new(192, 168, 69, 1)),
+        })
+    } else {
+        Config::dhcpv4(Default::default())
+    };
+
+    // Generate random seed
+    let mut seed = [0; 8];
+    OsRng.fill_bytes(&mut seed);
+    let seed = u64::from_le_bytes(seed);
+
+    // Init network stack
+    static RESOURCES: StaticCell<StackResources<3>> = StaticCell::new();
+    let (stack, runner) = embassy_net::new(device, config, RESOURCES.init(StackResources::new()), seed);
+
+    // Launch network task
+    spawner.spawn(net_task(runner)).unwrap();
+
+    // Then we can use it!
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+
+    socket.set_timeout(Some(Duration::from_secs(10)));
+
+    let remote_endpoint = (Ipv4Address::new(192, 168, 69, 100), 4433);
+    info!("connecting to {:?}...", remote_endpoint);
+    let r = socket.connect(remote_endpoint).await;
+    if let Err(e) = r {
+        warn!("connect error: {:?}", e);
+        return;
+    }
+    info!("connected!");
+
+    let ca = std::fs::read(&opts.ca).unwrap();
+    let tls_config = TlsConfig::new()
+        .with_server_name(&opts.server_name)
+        .with_ca(Certificate::X509(&ca));
+
+    let mut read_record_buffer = [0; MAX_RECORD_SIZE];
+    let mut write_record_buffer = [0; 4096];
+    let mut tls: TlsConnection<'_, _, Aes128GcmSha256> =
+        TlsConnection::new(socket, &mut read_record_buffer, &mut write_record_buffer);
+
+    let r = tls
+        .open::<CertVerifier<Aes128GcmSha256, SystemClock, 4096>, _>(&tls_config, &mut OsRng)
+        .await;
+    if let Err(e) = r {
+        warn!("TLS handshake error: {:?}", e);
+        return;
+    }
+    info!("TLS session established!");
+
+    for i in 0..10 {
+        let r = tls.write_all(format!("Hello! ({})\n", i).as_bytes()).await;
+        if let Err(e) = r {
+            warn!("write error: {:?}", e);
+            return;
+        }
+        if let Err(e) = tls.flush().await {
+            warn!("flush error: {:?}", e);
+            return;
+        }
+        embassy_time::Timer::after_secs(1).await;
+    }
+
+    let r = tls.close().await;
+    if let Err((_, e)) = r {
+        warn!("close error: {:?}", e);
+    }
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}
@@ -0,0 +1,142 @@
+use clap::Parser;
+use embassy_executor::{Executor, Spawner};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{Config, Ipv4Address, Ipv4Cidr, Stack, StackResources};
+use embassy_net_driver_channel::driver::HardwareAddress;
+use embassy_net_driver_channel::virtual_link::{self, LinkConfig};
+use embassy_net_driver_channel::Device;
+use embassy_time::{Duration, Instant};
+use embedded_io_async::{Read, Write};
+use heapless::Vec;
+use log::*;
+use static_cell::StaticCell;
+
+const MTU: usize = 1514;
+
+#[derive(Parser)]
+#[clap(version = "1.0")]
+struct Opts {
+    /// latency of the link, in milliseconds
+    #[clap(long, default_value = "20")]
+    latency: u64,
+    /// percentage of packets lost
+    #[clap(long, default_value = "2")]
+    loss: u8,
+    /// bandwidth of the link, in kbit/s
+    #[clap(long, default_value = "1000")]
+    bandwidth: u32,
+    /// seed of the losses, the same one gives the same losses
+    #[clap(long, default_value = "1")]
+    seed: u64,
+}
+
+#[embassy_executor::task]
+async fn switch_task(runner: virtual_link::Runner<'static, MTU, 2>) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task(pool_size = 2)]
+async fn net_task(mut runner: embassy_net::Runner<'static, Device<'static, MTU>>) -> ! {
+    runner.run().await
+}
+
+fn new_stack(spawner: Spawner, device: Device<'static, MTU>, address: Ipv4Address, seed: u64) -> Stack<'static> {
+    let config = Config::ipv4_static(embassy_net::StaticConfigV4 {
+        address: Ipv4Cidr::new(address, 24),
+        dns_servers: Vec::new(),
+        gateway: None,
+    });
+    let resources = Box::leak(Box::new(StackResources::<2>::new()));
+    let (stack, runner) = embassy_net::new(device, config, resources, seed);
+    spawner.spawn(net_task(runner)).unwrap();
+    stack
+}
+
+#[embassy_executor::task]
+async fn server_task(stack: Stack<'static>) {
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut buf = [0; 1024];
+    let mut socket = TcpSocket::new(stack, &mut rx_buffer, &mut tx_buffer);
+    socket.accept(1234).await.unwrap();
+    info!("server: accepted from {:?}", socket.remote_endpoint());
+
+    // Echo everything back
+    loop {
+        let n = socket.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        socket.write_all(&buf[..n]).await.unwrap();
+    }
+    socket.close();
+    socket.flush().await.unwrap();
+}
+
+#[embassy_executor::task]
+async fn main_task(spawner: Spawner) {
+    let opts: Opts = Opts::parse();
+
+    // Connect two stacks through a virtual switch with two ports
+    static STATE: StaticCell<virtual_link::State<MTU, 2, 16>> = StaticCell::new();
+    let addresses = [
+        HardwareAddress::Ethernet([0x02, 0, 0, 0, 0, 1]),
+        HardwareAddress::Ethernet([0x02, 0, 0, 0, 0, 2]),
+    ];
+    let (control, runner, [device_a, device_b]) =
+        virtual_link::new(STATE.init(virtual_link::State::new()), addresses, opts.seed);
+    spawner.spawn(switch_task(runner)).unwrap();
+
+    // Impair both directions of the link
+    let mut config = LinkConfig::new();
+    config.latency = Duration::from_millis(opts.latency);
+    config.loss_percent = opts.loss;
+    config.bandwidth = Some(opts.bandwidth * 1000);
+    control.set_config(0, config);
+    control.set_config(1, config);
+
+    let server = new_stack(spawner, device_a, Ipv4Address::new(10, 0, 0, 1), opts.seed);
+    let client = new_stack(spawner, device_b, Ipv4Address::new(10, 0, 0, 2), opts.seed + 1);
+    spawner.spawn(server_task(server)).unwrap();
+
+    let mut rx_buffer = [0; 4096];
+    let mut tx_buffer = [0; 4096];
+    let mut socket = TcpSocket::new(client, &mut rx_buffer, &mut tx_buffer);
+    socket.connect((Ipv4Address::new(10, 0, 0, 1), 1234)).await.unwrap();
+    info!("client: connected");
+
+    // Send 64 KiB in chunks fitting in the socket buffers, and check the echo of each
+    let start = Instant::now();
+    let data: std::vec::Vec<u8> = (0..65536u32).map(|i| (i % 251) as u8).collect();
+    let mut echo = [0; 2048];
+    for chunk in data.chunks(echo.len()) {
+        socket.write_all(chunk).await.unwrap();
+        socket.read_exact(&mut echo[..chunk.len()]).await.unwrap();
+        assert_eq!(&echo[..chunk.len()], chunk);
+    }
+    socket.close();
+
+    let elapsed = start.elapsed();
+    info!(
+        "client: {} bytes echoed in {} ms, {} kbit/s",
+        data.len(),
+        elapsed.as_millis(),
+        data.len() as u64 * 8 / elapsed.as_millis().max(1)
+    );
+    std::process::exit(0);
+}
+
+static EXECUTOR: StaticCell<Executor> = StaticCell::new();
+
+fn main() {
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .filter_module("async_io", log::LevelFilter::Info)
+        .format_timestamp_nanos()
+        .init();
+
+    let executor = EXECUTOR.init(Executor::new());
+    executor.run(|spawner| {
+        spawner.spawn(main_task(spawner)).unwrap();
+    });
+}